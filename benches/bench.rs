@@ -12,6 +12,7 @@ use test::Bencher;
 
 use miniz_oxide::deflate::compress_to_vec;
 use miniz_oxide::deflate::core::{create_comp_flags_from_zip_params, CompressorOxide};
+use miniz_oxide::inflate::decompress_slice;
 
 use miniz_oxide_c_api::miniz_def_free_func;
 
@@ -82,6 +83,31 @@ macro_rules! compress_bench {
     };
 }
 
+macro_rules! decompress_slice_bench {
+    ($bench_name:ident, $level:expr, $path_to_data:expr) => {
+        #[bench]
+        fn $bench_name(b: &mut ::Bencher) {
+            let input = ::get_test_file_data($path_to_data);
+            let compressed = ::compress_to_vec(input.as_slice(), $level);
+
+            let mut out = vec![0; input.len()];
+            b.iter(|| ::decompress_slice(&compressed, &mut out).unwrap());
+        }
+    };
+}
+
+macro_rules! compress_level_bench {
+    ($bench_name:ident, $level:expr, $path_to_data:expr) => {
+        #[bench]
+        fn $bench_name(b: &mut ::Bencher) {
+            let input = ::get_test_file_data($path_to_data);
+
+            b.bytes = input.len() as u64;
+            b.iter(|| ::compress_to_vec(&input, $level));
+        }
+    };
+}
+
 mod oxide {
     use miniz_oxide_c_api::{tdefl_compress_mem_to_heap, tinfl_decompress_mem_to_heap};
 
@@ -219,6 +245,58 @@ mod oxide {
     );
 }
 
+mod oxide_slice {
+    decompress_slice_bench!(decompress_short_lvl_1, 1, "benches/data/short");
+    decompress_slice_bench!(decompress_bin_lvl_1, 1, "benches/data/bin");
+    decompress_slice_bench!(decompress_bin_lvl_6, 6, "benches/data/bin");
+    decompress_slice_bench!(decompress_bin_lvl_9, 9, "benches/data/bin");
+
+    decompress_slice_bench!(decompress_code_lvl_1, 1, "benches/data/code");
+    decompress_slice_bench!(decompress_code_lvl_6, 6, "benches/data/code");
+    decompress_slice_bench!(decompress_code_lvl_9, 9, "benches/data/code");
+
+    decompress_slice_bench!(decompress_compressed_lvl_1, 1, "benches/data/compressed");
+    decompress_slice_bench!(decompress_compressed_lvl_6, 6, "benches/data/compressed");
+    decompress_slice_bench!(decompress_compressed_lvl_9, 9, "benches/data/compressed");
+}
+
+/// Every compression level, to compare the match finders they use.
+/// The compressed sizes can be printed with the `levels` example of miniz_oxide.
+mod oxide_levels {
+    compress_level_bench!(compress_bin_lvl_1, 1, "benches/data/bin");
+    compress_level_bench!(compress_bin_lvl_2, 2, "benches/data/bin");
+    compress_level_bench!(compress_bin_lvl_3, 3, "benches/data/bin");
+    compress_level_bench!(compress_bin_lvl_4, 4, "benches/data/bin");
+    compress_level_bench!(compress_bin_lvl_5, 5, "benches/data/bin");
+    compress_level_bench!(compress_bin_lvl_6, 6, "benches/data/bin");
+    compress_level_bench!(compress_bin_lvl_7, 7, "benches/data/bin");
+    compress_level_bench!(compress_bin_lvl_8, 8, "benches/data/bin");
+    compress_level_bench!(compress_bin_lvl_9, 9, "benches/data/bin");
+    compress_level_bench!(compress_bin_lvl_10, 10, "benches/data/bin");
+
+    compress_level_bench!(compress_code_lvl_1, 1, "benches/data/code");
+    compress_level_bench!(compress_code_lvl_2, 2, "benches/data/code");
+    compress_level_bench!(compress_code_lvl_3, 3, "benches/data/code");
+    compress_level_bench!(compress_code_lvl_4, 4, "benches/data/code");
+    compress_level_bench!(compress_code_lvl_5, 5, "benches/data/code");
+    compress_level_bench!(compress_code_lvl_6, 6, "benches/data/code");
+    compress_level_bench!(compress_code_lvl_7, 7, "benches/data/code");
+    compress_level_bench!(compress_code_lvl_8, 8, "benches/data/code");
+    compress_level_bench!(compress_code_lvl_9, 9, "benches/data/code");
+    compress_level_bench!(compress_code_lvl_10, 10, "benches/data/code");
+
+    compress_level_bench!(compress_compressed_lvl_1, 1, "benches/data/compressed");
+    compress_level_bench!(compress_compressed_lvl_2, 2, "benches/data/compressed");
+    compress_level_bench!(compress_compressed_lvl_3, 3, "benches/data/compressed");
+    compress_level_bench!(compress_compressed_lvl_4, 4, "benches/data/compressed");
+    compress_level_bench!(compress_compressed_lvl_5, 5, "benches/data/compressed");
+    compress_level_bench!(compress_compressed_lvl_6, 6, "benches/data/compressed");
+    compress_level_bench!(compress_compressed_lvl_7, 7, "benches/data/compressed");
+    compress_level_bench!(compress_compressed_lvl_8, 8, "benches/data/compressed");
+    compress_level_bench!(compress_compressed_lvl_9, 9, "benches/data/compressed");
+    compress_level_bench!(compress_compressed_lvl_10, 10, "benches/data/compressed");
+}
+
 mod miniz {
     use libc::{c_int, c_void};
 
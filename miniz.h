@@ -285,7 +285,7 @@ typedef struct mz_stream_s
     unsigned int avail_out;  /* number of bytes that can be written to next_out */
     mz_ulong total_out;      /* total number of bytes produced so far */
 
-    char *msg;                       /* error msg (unused) */
+    char *msg;                       /* error msg, or NULL */
     struct mz_internal_state *state; /* internal state, allocated by zalloc/zfree */
 
     mz_alloc_func zalloc; /* optional heap allocation function (defaults to malloc) */
@@ -343,9 +343,46 @@ int mz_deflate(mz_streamp pStream, int flush);
 /*  MZ_STREAM_ERROR if the stream is bogus. */
 int mz_deflateEnd(mz_streamp pStream);
 
-/* mz_deflateBound() returns a (very) conservative upper bound on the amount of data that could be generated by deflate(), assuming flush is set to only MZ_NO_FLUSH or MZ_FINISH. */
+/* mz_deflateBound() returns a conservative upper bound on the amount of data that could be generated by deflate() with the current settings of pStream, or with any settings if pStream is NULL or not a compression stream, assuming flush is set to only MZ_NO_FLUSH or MZ_FINISH. */
 mz_ulong mz_deflateBound(mz_streamp pStream, mz_ulong source_len);
 
+/* Tunes the match finding like zlib: once a match of good_length bytes is found fewer positions are checked, lazy matching is skipped for matches of max_lazy bytes or more, the search stops at a match of nice_length bytes, and up to max_chain hash chain entries are checked. Compression level 1 only uses max_chain. The values are reset when the level is changed. */
+int mz_deflateTune(mz_streamp pStream, int good_length, int max_lazy, int nice_length, int max_chain);
+
+/* Inserts the lower bits bits of value into the output before the next block. At most 16 bits can be pending. */
+/* Returns MZ_BUF_ERROR if there is no room for the bits, and MZ_STREAM_ERROR if the zlib header has not been written yet. */
+int mz_deflatePrime(mz_streamp pStream, int bits, int value);
+
+/* Returns the number of bytes and bits of output that have not been written yet in *pending and *bits. Either may be NULL. */
+int mz_deflatePending(mz_streamp pStream, unsigned int *pending, int *bits);
+
+/* Makes pDest a copy of pSource, with its own copy of the compressor state allocated with pSource's allocation functions. */
+/* Returns MZ_MEM_ERROR if the allocation fails, and MZ_STREAM_ERROR if either stream is invalid. */
+int mz_deflateCopy(mz_streamp pDest, mz_streamp pSource);
+
+/* gzip header, only defined for zlib compatibility. */
+typedef struct mz_gz_header_s
+{
+    int text;
+    mz_ulong time;
+    int xflags;
+    int os;
+    unsigned char *extra;
+    unsigned int extra_len;
+    unsigned int extra_max;
+    unsigned char *name;
+    unsigned int name_max;
+    unsigned char *comment;
+    unsigned int comm_max;
+    int hcrc;
+    int done;
+} mz_gz_header;
+
+typedef mz_gz_header *mz_gz_headerp;
+
+/* gzip streams are not supported, so this always returns MZ_STREAM_ERROR. */
+int mz_deflateSetHeader(mz_streamp pStream, mz_gz_headerp head);
+
 /* Single-call compression functions mz_compress() and mz_compress2(): */
 /* Returns MZ_OK on success, or one of the error codes from mz_deflate() on failure. */
 int mz_compress(unsigned char *pDest, mz_ulong *pDest_len, const unsigned char *pSource, mz_ulong source_len);
@@ -380,6 +417,29 @@ int mz_inflate(mz_streamp pStream, int flush);
 /* Deinitializes a decompressor. */
 int mz_inflateEnd(mz_streamp pStream);
 
+/* Resets a decompressor without reallocating anything, mz_inflateReset2() also changes the format like window_bits of mz_inflateInit2(). */
+int mz_inflateReset(mz_streamp pStream);
+int mz_inflateReset2(mz_streamp pStream, int window_bits);
+
+/* Makes pDest a copy of pSource, including the window of previous output, like mz_deflateCopy(). */
+int mz_inflateCopy(mz_streamp pDest, mz_streamp pSource);
+
+/* Inserts the lower bits bits of value into the input, to be read before the remaining input. bits must be at most 16. */
+int mz_inflatePrime(mz_streamp pStream, int bits, int value);
+
+/* Returns the position of the decompressor in the input like zlib: outside of compressed data, -65536 plus the number of bytes left to copy from a stored block. Inside compressed data, the number of bits back to the start of the current literal or match shifted left by 16, plus the number of bytes of it already decompressed. -65536 if the stream is bogus. */
+long mz_inflateMark(mz_streamp pStream);
+
+/* gzip streams are not supported, so this always returns MZ_STREAM_ERROR. */
+int mz_inflateGetHeader(mz_streamp pStream, mz_gz_headerp head);
+
+/* Callback based decompression of raw deflate streams, see zlib's inflateBack(). window is not used, but must not be NULL. */
+typedef unsigned int (*mz_in_func)(void *desc, const unsigned char **buf);
+typedef int (*mz_out_func)(void *desc, unsigned char *buf, unsigned int len);
+int mz_inflateBackInit(mz_streamp pStream, int window_bits, unsigned char *window);
+int mz_inflateBack(mz_streamp pStream, mz_in_func in, void *in_desc, mz_out_func out, void *out_desc);
+int mz_inflateBackEnd(mz_streamp pStream);
+
 /* Single-call decompression. */
 /* Returns MZ_OK on success, or one of the error codes from mz_inflate() on failure. */
 int mz_uncompress(unsigned char *pDest, mz_ulong *pDest_len, const unsigned char *pSource, mz_ulong source_len);
@@ -439,6 +499,13 @@ typedef void *const voidpc;
 #define deflate mz_deflate
 #define deflateEnd mz_deflateEnd
 #define deflateBound mz_deflateBound
+#define deflateTune mz_deflateTune
+#define deflatePrime mz_deflatePrime
+#define deflatePending mz_deflatePending
+#define deflateCopy mz_deflateCopy
+#define deflateSetHeader mz_deflateSetHeader
+#define gz_header mz_gz_header
+#define gz_headerp mz_gz_headerp
 #define compress mz_compress
 #define compress2 mz_compress2
 #define compressBound mz_compressBound
@@ -446,6 +513,17 @@ typedef void *const voidpc;
 #define inflateInit2 mz_inflateInit2
 #define inflate mz_inflate
 #define inflateEnd mz_inflateEnd
+#define inflateReset mz_inflateReset
+#define inflateReset2 mz_inflateReset2
+#define inflateCopy mz_inflateCopy
+#define inflatePrime mz_inflatePrime
+#define inflateMark mz_inflateMark
+#define inflateGetHeader mz_inflateGetHeader
+#define in_func mz_in_func
+#define out_func mz_out_func
+#define inflateBackInit(strm, window_bits, window) mz_inflateBackInit(strm, window_bits, window)
+#define inflateBack mz_inflateBack
+#define inflateBackEnd mz_inflateBackEnd
 #define uncompress mz_uncompress
 #define crc32 mz_crc32
 #define adler32 mz_adler32
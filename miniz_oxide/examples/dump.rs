@@ -0,0 +1,194 @@
+//! Prints a human-readable disassembly of a raw deflate or zlib stream.
+//!
+//! Usage: `cargo run --example dump -- [--raw | --zlib] [--tokens] <file>`
+//!
+//! If neither `--raw` nor `--zlib` is given, the format is guessed from the first two bytes.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use miniz_oxide::inflate::core::inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER;
+use miniz_oxide::inflate::core::{
+    decompress_with_visitor, DecompressorOxide, InflateVisitor, TINFL_LZ_DICT_SIZE,
+};
+use miniz_oxide::inflate::TINFLStatus;
+use miniz_oxide::{BlockType, LZToken};
+
+struct Dumper {
+    print_tokens: bool,
+    num_blocks: usize,
+    block_type: BlockType,
+    block_start: u64,
+    block_out: u64,
+    total_out: u64,
+}
+
+/// Format code lengths as `symbol:length` pairs, skipping unused symbols.
+fn code_sizes(sizes: &[u8]) -> String {
+    let used: Vec<String> = sizes
+        .iter()
+        .enumerate()
+        .filter(|&(_, &size)| size != 0)
+        .map(|(symbol, size)| format!("{}:{}", symbol, size))
+        .collect();
+    format!(
+        "{} of {} used [{}]",
+        used.len(),
+        sizes.len(),
+        used.join(" ")
+    )
+}
+
+impl InflateVisitor for Dumper {
+    fn zlib_header(&mut self, cmf: u8, flg: u8) {
+        println!(
+            "zlib header: cmf 0x{:02x} flg 0x{:02x} (method {}, window {} bytes, level {}, dict {})",
+            cmf,
+            flg,
+            cmf & 0xf,
+            1u32 << ((cmf >> 4) + 8),
+            flg >> 6,
+            (flg >> 5) & 1
+        );
+    }
+
+    fn block_header(&mut self, bit_pos: u64, is_final: bool, block_type: BlockType) {
+        self.block_type = block_type;
+        self.block_start = bit_pos;
+        self.block_out = 0;
+        println!(
+            "block {} at bit {} (byte {}.{}): {:?}{}",
+            self.num_blocks,
+            bit_pos,
+            bit_pos / 8,
+            bit_pos % 8,
+            block_type,
+            if is_final { ", final" } else { "" }
+        );
+        self.num_blocks += 1;
+    }
+
+    fn stored_block_len(&mut self, len: u16) {
+        println!("  stored length: {}", len);
+    }
+
+    fn huffman_tables(&mut self, litlen_code_sizes: &[u8], dist_code_sizes: &[u8]) {
+        if self.block_type == BlockType::Static {
+            println!("  fixed huffman tables");
+            return;
+        }
+        println!("  litlen code lengths: {}", code_sizes(litlen_code_sizes));
+        println!("  dist code lengths: {}", code_sizes(dist_code_sizes));
+    }
+
+    fn token(&mut self, token: LZToken) {
+        match token {
+            LZToken::Literal(_) => self.block_out += 1,
+            LZToken::Match { len, .. } => self.block_out += u64::from(len),
+            LZToken::EndOfBlock => (),
+        }
+        if self.print_tokens {
+            match token {
+                LZToken::Literal(c) if c.is_ascii_graphic() || c == b' ' => {
+                    println!("    literal '{}'", c as char)
+                }
+                LZToken::Literal(c) => println!("    literal 0x{:02x}", c),
+                LZToken::Match { len, dist } => println!("    match len {} dist {}", len, dist),
+                LZToken::EndOfBlock => println!("    end of block"),
+            }
+        }
+    }
+
+    fn block_end(&mut self, bit_pos: u64) {
+        self.total_out += self.block_out;
+        println!(
+            "  end at bit {}: {} bits in, {} bytes out",
+            bit_pos,
+            bit_pos - self.block_start,
+            self.block_out
+        );
+    }
+
+    fn zlib_trailer(&mut self, adler32: u32) {
+        println!("zlib trailer: adler32 0x{:08x}", adler32);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: dump [--raw | --zlib] [--tokens] <file>");
+    process::exit(2);
+}
+
+fn main() {
+    let mut zlib = None;
+    let mut print_tokens = false;
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--raw" => zlib = Some(false),
+            "--zlib" => zlib = Some(true),
+            "--tokens" => print_tokens = true,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+    let input = fs::read(&path).unwrap_or_else(|e| {
+        eprintln!("failed to read {}: {}", path, e);
+        process::exit(1);
+    });
+
+    // A zlib header uses the deflate method, and the two bytes form a multiple of 31.
+    let zlib = zlib.unwrap_or_else(|| {
+        input.len() >= 2
+            && input[0] & 0xf == 8
+            && (u16::from(input[0]) << 8 | u16::from(input[1])) % 31 == 0
+    });
+    let flags = if zlib {
+        TINFL_FLAG_PARSE_ZLIB_HEADER
+    } else {
+        0
+    };
+
+    let mut dumper = Dumper {
+        print_tokens,
+        num_blocks: 0,
+        block_type: BlockType::Stored,
+        block_start: 0,
+        block_out: 0,
+        total_out: 0,
+    };
+    let mut decomp = Box::<DecompressorOxide>::default();
+    // Use a wrapping buffer the size of the window, as only the token stream is of interest.
+    let mut out = vec![0; TINFL_LZ_DICT_SIZE];
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+    let status = loop {
+        let (status, in_consumed, out_consumed) = decompress_with_visitor(
+            &mut decomp,
+            &input[in_pos..],
+            &mut out,
+            out_pos,
+            flags,
+            &mut dumper,
+        );
+        in_pos += in_consumed;
+        out_pos = (out_pos + out_consumed) & (TINFL_LZ_DICT_SIZE - 1);
+        if status != TINFLStatus::HasMoreOutput {
+            break status;
+        }
+    };
+
+    println!(
+        "{:?}: {} blocks, {} of {} bytes read, {} bytes out",
+        status,
+        dumper.num_blocks,
+        in_pos,
+        input.len(),
+        dumper.total_out
+    );
+    if status != TINFLStatus::Done {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,75 @@
+//! Prints the compression ratio and speed of each compression level for the given files.
+//!
+//! Usage: `cargo run --release --example levels -- [--binary-tree] <file>...`
+//!
+//! With `--binary-tree`, matches are looked for with binary trees instead of hash chains.
+
+use std::env;
+use std::fs;
+use std::process;
+use std::time::{Duration, Instant};
+
+use miniz_oxide::deflate::core::{
+    compress_to_output, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush,
+};
+use miniz_oxide::inflate::decompress_to_vec;
+
+/// Compress `data` to a raw deflate stream.
+fn compress(data: &[u8], level: u8, binary_tree: bool) -> Vec<u8> {
+    let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(level.into(), -15, 0));
+    d.set_binary_tree_matches(binary_tree);
+    let mut compressed = Vec::new();
+    compress_to_output(&mut d, data, TDEFLFlush::Finish, |out: &[u8]| {
+        compressed.extend_from_slice(out);
+        true
+    });
+    compressed
+}
+
+/// Time the fastest of a few runs, to get less noisy numbers.
+fn best_time(runs: usize, mut f: impl FnMut()) -> Duration {
+    (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let mut paths: Vec<String> = env::args().skip(1).collect();
+    let binary_tree = paths.first().map(String::as_str) == Some("--binary-tree");
+    if binary_tree {
+        paths.remove(0);
+    }
+    if paths.is_empty() {
+        eprintln!("Usage: levels [--binary-tree] <file>...");
+        process::exit(2);
+    }
+
+    for path in &paths {
+        let data = fs::read(path).unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", path, e);
+            process::exit(1);
+        });
+        println!("{} ({} bytes)", path, data.len());
+        println!("level  compressed   ratio     MB/s");
+        for level in 0..=10 {
+            let compressed = compress(&data, level, binary_tree);
+            assert_eq!(decompress_to_vec(&compressed).unwrap(), data);
+            let time = best_time(5, || {
+                compress(&data, level, binary_tree);
+            });
+            let secs = time.as_secs() as f64 + f64::from(time.subsec_nanos()) * 1e-9;
+            println!(
+                "{:5} {:11} {:6.2}% {:8.1}",
+                level,
+                compressed.len(),
+                compressed.len() as f64 * 100.0 / data.len() as f64,
+                data.len() as f64 / secs / 1e6
+            );
+        }
+    }
+}
@@ -2,56 +2,171 @@
 //! to avoid stack copies. Box::new() doesn't at the moment, and using a vec means we would lose
 //! static length info.
 
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use alloc::vec;
+use core::ops::{Deref, DerefMut};
+
 use crate::deflate::core::{LZ_DICT_SIZE, MAX_MATCH_LEN};
 
-/// Size of the buffer of lz77 encoded data.
+/// Size of the buffer of lz77 encoded data at the highest memory level.
 pub const LZ_CODE_BUF_SIZE: usize = 64 * 1024;
-/// Size of the output buffer.
-pub const OUT_BUF_SIZE: usize = (LZ_CODE_BUF_SIZE * 13) / 10;
-pub const LZ_DICT_FULL_SIZE: usize = LZ_DICT_SIZE + MAX_MATCH_LEN - 1 + 1;
-
-/// Size of hash values in the hash chains.
-pub const LZ_HASH_BITS: i32 = 15;
-/// How many bits to shift when updating the current hash value.
-pub const LZ_HASH_SHIFT: i32 = (LZ_HASH_BITS + 2) / 3;
-/// Size of the chained hash tables.
+/// Size of the buffer of lz77 encoded data at the lowest memory level.
+pub const MIN_LZ_CODE_BUF_SIZE: usize = LZ_CODE_BUF_SIZE >> 8;
+/// Size of the window at the lowest memory levels.
+pub const MIN_LZ_DICT_SIZE: usize = LZ_DICT_SIZE >> 3;
+
+/// Size of hash values in the hash chains at the highest memory level.
+pub const LZ_HASH_BITS: u32 = 15;
+/// Size of hash values in the hash chains at the lowest memory level.
+pub const MIN_LZ_HASH_BITS: u32 = LZ_HASH_BITS - 8;
+/// Size of the chained hash tables at the highest memory level.
 pub const LZ_HASH_SIZE: usize = 1 << LZ_HASH_BITS;
 
-pub fn update_hash(current_hash: u32, byte: u8) -> u32 {
-    ((current_hash << LZ_HASH_SHIFT) ^ u32::from(byte)) & (LZ_HASH_SIZE as u32 - 1)
+/// Size of the dictionary buffer for a window of `window_size` bytes.
+///
+/// The first `MAX_MATCH_LEN - 1` bytes of the window are mirrored after the end so matches
+/// can be read without wrapping around, plus one byte of padding for `compress_fast`.
+pub const fn dict_buf_size(window_size: usize) -> usize {
+    window_size + MAX_MATCH_LEN - 1 + 1
 }
 
-pub struct HashBuffers {
-    pub dict: [u8; LZ_DICT_FULL_SIZE],
-    pub next: [u16; LZ_DICT_SIZE],
-    pub hash: [u16; LZ_DICT_SIZE],
+/// Size of the output buffer needed for a given lz code buffer size.
+///
+/// Compressed blocks take up at most 1.3 times the size of their lz codes, the extra space
+/// is for the block headers, which matter for the smaller code buffers.
+pub const fn out_buf_size(lz_code_buf_size: usize) -> usize {
+    (lz_code_buf_size * 13) / 10 + 512
 }
 
-impl HashBuffers {
+/// How many bits to shift when updating a hash value of `hash_bits` bits, so
+/// the hash covers three bytes.
+pub fn hash_shift(hash_bits: u32) -> u32 {
+    (hash_bits + 2) / 3
+}
+
+#[inline]
+pub fn update_hash(current_hash: u32, byte: u8, shift: u32, mask: u32) -> u32 {
+    ((current_hash << shift) ^ u32::from(byte)) & mask
+}
+
+/// The dictionary and hash chains, which are sized by the window.
+pub struct HashBuffers<'a> {
+    pub dict: Storage<'a, [u8]>,
+    pub next: Storage<'a, [u16]>,
+    /// The binary trees, two entries for each byte of the window, or empty when they aren't
+    /// used.
+    pub tree: Storage<'a, [u16]>,
+}
+
+impl HashBuffers<'_> {
+    /// Clear the buffers in place.
     #[inline]
     pub fn reset(&mut self) {
-        *self = HashBuffers::default();
+        self.dict.iter_mut().for_each(|b| *b = 0);
+        self.next.iter_mut().for_each(|n| *n = 0);
+        self.tree.iter_mut().for_each(|n| *n = 0);
     }
 }
 
-impl Default for HashBuffers {
-    fn default() -> HashBuffers {
+#[cfg(feature = "alloc")]
+impl Clone for HashBuffers<'_> {
+    fn clone(&self) -> Self {
         HashBuffers {
-            dict: [0; LZ_DICT_FULL_SIZE],
-            next: [0; LZ_DICT_SIZE],
-            hash: [0; LZ_DICT_SIZE],
+            dict: self.dict.clone(),
+            next: self.next.clone(),
+            tree: self.tree.clone(),
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        self.dict.clone_from(&source.dict);
+        self.next.clone_from(&source.next);
+        self.tree.clone_from(&source.tree);
+    }
+}
+
+/// One of the larger compressor buffers, either allocated by the compressor itself or
+/// provided by the caller, who can hand over the allocation or lend it to the compressor.
+/// Only the latter is available without the `alloc` feature.
+pub enum Storage<'a, T: ?Sized + 'a> {
+    #[cfg(feature = "alloc")]
+    Boxed(Box<T>),
+    #[cfg(feature = "alloc")]
+    Owned(Box<dyn DerefMut<Target = T> + Send + Sync + 'a>),
+    Borrowed(&'a mut T),
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Default> Default for Storage<'_, T> {
+    fn default() -> Self {
+        Storage::Boxed(Box::default())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Copy + Default> Storage<'_, [T]> {
+    /// Allocate a slice of `len` default values.
+    pub fn new_slice(len: usize) -> Self {
+        Storage::Boxed(vec![T::default(); len].into_boxed_slice())
+    }
+}
+
+/// Clones are always boxed, as the caller's storage can't be shared. `clone_from` copies
+/// into the existing storage instead when it has the same size, so it stays the caller's.
+#[cfg(feature = "alloc")]
+impl<T: Clone + Default> Clone for Storage<'_, T> {
+    fn clone(&self) -> Self {
+        let mut b: Box<T> = Box::default();
+        (*b).clone_from(&**self);
+        Storage::Boxed(b)
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        (**self).clone_from(&**source);
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Copy> Clone for Storage<'_, [T]> {
+    fn clone(&self) -> Self {
+        Storage::Boxed(Box::from(&self[..]))
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        if self.len() == source.len() {
+            self.copy_from_slice(source);
+        } else {
+            *self = source.clone();
         }
     }
 }
 
-pub struct LocalBuf {
-    pub b: [u8; OUT_BUF_SIZE],
+impl<T: ?Sized> Deref for Storage<'_, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        match self {
+            #[cfg(feature = "alloc")]
+            Storage::Boxed(b) => b,
+            #[cfg(feature = "alloc")]
+            Storage::Owned(b) => b,
+            Storage::Borrowed(b) => b,
+        }
+    }
 }
 
-impl Default for LocalBuf {
-    fn default() -> LocalBuf {
-        LocalBuf {
-            b: [0; OUT_BUF_SIZE],
+impl<T: ?Sized> DerefMut for Storage<'_, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        match self {
+            #[cfg(feature = "alloc")]
+            Storage::Boxed(b) => b,
+            #[cfg(feature = "alloc")]
+            Storage::Owned(b) => b,
+            Storage::Borrowed(b) => b,
         }
     }
 }
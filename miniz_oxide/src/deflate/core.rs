@@ -1,18 +1,26 @@
 //! Streaming compression functionality.
 
+#[cfg(feature = "alloc")]
 use alloc::boxed::Box;
 use core::convert::TryInto;
+#[cfg(feature = "alloc")]
+use core::ops::Deref;
+use core::ops::DerefMut;
 use core::{cmp, mem};
 
 use super::super::*;
 use super::deflate_flags::*;
 use super::CompressionLevel;
 use crate::deflate::buffer::{
-    update_hash, HashBuffers, LocalBuf, LZ_CODE_BUF_SIZE, LZ_DICT_FULL_SIZE, LZ_HASH_BITS,
-    LZ_HASH_SHIFT, LZ_HASH_SIZE, OUT_BUF_SIZE,
+    dict_buf_size, hash_shift, out_buf_size, update_hash, HashBuffers, Storage, LZ_CODE_BUF_SIZE,
+    LZ_HASH_BITS, LZ_HASH_SIZE, MIN_LZ_CODE_BUF_SIZE, MIN_LZ_DICT_SIZE, MIN_LZ_HASH_BITS,
 };
+#[cfg(feature = "stats")]
+use crate::deflate::stats::{BlockStats, CompressionStats};
+#[cfg(feature = "zlib-compat")]
+use crate::deflate::zlib_compat::{self, Sink, ZlibState, ZlibStatus};
 use crate::shared::{update_adler32, HUFFMAN_LENGTH_ORDER, MZ_ADLER32_INIT};
-use crate::DataFormat;
+use crate::{DataFormat, LZToken};
 
 // Currently not bubbled up outside this module, so can fill in with more
 // context eventually if needed.
@@ -23,6 +31,9 @@ const MAX_PROBES_MASK: i32 = 0xFFF;
 
 const MAX_SUPPORTED_HUFF_CODESIZE: usize = 32;
 
+/// The longest code length that can be stored in a deflate block header.
+const MAX_HUFF_CODESIZE: usize = 15;
+
 /// Length code for length values.
 #[rustfmt::skip]
 const LEN_SYM: [u16; 256] = [
@@ -67,7 +78,7 @@ const LEN_EXTRA: [u8; 256] = [
 
 /// Distance codes for distances smaller than 512.
 #[rustfmt::skip]
-const SMALL_DIST_SYM: [u8; 512] = [
+pub(crate) const SMALL_DIST_SYM: [u8; 512] = [
      0,  1,  2,  3,  4,  4,  5,  5,  6,  6,  6,  6,  7,  7,  7,  7,
      8,  8,  8,  8,  8,  8,  8,  8,  9,  9,  9,  9,  9,  9,  9,  9,
     10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10,
@@ -125,7 +136,7 @@ const SMALL_DIST_EXTRA: [u8; 512] = [
 
 /// Base values to calculate distances above 512.
 #[rustfmt::skip]
-const LARGE_DIST_SYM: [u8; 128] = [
+pub(crate) const LARGE_DIST_SYM: [u8; 128] = [
      0,  0, 18, 19, 20, 20, 21, 21, 22, 22, 22, 22, 23, 23, 23, 23,
     24, 24, 24, 24, 24, 24, 24, 24, 25, 25, 25, 25, 25, 25, 25, 25,
     26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26, 26,
@@ -158,6 +169,12 @@ const BITMASKS: [u32; 17] = [
 /// The maximum number of checks for matches in the hash table the compressor will make for each
 /// compression level.
 const NUM_PROBES: [u32; 11] = [0, 1, 6, 32, 16, 32, 128, 256, 512, 768, 1500];
+/// Length of a match after which fewer positions are checked for a longer one, unless changed
+/// with `CompressorOxide::tune`.
+const GOOD_MATCH_LEN: u32 = 32;
+/// Length of a match after which it is used without looking for a longer one at the next
+/// position, unless changed with `CompressorOxide::tune`.
+const MAX_LAZY_MATCH_LEN: u32 = 128;
 
 #[derive(Copy, Clone)]
 struct SymFreq {
@@ -185,6 +202,44 @@ pub mod deflate_flags {
     pub const TDEFL_FORCE_ALL_STATIC_BLOCKS: u32 = 0x0004_0000;
     /// Force the compressor to only output raw/uncompressed blocks.
     pub const TDEFL_FORCE_ALL_RAW_BLOCKS: u32 = 0x0008_0000;
+    /// Skip looking for matches in data that doesn't compress.
+    ///
+    /// When a block ends up being stored raw because compressing it didn't save any space,
+    /// the following blocks are stored raw without looking for matches, doubling the number of
+    /// raw blocks each time until a block compresses again. The first 4 KiB of each block are
+    /// checked as well, and if they don't seem to compress, the rest of the block is already
+    /// skipped. Has no effect for compression level 1, which uses a different compression
+    /// routine.
+    pub const TDEFL_ADAPTIVE_RAW_BLOCKS: u32 = 0x0010_0000;
+    /// Produce output that stays the same across versions of this crate and platforms.
+    ///
+    /// With this flag the compressor always uses the parsing of miniz, with the same hash
+    /// chains for all levels, and the output for a given input, flags and flush calls will not
+    /// change in later releases. Without it, improvements to the compressor may change the
+    /// output, though it is always the same for a given version.
+    /// See [`CompressorOxide::set_stable_output`].
+    ///
+    /// [`CompressorOxide::set_stable_output`]: ../struct.CompressorOxide.html#method.set_stable_output
+    pub const TDEFL_STABLE_OUTPUT_FLAG: u32 = 0x0020_0000;
+    /// Produce the same output as zlib 1.2.x's `deflate` at the level and strategy that
+    /// correspond to the other flags. Needs the `zlib-compat` feature, and is ignored without
+    /// it.
+    /// See [`CompressorOxide::set_zlib_compatible`].
+    ///
+    /// [`CompressorOxide::set_zlib_compatible`]: ../struct.CompressorOxide.html#method.set_zlib_compatible
+    pub const TDEFL_ZLIB_COMPATIBLE_FLAG: u32 = 0x0040_0000;
+    /// Look for matches in binary trees of the previous positions, sorted by the data following
+    /// them, instead of in hash chains.
+    ///
+    /// This is slower than the hash chains, and finds only slightly better matches at the
+    /// highest levels. The trees take another four bytes for each byte of the window, which
+    /// the compressor allocates when the flag is first used. Compressors created with
+    /// [`CompressorOxide::with_buffers`] need a larger `next` buffer for them, and keep using
+    /// hash chains otherwise. Ignored with `TDEFL_STABLE_OUTPUT_FLAG`, and has no effect for
+    /// compression level 1, which uses a different compression routine.
+    ///
+    /// [`CompressorOxide::with_buffers`]: ../struct.CompressorOxide.html#method.with_buffers
+    pub const TDEFL_BINARY_TREE_MATCHES: u32 = 0x0080_0000;
 }
 
 /// Strategy setting for compression.
@@ -269,8 +324,80 @@ const MAX_HUFF_SYMBOLS_1: usize = 32;
 const MAX_HUFF_SYMBOLS_2: usize = 19;
 /// Size of the chained hash table.
 pub(crate) const LZ_DICT_SIZE: usize = 32_768;
-/// Mask used when stepping through the hash chains.
-const LZ_DICT_SIZE_MASK: usize = (LZ_DICT_SIZE as u32 - 1) as usize;
+/// Size of the blocks output without looking for matches when using `TDEFL_ADAPTIVE_RAW_BLOCKS`,
+/// see `adaptive_raw_block_size` for smaller buffers.
+const ADAPTIVE_RAW_BLOCK_SIZE: u32 = 31 * 1024;
+/// The maximum number of blocks to output raw in a row when using `TDEFL_ADAPTIVE_RAW_BLOCKS`
+/// before checking if the data compresses again.
+const MAX_ADAPTIVE_RAW_BLOCKS: u32 = 16;
+/// Size of the raw blocks of `TDEFL_ADAPTIVE_RAW_BLOCKS` with an lz code buffer of
+/// `lz_code_buf_size` bytes and a window of `window_size` bytes.
+///
+/// The blocks are limited to the size of the code buffer, so that they fit in the output buffer
+/// along with the block and stream headers at the lower memory levels, and stay 1 KiB short of
+/// the window like the other blocks, so the data is still in the dictionary when output.
+fn adaptive_raw_block_size(lz_code_buf_size: usize, window_size: usize) -> u32 {
+    let block_size = cmp::min(ADAPTIVE_RAW_BLOCK_SIZE as usize, window_size - 1024);
+    cmp::min(block_size, lz_code_buf_size) as u32
+}
+
+/// Output the next blocks raw without looking for matches, twice as many as the last time
+/// until a block compresses again.
+fn skip_raw_blocks(raw_blocks_left: &mut u32, raw_blocks_backoff: &mut u32) {
+    *raw_blocks_left = *raw_blocks_backoff;
+    *raw_blocks_backoff = cmp::min(*raw_blocks_backoff * 2, MAX_ADAPTIVE_RAW_BLOCKS);
+}
+
+/// Number of bytes into a block after which `TDEFL_ADAPTIVE_RAW_BLOCKS` checks whether the
+/// data compresses, so that data that doesn't isn't searched for matches for a whole block.
+const ADAPTIVE_SAMPLE_SIZE: u32 = 4096;
+
+/// `log2(1 + i / 16)` in sixteenths, for the fractional part of `log2_fixed`.
+const LOG2_MANTISSA: [u8; 16] = [0, 1, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 15];
+
+/// Approximate `log2(x)` in sixteenths, for `x` > 0.
+fn log2_fixed(x: u32) -> u32 {
+    let int = 31 - x.leading_zeros();
+    let mantissa = if int >= 4 {
+        x >> (int - 4)
+    } else {
+        x << (4 - int)
+    };
+    int * 16 + u32::from(LOG2_MANTISSA[(mantissa & 15) as usize])
+}
+
+/// Check if the start of a block, with the symbol counts in `h` for `total_bytes` bytes of
+/// input, is unlikely to save more than a few percent when compressed.
+///
+/// Literals are counted at the entropy of their distribution, and matches at 16 bits each,
+/// which is roughly what they cost in blocks that barely compress.
+fn looks_incompressible(h: &HuffmanOxide, total_bytes: u32) -> bool {
+    let literals = &h.count[0][..256];
+    let num_literals: u32 = literals.iter().map(|&c| u32::from(c)).sum();
+    let num_matches: u32 = h.count[1].iter().map(|&c| u32::from(c)).sum();
+    if num_literals == 0 {
+        return false;
+    }
+    let log2_literals = log2_fixed(num_literals);
+    // Both in sixteenths of a bit.
+    let literal_bits: u64 = literals
+        .iter()
+        .filter(|&&c| c != 0)
+        .map(|&c| u64::from(c) * u64::from(log2_literals - log2_fixed(u32::from(c))))
+        .sum();
+    let estimated_bits = literal_bits + u64::from(num_matches) * 16 * 16;
+    estimated_bits * 32 >= u64::from(total_bytes) * 8 * 16 * 31
+}
+
+/// Whether `compress_fast` is used for `flags`, which is the case for compression level 1.
+fn uses_compress_fast(flags: u32) -> bool {
+    let one_probe = flags & MAX_PROBES_MASK as u32 == 1;
+    let greedy = flags & TDEFL_GREEDY_PARSING_FLAG != 0;
+    let filter_or_rle_or_raw =
+        flags & (TDEFL_FILTER_MATCHES | TDEFL_FORCE_ALL_RAW_BLOCKS | TDEFL_RLE_MATCHES) != 0;
+    one_probe && greedy && !filter_or_rle_or_raw
+}
+
 /// The minimum length of a match.
 const MIN_MATCH_LEN: u8 = 3;
 /// The maximum length of a match.
@@ -391,26 +518,216 @@ fn read_u16_le(slice: &[u8], pos: usize) -> u16 {
 }
 
 /// Main compression struct.
-pub struct CompressorOxide {
-    lz: LZOxide,
-    params: ParamsOxide,
-    huff: Box<HuffmanOxide>,
-    dict: DictOxide,
+pub struct CompressorOxide<'a> {
+    lz: LZOxide<'a>,
+    params: ParamsOxide<'a>,
+    huff: Storage<'a, HuffmanOxide>,
+    dict: DictOxide<'a>,
+    /// The port of zlib's compressor used with `TDEFL_ZLIB_COMPATIBLE_FLAG`, created on first
+    /// use.
+    #[cfg(feature = "zlib-compat")]
+    zlib: Option<Box<ZlibState>>,
+    #[cfg(feature = "stats")]
+    stats: Box<CompressionStats>,
+    #[cfg(feature = "stats")]
+    stats_timer: Option<fn() -> u64>,
 }
 
-impl CompressorOxide {
+impl<'a> CompressorOxide<'a> {
     /// Create a new `CompressorOxide` with the given flags.
     ///
+    /// Uses the largest buffers, see [`with_mem_level`](#method.with_mem_level).
+    ///
     /// # Notes
     /// This function may be changed to take different parameters in the future.
+    #[cfg(feature = "alloc")]
     pub fn new(flags: u32) -> Self {
+        CompressorOxide::with_mem_level(flags, MAX_MEM_LEVEL)
+    }
+
+    /// Create a new `CompressorOxide` with the given flags and memory level, which
+    /// trades compression ratio for a smaller memory footprint.
+    ///
+    /// See [`BufferSizes::for_mem_level`](struct.BufferSizes.html#method.for_mem_level)
+    /// for the buffer sizes used by each memory level.
+    #[cfg(feature = "alloc")]
+    pub fn with_mem_level(flags: u32, mem_level: u8) -> Self {
+        let sizes = BufferSizes::for_mem_level(mem_level);
         CompressorOxide {
-            lz: LZOxide::new(),
-            params: ParamsOxide::new(flags),
+            lz: LZOxide::new(Storage::new_slice(sizes.lz_codes)),
+            params: ParamsOxide::new(flags, Storage::new_slice(sizes.out_buf)),
             /// Put HuffmanOxide on the heap with default trick to avoid
             /// excessive stack copies.
-            huff: Box::default(),
-            dict: DictOxide::new(flags),
+            huff: Storage::default(),
+            dict: DictOxide::new(
+                flags,
+                // The binary trees are allocated once needed.
+                HashBuffers {
+                    dict: Storage::new_slice(sizes.dict),
+                    next: Storage::new_slice(sizes.next),
+                    tree: Storage::new_slice(0),
+                },
+                Storage::new_slice(sizes.hash_table),
+            ),
+            #[cfg(feature = "zlib-compat")]
+            zlib: None,
+            #[cfg(feature = "stats")]
+            stats: Box::default(),
+            #[cfg(feature = "stats")]
+            stats_timer: None,
+        }
+    }
+
+    /// Create a new `CompressorOxide` with the given flags, using the provided buffers
+    /// instead of allocating its own.
+    ///
+    /// This is mainly useful when the memory has to come from a custom allocator, and it
+    /// is the only way to create a compressor without the `alloc` feature.
+    /// The sizes of `dict`, `next`, `hash_table`, `lz_codes` and `out_buf` are normally taken
+    /// from [`BufferSizes`](struct.BufferSizes.html). The contents of the buffers are reset.
+    ///
+    /// The buffers are borrowed for as long as the compressor lives. Without a heap they can
+    /// be local arrays of the sizes in
+    /// [`BufferSizes::MAX`](struct.BufferSizes.html#associatedconstant.MAX), along with
+    /// [`CompressorBuffers::new`](struct.CompressorBuffers.html#method.new). Allocations can
+    /// be handed over with [`with_owned_buffers`](#method.with_owned_buffers) instead.
+    ///
+    /// `next` can also have three times the usual size, to hold the binary trees used with
+    /// [`TDEFL_BINARY_TREE_MATCHES`](deflate_flags/constant.TDEFL_BINARY_TREE_MATCHES.html).
+    /// Without them, that flag is ignored unless the compressor can allocate them itself.
+    ///
+    /// # Errors
+    /// Returns `MZError::Param` if `dict` is not `BufferSizes::dict` for a window that is a
+    /// power of two between 4096 and 32768, `next` doesn't have one or three entries for each
+    /// byte of the window, the length of `hash_table` is not a power of two between 128 and 32768,
+    /// the length of `lz_codes` is not between 256 and 65536, or `out_buf` is smaller than
+    /// `BufferSizes::out_buf` for that length of `lz_codes`.
+    pub fn with_buffers(
+        flags: u32,
+        buffers: &'a mut CompressorBuffers,
+        dict: &'a mut [u8],
+        next: &'a mut [u16],
+        hash_table: &'a mut [u16],
+        lz_codes: &'a mut [u8],
+        out_buf: &'a mut [u8],
+    ) -> Result<Self, MZError> {
+        let window_size = dict.len().saturating_sub(dict_buf_size(0));
+        let (next, tree) = if next.len() == 3 * window_size {
+            next.split_at_mut(window_size)
+        } else {
+            (next, &mut [][..])
+        };
+        CompressorOxide::from_storage(
+            flags,
+            Storage::Borrowed(&mut buffers.huff),
+            Storage::Borrowed(dict),
+            Storage::Borrowed(next),
+            Storage::Borrowed(tree),
+            Storage::Borrowed(hash_table),
+            Storage::Borrowed(lz_codes),
+            Storage::Borrowed(out_buf),
+        )
+    }
+
+    /// Like [`with_buffers`](#method.with_buffers), but takes ownership of the buffers,
+    /// which are dropped along with the compressor.
+    ///
+    /// This is used by the C API to keep the buffers allocated with the `zalloc`/`zfree`
+    /// functions of a stream in the compressor itself.
+    ///
+    /// The binary trees don't fit in `next` here, they are allocated by the compressor when
+    /// needed.
+    ///
+    /// # Errors
+    /// Returns `MZError::Param` for the same buffer sizes as `with_buffers`, and if `next`
+    /// has three times the usual size.
+    #[cfg(feature = "alloc")]
+    pub fn with_owned_buffers<B, D, N, H, L, O>(
+        flags: u32,
+        buffers: B,
+        dict: D,
+        next: N,
+        hash_table: H,
+        lz_codes: L,
+        out_buf: O,
+    ) -> Result<Self, MZError>
+    where
+        B: DerefMut<Target = CompressorBuffers> + Send + Sync + 'a,
+        D: DerefMut<Target = [u8]> + Send + Sync + 'a,
+        N: DerefMut<Target = [u16]> + Send + Sync + 'a,
+        H: DerefMut<Target = [u16]> + Send + Sync + 'a,
+        L: DerefMut<Target = [u8]> + Send + Sync + 'a,
+        O: DerefMut<Target = [u8]> + Send + Sync + 'a,
+    {
+        CompressorOxide::from_storage(
+            flags,
+            Storage::Owned(Box::new(OwnedHuffman(buffers))),
+            Storage::Owned(Box::new(dict)),
+            Storage::Owned(Box::new(next)),
+            Storage::new_slice(0),
+            Storage::Owned(Box::new(hash_table)),
+            Storage::Owned(Box::new(lz_codes)),
+            Storage::Owned(Box::new(out_buf)),
+        )
+    }
+
+    fn from_storage(
+        flags: u32,
+        mut huff: Storage<'a, HuffmanOxide>,
+        dict: Storage<'a, [u8]>,
+        next: Storage<'a, [u16]>,
+        tree: Storage<'a, [u16]>,
+        mut hash_table: Storage<'a, [u16]>,
+        mut lz_codes: Storage<'a, [u8]>,
+        mut out_buf: Storage<'a, [u8]>,
+    ) -> Result<Self, MZError> {
+        let window_size = dict.len().saturating_sub(dict_buf_size(0));
+        if !window_size.is_power_of_two()
+            || window_size < MIN_LZ_DICT_SIZE
+            || window_size > LZ_DICT_SIZE
+            || next.len() != window_size
+            || (tree.len() != 0 && tree.len() != 2 * window_size)
+            || !hash_table.len().is_power_of_two()
+            || hash_table.len() < 1 << MIN_LZ_HASH_BITS
+            || hash_table.len() > LZ_HASH_SIZE
+            || lz_codes.len() < MIN_LZ_CODE_BUF_SIZE
+            || lz_codes.len() > LZ_CODE_BUF_SIZE
+            || out_buf.len() < out_buf_size(lz_codes.len())
+        {
+            return Err(MZError::Param);
+        }
+
+        *huff = HuffmanOxide::default();
+        let mut b = HashBuffers { dict, next, tree };
+        b.reset();
+        memset(&mut hash_table[..], 0);
+        memset(&mut lz_codes[..], 0);
+        memset(&mut out_buf[..], 0);
+        Ok(CompressorOxide {
+            lz: LZOxide::new(lz_codes),
+            params: ParamsOxide::new(flags, out_buf),
+            huff,
+            dict: DictOxide::new(flags, b, hash_table),
+            #[cfg(feature = "zlib-compat")]
+            zlib: None,
+            #[cfg(feature = "stats")]
+            stats: Box::default(),
+            #[cfg(feature = "stats")]
+            stats_timer: None,
+        })
+    }
+
+    /// Get the sizes of the buffers used by this compressor, as given to
+    /// [`with_buffers`](#method.with_buffers).
+    ///
+    /// The binary trees are not included in `next`.
+    pub fn buffer_sizes(&self) -> BufferSizes {
+        BufferSizes {
+            dict: self.dict.b.dict.len(),
+            next: self.dict.b.next.len(),
+            hash_table: self.dict.hash.len(),
+            lz_codes: self.lz.codes.len(),
+            out_buf: self.params.local_buf.len(),
         }
     }
 
@@ -442,16 +759,357 @@ impl CompressorOxide {
         }
     }
 
+    /// Get an upper bound on the size of the output when compressing `input_len` bytes with
+    /// the current settings, including the zlib header and trailer if written.
+    ///
+    /// The bound holds for input given in any number of calls, as long as the stream is only
+    /// flushed when it is finished. Each sync or full flush can add up to 10 more bytes. In
+    /// zlib compatible mode at level 0, the blocks depend on the output buffer, so like for
+    /// zlib's `deflateBound`, [`compress`](fn.compress.html) has to be given a buffer of this
+    /// size in one go.
+    pub fn compress_bound(&self, input_len: usize) -> usize {
+        compress_bound_inner(
+            input_len,
+            self.params.flags,
+            self.lz.codes.len(),
+            self.dict.window_size(),
+            self.huff.has_custom_code_sizes(),
+            self.params.rsync.is_some(),
+        )
+    }
+
     /// Reset the state of the compressor, keeping the same parameters.
     ///
     /// This avoids re-allocating data.
     pub fn reset(&mut self) {
-        // LZ buf and huffman has no settings or dynamic memory
-        // that needs to be saved, so we simply replace them.
-        self.lz = LZOxide::new();
+        // LZ buf and huffman has no dynamic memory that needs to be saved, so we simply
+        // replace them, keeping any custom huffman tables.
+        self.lz.reset();
         self.params.reset();
+        let custom_code_sizes = self.huff.custom_code_sizes;
         *self.huff = HuffmanOxide::default();
+        self.huff.custom_code_sizes = custom_code_sizes;
         self.dict.reset();
+        #[cfg(feature = "zlib-compat")]
+        {
+            if let Some(ref mut zlib) = self.zlib {
+                zlib.reset();
+            }
+        }
+        #[cfg(feature = "stats")]
+        self.reset_stats();
+    }
+
+    /// Use the given code lengths for the literal/length and distance huffman tables of
+    /// dynamic blocks, instead of computing optimal ones for each block.
+    ///
+    /// Symbols past the end of the slices get a code length of 0 (i.e they are unused).
+    /// The tables are kept until cleared with
+    /// [`clear_custom_huffman_tables`](#method.clear_custom_huffman_tables), also across calls
+    /// to `reset`. If a block contains a symbol that has no code in the given tables,
+    /// optimal tables are computed for that block as usual.
+    ///
+    /// Blocks are still stored uncompressed if that turns out smaller, and static blocks are
+    /// only used if forced by the `TDEFL_FORCE_ALL_STATIC_BLOCKS` flag.
+    ///
+    /// # Errors
+    /// Returns `MZError::Param` if the code lengths do not describe a valid deflate
+    /// huffman code. That is, if there are more than 286 literal/length or 30 distance
+    /// codes, any length is longer than 15, the end of block symbol (256) has no code, or
+    /// the lengths do not form a complete prefix code. As in zlib, a table with a single code
+    /// of length 1, or a distance table without codes, is accepted as well.
+    pub fn set_custom_huffman_tables(
+        &mut self,
+        litlen_code_sizes: &[u8],
+        dist_code_sizes: &[u8],
+    ) -> Result<(), MZError> {
+        if litlen_code_sizes.len() > 286
+            || dist_code_sizes.len() > 30
+            || litlen_code_sizes.get(256).map_or(true, |&size| size == 0)
+            || !valid_code_sizes(litlen_code_sizes)
+            || !valid_code_sizes(dist_code_sizes)
+        {
+            return Err(MZError::Param);
+        }
+
+        let mut code_sizes = [[0; MAX_HUFF_SYMBOLS]; 2];
+        code_sizes[LITLEN_TABLE][..litlen_code_sizes.len()].copy_from_slice(litlen_code_sizes);
+        code_sizes[DIST_TABLE][..dist_code_sizes.len()].copy_from_slice(dist_code_sizes);
+        self.huff.custom_code_sizes = code_sizes;
+        Ok(())
+    }
+
+    /// Go back to computing optimal huffman tables for each dynamic block.
+    pub fn clear_custom_huffman_tables(&mut self) {
+        self.huff.custom_code_sizes = [[0; MAX_HUFF_SYMBOLS]; 2];
+    }
+
+    /// Set how many entries of the hash chains are checked when looking for a match,
+    /// overriding the number derived from the compression level (at most 4095).
+    ///
+    /// Once a match of 32 bytes or more is found, only a quarter as many are checked.
+    /// The match finder used is picked from this number the same way as for the levels,
+    /// so this behaves like a level with this many probes.
+    /// The value is reset when the compression level is changed.
+    pub fn set_max_probes(&mut self, max_probes: u32) {
+        let max_probes = cmp::min(max_probes, MAX_PROBES_MASK as u32);
+        let flags = (self.params.flags & !(MAX_PROBES_MASK as u32)) | max_probes;
+        self.dict.update_flags(flags);
+    }
+
+    /// Tune the match finding, like zlib's `deflateTune`.
+    ///
+    /// Once a match of `good_length` bytes is found, fewer positions are checked for a longer
+    /// one, lazy matching isn't tried for matches of `max_lazy` bytes or more, the search stops
+    /// at a match of `nice_length` bytes, and `max_chain` is passed to
+    /// [`set_max_probes`](#method.set_max_probes). The defaults are 32, 128 and 258 bytes.
+    /// Compression level 1 only uses `max_chain`.
+    ///
+    /// With [`set_zlib_compatible`](#method.set_zlib_compatible), the parameters are used the
+    /// same way as by zlib instead, so it has to be set first. The values are reset when the
+    /// compression level is changed.
+    pub fn tune(&mut self, good_length: u32, max_lazy: u32, nice_length: u32, max_chain: u32) {
+        self.set_max_probes(max_chain);
+        self.dict.good_match_len = good_length;
+        self.dict.nice_match_len = cmp::max(nice_length, u32::from(MIN_MATCH_LEN));
+        self.params.max_lazy_match_len = max_lazy;
+        #[cfg(feature = "zlib-compat")]
+        {
+            if self.params.flags & TDEFL_ZLIB_COMPATIBLE_FLAG != 0 {
+                self.zlib_state().tune(
+                    good_length as usize,
+                    max_lazy as usize,
+                    nice_length as usize,
+                    max_chain as usize,
+                );
+            }
+        }
+    }
+
+    /// Make the output stay the same across versions of this crate and platforms, e.g for
+    /// reproducible builds that hash compressed files.
+    ///
+    /// This sets `TDEFL_STABLE_OUTPUT_FLAG`, which is kept when changing the compression level.
+    /// The compressor then always parses the input like miniz and versions of this crate before
+    /// the faster match finders were added, and the output for a given level (or flags) will
+    /// not change in later releases. It only depends on the input and the flushes, not on how
+    /// the input and output are split up across calls or on the platform. Smaller buffers
+    /// (see [`with_mem_level`](#method.with_mem_level)) give different, but just as stable,
+    /// output. Settings changed with other methods, like
+    /// [`set_max_probes`](#method.set_max_probes) or [`set_rsyncable`](#method.set_rsyncable),
+    /// are not covered.
+    pub fn set_stable_output(&mut self, stable: bool) {
+        let flags = if stable {
+            self.params.flags | TDEFL_STABLE_OUTPUT_FLAG
+        } else {
+            self.params.flags & !TDEFL_STABLE_OUTPUT_FLAG
+        };
+        self.params.update_flags(flags);
+        self.dict.update_flags(flags);
+    }
+
+    /// Look for matches in binary trees instead of hash chains, for slightly better
+    /// compression at the highest levels at the cost of speed.
+    ///
+    /// This sets [`TDEFL_BINARY_TREE_MATCHES`](deflate_flags/constant.TDEFL_BINARY_TREE_MATCHES.html),
+    /// which is kept when changing the compression level and ignored with stable output.
+    pub fn set_binary_tree_matches(&mut self, binary_tree: bool) {
+        let flags = if binary_tree {
+            self.params.flags | TDEFL_BINARY_TREE_MATCHES
+        } else {
+            self.params.flags & !TDEFL_BINARY_TREE_MATCHES
+        };
+        self.params.update_flags(flags);
+        self.dict.update_flags(flags);
+    }
+
+    /// Make the output the same as that of zlib's `deflate` (zlib 1.2.x, tested against
+    /// 1.2.13), e.g for content-addressed stores that hash compressed objects.
+    ///
+    /// This sets `TDEFL_ZLIB_COMPATIBLE_FLAG`, which is kept when changing the compression
+    /// level. The compressor then uses a port of zlib's compressor, with its hash function,
+    /// lazy matching parameters and block splitting, at the zlib level and strategy
+    /// corresponding to the flags, a window of 32K and the default memory level of 8.
+    /// Changing the level or strategy works like `deflateParams` between two calls.
+    ///
+    /// With [`compress`](fn.compress.html) the output is the same as zlib's when given the
+    /// same input, output buffers and flushes. As the blocks of level 0 depend on the size of
+    /// the output buffer, [`compress_to_output`](fn.compress_to_output.html) behaves like
+    /// zlib with an unlimited output buffer. Settings that have no equivalent in zlib, like
+    /// custom huffman tables or [`set_rsyncable`](#method.set_rsyncable), are ignored. It has
+    /// to be set before compression starts.
+    ///
+    /// Only available with the `zlib-compat` feature, as the port is under the zlib license.
+    /// The feature enables `alloc` as well, since the port allocates its own buffers.
+    #[cfg(feature = "zlib-compat")]
+    pub fn set_zlib_compatible(&mut self, compatible: bool) {
+        let flags = if compatible {
+            self.params.flags | TDEFL_ZLIB_COMPATIBLE_FLAG
+        } else {
+            self.params.flags & !TDEFL_ZLIB_COMPATIBLE_FLAG
+        };
+        self.params.update_flags(flags);
+        self.dict.update_flags(flags);
+    }
+
+    /// Make the output rsyncable, like `gzip --rsyncable`.
+    ///
+    /// The input is split into chunks where a rolling hash of the last few bytes hits a
+    /// certain value, and each chunk ends with a full flush (`TDEFLFlush::Full`). As the chunk
+    /// boundaries only depend on the data around them and no match reaches back past them,
+    /// a change to the input only changes the output up to the next boundary, which lets
+    /// rsync and similar tools transfer the rest as unchanged. Chunks are 8 KiB long on
+    /// average and at least 2 KiB. Starting each with an empty dictionary costs some
+    /// compression ratio, from a few percent to over 20% for text with many long matches.
+    ///
+    /// The output is still a standard deflate stream. Enabling it part way through the data
+    /// is supported, the rolling hash starts over when the compressor is reset.
+    pub fn set_rsyncable(&mut self, rsyncable: bool) {
+        if !rsyncable {
+            self.params.rsync = None;
+        } else if self.params.rsync.is_none() {
+            self.params.rsync = Some(RsyncState::default());
+        }
+    }
+
+    /// Returns whether the output is made rsyncable, see
+    /// [`set_rsyncable`](#method.set_rsyncable).
+    pub fn rsyncable(&self) -> bool {
+        self.params.rsync.is_some()
+    }
+
+    /// Insert `bits` bits of `value` into the output, before the next block.
+    ///
+    /// This is mainly useful to continue a raw deflate stream that does not end at a byte
+    /// boundary.
+    ///
+    /// # Errors
+    /// Returns `MZError::Buf` if the bits don't fit in the bit buffer, which can hold 16 bits
+    /// including the bits of the previous block that are not output yet, and
+    /// `MZError::Param` if the zlib header is still to be written, as it has to come first.
+    pub fn prime(&mut self, bits: u32, value: u32) -> Result<(), MZError> {
+        if bits > 16 || self.params.saved_bits_in + bits > 16 {
+            return Err(MZError::Buf);
+        }
+        if self.params.flags & TDEFL_WRITE_ZLIB_HEADER != 0 && self.params.block_index == 0 {
+            return Err(MZError::Param);
+        }
+        if bits == 0 {
+            return Ok(());
+        }
+        let value = value & ((1 << bits) - 1);
+        self.params.saved_bit_buffer |= value << self.params.saved_bits_in;
+        self.params.saved_bits_in += bits;
+        Ok(())
+    }
+
+    /// Use `dictionary` as a preset dictionary, i.e let the compressed data refer back to it
+    /// as if it had been compressed just before, like zlib's `deflateSetDictionary`.
+    ///
+    /// Only the last window of the dictionary can be referred to, which is 32 KiB unless the
+    /// compressor was created with a low memory level. The decompressor has to be
+    /// given the same dictionary, for example by putting it at the start of the output buffer.
+    /// Calling this more than once before compressing any data appends to the dictionary.
+    ///
+    /// # Errors
+    /// Returns `MZError::Param` if compression has already started, or if the compressor
+    /// writes a zlib wrapper or is in zlib compatible mode, as setting the dictionary id in
+    /// the zlib header is not supported.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<(), MZError> {
+        let started = self.params.block_index != 0
+            || self.params.flush != TDEFLFlush::None
+            || self.params.flush_remaining != 0
+            || self.params.prev_return_status != TDEFLStatus::Okay
+            || self.dict.lookahead_size != 0
+            || self.lz.total_bytes != 0;
+        if started
+            || self.params.flags & (TDEFL_WRITE_ZLIB_HEADER | TDEFL_ZLIB_COMPATIBLE_FLAG) != 0
+        {
+            return Err(MZError::Param);
+        }
+
+        let window_size = self.dict.window_size();
+        let dictionary = &dictionary[dictionary.len().saturating_sub(window_size)..];
+        if dictionary.is_empty() {
+            return Ok(());
+        }
+
+        // Compress the dictionary as usual and throw away the output, which leaves it in the
+        // dictionary and hash tables, then start the stream over from there. The rolling hash
+        // of rsyncable mode is bypassed so the dictionary is not split up by full flushes.
+        let saved_bits = (self.params.saved_bit_buffer, self.params.saved_bits_in);
+        self.params.saved_bit_buffer = 0;
+        self.params.saved_bits_in = 0;
+        #[cfg(feature = "stats")]
+        let stats = self.stats.clone();
+        let (status, _, _) = compress_chunk(
+            self,
+            &mut CallbackOxide::new_callback_func(
+                dictionary,
+                CallbackFunc {
+                    put_buf_func: &mut |_| true,
+                },
+            ),
+            TDEFLFlush::Sync,
+        );
+        debug_assert_eq!(status, TDEFLStatus::Okay);
+        self.params.reset();
+        self.params.saved_bit_buffer = saved_bits.0;
+        self.params.saved_bits_in = saved_bits.1;
+        #[cfg(feature = "stats")]
+        {
+            self.stats = stats;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of bytes and bits of compressed data that have not been output
+    /// yet, e.g because the output buffer was full.
+    ///
+    /// The bits are only output with the next block.
+    pub fn pending_output(&self) -> (usize, u32) {
+        #[cfg(feature = "zlib-compat")]
+        {
+            if let Some(ref zlib) = self.zlib {
+                if self.params.flags & TDEFL_ZLIB_COMPATIBLE_FLAG != 0 {
+                    return zlib.pending_output();
+                }
+            }
+        }
+        (
+            self.params.flush_remaining as usize,
+            self.params.saved_bits_in,
+        )
+    }
+
+    /// Get the statistics collected since the compressor was created, or
+    /// [`reset`](#method.reset) or [`reset_stats`](#method.reset_stats) was last called.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> &CompressionStats {
+        &self.stats
+    }
+
+    /// Clear the collected statistics.
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&mut self) {
+        self.stats.clear();
+    }
+
+    /// Set a function returning the current time in some monotonic unit (e.g nanoseconds),
+    /// used to measure the time spent looking for matches.
+    ///
+    /// The timer is called twice for every match search, so it should be cheap.
+    /// Pass `None` to stop measuring time.
+    #[cfg(feature = "stats")]
+    pub fn set_stats_timer(&mut self, timer: Option<fn() -> u64>) {
+        self.stats_timer = timer;
+    }
+
+    /// Read the stats timer, or return 0 if there is none.
+    #[cfg(feature = "stats")]
+    #[inline]
+    fn stats_time(&self) -> u64 {
+        self.stats_timer.map_or(0, |timer| timer())
     }
 
     /// Set the compression level of the compressor.
@@ -488,28 +1146,181 @@ impl CompressorOxide {
             level.into(),
             data_format.to_window_bits(),
             CompressionStrategy::Default as i32,
-        );
+        ) | (self.params.flags
+            & (TDEFL_STABLE_OUTPUT_FLAG | TDEFL_ZLIB_COMPATIBLE_FLAG | TDEFL_BINARY_TREE_MATCHES));
         self.params.update_flags(flags);
+        self.params.max_lazy_match_len = MAX_LAZY_MATCH_LEN;
         self.dict.update_flags(flags);
+        self.dict.good_match_len = GOOD_MATCH_LEN;
+        self.dict.nice_match_len = MAX_MATCH_LEN as u32;
+    }
+
+    /// Get the state of the zlib compatible compressor, creating it if needed.
+    #[cfg(feature = "zlib-compat")]
+    fn zlib_state(&mut self) -> &mut ZlibState {
+        let (level, strategy) = zlib_compat::params_from_flags(self.params.flags);
+        let zlib_header = self.params.flags & TDEFL_WRITE_ZLIB_HEADER != 0;
+        let zlib = self
+            .zlib
+            .get_or_insert_with(|| ZlibState::new(level, strategy, zlib_header));
+        zlib.set_zlib_header(zlib_header);
+        zlib
+    }
+}
+
+/// Clones get their own heap allocated buffers, also when created with
+/// [`with_buffers`](#method.with_buffers). `clone_from` copies into the buffers of the
+/// target instead if they have the same sizes.
+#[cfg(feature = "alloc")]
+impl Clone for CompressorOxide<'_> {
+    fn clone(&self) -> Self {
+        CompressorOxide {
+            lz: self.lz.clone(),
+            params: self.params.clone(),
+            huff: self.huff.clone(),
+            dict: self.dict.clone(),
+            #[cfg(feature = "zlib-compat")]
+            zlib: self.zlib.clone(),
+            #[cfg(feature = "stats")]
+            stats: self.stats.clone(),
+            #[cfg(feature = "stats")]
+            stats_timer: self.stats_timer,
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        self.lz.clone_from(&source.lz);
+        self.params.clone_from(&source.params);
+        self.huff.clone_from(&source.huff);
+        self.dict.clone_from(&source.dict);
+        #[cfg(feature = "zlib-compat")]
+        self.zlib.clone_from(&source.zlib);
+        #[cfg(feature = "stats")]
+        {
+            self.stats.clone_from(&source.stats);
+            self.stats_timer = source.stats_timer;
+        }
     }
 }
 
-impl Default for CompressorOxide {
+#[cfg(feature = "alloc")]
+impl Default for CompressorOxide<'_> {
     /// Initialize the compressor with a level of 4, zlib wrapper and
     /// the default strategy.
     #[inline(always)]
     fn default() -> Self {
-        CompressorOxide {
-            lz: LZOxide::new(),
-            params: ParamsOxide::new(DEFAULT_FLAGS),
-            /// Put HuffmanOxide on the heap with default trick to avoid
-            /// excessive stack copies.
-            huff: Box::default(),
-            dict: DictOxide::new(DEFAULT_FLAGS),
+        CompressorOxide::new(DEFAULT_FLAGS)
+    }
+}
+
+/// The highest memory level, used by default.
+pub const MAX_MEM_LEVEL: u8 = 9;
+
+/// Sizes of the compressor buffers that depend on the memory level.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BufferSizes {
+    /// Size of the dictionary buffer in bytes, the window of previous data that matches can
+    /// refer back to, followed by a copy of its first 257 bytes and a byte of padding.
+    pub dict: usize,
+    /// Number of entries linking the positions of the window into hash chains, one per byte.
+    pub next: usize,
+    /// Number of entries in the hash table used to find matches.
+    pub hash_table: usize,
+    /// Size of the buffer of lz codes in bytes, which limits the size of the output blocks.
+    pub lz_codes: usize,
+    /// Size of the output buffer in bytes, used when the output doesn't fit in the
+    /// caller's buffer.
+    pub out_buf: usize,
+}
+
+impl BufferSizes {
+    /// The buffer sizes at the highest memory level, which is used by default.
+    ///
+    /// As a constant, this can be used for the lengths of arrays, e.g. for the buffers
+    /// passed to [`CompressorOxide::with_buffers`](struct.CompressorOxide.html#method.with_buffers).
+    pub const MAX: BufferSizes = BufferSizes {
+        dict: dict_buf_size(LZ_DICT_SIZE),
+        next: LZ_DICT_SIZE,
+        hash_table: LZ_HASH_SIZE,
+        lz_codes: LZ_CODE_BUF_SIZE,
+        out_buf: out_buf_size(LZ_CODE_BUF_SIZE),
+    };
+
+    /// Get the buffer sizes for a memory level from 1 to 9, like the `memLevel` parameter
+    /// of zlib. Values outside of this range are clamped.
+    ///
+    /// Each step down halves the hash table and block buffers, and from level 3 down the
+    /// 32 KiB window as well, taking the buffers from around 310 KiB at level 9 to 14 KiB at
+    /// level 1. Smaller hash tables, blocks and windows compress worse. The huffman tables in
+    /// [`CompressorBuffers`](struct.CompressorBuffers.html) add another 5 KiB.
+    pub fn for_mem_level(mem_level: u8) -> BufferSizes {
+        let shift = MAX_MEM_LEVEL - cmp::min(cmp::max(mem_level, 1), MAX_MEM_LEVEL);
+        let window_size = LZ_DICT_SIZE >> shift.saturating_sub(5);
+        let lz_codes = LZ_CODE_BUF_SIZE >> shift;
+        BufferSizes {
+            dict: dict_buf_size(window_size),
+            next: window_size,
+            hash_table: LZ_HASH_SIZE >> shift,
+            lz_codes,
+            out_buf: out_buf_size(lz_codes),
+        }
+    }
+
+    /// Size of the window that matches can refer back to.
+    fn window_size(&self) -> usize {
+        self.dict.saturating_sub(dict_buf_size(0))
+    }
+}
+
+/// The buffers of a [`CompressorOxide`](struct.CompressorOxide.html) that don't depend on the
+/// memory level, for use with
+/// [`CompressorOxide::with_buffers`](struct.CompressorOxide.html#method.with_buffers).
+///
+/// These are the huffman tables, which take up around 5 KiB. Without the `alloc` feature,
+/// [`new`](#method.new) can be used to put them in a `static`.
+///
+/// Memory with all bytes set to zero holds a valid `CompressorBuffers`, the same as the one
+/// returned by `new`, so it can also be initialised in place after allocating it some
+/// other way.
+#[derive(Clone)]
+pub struct CompressorBuffers {
+    huff: HuffmanOxide,
+}
+
+impl CompressorBuffers {
+    pub const fn new() -> Self {
+        CompressorBuffers {
+            huff: HuffmanOxide::new(),
         }
     }
 }
 
+impl Default for CompressorBuffers {
+    fn default() -> Self {
+        CompressorBuffers::new()
+    }
+}
+
+/// Gives access to the huffman tables in `CompressorBuffers` handed over to the compressor.
+#[cfg(feature = "alloc")]
+struct OwnedHuffman<B>(B);
+
+#[cfg(feature = "alloc")]
+impl<B: Deref<Target = CompressorBuffers>> Deref for OwnedHuffman<B> {
+    type Target = HuffmanOxide;
+
+    fn deref(&self) -> &HuffmanOxide {
+        &self.0.huff
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<B: DerefMut<Target = CompressorBuffers>> DerefMut for OwnedHuffman<B> {
+    fn deref_mut(&mut self) -> &mut HuffmanOxide {
+        &mut self.0.huff
+    }
+}
+
 /// Callback function and user used in `compress_to_output`.
 pub struct CallbackFunc<'a> {
     pub put_buf_func: &'a mut dyn FnMut(&[u8]) -> bool,
@@ -524,7 +1335,7 @@ impl<'a> CallbackFunc<'a> {
         // TODO: As this could be unsafe since
         // we can't verify the function pointer
         // this whole function should maybe be unsafe as well.
-        let call_success = (self.put_buf_func)(&params.local_buf.b[0..saved_output.pos as usize]);
+        let call_success = (self.put_buf_func)(&params.local_buf[0..saved_output.pos as usize]);
 
         if !call_success {
             params.prev_return_status = TDEFLStatus::PutBufFailed;
@@ -551,7 +1362,7 @@ impl<'a> CallbackBuf<'a> {
                 self.out_buf.len() - params.out_buf_ofs,
             );
             (&mut self.out_buf[params.out_buf_ofs..params.out_buf_ofs + n])
-                .copy_from_slice(&params.local_buf.b[..n]);
+                .copy_from_slice(&params.local_buf[..n]);
 
             params.out_buf_ofs += n;
             if saved_output.pos != n {
@@ -578,9 +1389,9 @@ impl<'a> CallbackOut<'a> {
         out_buf_ofs: usize,
     ) -> OutputBufferOxide<'b> {
         let is_local;
-        let buf_len = OUT_BUF_SIZE - 16;
+        let buf_len = local_buf.len() - 16;
         let chosen_buffer = match *self {
-            CallbackOut::Buf(ref mut cb) if cb.out_buf.len() - out_buf_ofs >= OUT_BUF_SIZE => {
+            CallbackOut::Buf(ref mut cb) if cb.out_buf.len() - out_buf_ofs >= local_buf.len() => {
                 is_local = false;
                 &mut cb.out_buf[out_buf_ofs..out_buf_ofs + buf_len]
             }
@@ -697,6 +1508,13 @@ impl<'a> OutputBufferOxide<'a> {
             self.put_bits(0, len);
         }
     }
+
+    /// Write whole bytes, the output has to be at a byte boundary.
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        debug_assert!(self.bits_in == 0);
+        self.inner[self.inner_pos..self.inner_pos + bytes.len()].copy_from_slice(bytes);
+        self.inner_pos += bytes.len();
+    }
 }
 
 struct SavedOutputBufferOxide {
@@ -740,6 +1558,7 @@ impl BitBuffer {
 /// NOTE: Only the literal/lengths have enough symbols to actually use
 /// the full array. It's unclear why it's defined like this in miniz,
 /// it could be for cache/alignment reasons.
+#[derive(Clone)]
 struct HuffmanOxide {
     /// Number of occurrences of each symbol.
     pub count: [[u16; MAX_HUFF_SYMBOLS]; MAX_HUFF_TABLES],
@@ -747,6 +1566,34 @@ struct HuffmanOxide {
     pub codes: [[u16; MAX_HUFF_SYMBOLS]; MAX_HUFF_TABLES],
     /// The length of the huffman code assigned to the symbol.
     pub code_sizes: [[u8; MAX_HUFF_SYMBOLS]; MAX_HUFF_TABLES],
+    /// Caller-supplied code lengths for the literal/length and distance tables, used
+    /// instead of optimized ones in dynamic blocks. Left at zero when there are none, as
+    /// custom tables always have a code for the end of block symbol.
+    pub custom_code_sizes: [[u8; MAX_HUFF_SYMBOLS]; 2],
+}
+
+/// Check that the code lengths form a valid huffman code, that is, that they
+/// are at most `MAX_HUFF_CODESIZE` long and form a complete prefix code.
+///
+/// Like zlib's inflate, an incomplete code is only accepted if no code or a single code of
+/// length 1 is used.
+fn valid_code_sizes(code_sizes: &[u8]) -> bool {
+    let mut max_code_size = 0;
+    let mut total: u32 = 0;
+    for &code_size in code_sizes {
+        if code_size as usize > MAX_HUFF_CODESIZE {
+            return false;
+        }
+        if code_size != 0 {
+            max_code_size = cmp::max(max_code_size, code_size);
+            total += 1 << (MAX_HUFF_CODESIZE - code_size as usize);
+        }
+    }
+    // A single code of length 1 takes up half of the code space, anything else that is
+    // incomplete leaves codes unused that zlib's inflate rejects.
+    total == 1 << MAX_HUFF_CODESIZE
+        || total == 0
+        || (max_code_size == 1 && total == 1 << (MAX_HUFF_CODESIZE - 1))
 }
 
 /// Tables used for literal/lengths in `HuffmanOxide`.
@@ -825,15 +1672,25 @@ fn write(src: &[u8], dst: &mut [u8], dst_pos: &mut usize) -> Result<()> {
 
 impl Default for HuffmanOxide {
     fn default() -> Self {
+        HuffmanOxide::new()
+    }
+}
+
+impl HuffmanOxide {
+    const fn new() -> Self {
         HuffmanOxide {
             count: [[0; MAX_HUFF_SYMBOLS]; MAX_HUFF_TABLES],
             codes: [[0; MAX_HUFF_SYMBOLS]; MAX_HUFF_TABLES],
             code_sizes: [[0; MAX_HUFF_SYMBOLS]; MAX_HUFF_TABLES],
+            custom_code_sizes: [[0; MAX_HUFF_SYMBOLS]; 2],
         }
     }
-}
 
-impl HuffmanOxide {
+    /// Whether custom code lengths are set for dynamic blocks.
+    fn has_custom_code_sizes(&self) -> bool {
+        self.custom_code_sizes[LITLEN_TABLE][256] != 0
+    }
+
     fn radix_sort_symbols<'a>(
         symbols0: &'a mut [SymFreq],
         symbols1: &'a mut [SymFreq],
@@ -1059,8 +1916,25 @@ impl HuffmanOxide {
         // There will always be one, and only one end of block code.
         self.count[0][256] = 1;
 
-        self.optimize_table(0, MAX_HUFF_SYMBOLS_0, 15, false);
-        self.optimize_table(1, MAX_HUFF_SYMBOLS_1, 15, false);
+        // Use the custom tables if there are any, and they have codes for all the symbols
+        // in this block.
+        let use_custom = self.has_custom_code_sizes()
+            && (0..2).all(|table| {
+                self.count[table]
+                    .iter()
+                    .zip(self.custom_code_sizes[table].iter())
+                    .all(|(&count, &code_size)| count == 0 || code_size != 0)
+            });
+
+        if use_custom {
+            self.code_sizes[LITLEN_TABLE] = self.custom_code_sizes[LITLEN_TABLE];
+            self.code_sizes[DIST_TABLE] = self.custom_code_sizes[DIST_TABLE];
+            self.optimize_table(0, MAX_HUFF_SYMBOLS_0, 15, true);
+            self.optimize_table(1, MAX_HUFF_SYMBOLS_1, 15, true);
+        } else {
+            self.optimize_table(0, MAX_HUFF_SYMBOLS_0, 15, false);
+            self.optimize_table(1, MAX_HUFF_SYMBOLS_1, 15, false);
+        }
 
         let num_lit_codes = 286
             - &self.code_sizes[0][257..286]
@@ -1170,13 +2044,84 @@ impl HuffmanOxide {
     }
 }
 
-struct DictOxide {
+/// Algorithm used by `compress_normal` to look for matches, chosen from the flags.
+///
+/// The hash chains of miniz are used unless another one clearly does better, which on the
+/// files in `benches/data` is only the case for `Hash4` at level 2. Level 1 uses `compress_fast` instead, which has its own hash table.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum MatchFinder {
+    /// Chains of the previous positions with the same hash of the next 3 bytes, like miniz.
+    Hash3,
+    /// Chains of the previous positions with the same hash of the next 4 bytes, for greedy
+    /// parsing with few probes. Fewer collisions than with 3 bytes make each probe count.
+    Hash4,
+    /// Binary trees of the previous positions with the same hash of the next 4 bytes, sorted
+    /// by the data following them, with `TDEFL_BINARY_TREE_MATCHES`. A search follows the path
+    /// down the tree to where the current position belongs, which goes through the candidates
+    /// sharing the longest prefixes with it, rather than the most recent ones like a hash chain.
+    BinaryTree,
+}
+
+/// How far positions skipped over by matches are compared to the ones in the binary trees,
+/// except at level 10.
+///
+/// Sorting them by the first bytes is enough to find them again, and saves comparing the full
+/// length of each match, mainly in runs of the same bytes.
+const TREE_INSERT_LEN: u32 = 32;
+
+fn tree_insert_len_from_flags(flags: u32) -> u32 {
+    if flags & MAX_PROBES_MASK as u32 >= NUM_PROBES[10] {
+        MAX_MATCH_LEN as u32
+    } else {
+        TREE_INSERT_LEN
+    }
+}
+
+fn match_finder_from_flags(flags: u32) -> MatchFinder {
+    if flags & TDEFL_STABLE_OUTPUT_FLAG != 0 {
+        MatchFinder::Hash3
+    } else if flags & TDEFL_BINARY_TREE_MATCHES != 0 {
+        MatchFinder::BinaryTree
+    } else if flags & TDEFL_GREEDY_PARSING_FLAG != 0
+        && flags & MAX_PROBES_MASK as u32 <= NUM_PROBES[2]
+    {
+        MatchFinder::Hash4
+    } else {
+        MatchFinder::Hash3
+    }
+}
+
+struct DictOxide<'a> {
     /// The maximum number of checks in the hash chain, for the initial,
     /// and the lazy match respectively.
+    ///
+    /// They also limit the depth of the binary tree searches, the second one being used to
+    /// insert the positions skipped over by matches.
     pub max_probes: [u32; 2],
-    /// Buffer of input data.
-    /// Padded with 1 byte to simplify matching code in `compress_fast`.
-    pub b: Box<HashBuffers>,
+    /// Length of a match after which the second number of probes is used.
+    pub good_match_len: u32,
+    /// Length of a match after which no longer one is looked for.
+    pub nice_match_len: u32,
+    /// How matches are looked for.
+    pub finder: MatchFinder,
+    /// Buffer of input data, padded with 1 byte to simplify matching code in
+    /// `compress_fast`, the links of the hash chains, and the binary trees.
+    ///
+    /// With `MatchFinder::BinaryTree`, `b.next` is the table of `hash3` instead, and the
+    /// positions sorting before and after a position `pos` are stored at `2 * pos` and
+    /// `2 * pos + 1` of `b.tree`.
+    pub b: HashBuffers<'a>,
+    /// Mask wrapping positions into the window, one less than its size.
+    pub window_mask: usize,
+    /// The heads of the hash chains, the size depends on the memory level.
+    pub hash: Storage<'a, [u16]>,
+    /// Shift and mask used to compute hash values for `hash`.
+    pub hash_shift: u32,
+    pub hash_mask: u32,
+    /// The next position to insert into the binary trees.
+    pub tree_pos: usize,
+    /// How far the positions skipped over by matches are compared when inserting them.
+    pub tree_insert_len: u32,
 
     pub code_buf_dict_pos: usize,
     pub lookahead_size: usize,
@@ -1184,6 +2129,35 @@ struct DictOxide {
     pub size: usize,
 }
 
+#[cfg(feature = "alloc")]
+impl Clone for DictOxide<'_> {
+    fn clone(&self) -> Self {
+        DictOxide {
+            b: self.b.clone(),
+            hash: self.hash.clone(),
+            ..*self
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        self.b.clone_from(&source.b);
+        self.hash.clone_from(&source.hash);
+        self.max_probes = source.max_probes;
+        self.good_match_len = source.good_match_len;
+        self.nice_match_len = source.nice_match_len;
+        self.finder = source.finder;
+        self.window_mask = source.window_mask;
+        self.hash_shift = source.hash_shift;
+        self.hash_mask = source.hash_mask;
+        self.tree_pos = source.tree_pos;
+        self.tree_insert_len = source.tree_insert_len;
+        self.code_buf_dict_pos = source.code_buf_dict_pos;
+        self.lookahead_size = source.lookahead_size;
+        self.lookahead_pos = source.lookahead_pos;
+        self.size = source.size;
+    }
+}
+
 fn probes_from_flags(flags: u32) -> [u32; 2] {
     [
         1 + ((flags & 0xFFF) + 2) / 3,
@@ -1191,39 +2165,97 @@ fn probes_from_flags(flags: u32) -> [u32; 2] {
     ]
 }
 
-impl DictOxide {
-    fn new(flags: u32) -> Self {
-        DictOxide {
+impl<'a> DictOxide<'a> {
+    fn new(flags: u32, b: HashBuffers<'a>, hash: Storage<'a, [u16]>) -> Self {
+        let hash_bits = hash.len().trailing_zeros();
+        let mut dict = DictOxide {
             max_probes: probes_from_flags(flags),
-            b: Box::default(),
+            good_match_len: GOOD_MATCH_LEN,
+            nice_match_len: MAX_MATCH_LEN as u32,
+            finder: MatchFinder::Hash3,
+            window_mask: b.dict.len() - dict_buf_size(0) - 1,
+            b,
+            hash_shift: hash_shift(hash_bits),
+            hash_mask: hash.len() as u32 - 1,
+            hash,
+            tree_pos: 0,
+            tree_insert_len: tree_insert_len_from_flags(flags),
             code_buf_dict_pos: 0,
             lookahead_size: 0,
             lookahead_pos: 0,
             size: 0,
-        }
+        };
+        dict.finder = dict.finder_for(flags);
+        dict
     }
 
     fn update_flags(&mut self, flags: u32) {
         self.max_probes = probes_from_flags(flags);
+        self.tree_insert_len = tree_insert_len_from_flags(flags);
+        let finder = self.finder_for(flags);
+        if finder != self.finder {
+            // The tables of the old match finder don't make sense to the new one, so start
+            // over with them empty, only matching the data added from now on.
+            memset(&mut self.hash[..], 0);
+            self.tree_pos = self.lookahead_pos;
+            self.finder = finder;
+        }
+    }
+
+    /// Get the match finder to use for `flags`, allocating the binary trees if they are
+    /// needed and the caller didn't provide them.
+    fn finder_for(&mut self, flags: u32) -> MatchFinder {
+        let finder = match_finder_from_flags(flags);
+        let tree_size = 2 * self.window_size();
+        if finder == MatchFinder::BinaryTree && self.b.tree.len() < tree_size {
+            // Without a heap, stay with the hash chains.
+            #[cfg(not(feature = "alloc"))]
+            return MatchFinder::Hash3;
+            #[cfg(feature = "alloc")]
+            {
+                self.b.tree = Storage::new_slice(tree_size);
+            }
+        }
+        finder
+    }
+
+    /// Size of the window of previous data that matches can refer back to.
+    #[inline]
+    fn window_size(&self) -> usize {
+        self.window_mask + 1
     }
 
     fn reset(&mut self) {
         self.b.reset();
+        memset(&mut self.hash[..], 0);
+        self.tree_pos = 0;
         self.code_buf_dict_pos = 0;
         self.lookahead_size = 0;
         self.lookahead_pos = 0;
         self.size = 0;
     }
 
-    /// Do an unaligned read of the data at `pos` in the dictionary and treat it as if it was of
+    /// Write a byte to the dictionary at `pos`, mirroring it after the end of the
+    /// dictionary if needed.
+    #[inline]
+    fn write_byte(&mut self, pos: usize, c: u8) {
+        let window_size = self.window_size();
+        let pos = pos & self.window_mask;
+        self.b.dict[pos] = c;
+        if pos < MAX_MATCH_LEN - 1 {
+            self.b.dict[window_size + pos] = c;
+        }
+    }
+
+    /// Do an unaligned read of the data at `pos` in the dictionary and treat it as if it was of
     /// type T.
     #[inline]
     fn read_unaligned_u32(&self, pos: usize) -> u32 {
         // Masking the value here helps avoid bounds checks.
-        let pos = (pos & LZ_DICT_SIZE_MASK) as usize;
+        let pos = pos & self.window_mask;
         let end = pos + 4;
         // Somehow this assertion makes things faster.
-        assert!(end < LZ_DICT_FULL_SIZE);
+        assert!(end < self.b.dict.len());
 
         let bytes: [u8; 4] = self.b.dict[pos..end].try_into().unwrap();
         u32::from_le_bytes(bytes)
@@ -1245,11 +2277,207 @@ impl DictOxide {
         read_u16_le(&self.b.dict[..], pos)
     }
 
+    /// Hash of the 4 bytes at `pos`, used by the match finders other than `Hash3`.
+    #[inline]
+    fn hash4(&self, pos: usize) -> usize {
+        let bytes = self.read_unaligned_u32(pos);
+        (bytes.wrapping_mul(0x9E37_79B1) >> self.hash_mask.leading_zeros()) as usize
+    }
+
+    /// Hash of the 3 bytes at `pos`, indexing `b.next` when using `MatchFinder::BinaryTree`.
+    #[inline]
+    fn hash3(&self, pos: usize) -> usize {
+        let bytes = self.read_unaligned_u32(pos) & 0xFF_FFFF;
+        (bytes.wrapping_mul(0x9E37_79B1) >> (32 - self.window_size().trailing_zeros())) as usize
+    }
+
+    /// Insert `pos` into the hash chain of its 4-byte hash.
+    #[inline]
+    fn insert_hash4(&mut self, pos: usize) {
+        let hash = self.hash4(pos);
+        self.b.next[pos & self.window_mask] = self.hash[hash];
+        self.hash[hash] = pos as u16;
+    }
+
     /// Try to find a match for the data at lookahead_pos in the dictionary that is
-    /// longer than `match_len`.
+    /// longer than `match_len`, using the current match finder.
     /// Returns a tuple containing (match_distance, match_length). Will be equal to the input
     /// values if no better matches were found.
+    #[inline]
     fn find_match(
+        &mut self,
+        lookahead_pos: usize,
+        max_dist: usize,
+        max_match_len: u32,
+        match_dist: u32,
+        match_len: u32,
+    ) -> (u32, u32) {
+        if self.finder != MatchFinder::BinaryTree {
+            return self.find_match_chain(
+                lookahead_pos,
+                max_dist,
+                max_match_len,
+                match_dist,
+                match_len,
+            );
+        }
+
+        // The positions skipped over by the last match have to be added to the trees first.
+        // Longer gaps come from data copied to raw blocks, which isn't worth going back to.
+        // These positions are only inserted, so they get the shallower search used once a long
+        // match is found.
+        let first = cmp::max(self.tree_pos, lookahead_pos - max_dist);
+        if lookahead_pos - first <= MAX_MATCH_LEN {
+            for pos in first..lookahead_pos {
+                let back = lookahead_pos - pos;
+                let depth = self.max_probes[1];
+                let limit = cmp::min(max_match_len + back as u32, self.tree_insert_len);
+                // Start from a match of 3 bytes, it's no use looking for shorter ones.
+                let match_len = u32::from(MIN_MATCH_LEN);
+                self.find_match_tree(pos, max_dist - back, limit, depth, 0, match_len);
+            }
+        }
+        self.tree_pos = lookahead_pos + 1;
+        let depth = self.max_probes[(match_len >= self.good_match_len) as usize];
+        self.find_match_tree(
+            lookahead_pos,
+            max_dist,
+            max_match_len,
+            depth,
+            match_dist,
+            match_len,
+        )
+    }
+
+    /// Number of bytes the data at `pos` and `other` have in common, up to `limit`, knowing
+    /// that the first `len` are the same.
+    #[inline]
+    fn common_len(&self, pos: usize, other: usize, mut len: usize, limit: usize) -> usize {
+        while len + 8 <= limit {
+            let xor_data =
+                self.read_unaligned_u64(pos + len) ^ self.read_unaligned_u64(other + len);
+            if xor_data != 0 {
+                return len + (xor_data.trailing_zeros() >> 3) as usize;
+            }
+            len += 8;
+        }
+        while len < limit && self.b.dict[pos + len] == self.b.dict[other + len] {
+            len += 1;
+        }
+        len
+    }
+
+    /// Find a match for the data at `lookahead_pos` in the binary tree for its 4-byte hash,
+    /// looking at up to `depth_left` positions, and insert the position as the new root of
+    /// the tree.
+    ///
+    /// The tree is sorted by the data following each position, so the search only goes down
+    /// the path where the new position belongs, checking the candidates sharing the longest
+    /// prefixes with it on the way, and splitting the tree into the positions sorting before
+    /// and after it. Works like the `bt4` match finder of LZMA.
+    fn find_match_tree(
+        &mut self,
+        lookahead_pos: usize,
+        max_dist: usize,
+        max_match_len: u32,
+        mut depth_left: u32,
+        mut match_dist: u32,
+        mut match_len: u32,
+    ) -> (u32, u32) {
+        let len_limit = cmp::min(MAX_MATCH_LEN as u32, max_match_len) as usize;
+        if len_limit < usize::from(MIN_MATCH_LEN) {
+            return (match_dist, match_len);
+        }
+
+        let pos = lookahead_pos & self.window_mask;
+        // The trees only hold positions sharing 4 bytes, so the last position with the same
+        // 3 bytes is kept as well. `b.next` isn't used for hash chains here, and serves as the
+        // table of these positions.
+        let hash = self.hash3(pos);
+        let short_match = self.b.next[hash];
+        self.b.next[hash] = lookahead_pos as u16;
+        let dist = (lookahead_pos as u16).wrapping_sub(short_match) as usize;
+        if match_len < u32::from(MIN_MATCH_LEN) && short_match != 0 && dist != 0 && dist <= max_dist
+        {
+            let len = self.common_len(pos, short_match as usize & self.window_mask, 0, len_limit);
+            if len >= usize::from(MIN_MATCH_LEN) {
+                match_dist = dist as u32;
+                match_len = len as u32;
+            }
+        }
+
+        if len_limit < 4 {
+            // Not enough data left to hash.
+            return (match_dist, match_len);
+        }
+
+        let hash = self.hash4(pos);
+        let mut cur_match = self.hash[hash];
+        self.hash[hash] = lookahead_pos as u16;
+
+        // Where to link the next positions found to sort before and after the new one.
+        let mut before_slot = 2 * pos;
+        let mut after_slot = 2 * pos + 1;
+        // How many bytes all positions before and after are known to share with the new one.
+        let mut before_len = 0;
+        let mut after_len = 0;
+
+        loop {
+            let dist = (lookahead_pos as u16).wrapping_sub(cur_match) as usize;
+            if cur_match == 0 || dist == 0 || dist > max_dist || depth_left == 0 {
+                self.b.tree[before_slot] = 0;
+                self.b.tree[after_slot] = 0;
+                break;
+            }
+            depth_left -= 1;
+
+            let node = cur_match as usize & self.window_mask;
+            let len = self.common_len(pos, node, cmp::min(before_len, after_len), len_limit);
+            // Nodes replaced by an insertion that stopped early pass on their children
+            // without being sure they are sorted around the new position, so the prefix that
+            // was skipped has to be checked before using the match.
+            if len as u32 > match_len && self.common_len(pos, node, 0, len) == len {
+                if match_len < self.good_match_len && len as u32 >= self.good_match_len {
+                    // Like for the hash chains, look at fewer positions once a long match is found.
+                    depth_left = cmp::min(depth_left, self.max_probes[1]);
+                }
+                if len as u32 >= self.nice_match_len {
+                    // Long enough, finish inserting the position here.
+                    depth_left = 0;
+                }
+                match_dist = dist as u32;
+                match_len = len as u32;
+            }
+
+            if len == len_limit {
+                // As far as we can tell the node has the same data as the new position, so the
+                // new position takes its place in the tree.
+                self.b.tree[before_slot] = self.b.tree[2 * node];
+                self.b.tree[after_slot] = self.b.tree[2 * node + 1];
+                break;
+            }
+
+            if self.b.dict[node + len] < self.b.dict[pos + len] {
+                self.b.tree[before_slot] = cur_match;
+                before_slot = 2 * node + 1;
+                before_len = len;
+                cur_match = self.b.tree[before_slot];
+            } else {
+                self.b.tree[after_slot] = cur_match;
+                after_slot = 2 * node;
+                after_len = len;
+                cur_match = self.b.tree[after_slot];
+            }
+        }
+
+        (match_dist, match_len)
+    }
+
+    /// Try to find a match for the data at lookahead_pos in the hash chains that is
+    /// longer than `match_len`.
+    /// Returns a tuple containing (match_distance, match_length). Will be equal to the input
+    /// values if no better matches were found.
+    fn find_match_chain(
         &self,
         lookahead_pos: usize,
         max_dist: usize,
@@ -1265,13 +2493,15 @@ impl DictOxide {
         let max_match_len = cmp::min(MAX_MATCH_LEN as u32, max_match_len);
         match_len = cmp::max(match_len, 1);
 
-        let pos = lookahead_pos as usize & LZ_DICT_SIZE_MASK;
+        let pos = lookahead_pos as usize & self.window_mask;
         let mut probe_pos = pos;
         // Number of probes into the hash chains.
-        let mut num_probes_left = self.max_probes[(match_len >= 32) as usize];
+        let mut num_probes_left = self.max_probes[(match_len >= self.good_match_len) as usize];
+        // Stop searching once a match of this length is found.
+        let nice_match_len = cmp::min(max_match_len, self.nice_match_len);
 
         // If we already have a match of the full length don't bother searching for another one.
-        if max_match_len <= match_len {
+        if nice_match_len <= match_len {
             return (match_dist, match_len);
         }
 
@@ -1303,7 +2533,7 @@ impl DictOxide {
 
                     // Mask the position value to get the position in the hash chain of the next
                     // position to match against.
-                    probe_pos = next_probe_pos & LZ_DICT_SIZE_MASK;
+                    probe_pos = next_probe_pos & self.window_mask;
 
                     if self.read_as_u16((probe_pos + match_len as usize - 1) as usize) == c01 {
                         break 'found;
@@ -1341,7 +2571,7 @@ impl DictOxide {
                     if probe_len > match_len as usize {
                         match_dist = dist as u32;
                         match_len = cmp::min(max_match_len, probe_len as u32);
-                        if match_len == max_match_len {
+                        if match_len >= nice_match_len {
                             // We found a match that had the maximum allowed length,
                             // so there is now point searching further.
                             return (match_dist, match_len);
@@ -1359,9 +2589,59 @@ impl DictOxide {
     }
 }
 
-struct ParamsOxide {
+/// Number of bits of the rolling hash checked to split rsyncable output into chunks, which
+/// are `1 << RSYNC_BITS` bytes long on average.
+const RSYNC_BITS: u32 = 13;
+/// Value of the top `RSYNC_BITS` bits of the rolling hash that ends a chunk. Over a run of the
+/// same byte the hash settles at a value with other top bits, so runs don't end up as lots of
+/// tiny chunks.
+const RSYNC_HIT: u32 = 0x7FF;
+/// Minimum length of a chunk, so data repeating with a short period doesn't get a full
+/// flush every few bytes.
+const RSYNC_MIN_CHUNK: usize = 2048;
+
+/// Splits the input of an rsyncable compressor into chunks ending where a rolling hash of the
+/// last 32 bytes hits `RSYNC_HIT`.
+///
+/// The hash is a gear hash, adding a scrambled value of each byte, so that chunks end at about
+/// the same rate in text using only a few different byte values as in other data.
+#[derive(Copy, Clone, Default)]
+struct RsyncState {
+    hash: u32,
+    /// Number of bytes since the end of the last chunk.
+    chunk_len: usize,
+    /// The input up to the end of a chunk has been compressed, but the full flush ending it
+    /// has not been done yet.
+    flush_pending: bool,
+}
+
+impl RsyncState {
+    /// Roll the hash over `input`, stopping at the end of the current chunk.
+    ///
+    /// Returns the number of bytes up to the end of the chunk, or `None` if it doesn't end
+    /// in `input`.
+    fn roll(&mut self, input: &[u8]) -> Option<usize> {
+        for (i, &byte) in input.iter().enumerate() {
+            let mut gear = (u32::from(byte) + 1).wrapping_mul(0x9E37_79B1);
+            gear ^= gear >> 16;
+            gear = gear.wrapping_mul(0x85EB_CA6B);
+            gear ^= gear >> 13;
+            self.hash = (self.hash << 1).wrapping_add(gear);
+            self.chunk_len += 1;
+            if self.hash >> (32 - RSYNC_BITS) == RSYNC_HIT && self.chunk_len >= RSYNC_MIN_CHUNK {
+                self.chunk_len = 0;
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+}
+
+struct ParamsOxide<'a> {
     pub flags: u32,
     pub greedy_parsing: bool,
+    /// Length of a match after which it is used without trying a lazy match.
+    pub max_lazy_match_len: u32,
     pub block_index: u32,
 
     pub saved_match_dist: u32,
@@ -1383,14 +2663,43 @@ struct ParamsOxide {
     pub saved_bit_buffer: u32,
     pub saved_bits_in: u32,
 
-    pub local_buf: Box<LocalBuf>,
+    /// Number of blocks left to output raw without looking for matches when using
+    /// `TDEFL_ADAPTIVE_RAW_BLOCKS`.
+    pub raw_blocks_left: u32,
+    /// How many blocks to output raw the next time a block doesn't compress.
+    pub raw_blocks_backoff: u32,
+
+    /// State of the chunking of rsyncable output, `None` when not enabled.
+    pub rsync: Option<RsyncState>,
+
+    pub local_buf: Storage<'a, [u8]>,
 }
 
-impl ParamsOxide {
-    fn new(flags: u32) -> Self {
+#[cfg(feature = "alloc")]
+impl Clone for ParamsOxide<'_> {
+    fn clone(&self) -> Self {
+        ParamsOxide {
+            local_buf: self.local_buf.clone(),
+            ..*self
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        let mut local_buf = mem::replace(&mut self.local_buf, Storage::Boxed(Box::default()));
+        local_buf.clone_from(&source.local_buf);
+        *self = ParamsOxide {
+            local_buf,
+            ..*source
+        };
+    }
+}
+
+impl<'a> ParamsOxide<'a> {
+    fn new(flags: u32, local_buf: Storage<'a, [u8]>) -> Self {
         ParamsOxide {
             flags,
             greedy_parsing: flags & TDEFL_GREEDY_PARSING_FLAG != 0,
+            max_lazy_match_len: MAX_LAZY_MATCH_LEN,
             block_index: 0,
             saved_match_dist: 0,
             saved_match_len: 0,
@@ -1405,7 +2714,10 @@ impl ParamsOxide {
             prev_return_status: TDEFLStatus::Okay,
             saved_bit_buffer: 0,
             saved_bits_in: 0,
-            local_buf: Box::default(),
+            raw_blocks_left: 0,
+            raw_blocks_backoff: 1,
+            rsync: None,
+            local_buf,
         }
     }
 
@@ -1430,12 +2742,17 @@ impl ParamsOxide {
         self.prev_return_status = TDEFLStatus::Okay;
         self.saved_bit_buffer = 0;
         self.saved_bits_in = 0;
-        self.local_buf.b = [0; OUT_BUF_SIZE];
+        self.raw_blocks_left = 0;
+        self.raw_blocks_backoff = 1;
+        if let Some(ref mut rsync) = self.rsync {
+            *rsync = RsyncState::default();
+        }
+        memset(&mut self.local_buf[..], 0);
     }
 }
 
-struct LZOxide {
-    pub codes: [u8; LZ_CODE_BUF_SIZE],
+struct LZOxide<'a> {
+    pub codes: Storage<'a, [u8]>,
     pub code_position: usize,
     pub flag_position: usize,
 
@@ -1445,10 +2762,26 @@ struct LZOxide {
     pub num_flags_left: u32,
 }
 
-impl LZOxide {
-    fn new() -> Self {
+#[cfg(feature = "alloc")]
+impl Clone for LZOxide<'_> {
+    fn clone(&self) -> Self {
+        LZOxide {
+            codes: self.codes.clone(),
+            ..*self
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        let mut codes = mem::replace(&mut self.codes, Storage::Boxed(Box::default()));
+        codes.clone_from(&source.codes);
+        *self = LZOxide { codes, ..*source };
+    }
+}
+
+impl<'a> LZOxide<'a> {
+    fn new(codes: Storage<'a, [u8]>) -> Self {
         LZOxide {
-            codes: [0; LZ_CODE_BUF_SIZE],
+            codes,
             code_position: 1,
             flag_position: 0,
             total_bytes: 0,
@@ -1456,6 +2789,20 @@ impl LZOxide {
         }
     }
 
+    fn reset(&mut self) {
+        memset(&mut self.codes[..], 0);
+        self.code_position = 1;
+        self.flag_position = 0;
+        self.total_bytes = 0;
+        self.num_flags_left = 8;
+    }
+
+    /// Whether the code buffer is too full to hold another match and flag byte.
+    #[inline]
+    fn is_tight(&self) -> bool {
+        self.code_position > self.codes.len() - 8
+    }
+
     fn write_code(&mut self, val: u8) {
         self.codes[self.code_position] = val;
         self.code_position += 1;
@@ -1606,16 +2953,22 @@ fn flush_block(
     callback: &mut CallbackOxide,
     flush: TDEFLFlush,
 ) -> Result<i32> {
+    let window_mask = d.dict.window_mask;
+    let window_size = window_mask + 1;
     let mut saved_buffer;
     {
         let mut output = callback
             .out
-            .new_output_buffer(&mut d.params.local_buf.b, d.params.out_buf_ofs);
+            .new_output_buffer(&mut d.params.local_buf, d.params.out_buf_ofs);
         output.bit_buffer = d.params.saved_bit_buffer;
         output.bits_in = d.params.saved_bits_in;
 
-        let use_raw_block = (d.params.flags & TDEFL_FORCE_ALL_RAW_BLOCKS != 0)
+        // Blocks output while skipping incompressible data don't have any lz codes, so they
+        // always have to be stored raw.
+        let skipped_matching = d.params.raw_blocks_left > 0;
+        let use_raw_block = (d.params.flags & TDEFL_FORCE_ALL_RAW_BLOCKS != 0 || skipped_matching)
             && (d.dict.lookahead_pos - d.dict.code_buf_dict_pos) <= d.dict.size;
+        debug_assert!(use_raw_block || !skipped_matching);
 
         assert!(d.params.flush_remaining == 0);
         d.params.flush_ofs = 0;
@@ -1630,14 +2983,17 @@ fn flush_block(
             output.put_bits(header[1].into(), 8);
         }
 
+        #[cfg(feature = "stats")]
+        let block_start = output.inner_pos as u64 * 8 + u64::from(output.bits_in);
+
         // Output the block header.
         output.put_bits((flush == TDEFLFlush::Finish) as u32, 1);
 
         saved_buffer = output.save();
 
+        let use_static = (d.params.flags & TDEFL_FORCE_ALL_STATIC_BLOCKS != 0)
+            || (d.lz.total_bytes < 48 && !d.huff.has_custom_code_sizes());
         let comp_success = if !use_raw_block {
-            let use_static =
-                (d.params.flags & TDEFL_FORCE_ALL_STATIC_BLOCKS != 0) || (d.lz.total_bytes < 48);
             compress_block(&mut d.huff, &mut output, &d.lz, use_static)?
         } else {
             false
@@ -1669,16 +3025,34 @@ fn flush_block(
             output.put_bits(d.lz.total_bytes & 0xFFFF, 16);
             output.put_bits(!d.lz.total_bytes & 0xFFFF, 16);
 
-            // Write the actual bytes.
-            for i in 0..d.lz.total_bytes {
-                let pos = (d.dict.code_buf_dict_pos + i as usize) & LZ_DICT_SIZE_MASK;
-                output.put_bits(u32::from(d.dict.b.dict[pos as usize]), 8);
-            }
+            // Write the actual bytes, which may wrap around the end of the dictionary.
+            let start = d.dict.code_buf_dict_pos & window_mask;
+            let len = d.lz.total_bytes as usize;
+            let first_len = cmp::min(len, window_size - start);
+            output.write_bytes(&d.dict.b.dict[start..start + first_len]);
+            output.write_bytes(&d.dict.b.dict[..len - first_len]);
         } else if !comp_success {
             output.load(saved_buffer);
             compress_block(&mut d.huff, &mut output, &d.lz, true)?;
         }
 
+        #[cfg(feature = "stats")]
+        {
+            let block_type = if use_raw_block || expanded {
+                BlockType::Stored
+            } else if use_static || !comp_success {
+                BlockType::Static
+            } else {
+                BlockType::Dynamic
+            };
+            d.stats.record_block(BlockStats {
+                block_type,
+                input_bytes: d.lz.total_bytes,
+                output_bits: output.inner_pos as u64 * 8 + u64::from(output.bits_in) - block_start,
+            });
+            d.stats.record_lz_codes(&d.lz.codes[..d.lz.code_position]);
+        }
+
         if flush != TDEFLFlush::None {
             if flush == TDEFLFlush::Finish {
                 output.pad_to_bytes();
@@ -1692,10 +3066,37 @@ fn flush_block(
             } else {
                 // Sync or Full flush.
                 // Output an empty raw block.
+                #[cfg(feature = "stats")]
+                let block_start = output.inner_pos as u64 * 8 + u64::from(output.bits_in);
                 output.put_bits(0, 3);
                 output.pad_to_bytes();
                 output.put_bits(0, 16);
                 output.put_bits(0xFFFF, 16);
+                #[cfg(feature = "stats")]
+                d.stats.record_block(BlockStats {
+                    block_type: BlockType::Stored,
+                    input_bytes: 0,
+                    output_bits: output.inner_pos as u64 * 8 + u64::from(output.bits_in)
+                        - block_start,
+                });
+            }
+        }
+
+        // Skipped blocks are counted down even if the flags changed since, so that matching
+        // resumes.
+        let adaptive =
+            d.params.flags & TDEFL_ADAPTIVE_RAW_BLOCKS != 0 && !uses_compress_fast(d.params.flags);
+        if skipped_matching && d.lz.total_bytes > 0 {
+            d.params.raw_blocks_left -= 1;
+        } else if adaptive && d.lz.total_bytes > 0 {
+            if expanded {
+                // The block didn't compress, so skip looking for matches for a while.
+                skip_raw_blocks(
+                    &mut d.params.raw_blocks_left,
+                    &mut d.params.raw_blocks_backoff,
+                );
+            } else {
+                d.params.raw_blocks_backoff = 1;
             }
         }
 
@@ -1754,6 +3155,8 @@ fn record_match(h: &mut HuffmanOxide, lz: &mut LZOxide, mut match_len: u32, mut
 }
 
 fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool {
+    let window_mask = d.dict.window_mask;
+    let window_size = window_mask + 1;
     let mut src_pos = d.params.src_pos;
     let in_buf = match callback.in_buf {
         None => return true,
@@ -1767,20 +3170,83 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
     let mut saved_match_len = d.params.saved_match_len;
 
     while src_pos < in_buf.len() || (d.params.flush != TDEFLFlush::None && lookahead_size != 0) {
+        if d.params.raw_blocks_left > 0 && saved_match_len == 0 {
+            // The data doesn't seem to compress, so copy it straight into the dictionary
+            // without hashing it or looking for matches, to be output as a raw block.
+            let block_size = adaptive_raw_block_size(d.lz.codes.len(), window_size);
+            let block_left = block_size.saturating_sub(d.lz.total_bytes) as usize;
+            let num_bytes = cmp::min(
+                in_buf.len() - src_pos,
+                block_left.saturating_sub(lookahead_size),
+            );
+            let input = &in_buf[src_pos..src_pos + num_bytes];
+            let dst_pos = (lookahead_pos + lookahead_size) & window_mask;
+            let n = cmp::min(window_size - dst_pos, num_bytes);
+            d.dict.b.dict[dst_pos..dst_pos + n].copy_from_slice(&input[..n]);
+            d.dict.b.dict[..num_bytes - n].copy_from_slice(&input[n..]);
+            // Update the copy of the start of the dictionary kept after the end.
+            let (dict, mirror) = d.dict.b.dict.split_at_mut(window_size);
+            mirror[..MAX_MATCH_LEN - 1].copy_from_slice(&dict[..MAX_MATCH_LEN - 1]);
+
+            src_pos += num_bytes;
+            let len = lookahead_size + num_bytes;
+            d.lz.total_bytes += len as u32;
+            lookahead_pos += len;
+            lookahead_size = 0;
+            d.dict.size = cmp::min(d.dict.size + len, window_size);
+
+            if d.lz.total_bytes >= block_size {
+                d.params.src_pos = src_pos;
+                d.dict.lookahead_size = lookahead_size;
+                d.dict.lookahead_pos = lookahead_pos;
+
+                let n = flush_block(d, callback, TDEFLFlush::None)
+                    .unwrap_or(TDEFLStatus::PutBufFailed as i32);
+                if n != 0 {
+                    d.params.saved_lit = saved_lit;
+                    d.params.saved_match_dist = saved_match_dist;
+                    d.params.saved_match_len = saved_match_len;
+                    return n > 0;
+                }
+            }
+            continue;
+        }
+
         let src_buf_left = in_buf.len() - src_pos;
         let num_bytes_to_process = cmp::min(src_buf_left, MAX_MATCH_LEN - lookahead_size as usize);
 
-        if lookahead_size + d.dict.size >= usize::from(MIN_MATCH_LEN) - 1
+        if d.dict.finder != MatchFinder::Hash3 {
+            let start = lookahead_pos + lookahead_size;
+            let input = &in_buf[src_pos..src_pos + num_bytes_to_process];
+            for (i, &c) in input.iter().enumerate() {
+                d.dict.write_byte(start + i, c);
+            }
+            lookahead_size += num_bytes_to_process;
+            src_pos += num_bytes_to_process;
+
+            // Hash the positions that now have 4 bytes available. The binary trees are updated
+            // when looking for matches instead.
+            if d.dict.finder == MatchFinder::Hash4 {
+                let end = start + num_bytes_to_process;
+                for ins_pos in start.saturating_sub(3)..end.saturating_sub(3) {
+                    d.dict.insert_hash4(ins_pos);
+                }
+            }
+        } else if lookahead_size + d.dict.size >= usize::from(MIN_MATCH_LEN) - 1
             && num_bytes_to_process > 0
         {
             let dictb = &mut d.dict.b;
+            let hash_table = &mut d.dict.hash;
+            let (hash_shift, hash_mask) = (d.dict.hash_shift, d.dict.hash_mask);
 
-            let mut dst_pos = (lookahead_pos + lookahead_size as usize) & LZ_DICT_SIZE_MASK;
+            let mut dst_pos = (lookahead_pos + lookahead_size as usize) & window_mask;
             let mut ins_pos = lookahead_pos + lookahead_size as usize - 2;
             // Start the hash value from the first two bytes
             let mut hash = update_hash(
-                u32::from(dictb.dict[(ins_pos & LZ_DICT_SIZE_MASK) as usize]),
-                dictb.dict[((ins_pos + 1) & LZ_DICT_SIZE_MASK) as usize],
+                u32::from(dictb.dict[(ins_pos & window_mask) as usize]),
+                dictb.dict[((ins_pos + 1) & window_mask) as usize],
+                hash_shift,
+                hash_mask,
             );
 
             lookahead_size += num_bytes_to_process;
@@ -1789,50 +3255,53 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
                 // Add byte to input buffer.
                 dictb.dict[dst_pos as usize] = c;
                 if (dst_pos as usize) < MAX_MATCH_LEN - 1 {
-                    dictb.dict[LZ_DICT_SIZE + dst_pos as usize] = c;
+                    dictb.dict[window_size + dst_pos as usize] = c;
                 }
 
                 // Generate hash from the current byte,
-                hash = update_hash(hash, c);
-                dictb.next[(ins_pos & LZ_DICT_SIZE_MASK) as usize] = dictb.hash[hash as usize];
+                hash = update_hash(hash, c, hash_shift, hash_mask);
+                dictb.next[(ins_pos & window_mask) as usize] = hash_table[hash as usize];
                 // and insert it into the hash chain.
-                dictb.hash[hash as usize] = ins_pos as u16;
-                dst_pos = (dst_pos + 1) & LZ_DICT_SIZE_MASK;
+                hash_table[hash as usize] = ins_pos as u16;
+                dst_pos = (dst_pos + 1) & window_mask;
                 ins_pos += 1;
             }
             src_pos += num_bytes_to_process;
         } else {
             let dictb = &mut d.dict.b;
+            let hash_table = &mut d.dict.hash;
+            let (hash_shift, hash_mask) = (d.dict.hash_shift, d.dict.hash_mask);
             for &c in &in_buf[src_pos..src_pos + num_bytes_to_process] {
-                let dst_pos = (lookahead_pos + lookahead_size) & LZ_DICT_SIZE_MASK;
+                let dst_pos = (lookahead_pos + lookahead_size) & window_mask;
                 dictb.dict[dst_pos as usize] = c;
                 if (dst_pos as usize) < MAX_MATCH_LEN - 1 {
-                    dictb.dict[LZ_DICT_SIZE + dst_pos as usize] = c;
+                    dictb.dict[window_size + dst_pos as usize] = c;
                 }
 
                 lookahead_size += 1;
                 if lookahead_size + d.dict.size >= MIN_MATCH_LEN.into() {
                     let ins_pos = lookahead_pos + lookahead_size - 3;
-                    let hash = ((u32::from(dictb.dict[(ins_pos & LZ_DICT_SIZE_MASK) as usize])
-                        << (LZ_HASH_SHIFT * 2))
-                        ^ ((u32::from(dictb.dict[((ins_pos + 1) & LZ_DICT_SIZE_MASK) as usize])
-                            << LZ_HASH_SHIFT)
+                    let hash = ((u32::from(dictb.dict[(ins_pos & window_mask) as usize])
+                        << (hash_shift * 2))
+                        ^ ((u32::from(dictb.dict[((ins_pos + 1) & window_mask) as usize])
+                            << hash_shift)
                             ^ u32::from(c)))
-                        & (LZ_HASH_SIZE as u32 - 1);
+                        & hash_mask;
 
-                    dictb.next[(ins_pos & LZ_DICT_SIZE_MASK) as usize] = dictb.hash[hash as usize];
-                    dictb.hash[hash as usize] = ins_pos as u16;
+                    dictb.next[(ins_pos & window_mask) as usize] = hash_table[hash as usize];
+                    hash_table[hash as usize] = ins_pos as u16;
                 }
             }
 
             src_pos += num_bytes_to_process;
         }
 
-        d.dict.size = cmp::min(LZ_DICT_SIZE - lookahead_size, d.dict.size);
+        d.dict.size = cmp::min(window_size - lookahead_size, d.dict.size);
         if d.params.flush == TDEFLFlush::None && (lookahead_size as usize) < MAX_MATCH_LEN {
             break;
         }
 
+        let prev_total_bytes = d.lz.total_bytes;
         let mut len_to_move = 1;
         let mut cur_match_dist = 0;
         let mut cur_match_len = if saved_match_len != 0 {
@@ -1840,11 +3309,11 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
         } else {
             u32::from(MIN_MATCH_LEN) - 1
         };
-        let cur_pos = lookahead_pos & LZ_DICT_SIZE_MASK;
+        let cur_pos = lookahead_pos & window_mask;
         if d.params.flags & (TDEFL_RLE_MATCHES | TDEFL_FORCE_ALL_RAW_BLOCKS) != 0 {
             // If TDEFL_RLE_MATCHES is set, we only look for repeating sequences of the current byte.
             if d.dict.size != 0 && d.params.flags & TDEFL_FORCE_ALL_RAW_BLOCKS == 0 {
-                let c = d.dict.b.dict[((cur_pos.wrapping_sub(1)) & LZ_DICT_SIZE_MASK) as usize];
+                let c = d.dict.b.dict[((cur_pos.wrapping_sub(1)) & window_mask) as usize];
                 cur_match_len = d.dict.b.dict[cur_pos as usize..(cur_pos + lookahead_size) as usize]
                     .iter()
                     .take_while(|&x| *x == c)
@@ -1857,6 +3326,8 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
             }
         } else {
             // Try to find a match for the bytes at the current position.
+            #[cfg(feature = "stats")]
+            let start_time = d.stats_time();
             let dist_len = d.dict.find_match(
                 lookahead_pos,
                 d.dict.size,
@@ -1866,6 +3337,10 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
             );
             cur_match_dist = dist_len.0;
             cur_match_len = dist_len.1;
+            #[cfg(feature = "stats")]
+            {
+                d.stats.match_finder_time += d.stats_time().wrapping_sub(start_time);
+            }
         }
 
         let far_and_small = cur_match_len == MIN_MATCH_LEN.into() && cur_match_dist >= 8 * 1024;
@@ -1878,7 +3353,7 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
         if saved_match_len != 0 {
             if cur_match_len > saved_match_len {
                 record_literal(&mut d.huff, &mut d.lz, saved_lit);
-                if cur_match_len >= 128 {
+                if cur_match_len >= d.params.max_lazy_match_len {
                     record_match(&mut d.huff, &mut d.lz, cur_match_len, cur_match_dist);
                     saved_match_len = 0;
                     len_to_move = cur_match_len as usize;
@@ -1893,17 +3368,14 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
                 saved_match_len = 0;
             }
         } else if cur_match_dist == 0 {
-            record_literal(
-                &mut d.huff,
-                &mut d.lz,
-                d.dict.b.dict[cmp::min(cur_pos as usize, d.dict.b.dict.len() - 1)],
-            );
+            let lit = d.dict.b.dict[cmp::min(cur_pos as usize, d.dict.b.dict.len() - 1)];
+            record_literal(&mut d.huff, &mut d.lz, lit);
         } else if d.params.greedy_parsing
             || (d.params.flags & TDEFL_RLE_MATCHES != 0)
-            || cur_match_len >= 128
+            || cur_match_len >= d.params.max_lazy_match_len
         {
             // If we are using lazy matching, check for matches at the next byte if the current
-            // match was shorter than 128 bytes.
+            // match was shorter than `max_lazy_match_len` (128 bytes by default).
             record_match(&mut d.huff, &mut d.lz, cur_match_len, cur_match_dist);
             len_to_move = cur_match_len as usize;
         } else {
@@ -1915,12 +3387,26 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
         lookahead_pos += len_to_move;
         assert!(lookahead_size >= len_to_move);
         lookahead_size -= len_to_move;
-        d.dict.size = cmp::min(d.dict.size + len_to_move, LZ_DICT_SIZE);
+        d.dict.size = cmp::min(d.dict.size + len_to_move, window_size);
 
-        let lz_buf_tight = d.lz.code_position > LZ_CODE_BUF_SIZE - 8;
+        let lz_buf_tight = d.lz.is_tight();
         let raw = d.params.flags & TDEFL_FORCE_ALL_RAW_BLOCKS != 0;
         let fat = ((d.lz.code_position * 115) >> 7) >= d.lz.total_bytes as usize;
-        let fat_or_raw = (d.lz.total_bytes > 31 * 1024) && (fat || raw);
+        let fat_or_raw = (d.lz.total_bytes as usize > window_size - 1024) && (fat || raw);
+        // If the start of the block doesn't compress, skip the rest of it already. The data
+        // is still in the dictionary to store the whole block raw.
+        let sample_end =
+            prev_total_bytes < ADAPTIVE_SAMPLE_SIZE && d.lz.total_bytes >= ADAPTIVE_SAMPLE_SIZE;
+        if sample_end
+            && d.params.flags & TDEFL_ADAPTIVE_RAW_BLOCKS != 0
+            && d.lz.total_bytes < adaptive_raw_block_size(d.lz.codes.len(), window_size)
+            && looks_incompressible(&d.huff, d.lz.total_bytes)
+        {
+            skip_raw_blocks(
+                &mut d.params.raw_blocks_left,
+                &mut d.params.raw_blocks_backoff,
+            );
+        }
 
         if lz_buf_tight || fat_or_raw {
             d.params.src_pos = src_pos;
@@ -1951,60 +3437,71 @@ fn compress_normal(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> boo
 const COMP_FAST_LOOKAHEAD_SIZE: usize = 4096;
 
 fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool {
+    let window_mask = d.dict.window_mask;
+    let window_size = window_mask + 1;
+    // Leave at least half of a small window for the data to match against.
+    let lookahead_limit = cmp::min(COMP_FAST_LOOKAHEAD_SIZE, window_size / 2);
     let mut src_pos = d.params.src_pos;
     let mut lookahead_size = d.dict.lookahead_size;
     let mut lookahead_pos = d.dict.lookahead_pos;
 
-    let mut cur_pos = lookahead_pos & LZ_DICT_SIZE_MASK;
+    let mut cur_pos = lookahead_pos & window_mask;
     let in_buf = match callback.in_buf {
         None => return true,
         Some(in_buf) => in_buf,
     };
 
-    debug_assert!(d.lz.code_position < LZ_CODE_BUF_SIZE - 2);
+    debug_assert!(d.lz.code_position < d.lz.codes.len() - 2);
+    // Use a smaller hash table than the one of the normal compressor, unless that is
+    // even smaller because of a low memory level.
+    let level1_hash_mask = cmp::min(LEVEL1_HASH_SIZE_MASK, d.dict.hash_mask);
 
     while src_pos < in_buf.len() || (d.params.flush != TDEFLFlush::None && lookahead_size > 0) {
-        let mut dst_pos = ((lookahead_pos + lookahead_size) & LZ_DICT_SIZE_MASK) as usize;
+        let mut dst_pos = ((lookahead_pos + lookahead_size) & window_mask) as usize;
         let mut num_bytes_to_process = cmp::min(
             in_buf.len() - src_pos,
-            (COMP_FAST_LOOKAHEAD_SIZE - lookahead_size) as usize,
+            (lookahead_limit - lookahead_size) as usize,
         );
         lookahead_size += num_bytes_to_process;
 
         while num_bytes_to_process != 0 {
-            let n = cmp::min(LZ_DICT_SIZE - dst_pos, num_bytes_to_process);
+            let n = cmp::min(window_size - dst_pos, num_bytes_to_process);
             d.dict.b.dict[dst_pos..dst_pos + n].copy_from_slice(&in_buf[src_pos..src_pos + n]);
 
             if dst_pos < MAX_MATCH_LEN - 1 {
                 let m = cmp::min(n, MAX_MATCH_LEN - 1 - dst_pos);
-                d.dict.b.dict[dst_pos + LZ_DICT_SIZE..dst_pos + LZ_DICT_SIZE + m]
+                d.dict.b.dict[dst_pos + window_size..dst_pos + window_size + m]
                     .copy_from_slice(&in_buf[src_pos..src_pos + m]);
             }
 
             src_pos += n;
-            dst_pos = (dst_pos + n) & LZ_DICT_SIZE_MASK as usize;
+            dst_pos = (dst_pos + n) & window_mask as usize;
             num_bytes_to_process -= n;
         }
 
-        d.dict.size = cmp::min(LZ_DICT_SIZE - lookahead_size, d.dict.size);
-        if d.params.flush == TDEFLFlush::None && lookahead_size < COMP_FAST_LOOKAHEAD_SIZE {
+        d.dict.size = cmp::min(window_size - lookahead_size, d.dict.size);
+        if d.params.flush == TDEFLFlush::None && lookahead_size < lookahead_limit {
             break;
         }
 
         while lookahead_size >= 4 {
+            #[cfg(feature = "stats")]
+            let start_time = d.stats_time();
             let mut cur_match_len = 1;
 
             let first_trigram = d.dict.read_unaligned_u32(cur_pos) & 0xFF_FFFF;
 
-            let hash = (first_trigram ^ (first_trigram >> (24 - (LZ_HASH_BITS - 8))))
-                & LEVEL1_HASH_SIZE_MASK;
+            let hash =
+                (first_trigram ^ (first_trigram >> (24 - (LZ_HASH_BITS - 8)))) & level1_hash_mask;
 
-            let mut probe_pos = usize::from(d.dict.b.hash[hash as usize]);
-            d.dict.b.hash[hash as usize] = lookahead_pos as u16;
+            let mut probe_pos = usize::from(d.dict.hash[hash as usize]);
+            d.dict.hash[hash as usize] = lookahead_pos as u16;
 
-            let mut cur_match_dist = (lookahead_pos - probe_pos as usize) as u16;
+            // Entries left by compress_normal after a level change can be ahead of the
+            // lookahead, those give a distance that is too far back.
+            let mut cur_match_dist = lookahead_pos.wrapping_sub(probe_pos as usize) as u16;
             if cur_match_dist as usize <= d.dict.size {
-                probe_pos &= LZ_DICT_SIZE_MASK;
+                probe_pos &= window_mask;
 
                 let trigram = d.dict.read_unaligned_u32(probe_pos) & 0xFF_FFFF;
 
@@ -2012,26 +3509,30 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
                     // Trigram was tested, so we can start with "+ 3" displacement.
                     let mut p = cur_pos + 3;
                     let mut q = probe_pos + 3;
-                    cur_match_len = 'find_match: loop {
-                        for _ in 0..32 {
-                            let p_data: u64 = d.dict.read_unaligned_u64(p);
-                            let q_data: u64 = d.dict.read_unaligned_u64(q);
-                            let xor_data = p_data ^ q_data;
-                            if xor_data == 0 {
-                                p += 8;
-                                q += 8;
-                            } else {
-                                let trailing = xor_data.trailing_zeros();
-                                break 'find_match p as u32 - cur_pos as u32 + (trailing >> 3);
-                            }
-                        }
-
-                        break 'find_match if cur_match_dist == 0 {
-                            0
-                        } else {
-                            MAX_MATCH_LEN as u32
-                        };
+                    // If no difference is found in the next 256 bytes, the match is at least
+                    // as long as the longest allowed one.
+                    cur_match_len = if cur_match_dist == 0 {
+                        0
+                    } else {
+                        MAX_MATCH_LEN as u32
                     };
+                    for _ in 0..32 {
+                        let p_data: u64 = d.dict.read_unaligned_u64(p);
+                        let q_data: u64 = d.dict.read_unaligned_u64(q);
+                        let xor_data = p_data ^ q_data;
+                        if xor_data == 0 {
+                            p += 8;
+                            q += 8;
+                        } else {
+                            let trailing = xor_data.trailing_zeros();
+                            cur_match_len = p as u32 - cur_pos as u32 + (trailing >> 3);
+                            break;
+                        }
+                    }
+                    #[cfg(feature = "stats")]
+                    {
+                        d.stats.match_finder_time += d.stats_time().wrapping_sub(start_time);
+                    }
 
                     if cur_match_len < MIN_MATCH_LEN.into()
                         || (cur_match_len == MIN_MATCH_LEN.into() && cur_match_dist >= 8 * 1024)
@@ -2047,7 +3548,7 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
                         cur_match_len = cmp::min(cur_match_len, lookahead_size as u32);
                         debug_assert!(cur_match_len >= MIN_MATCH_LEN.into());
                         debug_assert!(cur_match_dist >= 1);
-                        debug_assert!(cur_match_dist as usize <= LZ_DICT_SIZE);
+                        debug_assert!(cur_match_dist as usize <= window_size);
                         cur_match_dist -= 1;
 
                         d.lz.write_code((cur_match_len - u32::from(MIN_MATCH_LEN)) as u8);
@@ -2067,6 +3568,10 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
                             as usize] += 1;
                     }
                 } else {
+                    #[cfg(feature = "stats")]
+                    {
+                        d.stats.match_finder_time += d.stats_time().wrapping_sub(start_time);
+                    }
                     d.lz.write_code(first_trigram as u8);
                     *d.lz.get_flag() >>= 1;
                     d.huff.count[0][first_trigram as u8 as usize] += 1;
@@ -2075,11 +3580,11 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
                 d.lz.consume_flag();
                 d.lz.total_bytes += cur_match_len;
                 lookahead_pos += cur_match_len as usize;
-                d.dict.size = cmp::min(d.dict.size + cur_match_len as usize, LZ_DICT_SIZE);
-                cur_pos = (cur_pos + cur_match_len as usize) & LZ_DICT_SIZE_MASK;
+                d.dict.size = cmp::min(d.dict.size + cur_match_len as usize, window_size);
+                cur_pos = (cur_pos + cur_match_len as usize) & window_mask;
                 lookahead_size -= cur_match_len as usize;
 
-                if d.lz.code_position > LZ_CODE_BUF_SIZE - 8 {
+                if d.lz.is_tight() {
                     // These values are used in flush_block, so we need to write them back here.
                     d.dict.lookahead_size = lookahead_size;
                     d.dict.lookahead_pos = lookahead_pos;
@@ -2096,7 +3601,7 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
                         d.params.src_pos = src_pos;
                         return n > 0;
                     }
-                    debug_assert!(d.lz.code_position < LZ_CODE_BUF_SIZE - 2);
+                    debug_assert!(d.lz.code_position < d.lz.codes.len() - 2);
 
                     lookahead_size = d.dict.lookahead_size;
                     lookahead_pos = d.dict.lookahead_pos;
@@ -2113,11 +3618,11 @@ fn compress_fast(d: &mut CompressorOxide, callback: &mut CallbackOxide) -> bool
 
             d.huff.count[0][lit as usize] += 1;
             lookahead_pos += 1;
-            d.dict.size = cmp::min(d.dict.size + 1, LZ_DICT_SIZE);
-            cur_pos = (cur_pos + 1) & LZ_DICT_SIZE_MASK;
+            d.dict.size = cmp::min(d.dict.size + 1, window_size);
+            cur_pos = (cur_pos + 1) & window_mask;
             lookahead_size -= 1;
 
-            if d.lz.code_position > LZ_CODE_BUF_SIZE - 8 {
+            if d.lz.is_tight() {
                 // These values are used in flush_block, so we need to write them back here.
                 d.dict.lookahead_size = lookahead_size;
                 d.dict.lookahead_pos = lookahead_pos;
@@ -2153,7 +3658,7 @@ fn flush_output_buffer(c: &mut CallbackOxide, p: &mut ParamsOxide) -> (TDEFLStat
         let n = cmp::min(cb.out_buf.len() - p.out_buf_ofs, p.flush_remaining as usize);
         if n != 0 {
             (&mut cb.out_buf[p.out_buf_ofs..p.out_buf_ofs + n])
-                .copy_from_slice(&p.local_buf.b[p.flush_ofs as usize..p.flush_ofs as usize + n]);
+                .copy_from_slice(&p.local_buf[p.flush_ofs as usize..p.flush_ofs as usize + n]);
         }
         p.flush_ofs += n as u32;
         p.flush_remaining -= n as u32;
@@ -2223,9 +3728,28 @@ fn compress_inner(
     callback: &mut CallbackOxide,
     flush: TDEFLFlush,
 ) -> (TDEFLStatus, usize, usize) {
-    d.params.out_buf_ofs = 0;
-    d.params.src_pos = 0;
+    #[cfg(feature = "zlib-compat")]
+    {
+        if d.params.flags & TDEFL_ZLIB_COMPATIBLE_FLAG != 0 {
+            return compress_zlib_compatible(d, callback, flush);
+        }
+    }
 
+    match callback.in_buf {
+        Some(in_buf) if d.params.rsync.is_some() => {
+            compress_rsyncable(d, in_buf, &mut callback.out, flush)
+        }
+        _ => compress_chunk(d, callback, flush),
+    }
+}
+
+/// Compress with the port of zlib's compressor, for `TDEFL_ZLIB_COMPATIBLE_FLAG`.
+#[cfg(feature = "zlib-compat")]
+fn compress_zlib_compatible(
+    d: &mut CompressorOxide,
+    callback: &mut CallbackOxide,
+    flush: TDEFLFlush,
+) -> (TDEFLStatus, usize, usize) {
     let prev_ok = d.params.prev_return_status == TDEFLStatus::Okay;
     let flush_finish_once = d.params.flush != TDEFLFlush::Finish || flush == TDEFLFlush::Finish;
 
@@ -2235,19 +3759,112 @@ fn compress_inner(
         return (d.params.prev_return_status, 0, 0);
     }
 
-    if d.params.flush_remaining != 0 || d.params.finished {
-        let res = flush_output_buffer(callback, &mut d.params);
-        d.params.prev_return_status = res.0;
-        return res;
+    let (level, strategy) = zlib_compat::params_from_flags(d.params.flags);
+    let zlib = d.zlib_state();
+
+    let mut sink = match callback.out {
+        CallbackOut::Func(ref mut cf) => Sink::Func {
+            func: &mut *cf.put_buf_func,
+            failed: false,
+        },
+        CallbackOut::Buf(ref mut cb) => Sink::Buf {
+            buf: &mut *cb.out_buf,
+            pos: 0,
+        },
+    };
+    let in_buf = callback.in_buf.unwrap_or(&[]);
+    let (status, in_pos) = zlib.deflate(in_buf, &mut sink, flush, level, strategy);
+    d.params.adler32 = zlib.adler32();
+
+    let (out_pos, failed) = match sink {
+        Sink::Buf { pos, .. } => (pos, false),
+        Sink::Func { failed, .. } => (0, failed),
+    };
+    d.params.prev_return_status = match status {
+        _ if failed => TDEFLStatus::PutBufFailed,
+        ZlibStatus::StreamEnd => TDEFLStatus::Done,
+        ZlibStatus::StreamError => TDEFLStatus::BadParam,
+        ZlibStatus::Ok | ZlibStatus::BufError => TDEFLStatus::Okay,
+    };
+    callback.update_size(Some(in_pos), Some(out_pos));
+    (d.params.prev_return_status, in_pos, out_pos)
+}
+
+/// Compress `in_buf` for an rsyncable compressor, with a full flush at the end of each chunk
+/// found by the rolling hash.
+fn compress_rsyncable(
+    d: &mut CompressorOxide,
+    in_buf: &[u8],
+    out: &mut CallbackOut,
+    flush: TDEFLFlush,
+) -> (TDEFLStatus, usize, usize) {
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+    loop {
+        let rsync = d.params.rsync.unwrap_or_default();
+        let mut next = rsync;
+        // Once the stream is being finished, the remaining input goes in the final block.
+        let chunk_end = if rsync.flush_pending {
+            Some(0)
+        } else if d.params.flush == TDEFLFlush::Finish {
+            None
+        } else {
+            next.roll(&in_buf[in_pos..])
+        };
+        let (chunk_len, chunk_flush) = match chunk_end {
+            Some(len) => (len, TDEFLFlush::Full),
+            None => (in_buf.len() - in_pos, flush),
+        };
+
+        let chunk = &in_buf[in_pos..in_pos + chunk_len];
+        let mut callback = match *out {
+            CallbackOut::Func(ref mut cf) => CallbackOxide::new_callback_func(
+                chunk,
+                CallbackFunc {
+                    put_buf_func: &mut *cf.put_buf_func,
+                },
+            ),
+            CallbackOut::Buf(ref mut cb) => {
+                CallbackOxide::new_callback_buf(chunk, &mut cb.out_buf[out_pos..])
+            }
+        };
+        let res = compress_chunk(d, &mut callback, chunk_flush);
+        in_pos += res.1;
+        out_pos += res.2;
+
+        if res.1 < chunk_len {
+            let mut rsync = rsync;
+            rsync.roll(&chunk[..res.1]);
+            d.params.rsync = Some(rsync);
+        } else if chunk_end.is_some() {
+            // The full flush empties the dictionary, which is not empty before it as chunks
+            // are never empty.
+            let flushed = d.dict.size == 0 && d.dict.lookahead_size == 0;
+            next.flush_pending = !flushed;
+            d.params.rsync = Some(next);
+            if flushed && res.0 == TDEFLStatus::Okay && d.params.flush_remaining == 0 {
+                continue;
+            }
+        } else {
+            d.params.rsync = Some(next);
+        }
+        return (res.0, in_pos, out_pos);
     }
+}
 
-    let one_probe = d.params.flags & MAX_PROBES_MASK as u32 == 1;
-    let greedy = d.params.flags & TDEFL_GREEDY_PARSING_FLAG != 0;
-    let filter_or_rle_or_raw = d.params.flags
-        & (TDEFL_FILTER_MATCHES | TDEFL_FORCE_ALL_RAW_BLOCKS | TDEFL_RLE_MATCHES)
-        != 0;
+/// Compress the input of `callback`, flushing as requested by `flush`.
+fn compress_chunk(
+    d: &mut CompressorOxide,
+    callback: &mut CallbackOxide,
+    flush: TDEFLFlush,
+) -> (TDEFLStatus, usize, usize) {
+    if let Some(res) = start_compress(d, callback, flush) {
+        return res;
+    }
 
-    let compress_success = if one_probe && greedy && !filter_or_rle_or_raw {
+    // Blocks that are skipped as they don't compress are finished by `compress_normal`, even
+    // if the level was changed to 1 since.
+    let compress_success = if uses_compress_fast(d.params.flags) && d.params.raw_blocks_left == 0 {
         compress_fast(d, callback)
     } else {
         compress_normal(d, callback)
@@ -2267,8 +3884,48 @@ fn compress_inner(
         }
     }
 
-    let flush_none = d.params.flush == TDEFLFlush::None;
     let in_left = callback.in_buf.map_or(0, |buf| buf.len()) - d.params.src_pos;
+    finish_compress(d, callback, in_left)
+}
+
+/// Common setup for a call to one of the compression functions.
+///
+/// Returns `Some` with the result of the call if there is nothing more to do, either
+/// because of invalid parameters or because there is still buffered output to flush.
+fn start_compress(
+    d: &mut CompressorOxide,
+    callback: &mut CallbackOxide,
+    flush: TDEFLFlush,
+) -> Option<(TDEFLStatus, usize, usize)> {
+    d.params.out_buf_ofs = 0;
+    d.params.src_pos = 0;
+
+    let prev_ok = d.params.prev_return_status == TDEFLStatus::Okay;
+    let flush_finish_once = d.params.flush != TDEFLFlush::Finish || flush == TDEFLFlush::Finish;
+
+    d.params.flush = flush;
+    if !prev_ok || !flush_finish_once {
+        d.params.prev_return_status = TDEFLStatus::BadParam;
+        return Some((d.params.prev_return_status, 0, 0));
+    }
+
+    if d.params.flush_remaining != 0 || d.params.finished {
+        let res = flush_output_buffer(callback, &mut d.params);
+        d.params.prev_return_status = res.0;
+        return Some(res);
+    }
+
+    None
+}
+
+/// Flush the final block if requested and all the input was processed, and
+/// pass on any buffered output.
+fn finish_compress(
+    d: &mut CompressorOxide,
+    callback: &mut CallbackOxide,
+    in_left: usize,
+) -> (TDEFLStatus, usize, usize) {
+    let flush_none = d.params.flush == TDEFLFlush::None;
     let remaining = in_left != 0 || d.params.flush_remaining != 0;
     if !flush_none && d.dict.lookahead_size == 0 && !remaining {
         let flush = d.params.flush;
@@ -2291,8 +3948,9 @@ fn compress_inner(
             _ => {
                 d.params.finished = d.params.flush == TDEFLFlush::Finish;
                 if d.params.flush == TDEFLFlush::Full {
-                    memset(&mut d.dict.b.hash[..], 0);
+                    memset(&mut d.dict.hash[..], 0);
                     memset(&mut d.dict.b.next[..], 0);
+                    memset(&mut d.dict.b.tree[..], 0);
                     d.dict.size = 0;
                 }
             }
@@ -2305,34 +3963,260 @@ fn compress_inner(
     res
 }
 
-/// Create a set of compression flags using parameters used by zlib and other compressors.
-/// Mainly intented for use with transition from c libraries as it deals with raw integers.
+/// Compress a stream of pre-parsed LZ77 tokens, putting the output into `out_buf`.
 ///
-/// # Parameters
-/// `level` determines compression level. Clamped to maximum of 10. Negative values result in
-/// `Compressionlevel::DefaultLevel`.
-/// `window_bits`: Above 0, wraps the stream in a zlib wrapper, 0 or negative for a raw deflate
-/// stream.
-/// `strategy`: Sets the strategy if this conforms to any of the values in `CompressionStrategy`.
+/// This skips the match finder altogether and feeds the tokens directly to the huffman
+/// back-end, so the blocks are emitted just as if the compressor had found the
+/// given literals and matches itself. `LZToken::EndOfBlock` ends the current block.
+/// The block types used, the zlib wrapper and the flush behaviour are decided
+/// by the compressor flags and `flush` in the same way as for [`compress`](fn.compress.html).
 ///
-/// # Notes
-/// This function may be removed or moved to the `miniz_oxide_c_api` in the future.
-pub fn create_comp_flags_from_zip_params(level: i32, window_bits: i32, strategy: i32) -> u32 {
-    let num_probes = (if level >= 0 {
-        cmp::min(10, level)
-    } else {
-        CompressionLevel::DefaultLevel as i32
-    }) as usize;
-    let greedy = if level <= 3 {
-        TDEFL_GREEDY_PARSING_FLAG
-    } else {
-        0
-    };
-    let mut comp_flags = NUM_PROBES[num_probes] | greedy;
+/// Match distances can not reach further back than the data encoded since the start
+/// of the stream or the last full flush, and by no more than the window, which is 32 KiB
+/// unless the compressor was created with a low memory level.
+/// An invalid token makes the compressor return `TDEFLStatus::BadParam`.
+///
+/// Token input should not be mixed with byte input on the same compressor without a reset.
+///
+/// # Returns
+/// Returns a tuple containing the current status of the compressor, the number of tokens
+/// consumed and the current position in the output buffer.
+pub fn compress_tokens(
+    d: &mut CompressorOxide,
+    tokens: &[LZToken],
+    out_buf: &mut [u8],
+    flush: TDEFLFlush,
+) -> (TDEFLStatus, usize, usize) {
+    compress_tokens_inner(
+        d,
+        &mut CallbackOxide::new_callback_buf(&[], out_buf),
+        tokens,
+        flush,
+    )
+}
 
-    if window_bits > 0 {
-        comp_flags |= TDEFL_WRITE_ZLIB_HEADER;
-    }
+/// Compress a stream of pre-parsed LZ77 tokens. Callbacks output.
+///
+/// See [`compress_tokens`](fn.compress_tokens.html) for details.
+///
+/// # Returns
+/// Returns a tuple containing the current status of the compressor, and the number of tokens
+/// consumed.
+pub fn compress_tokens_to_output(
+    d: &mut CompressorOxide,
+    tokens: &[LZToken],
+    flush: TDEFLFlush,
+    mut callback_func: impl FnMut(&[u8]) -> bool,
+) -> (TDEFLStatus, usize) {
+    let res = compress_tokens_inner(
+        d,
+        &mut CallbackOxide::new_callback_func(
+            &[],
+            CallbackFunc {
+                put_buf_func: &mut callback_func,
+            },
+        ),
+        tokens,
+        flush,
+    );
+
+    (res.0, res.1)
+}
+
+fn compress_tokens_inner(
+    d: &mut CompressorOxide,
+    callback: &mut CallbackOxide,
+    tokens: &[LZToken],
+    flush: TDEFLFlush,
+) -> (TDEFLStatus, usize, usize) {
+    if let Some(res) = start_compress(d, callback, flush) {
+        return res;
+    }
+
+    if !compress_lz_tokens(d, callback, tokens) {
+        return (
+            d.params.prev_return_status,
+            d.params.src_pos,
+            d.params.out_buf_ofs,
+        );
+    }
+
+    let in_left = tokens.len() - d.params.src_pos;
+    finish_compress(d, callback, in_left)
+}
+
+/// Record the tokens in the lz buffer, writing the bytes they represent to the dictionary
+/// so raw blocks and the checksum can be produced as usual.
+///
+/// `d.params.src_pos` is used to keep track of the number of tokens consumed.
+fn compress_lz_tokens(
+    d: &mut CompressorOxide,
+    callback: &mut CallbackOxide,
+    tokens: &[LZToken],
+) -> bool {
+    let window_mask = d.dict.window_mask;
+    let window_size = window_mask + 1;
+    let compute_adler = d.params.flags & (TDEFL_WRITE_ZLIB_HEADER | TDEFL_COMPUTE_ADLER32) != 0;
+    let mut token_pos = d.params.src_pos;
+
+    while token_pos < tokens.len() {
+        let pos = d.dict.lookahead_pos;
+        let len = match tokens[token_pos] {
+            LZToken::Literal(lit) => {
+                d.dict.write_byte(pos, lit);
+                record_literal(&mut d.huff, &mut d.lz, lit);
+                1
+            }
+            LZToken::Match { len, dist } => {
+                let (len, dist) = (usize::from(len), usize::from(dist));
+                if len < MIN_MATCH_LEN.into()
+                    || len > MAX_MATCH_LEN
+                    || dist == 0
+                    || dist > d.dict.size
+                {
+                    d.params.src_pos = token_pos;
+                    d.params.prev_return_status = TDEFLStatus::BadParam;
+                    return false;
+                }
+
+                for i in pos..pos + len {
+                    let c = d.dict.b.dict[(i - dist) & window_mask];
+                    d.dict.write_byte(i, c);
+                }
+                record_match(&mut d.huff, &mut d.lz, len as u32, dist as u32);
+                len
+            }
+            LZToken::EndOfBlock => 0,
+        };
+        token_pos += 1;
+
+        if compute_adler && len != 0 {
+            // The start of the dictionary is mirrored after the end, so the bytes can
+            // always be read as one slice.
+            let start = pos & window_mask;
+            d.params.adler32 = update_adler32(d.params.adler32, &d.dict.b.dict[start..start + len]);
+        }
+
+        d.dict.lookahead_pos += len;
+        d.dict.size = cmp::min(d.dict.size + len, window_size);
+
+        let end_of_block = len == 0;
+        let lz_buf_tight = d.lz.is_tight();
+        let raw = d.params.flags & TDEFL_FORCE_ALL_RAW_BLOCKS != 0;
+        let fat = ((d.lz.code_position * 115) >> 7) >= d.lz.total_bytes as usize;
+        let fat_or_raw = (d.lz.total_bytes as usize > window_size - 1024) && (fat || raw);
+
+        if end_of_block || lz_buf_tight || fat_or_raw {
+            d.params.src_pos = token_pos;
+            let n = match flush_block(d, callback, TDEFLFlush::None) {
+                Err(_) => {
+                    d.params.prev_return_status = TDEFLStatus::PutBufFailed;
+                    return false;
+                }
+                Ok(status) => status,
+            };
+            if n != 0 {
+                return n > 0;
+            }
+        }
+    }
+
+    d.params.src_pos = token_pos;
+    true
+}
+
+/// Get an upper bound on the size of the output when compressing `input_len` bytes with a
+/// compressor created from `flags` and buffers of `buffer_sizes`.
+///
+/// See [`CompressorOxide::compress_bound`](struct.CompressorOxide.html#method.compress_bound),
+/// which also accounts for custom huffman tables and rsyncable output.
+pub fn compress_bound_from_flags(input_len: usize, flags: u32, buffer_sizes: BufferSizes) -> usize {
+    let lz_codes = cmp::max(buffer_sizes.lz_codes, MIN_LZ_CODE_BUF_SIZE);
+    let window_size = cmp::max(buffer_sizes.window_size(), MIN_LZ_DICT_SIZE);
+    compress_bound_inner(input_len, flags, lz_codes, window_size, false, false)
+}
+
+fn compress_bound_inner(
+    input_len: usize,
+    flags: u32,
+    lz_codes: usize,
+    window_size: usize,
+    custom_tables: bool,
+    rsyncable: bool,
+) -> usize {
+    let wrapper = if flags & TDEFL_WRITE_ZLIB_HEADER != 0 {
+        6
+    } else {
+        0
+    };
+
+    if cfg!(feature = "zlib-compat") && flags & TDEFL_ZLIB_COMPATIBLE_FLAG != 0 {
+        // The bound zlib's `deflateBound` gives for its default settings, which are the ones
+        // the compatible mode uses.
+        let overhead = (input_len >> 12) + (input_len >> 14) + (input_len >> 25) + 7;
+        return input_len.saturating_add(overhead + wrapper);
+    }
+
+    // Blocks end when the lz code buffer is full, which takes at least 8 bytes of input for
+    // every 9 bytes of codes, or 1 KiB short of the window for input that doesn't compress.
+    // A block that would be larger than storing the data takes 5 bytes on top of the data
+    // instead, apart from a final block of up to 32 bytes, which can take one more.
+    let literals_per_block = (lz_codes - 9) / 9 * 8;
+    let mut blocks = input_len / cmp::min(window_size - 1024, literals_per_block) + 1;
+    if rsyncable {
+        // Each chunk ends the block early, and the full flush after it adds an empty
+        // stored block.
+        blocks += 2 * (input_len / RSYNC_MIN_CHUNK);
+    }
+    let mut overhead = blocks * 5 + 1 + wrapper;
+
+    // With matches, blocks can get larger than the dictionary, so the data is no longer
+    // around to store it if that would be smaller.
+    if flags & TDEFL_FORCE_ALL_RAW_BLOCKS == 0 && lz_codes >= window_size {
+        overhead += if flags & TDEFL_FORCE_ALL_STATIC_BLOCKS != 0 {
+            // Static codes take at most 9 bits per byte.
+            (input_len >> 3) + 1
+        } else if custom_tables {
+            // Custom codes can be up to 15 bits long, with up to 16 bits per byte for matches.
+            input_len
+        } else {
+            // Dynamic codes for data that doesn't compress are around 0.1% larger than the
+            // data, as some literals get 9 bit codes to make room for the other symbols.
+            input_len >> 8
+        };
+    }
+
+    input_len.saturating_add(overhead)
+}
+
+/// Create a set of compression flags using parameters used by zlib and other compressors.
+/// Mainly intented for use with transition from c libraries as it deals with raw integers.
+///
+/// # Parameters
+/// `level` determines compression level. Clamped to maximum of 10. Negative values result in
+/// `Compressionlevel::DefaultLevel`.
+/// `window_bits`: Above 0, wraps the stream in a zlib wrapper, 0 or negative for a raw deflate
+/// stream.
+/// `strategy`: Sets the strategy if this conforms to any of the values in `CompressionStrategy`.
+///
+/// # Notes
+/// This function may be removed or moved to the `miniz_oxide_c_api` in the future.
+pub fn create_comp_flags_from_zip_params(level: i32, window_bits: i32, strategy: i32) -> u32 {
+    let num_probes = (if level >= 0 {
+        cmp::min(10, level)
+    } else {
+        CompressionLevel::DefaultLevel as i32
+    }) as usize;
+    let greedy = if level <= 3 {
+        TDEFL_GREEDY_PARSING_FLAG
+    } else {
+        0
+    };
+    let mut comp_flags = NUM_PROBES[num_probes] | greedy;
+
+    if window_bits > 0 {
+        comp_flags |= TDEFL_WRITE_ZLIB_HEADER;
+    }
 
     if level == 0 {
         comp_flags |= TDEFL_FORCE_ALL_RAW_BLOCKS;
@@ -2349,14 +4233,269 @@ pub fn create_comp_flags_from_zip_params(level: i32, window_bits: i32, strategy:
     comp_flags
 }
 
-#[cfg(test)]
+/// Settings for a [`CompressorOxide`](struct.CompressorOxide.html), as an alternative to
+/// putting together the flags in [`deflate_flags`](deflate_flags/index.html) by hand.
+///
+/// The defaults are level 6, the default strategy, the zlib format, a 32 KiB window and the
+/// largest buffers. Combinations of settings that contradict each other, like a strategy at
+/// level 0, where the data is only stored, are rejected with `MZError::Param` by
+/// [`flags`](#method.flags), [`build`](#method.build) and [`configure`](#method.configure).
+///
+/// ```
+/// use miniz_oxide::deflate::core::{CompressionOptions, CompressionStrategy};
+/// use miniz_oxide::DataFormat;
+///
+/// let compressor = CompressionOptions::new()
+///     .level(9)
+///     .strategy(CompressionStrategy::Filtered)
+///     .format(DataFormat::Raw)
+///     .build()
+///     .unwrap();
+/// # let _ = compressor;
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CompressionOptions<'a> {
+    level: u8,
+    strategy: CompressionStrategy,
+    format: DataFormat,
+    window_bits: u8,
+    greedy_parsing: Option<bool>,
+    max_probes: Option<u32>,
+    dictionary: Option<&'a [u8]>,
+    mem_level: Option<u8>,
+    stable_output: bool,
+    zlib_compatible: bool,
+    binary_tree_matches: bool,
+}
+
+impl<'a> CompressionOptions<'a> {
+    /// Create the default settings.
+    pub fn new() -> Self {
+        CompressionOptions {
+            level: CompressionLevel::DefaultLevel as u8,
+            strategy: CompressionStrategy::Default,
+            format: DataFormat::Zlib,
+            window_bits: MZ_DEFAULT_WINDOW_BITS as u8,
+            greedy_parsing: None,
+            max_probes: None,
+            dictionary: None,
+            mem_level: None,
+            stable_output: false,
+            zlib_compatible: false,
+            binary_tree_matches: false,
+        }
+    }
+
+    /// Set the compression level, from 0 (no compression) to 10.
+    pub fn level(mut self, level: u8) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Set the compression strategy. Has to be the default one at level 0.
+    pub fn strategy(mut self, strategy: CompressionStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Set whether to output a zlib or a raw deflate stream.
+    pub fn format(mut self, format: DataFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set the base two logarithm of the window size, like the `windowBits` parameter of zlib
+    /// without the sign or offset for the format. Only 15, a 32 KiB window, is supported.
+    pub fn window_bits(mut self, window_bits: u8) -> Self {
+        self.window_bits = window_bits;
+        self
+    }
+
+    /// Use greedy parsing, which takes the first match found, or lazy parsing, which also
+    /// checks if the next byte starts a longer match, instead of the one picked by the level.
+    ///
+    /// Can't be used when no matches are searched for, i.e at level 0 or with the
+    /// `HuffmanOnly` strategy, or in zlib compatible mode.
+    pub fn greedy_parsing(mut self, greedy: bool) -> Self {
+        self.greedy_parsing = Some(greedy);
+        self
+    }
+
+    /// Set how many entries of the hash chains are checked when looking for a match
+    /// (at most 4095), instead of the number picked by the level.
+    /// See [`CompressorOxide::set_max_probes`](struct.CompressorOxide.html#method.set_max_probes).
+    ///
+    /// Can't be used when no matches are searched for, i.e at level 0 or with the
+    /// `HuffmanOnly` strategy, with the `RLE` strategy, which only checks one position, or in
+    /// zlib compatible mode.
+    pub fn max_probes(mut self, max_probes: u32) -> Self {
+        self.max_probes = Some(max_probes);
+        self
+    }
+
+    /// Set a preset dictionary.
+    /// See [`CompressorOxide::set_dictionary`](struct.CompressorOxide.html#method.set_dictionary).
+    ///
+    /// Only supported for the raw format and not in zlib compatible mode.
+    pub fn dictionary(mut self, dictionary: &'a [u8]) -> Self {
+        self.dictionary = Some(dictionary);
+        self
+    }
+
+    /// Set the memory level, from 1 to 9, which determines the size of the hash table and
+    /// the maximum size of the blocks.
+    /// See [`BufferSizes::for_mem_level`](struct.BufferSizes.html#method.for_mem_level).
+    pub fn mem_level(mut self, mem_level: u8) -> Self {
+        self.mem_level = Some(mem_level);
+        self
+    }
+
+    /// Make the output stay the same across versions of this crate.
+    /// See [`CompressorOxide::set_stable_output`](struct.CompressorOxide.html#method.set_stable_output).
+    ///
+    /// Can't be combined with zlib compatible mode.
+    pub fn stable_output(mut self, stable: bool) -> Self {
+        self.stable_output = stable;
+        self
+    }
+
+    /// Make the output the same as that of zlib's `deflate`.
+    /// See [`CompressorOxide::set_zlib_compatible`](struct.CompressorOxide.html#method.set_zlib_compatible).
+    #[cfg(feature = "zlib-compat")]
+    pub fn zlib_compatible(mut self, compatible: bool) -> Self {
+        self.zlib_compatible = compatible;
+        self
+    }
+
+    /// Look for matches in binary trees instead of hash chains.
+    /// See [`TDEFL_BINARY_TREE_MATCHES`](deflate_flags/constant.TDEFL_BINARY_TREE_MATCHES.html).
+    ///
+    /// Can't be combined with stable output or zlib compatible mode.
+    pub fn binary_tree_matches(mut self, binary_tree: bool) -> Self {
+        self.binary_tree_matches = binary_tree;
+        self
+    }
+
+    /// Get the compressor flags for these settings.
+    ///
+    /// The memory level and dictionary are not part of the flags.
+    ///
+    /// # Errors
+    /// Returns `MZError::Param` if a setting is out of range or the settings contradict each
+    /// other, as described for the individual settings.
+    pub fn flags(&self) -> Result<u32, MZError> {
+        let no_matches = self.level == 0 || self.strategy == CompressionStrategy::HuffmanOnly;
+        let parsing_set = self.greedy_parsing.is_some() || self.max_probes.is_some();
+        if self.level > CompressionLevel::UberCompression as u8
+            || i32::from(self.window_bits) != MZ_DEFAULT_WINDOW_BITS
+            || self
+                .max_probes
+                .map_or(false, |probes| probes > MAX_PROBES_MASK as u32)
+            || self
+                .mem_level
+                .map_or(false, |level| level < 1 || level > MAX_MEM_LEVEL)
+            || (self.level == 0 && self.strategy != CompressionStrategy::Default)
+            || (parsing_set && (no_matches || self.zlib_compatible))
+            || (self.max_probes.is_some() && self.strategy == CompressionStrategy::RLE)
+            || (self.dictionary.is_some()
+                && (self.format == DataFormat::Zlib || self.zlib_compatible))
+            || (self.stable_output && self.zlib_compatible)
+            || (self.binary_tree_matches && (self.stable_output || self.zlib_compatible))
+        {
+            return Err(MZError::Param);
+        }
+
+        let mut flags = create_comp_flags_from_zip_params(
+            self.level.into(),
+            self.format.to_window_bits(),
+            self.strategy as i32,
+        );
+        if let Some(max_probes) = self.max_probes {
+            flags = (flags & !(MAX_PROBES_MASK as u32)) | max_probes;
+        }
+        match self.greedy_parsing {
+            Some(true) => flags |= TDEFL_GREEDY_PARSING_FLAG,
+            Some(false) => flags &= !TDEFL_GREEDY_PARSING_FLAG,
+            None => (),
+        }
+        if self.stable_output {
+            flags |= TDEFL_STABLE_OUTPUT_FLAG;
+        }
+        if self.zlib_compatible {
+            flags |= TDEFL_ZLIB_COMPATIBLE_FLAG;
+        }
+        if self.binary_tree_matches {
+            flags |= TDEFL_BINARY_TREE_MATCHES;
+        }
+        Ok(flags)
+    }
+
+    /// Create a compressor with these settings.
+    ///
+    /// # Errors
+    /// Returns `MZError::Param` in the same cases as [`flags`](#method.flags).
+    #[cfg(feature = "alloc")]
+    pub fn build(&self) -> Result<CompressorOxide<'static>, MZError> {
+        let flags = self.flags()?;
+        let mut d = CompressorOxide::with_mem_level(flags, self.mem_level.unwrap_or(MAX_MEM_LEVEL));
+        if let Some(dictionary) = self.dictionary {
+            d.set_dictionary(dictionary)?;
+        }
+        Ok(d)
+    }
+
+    /// Reset `d` and apply these settings to it, e.g for a compressor created with
+    /// [`CompressorOxide::with_buffers`](struct.CompressorOxide.html#method.with_buffers).
+    ///
+    /// Settings that are not covered, like custom huffman tables or rsyncable mode, are kept.
+    ///
+    /// # Errors
+    /// Returns `MZError::Param` in the same cases as [`flags`](#method.flags), and if a memory
+    /// level is set that doesn't match the buffer sizes of `d`.
+    pub fn configure(&self, d: &mut CompressorOxide) -> Result<(), MZError> {
+        let flags = self.flags()?;
+        if let Some(mem_level) = self.mem_level {
+            if BufferSizes::for_mem_level(mem_level) != d.buffer_sizes() {
+                return Err(MZError::Param);
+            }
+        }
+        d.reset();
+        d.params.update_flags(flags);
+        d.dict.update_flags(flags);
+        if let Some(dictionary) = self.dictionary {
+            d.set_dictionary(dictionary)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Default for CompressionOptions<'a> {
+    fn default() -> Self {
+        CompressionOptions::new()
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
 mod test {
     use super::{
-        compress_to_output, create_comp_flags_from_zip_params, read_u16_le, write_u16_le,
-        CompressionStrategy, CompressorOxide, TDEFLFlush, TDEFLStatus, DEFAULT_FLAGS,
-        MZ_DEFAULT_WINDOW_BITS,
+        compress, compress_to_output, compress_tokens, compress_tokens_to_output,
+        create_comp_flags_from_zip_params, read_u16_le, write_u16_le, BufferSizes,
+        CompressionOptions, CompressionStrategy, CompressorBuffers, CompressorOxide, MatchFinder,
+        TDEFLFlush, TDEFLStatus, ADAPTIVE_RAW_BLOCK_SIZE, DEFAULT_FLAGS, LZ_DICT_SIZE,
+        MAX_ADAPTIVE_RAW_BLOCKS, MAX_MEM_LEVEL, MAX_PROBES_MASK, MZ_DEFAULT_WINDOW_BITS,
+    };
+    use super::{looks_incompressible, HuffmanOxide};
+    use crate::deflate::core::deflate_flags::{
+        TDEFL_ADAPTIVE_RAW_BLOCKS, TDEFL_BINARY_TREE_MATCHES, TDEFL_GREEDY_PARSING_FLAG,
+        TDEFL_STABLE_OUTPUT_FLAG, TDEFL_ZLIB_COMPATIBLE_FLAG,
+    };
+    use crate::inflate::core::{
+        decompress_with_tokens, decompress_with_visitor, inflate_flags, DecompressorOxide,
+        InflateVisitor,
     };
-    use crate::inflate::decompress_to_vec;
+    use crate::inflate::{decompress_to_vec, decompress_to_vec_zlib, TINFLStatus};
+    use crate::test_util::{compress_chunked, random_data, test_data, Lcg};
+    use crate::{BlockType, DataFormat, LZToken, MZError};
     use std::prelude::v1::*;
     use std::vec;
 
@@ -2402,4 +4541,1314 @@ mod test {
         let decoded = decompress_to_vec(&encoded[..]).unwrap();
         assert_eq!(&decoded[..], &slice[..]);
     }
+
+    #[test]
+    fn compress_tokens_roundtrip() {
+        let mut tokens: Vec<LZToken> = b"abcd".iter().map(|&b| LZToken::Literal(b)).collect();
+        tokens.push(LZToken::Match { len: 10, dist: 3 });
+        tokens.push(LZToken::EndOfBlock);
+        tokens.push(LZToken::Literal(b'x'));
+        tokens.push(LZToken::Match { len: 258, dist: 15 });
+        tokens.push(LZToken::EndOfBlock);
+
+        let mut expected = b"abcdbcdbcdbcdb".to_vec();
+        expected.push(b'x');
+        for _ in 0..258 {
+            let b = expected[expected.len() - 15];
+            expected.push(b);
+        }
+
+        let flags = create_comp_flags_from_zip_params(6, MZ_DEFAULT_WINDOW_BITS, 0);
+        let mut d = CompressorOxide::new(flags);
+        let mut encoded = vec![];
+        let (status, consumed) =
+            compress_tokens_to_output(&mut d, &tokens, TDEFLFlush::Finish, |out: &[u8]| {
+                encoded.extend_from_slice(out);
+                true
+            });
+        assert_eq!(status, TDEFLStatus::Done);
+        assert_eq!(consumed, tokens.len());
+
+        let decoded = decompress_to_vec_zlib(&encoded).unwrap();
+        assert_eq!(decoded, expected);
+
+        // Decoding the token stream again should give back the same tokens, with an
+        // extra empty block at the end from finishing the stream.
+        let mut decoded_tokens = vec![];
+        let mut out = vec![0; expected.len()];
+        let flags = inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
+            | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+        let res = decompress_with_tokens(
+            &mut DecompressorOxide::new(),
+            &encoded,
+            &mut out,
+            0,
+            flags,
+            |token| decoded_tokens.push(token),
+        );
+        assert_eq!(res, (TINFLStatus::Done, encoded.len(), expected.len()));
+        tokens.push(LZToken::EndOfBlock);
+        assert_eq!(decoded_tokens, tokens);
+    }
+
+    #[test]
+    fn compress_tokens_bad_distance() {
+        let tokens = [
+            LZToken::Literal(1),
+            LZToken::Literal(2),
+            LZToken::Match { len: 3, dist: 3 },
+        ];
+        let mut d = CompressorOxide::new(0);
+        let mut out = [0; 64];
+        let (status, consumed, _) = compress_tokens(&mut d, &tokens, &mut out, TDEFLFlush::Finish);
+        assert_eq!(status, TDEFLStatus::BadParam);
+        assert_eq!(consumed, 2);
+    }
+
+    /// Collects the code lengths of the litlen table of each block.
+    struct LitlenTables(Vec<Vec<u8>>);
+
+    impl InflateVisitor for LitlenTables {
+        fn huffman_tables(&mut self, litlen_code_sizes: &[u8], _dist_code_sizes: &[u8]) {
+            self.0.push(litlen_code_sizes.to_vec());
+        }
+    }
+
+    fn custom_tables() -> (Vec<u8>, Vec<u8>) {
+        // Short codes for the literals 0-15, as that's what `test_data` uses:
+        // 16 * 2^-5 + 242 * 2^-9 + 28 * 2^-10 = 1
+        let mut litlen = vec![5; 16];
+        litlen.resize(258, 9);
+        litlen.resize(286, 10);
+        // 2 * 2^-4 + 28 * 2^-5 = 1
+        let mut dist = vec![4; 2];
+        dist.resize(30, 5);
+        (litlen, dist)
+    }
+
+    fn compress_with_custom_tables(data: &[u8], litlen: &[u8], dist: &[u8]) -> Vec<u8> {
+        let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(6, 15, 0));
+        d.set_custom_huffman_tables(litlen, dist).unwrap();
+        let mut encoded = vec![];
+        let (status, _) = compress_to_output(&mut d, data, TDEFLFlush::Finish, |out: &[u8]| {
+            encoded.extend_from_slice(out);
+            true
+        });
+        assert_eq!(status, TDEFLStatus::Done);
+        encoded
+    }
+
+    fn litlen_tables(encoded: &[u8], expected_len: usize) -> Vec<Vec<u8>> {
+        let mut tables = LitlenTables(vec![]);
+        let mut out = vec![0; expected_len];
+        let flags = inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
+            | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+        let res = decompress_with_visitor(
+            &mut DecompressorOxide::new(),
+            encoded,
+            &mut out,
+            0,
+            flags,
+            &mut tables,
+        );
+        assert_eq!(res, (TINFLStatus::Done, encoded.len(), expected_len));
+        tables.0
+    }
+
+    #[test]
+    fn custom_huffman_tables() {
+        let (litlen, dist) = custom_tables();
+        let data = test_data(300_000);
+        let encoded = compress_with_custom_tables(&data, &litlen, &dist);
+        assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), data);
+
+        let tables = litlen_tables(&encoded, data.len());
+        assert!(tables.len() > 1);
+        assert!(tables.iter().all(|table| *table == litlen));
+
+        // The tables should be kept when resetting the compressor.
+        let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(6, 15, 0));
+        d.set_custom_huffman_tables(&litlen, &dist).unwrap();
+        d.reset();
+        assert!(d.huff.has_custom_code_sizes());
+        d.clear_custom_huffman_tables();
+        assert!(!d.huff.has_custom_code_sizes());
+    }
+
+    #[test]
+    fn custom_huffman_tables_missing_symbol() {
+        // Take the code for the literal 1 and give it to 0 instead.
+        let (mut litlen, dist) = custom_tables();
+        litlen[0] = 4;
+        litlen[1] = 0;
+
+        // The data contains the literal 1, so optimized tables have to be used instead.
+        let data = test_data(1000);
+        let encoded = compress_with_custom_tables(&data, &litlen, &dist);
+        assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), data);
+        assert_ne!(litlen_tables(&encoded, data.len()), [litlen.clone()]);
+
+        // But they are used if the data only contains symbols that are in the table.
+        let data: Vec<u8> = data.into_iter().filter(|&b| b != 1).collect();
+        let encoded = compress_with_custom_tables(&data, &litlen, &dist);
+        assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), data);
+        assert_eq!(litlen_tables(&encoded, data.len()), [litlen]);
+    }
+
+    #[test]
+    fn custom_huffman_tables_invalid() {
+        let (litlen, dist) = custom_tables();
+        let mut d = CompressorOxide::default();
+        assert_eq!(d.set_custom_huffman_tables(&litlen, &dist), Ok(()));
+
+        // Incomplete code.
+        let mut bad = litlen.clone();
+        bad[0] = 6;
+        assert_eq!(
+            d.set_custom_huffman_tables(&bad, &dist),
+            Err(MZError::Param)
+        );
+        // Code too long.
+        let mut bad = vec![0; 257];
+        bad[0] = 16;
+        bad[256] = 1;
+        assert_eq!(
+            d.set_custom_huffman_tables(&bad, &dist),
+            Err(MZError::Param)
+        );
+        // No end of block code.
+        let mut bad = vec![0; 257];
+        bad[0] = 1;
+        assert_eq!(
+            d.set_custom_huffman_tables(&bad, &dist),
+            Err(MZError::Param)
+        );
+        // A single code has to have a length of 1, or zlib's inflate rejects the stream.
+        let mut single = vec![0; 257];
+        single[256] = 1;
+        assert_eq!(d.set_custom_huffman_tables(&single, &[0, 1]), Ok(()));
+        assert_eq!(d.set_custom_huffman_tables(&litlen, &[]), Ok(()));
+        single[256] = 2;
+        assert_eq!(
+            d.set_custom_huffman_tables(&single, &dist),
+            Err(MZError::Param)
+        );
+        assert_eq!(
+            d.set_custom_huffman_tables(&litlen, &[0, 0, 5]),
+            Err(MZError::Param)
+        );
+        // Too many distance codes.
+        assert_eq!(
+            d.set_custom_huffman_tables(&litlen, &[5; 32]),
+            Err(MZError::Param)
+        );
+        // The previous valid tables are kept.
+        assert!(d.huff.has_custom_code_sizes());
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn compression_stats() {
+        use crate::deflate::stats::MAX_RECORDED_BLOCKS;
+        use core::sync::atomic::{AtomicU64, Ordering};
+
+        /// Records the size of each block in bits.
+        struct BlockSizes(Vec<(BlockType, u64)>);
+
+        impl InflateVisitor for BlockSizes {
+            fn block_header(&mut self, bit_pos: u64, _is_final: bool, block_type: BlockType) {
+                self.0.push((block_type, bit_pos));
+            }
+            fn block_end(&mut self, bit_pos: u64) {
+                let block = self.0.last_mut().unwrap();
+                block.1 = bit_pos - block.1;
+            }
+        }
+
+        static TIME: AtomicU64 = AtomicU64::new(0);
+        fn timer() -> u64 {
+            TIME.fetch_add(1, Ordering::Relaxed)
+        }
+
+        let data = test_data(300_000);
+        for &level in &[0, 1, 6] {
+            let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(level, 15, 0));
+            d.set_stats_timer(Some(timer));
+            let mut encoded = vec![];
+            let (half, rest) = data.split_at(data.len() / 2);
+            for &(input, flush) in &[(half, TDEFLFlush::Sync), (rest, TDEFLFlush::Finish)] {
+                let (status, _) = compress_to_output(&mut d, input, flush, |out: &[u8]| {
+                    encoded.extend_from_slice(out);
+                    true
+                });
+                let expected = if flush == TDEFLFlush::Finish {
+                    TDEFLStatus::Done
+                } else {
+                    TDEFLStatus::Okay
+                };
+                assert_eq!(status, expected);
+            }
+
+            let stats = d.stats();
+            let matched: u64 = (3..259)
+                .map(|len| stats.match_lengths[len] * len as u64)
+                .sum();
+            assert_eq!(stats.literals + matched, data.len() as u64);
+            assert_eq!(
+                stats.matches,
+                stats.match_distance_codes.iter().sum::<u64>()
+            );
+            let input_bytes: u64 = stats.blocks.iter().map(|b| u64::from(b.input_bytes)).sum();
+            assert_eq!(input_bytes, data.len() as u64);
+            if level == 0 {
+                assert_eq!(
+                    stats.num_blocks(BlockType::Stored),
+                    stats.blocks.len() as u64
+                );
+                assert_eq!(stats.matches, 0);
+            } else {
+                assert!(stats.num_blocks(BlockType::Dynamic) > 1);
+                assert!(stats.matches > 0);
+                assert!(stats.match_finder_time > 0);
+            }
+
+            let mut sizes = BlockSizes(vec![]);
+            let mut out = vec![0; data.len()];
+            let flags = inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
+                | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+            let res = decompress_with_visitor(
+                &mut DecompressorOxide::new(),
+                &encoded,
+                &mut out,
+                0,
+                flags,
+                &mut sizes,
+            );
+            assert_eq!(res.0, TINFLStatus::Done);
+            let blocks: Vec<_> = stats
+                .blocks
+                .iter()
+                .map(|b| (b.block_type, b.output_bits))
+                .collect();
+            assert_eq!(blocks, sizes.0);
+
+            d.reset_stats();
+            assert!(d.stats().blocks.is_empty());
+            assert_eq!(d.stats().literals, 0);
+        }
+
+        // Only the most recent blocks are kept, but all of them are counted.
+        let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(6, 15, 0));
+        for chunk in data[..6000].chunks(10) {
+            compress_to_output(&mut d, chunk, TDEFLFlush::Sync, |_| true);
+        }
+        let stats = d.stats();
+        assert_eq!(stats.blocks.len(), MAX_RECORDED_BLOCKS);
+        assert_eq!(stats.num_blocks(BlockType::Stored), 600);
+        assert_eq!(
+            stats.num_blocks(BlockType::Static) + stats.num_blocks(BlockType::Dynamic),
+            600
+        );
+        assert_eq!(stats.blocks.back().unwrap().input_bytes, 0);
+        d.reset();
+        assert!(d.stats().blocks.is_empty());
+        assert_eq!(d.stats().num_blocks(BlockType::Stored), 0);
+    }
+
+    /// Records the type of each block.
+    struct BlockTypes(Vec<BlockType>);
+
+    impl InflateVisitor for BlockTypes {
+        fn block_header(&mut self, _bit_pos: u64, _is_final: bool, block_type: BlockType) {
+            self.0.push(block_type);
+        }
+    }
+
+    #[test]
+    fn adaptive_raw_blocks() {
+        let text = include_bytes!("../../tests/test_data/numbers.txt");
+        let mut data = text[..40_000].to_vec();
+        data.extend_from_slice(&random_data(500_000));
+        // Enough to get past the longest run of raw blocks.
+        while data.len() < 540_000 + (MAX_ADAPTIVE_RAW_BLOCKS * ADAPTIVE_RAW_BLOCK_SIZE) as usize {
+            data.extend_from_slice(&text[..]);
+        }
+
+        let flags = create_comp_flags_from_zip_params(6, 15, 0) | TDEFL_ADAPTIVE_RAW_BLOCKS;
+        let mut d = CompressorOxide::new(flags);
+        let encoded = compress_chunked(&mut d, &data, data.len(), TDEFLFlush::None);
+        assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), data);
+
+        let mut block_types = BlockTypes(vec![]);
+        let mut out = vec![0; data.len()];
+        let res = decompress_with_visitor(
+            &mut DecompressorOxide::new(),
+            &encoded,
+            &mut out,
+            0,
+            inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
+                | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+            &mut block_types,
+        );
+        assert_eq!(res.0, TINFLStatus::Done);
+        let block_types = block_types.0;
+        // Compressed, then stored for the random data, then compressed again.
+        assert_eq!(block_types[0], BlockType::Dynamic);
+        assert_eq!(*block_types.last().unwrap(), BlockType::Dynamic);
+        let stored = block_types
+            .iter()
+            .filter(|&&block_type| block_type == BlockType::Stored)
+            .count();
+        assert!(stored >= 500_000 / ADAPTIVE_RAW_BLOCK_SIZE as usize);
+
+        // Incompressible data is stored either way, so the output should be about the same
+        // size as without skipping matches.
+        let data = random_data(500_000);
+        let mut d = CompressorOxide::new(flags);
+        let encoded = compress_chunked(&mut d, &data, data.len(), TDEFLFlush::None);
+        let mut d = CompressorOxide::new(flags & !TDEFL_ADAPTIVE_RAW_BLOCKS);
+        let normal = compress_chunked(&mut d, &data, data.len(), TDEFLFlush::None);
+        assert!(encoded.len() <= normal.len() + 100);
+    }
+
+    #[test]
+    fn adaptive_raw_blocks_chunked() {
+        let mut data = random_data(100_000);
+        data.extend_from_slice(&test_data(100_000));
+        data.extend_from_slice(&random_data(100_000));
+
+        let flags = create_comp_flags_from_zip_params(9, -15, 0) | TDEFL_ADAPTIVE_RAW_BLOCKS;
+        for &(chunk_size, flush) in &[
+            (1000, TDEFLFlush::None),
+            (7919, TDEFLFlush::Sync),
+            (40_000, TDEFLFlush::Full),
+        ] {
+            let mut d = CompressorOxide::new(flags);
+            let encoded = compress_chunked(&mut d, &data, chunk_size, flush);
+            assert_eq!(decompress_to_vec(&encoded).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn adaptive_raw_blocks_mem_levels() {
+        let data = random_data(200_000);
+        for mem_level in 1..=MAX_MEM_LEVEL {
+            let flags = create_comp_flags_from_zip_params(6, 15, 0) | TDEFL_ADAPTIVE_RAW_BLOCKS;
+            let mut d = CompressorOxide::with_mem_level(flags, mem_level);
+            let encoded = compress_chunked(&mut d, &data, data.len(), TDEFLFlush::None);
+            assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), data);
+            let mut d =
+                CompressorOxide::with_mem_level(flags & !TDEFL_ADAPTIVE_RAW_BLOCKS, mem_level);
+            let normal = compress_chunked(&mut d, &data, data.len(), TDEFLFlush::None);
+            assert!(encoded.len() <= normal.len());
+        }
+    }
+
+    #[test]
+    fn adaptive_raw_blocks_sample() {
+        let text = include_bytes!("../../tests/test_data/numbers.txt");
+        assert!(!looks_incompressible_data(&text[..8192]));
+        assert!(!looks_incompressible_data(&test_data(8192)));
+        assert!(looks_incompressible_data(&random_data(8192)));
+
+        // Data that doesn't compress is noticed part-way through the first block.
+        let flags = create_comp_flags_from_zip_params(6, 15, 0) | TDEFL_ADAPTIVE_RAW_BLOCKS;
+        let mut d = CompressorOxide::new(flags);
+        let mut out = vec![0; 20_000];
+        compress(&mut d, &random_data(8192), &mut out, TDEFLFlush::None);
+        assert!(d.params.raw_blocks_left > 0);
+        let mut d = CompressorOxide::new(flags);
+        compress(&mut d, &text[..8192], &mut out, TDEFLFlush::None);
+        assert_eq!(d.params.raw_blocks_left, 0);
+
+        // Level 1 doesn't skip any data.
+        let data = random_data(200_000);
+        let flags = create_comp_flags_from_zip_params(1, 15, 0);
+        let expected = compress_chunked(
+            &mut CompressorOxide::new(flags),
+            &data,
+            data.len(),
+            TDEFLFlush::None,
+        );
+        let mut d = CompressorOxide::new(flags | TDEFL_ADAPTIVE_RAW_BLOCKS);
+        let encoded = compress_chunked(&mut d, &data, data.len(), TDEFLFlush::None);
+        assert_eq!(encoded, expected);
+
+        // Switching to level 1 while skipping finishes the skipped blocks first.
+        let flags = create_comp_flags_from_zip_params(6, 15, 0) | TDEFL_ADAPTIVE_RAW_BLOCKS;
+        let mut d = CompressorOxide::new(flags);
+        let mut encoded = vec![];
+        for (i, chunk) in data.chunks(10_000).enumerate() {
+            if i == 2 {
+                d.set_compression_level_raw(1);
+            }
+            compress_to_output(&mut d, chunk, TDEFLFlush::None, |out: &[u8]| {
+                encoded.extend_from_slice(out);
+                true
+            });
+        }
+        compress_to_output(&mut d, &[], TDEFLFlush::Finish, |out: &[u8]| {
+            encoded.extend_from_slice(out);
+            true
+        });
+        assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn adaptive_raw_blocks_mid_block() {
+        // Data that stops compressing part-way through a block, before and after the sample
+        // is taken, and close to the end of the block.
+        let text = include_bytes!("../../tests/test_data/numbers.txt");
+        let flags = create_comp_flags_from_zip_params(6, 15, 0) | TDEFL_ADAPTIVE_RAW_BLOCKS;
+        for &split in &[100, 3000, 5000, 20_000, 31 * 1024 - 10] {
+            let mut data = text[..split].to_vec();
+            data.extend_from_slice(&random_data(80_000));
+            for mem_level in 1..=MAX_MEM_LEVEL {
+                for &chunk_size in &[data.len(), 1000, 61] {
+                    let mut d = CompressorOxide::with_mem_level(flags, mem_level);
+                    let mut encoded = vec![];
+                    let mut skipped = false;
+                    for chunk in data.chunks(chunk_size) {
+                        compress_to_output(&mut d, chunk, TDEFLFlush::None, |out: &[u8]| {
+                            encoded.extend_from_slice(out);
+                            true
+                        });
+                        skipped |= d.params.raw_blocks_left > 0;
+                    }
+                    compress_to_output(&mut d, &[], TDEFLFlush::Finish, |out: &[u8]| {
+                        encoded.extend_from_slice(out);
+                        true
+                    });
+                    // Only seen between calls, a single call can finish the skipped blocks.
+                    assert!(skipped || chunk_size == data.len());
+                    assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), data);
+                }
+            }
+        }
+    }
+
+    /// Check the estimate of `TDEFL_ADAPTIVE_RAW_BLOCKS` for `data` as literals.
+    fn looks_incompressible_data(data: &[u8]) -> bool {
+        let mut h = HuffmanOxide::default();
+        for &b in data {
+            h.count[0][usize::from(b)] += 1;
+        }
+        looks_incompressible(&h, data.len() as u32)
+    }
+
+    #[test]
+    fn compressor_with_buffers() {
+        let data = test_data(200_000);
+        let flags = create_comp_flags_from_zip_params(6, 15, 0);
+        let expected = compress_chunked(
+            &mut CompressorOxide::new(flags),
+            &data,
+            10_000,
+            TDEFLFlush::None,
+        );
+
+        // Dirty the buffers first to check that they are reset.
+        let sizes = BufferSizes::for_mem_level(MAX_MEM_LEVEL);
+        let mut buffers = Box::<CompressorBuffers>::default();
+        let mut dict = vec![0xff; sizes.dict];
+        let mut next = vec![7; sizes.next];
+        let mut hash_table = vec![7; sizes.hash_table];
+        let mut lz_codes = vec![0; sizes.lz_codes];
+        let mut out_buf = vec![0; sizes.out_buf];
+        let mut d = CompressorOxide::with_buffers(
+            flags,
+            &mut buffers,
+            &mut dict,
+            &mut next,
+            &mut hash_table,
+            &mut lz_codes,
+            &mut out_buf,
+        )
+        .unwrap();
+        assert_eq!(d.buffer_sizes(), sizes);
+        let encoded = compress_chunked(&mut d, &data, 10_000, TDEFLFlush::None);
+        assert_eq!(encoded, expected);
+        d.reset();
+        let encoded = compress_chunked(&mut d, &data, 10_000, TDEFLFlush::None);
+        assert_eq!(encoded, expected);
+
+        // The buffers can be handed over to the compressor instead.
+        let mut d = CompressorOxide::with_owned_buffers(
+            flags,
+            Box::<CompressorBuffers>::default(),
+            vec![0xff; sizes.dict],
+            vec![7; sizes.next],
+            vec![7; sizes.hash_table],
+            vec![0; sizes.lz_codes],
+            vec![0; sizes.out_buf],
+        )
+        .unwrap();
+        assert_eq!(d.buffer_sizes(), sizes);
+        let encoded = compress_chunked(&mut d, &data, 10_000, TDEFLFlush::None);
+        assert_eq!(encoded, expected);
+    }
+
+    #[test]
+    fn compressor_without_alloc() {
+        // The buffers a caller without a heap would keep on the stack or in statics.
+        let sizes = BufferSizes::MAX;
+        assert_eq!(sizes, BufferSizes::for_mem_level(MAX_MEM_LEVEL));
+        let mut buffers = CompressorBuffers::new();
+        let mut dict = [0; BufferSizes::MAX.dict];
+        let mut next = [0; BufferSizes::MAX.next];
+        let mut hash_table = [0; BufferSizes::MAX.hash_table];
+        let mut lz_codes = [0; BufferSizes::MAX.lz_codes];
+        let mut out_buf = [0; BufferSizes::MAX.out_buf];
+
+        let data = test_data(100_000);
+        let flags = create_comp_flags_from_zip_params(6, 15, 0);
+        let mut d = CompressorOxide::with_buffers(
+            flags,
+            &mut buffers,
+            &mut dict,
+            &mut next,
+            &mut hash_table,
+            &mut lz_codes,
+            &mut out_buf,
+        )
+        .unwrap();
+        let mut encoded = vec![0; data.len()];
+        let (status, bytes_in, bytes_out) =
+            compress(&mut d, &data, &mut encoded, TDEFLFlush::Finish);
+        assert_eq!((status, bytes_in), (TDEFLStatus::Done, data.len()));
+        encoded.truncate(bytes_out);
+        assert_eq!(
+            encoded,
+            compress_chunked(
+                &mut CompressorOxide::new(flags),
+                &data,
+                data.len(),
+                TDEFLFlush::None
+            )
+        );
+    }
+
+    #[test]
+    fn compressor_with_buffers_invalid_sizes() {
+        let sizes = BufferSizes::for_mem_level(4);
+        let small = BufferSizes::for_mem_level(1);
+        let (d, n, h, l, o) = (
+            sizes.dict,
+            sizes.next,
+            sizes.hash_table,
+            sizes.lz_codes,
+            sizes.out_buf,
+        );
+        for &(dict, next, hash_table, lz_codes, out_buf) in &[
+            (d - 1, n - 1, h, l, o),
+            (d, n / 2, h, l, o),
+            (small.dict / 2, small.next / 2, h, l, o),
+            (d * 2, n * 2, h, l, o),
+            (d, n, h + 1, l, o),
+            (d, n, 64, 256, o),
+            (d, n, h, 100, o),
+            (d, n, h, l, o - 1),
+        ] {
+            let result = CompressorOxide::with_owned_buffers(
+                DEFAULT_FLAGS,
+                Box::<CompressorBuffers>::default(),
+                vec![0; dict],
+                vec![0; next],
+                vec![0; hash_table],
+                vec![0; lz_codes],
+                vec![0; out_buf],
+            );
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn mem_levels() {
+        let mut data = include_bytes!("../../tests/test_data/numbers.txt").to_vec();
+        data.extend_from_slice(&test_data(100_000));
+        let mut prev_sizes: Option<BufferSizes> = None;
+        let mut encoded_len = Vec::new();
+        for mem_level in 1..=MAX_MEM_LEVEL {
+            for &level in &[1, 6, 9] {
+                let flags = create_comp_flags_from_zip_params(level, 15, 0);
+                let mut d = CompressorOxide::with_mem_level(flags, mem_level);
+                let encoded = compress_chunked(&mut d, &data, 100_000, TDEFLFlush::None);
+                assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), data);
+                if level == 9 {
+                    encoded_len.push(encoded.len());
+                }
+            }
+
+            let sizes = CompressorOxide::with_mem_level(DEFAULT_FLAGS, mem_level).buffer_sizes();
+            if let Some(prev_sizes) = prev_sizes {
+                assert_eq!(sizes.hash_table, prev_sizes.hash_table * 2);
+                assert_eq!(sizes.lz_codes, prev_sizes.lz_codes * 2);
+                assert!(sizes.out_buf > prev_sizes.out_buf);
+                assert!(sizes.next == prev_sizes.next * 2 || sizes.next == LZ_DICT_SIZE);
+            }
+            prev_sizes = Some(sizes);
+        }
+        // Smaller buffers cost some compression compared to the default memory level of 8.
+        assert!(encoded_len[0] > encoded_len[7]);
+
+        // The lowest level uses less than 16 KiB for these buffers.
+        let sizes = BufferSizes::for_mem_level(1);
+        assert_eq!(sizes.next, 4096);
+        let total = sizes.dict + sizes.next * 2 + sizes.hash_table * 2 + sizes.lz_codes;
+        assert!(total + sizes.out_buf < 16 * 1024);
+        assert_eq!(BufferSizes::for_mem_level(0), sizes);
+        assert_eq!(
+            BufferSizes::for_mem_level(20),
+            CompressorOxide::default().buffer_sizes()
+        );
+    }
+
+    #[test]
+    fn prime_bits() {
+        let data = test_data(10_000);
+        let flags = create_comp_flags_from_zip_params(6, -15, 0);
+        let mut d = CompressorOxide::new(flags);
+        d.prime(3, 0b1101).unwrap();
+        assert_eq!(d.pending_output(), (0, 3));
+        assert_eq!(d.prime(14, 0), Err(MZError::Buf));
+        let encoded = compress_chunked(&mut d, &data, 10_000, TDEFLFlush::None);
+        assert_eq!(encoded[0] & 0b111, 0b101);
+
+        // Skip the primed bits when decompressing.
+        let mut r = DecompressorOxide::new();
+        r.prime(5, u32::from(encoded[0] >> 3)).unwrap();
+        let mut decoded = vec![0; data.len()];
+        let (status, _, out_len) = crate::inflate::core::decompress(
+            &mut r,
+            &encoded[1..],
+            &mut decoded,
+            0,
+            inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+        );
+        assert_eq!(status, TINFLStatus::Done);
+        assert_eq!(&decoded[..out_len], &data[..]);
+
+        // The zlib header has to come first.
+        let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(6, 15, 0));
+        assert_eq!(d.prime(3, 0), Err(MZError::Param));
+    }
+
+    #[test]
+    fn pending_output() {
+        let data = test_data(10_000);
+        let mut d = CompressorOxide::default();
+        let mut output = [0; 16];
+        let (status, in_pos, out_pos) =
+            super::compress(&mut d, &data, &mut output, TDEFLFlush::Finish);
+        assert_eq!(status, TDEFLStatus::Okay);
+        assert_eq!((in_pos, out_pos), (data.len(), output.len()));
+        let (pending, _) = d.pending_output();
+        assert!(pending > 0);
+
+        let mut rest = vec![0; pending];
+        let (status, _, out_pos) = super::compress(&mut d, &[], &mut rest, TDEFLFlush::Finish);
+        assert_eq!(status, TDEFLStatus::Done);
+        assert_eq!(out_pos, pending);
+        assert_eq!(d.pending_output(), (0, 0));
+    }
+
+    #[test]
+    fn max_probes() {
+        let data = include_bytes!("../../tests/test_data/numbers.txt");
+        let flags = create_comp_flags_from_zip_params(9, 15, 0);
+        let mut d = CompressorOxide::new(flags);
+        d.set_max_probes(7);
+        let encoded = compress_chunked(&mut d, data, 100_000, TDEFLFlush::None);
+        // Same as setting the number of probes in the flags.
+        let flags = (flags & !(MAX_PROBES_MASK as u32)) | 7;
+        let expected = compress_chunked(
+            &mut CompressorOxide::new(flags),
+            data,
+            100_000,
+            TDEFLFlush::None,
+        );
+        // The zlib header still reflects the compression level.
+        assert_eq!(encoded[2..], expected[2..]);
+        assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), &data[..]);
+    }
+
+    #[test]
+    fn tune() {
+        // Random data with long repeats, for the parameters to make a difference.
+        let mut data = random_data(20_000);
+        for i in 0..100 {
+            let start = i * 4567 % 15_000;
+            let repeat = data[start..start + 100 + i * 37].to_vec();
+            data.extend_from_slice(&repeat);
+        }
+        let flags = create_comp_flags_from_zip_params(9, 15, 0);
+        let compress_with =
+            |d: &mut CompressorOxide| compress_chunked(d, &data, 100_000, TDEFLFlush::None);
+        // The defaults only leave the number of probes.
+        let mut d = CompressorOxide::new(flags);
+        d.tune(32, 128, 258, 7);
+        let mut expected = CompressorOxide::new(flags);
+        expected.set_max_probes(7);
+        assert_eq!(compress_with(&mut d), compress_with(&mut expected));
+
+        for &level in &[6, 9, 10] {
+            let flags = create_comp_flags_from_zip_params(level, 15, 0);
+            let default = compress_with(&mut CompressorOxide::new(flags));
+            let mut d = CompressorOxide::new(flags);
+            d.tune(4, 8, 16, 4095);
+            let encoded = compress_with(&mut d);
+            assert_ne!(encoded, default);
+            assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), &data[..]);
+            // Changing the level goes back to the defaults.
+            let mut d = CompressorOxide::new(flags);
+            d.tune(4, 8, 16, 4095);
+            d.set_compression_level_raw(level as u8);
+            assert_eq!(compress_with(&mut d), default);
+        }
+
+        // The zlib compatible compressor takes them like zlib, with the parameters of level 6
+        // the output only differs from that level in the zlib header.
+        #[cfg(feature = "zlib-compat")]
+        {
+            let mut d = CompressorOxide::new(flags);
+            d.set_zlib_compatible(true);
+            d.tune(8, 16, 128, 128);
+            let mut expected = CompressorOxide::new(create_comp_flags_from_zip_params(6, 15, 0));
+            expected.set_zlib_compatible(true);
+            let (encoded, expected) = (compress_with(&mut d), compress_with(&mut expected));
+            assert_eq!(encoded[2..], expected[2..]);
+            assert_ne!(encoded[..2], expected[..2]);
+        }
+    }
+
+    #[test]
+    fn clone_compressor() {
+        let data = test_data(200_000);
+        let flags = create_comp_flags_from_zip_params(6, 15, 0);
+        let mut d = CompressorOxide::new(flags);
+        let mut encoded = vec![];
+        compress_to_output(&mut d, &data[..50_000], TDEFLFlush::None, |out: &[u8]| {
+            encoded.extend_from_slice(out);
+            true
+        });
+        let finish = |c: &mut CompressorOxide| {
+            compress_chunked(c, &data[50_000..], 10_000, TDEFLFlush::None)
+        };
+
+        let rest = finish(&mut d.clone());
+        encoded.extend_from_slice(&rest);
+        assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), data);
+
+        // Copying into a compressor using borrowed buffers keeps using them, as long as
+        // the sizes match.
+        let sizes = BufferSizes::for_mem_level(MAX_MEM_LEVEL);
+        let (mut dict, mut next) = (vec![0; sizes.dict], vec![0; sizes.next]);
+        let (mut hash_table, mut lz_codes) = (vec![0; sizes.hash_table], vec![0; sizes.lz_codes]);
+        let mut out_buf = vec![0; sizes.out_buf];
+        let out_buf_ptr = out_buf.as_ptr();
+        let mut buffers = Box::default();
+        let mut borrowed = CompressorOxide::with_buffers(
+            DEFAULT_FLAGS,
+            &mut buffers,
+            &mut dict,
+            &mut next,
+            &mut hash_table,
+            &mut lz_codes,
+            &mut out_buf,
+        )
+        .unwrap();
+        let mut small = CompressorOxide::with_mem_level(DEFAULT_FLAGS, 1);
+        for c in &mut [&mut borrowed, &mut small] {
+            c.clone_from(&d);
+            assert_eq!(c.buffer_sizes(), sizes);
+            assert_eq!(finish(c), rest);
+        }
+        assert_eq!(borrowed.params.local_buf.as_ptr(), out_buf_ptr);
+        assert_eq!(finish(&mut d), rest);
+    }
+
+    #[test]
+    fn stable_output() {
+        let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(9, 15, 0));
+        d.set_binary_tree_matches(true);
+        assert_eq!(d.dict.finder, MatchFinder::BinaryTree);
+        d.set_stable_output(true);
+        assert_eq!(d.dict.finder, MatchFinder::Hash3);
+        assert_ne!(d.flags() as u32 & TDEFL_STABLE_OUTPUT_FLAG, 0);
+
+        // Kept when changing the level.
+        d.set_compression_level_raw(2);
+        assert_eq!(d.dict.finder, MatchFinder::Hash3);
+        assert_ne!(d.flags() as u32 & TDEFL_STABLE_OUTPUT_FLAG, 0);
+        d.set_stable_output(false);
+        assert_eq!(d.dict.finder, MatchFinder::BinaryTree);
+        d.set_binary_tree_matches(false);
+        assert_eq!(d.dict.finder, MatchFinder::Hash4);
+
+        let flags = create_comp_flags_from_zip_params(3, 15, 0) | TDEFL_STABLE_OUTPUT_FLAG;
+        assert_eq!(CompressorOxide::new(flags).dict.finder, MatchFinder::Hash3);
+    }
+
+    #[test]
+    #[cfg(feature = "zlib-compat")]
+    fn zlib_compatible() {
+        let data = b"Hello, hello, hello, hello! Goodbye, goodbye.";
+        // Output of zlib 1.2.13 at level 6.
+        let expected = [
+            120, 156, 243, 72, 205, 201, 201, 215, 81, 200, 192, 164, 20, 21, 220, 243, 243, 83,
+            146, 42, 83, 117, 20, 210, 33, 12, 61, 0, 98, 86, 15, 130,
+        ];
+        let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(6, 15, 0));
+        d.set_zlib_compatible(true);
+        let mut out = [0; 64];
+        let (status, in_pos, out_pos) = compress(&mut d, data, &mut out, TDEFLFlush::Finish);
+        assert_eq!((status, in_pos), (TDEFLStatus::Done, data.len()));
+        assert_eq!(&out[..out_pos], &expected[..]);
+        assert_eq!(d.adler32(), 0x6256_0f82);
+
+        // Kept when changing the level and format, zlib produces the same raw stream at level 1.
+        d.reset();
+        d.set_format_and_level(DataFormat::Raw, 1);
+        assert_ne!(d.flags() as u32 & TDEFL_ZLIB_COMPATIBLE_FLAG, 0);
+        let (status, _, out_pos) = compress(&mut d, data, &mut out, TDEFLFlush::Finish);
+        assert_eq!(status, TDEFLStatus::Done);
+        assert_eq!(&out[..out_pos], &expected[2..expected.len() - 4]);
+
+        // Changing the level mid-stream works like `deflateParams`.
+        let text = include_bytes!("../../tests/test_data/numbers.txt");
+        let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(9, 15, 0));
+        d.set_zlib_compatible(true);
+        let mut encoded = vec![];
+        let mut sink = |out: &[u8]| {
+            encoded.extend_from_slice(out);
+            true
+        };
+        let half = text.len() / 2;
+        compress_to_output(&mut d, &text[..half], TDEFLFlush::None, &mut sink);
+        d.set_compression_level_raw(1);
+        let (status, _) = compress_to_output(&mut d, &text[half..], TDEFLFlush::Finish, &mut sink);
+        assert_eq!(status, TDEFLStatus::Done);
+        assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), &text[..]);
+
+        // Compressing after the stream is done is an error, as with the regular compressor.
+        let (status, _) = compress_to_output(&mut d, &[], TDEFLFlush::Finish, |_| true);
+        assert_eq!(status, TDEFLStatus::BadParam);
+    }
+
+    #[test]
+    fn compress_bound() {
+        // Data that doesn't compress, with bytes that take 9 bits in static blocks, and
+        // random bytes for dynamic blocks.
+        let mut lcg = Lcg(0x1234_5678);
+        let high: Vec<u8> = (0..50_000)
+            .map(|_| 144 + ((lcg.next_u32() >> 16) % 112) as u8)
+            .collect();
+        let random = random_data(50_000);
+
+        let levels = (0..=10).map(|level| (level, 0));
+        let strategies = (1..=4).flat_map(|strategy| vec![(1, strategy), (6, strategy)]);
+        for (level, strategy) in levels.chain(strategies) {
+            let window_bits = if level % 2 == 0 { 15 } else { -15 };
+            let flags = create_comp_flags_from_zip_params(level, window_bits, strategy);
+            for &mem_level in &[1, 9] {
+                for data in &[&high[..], &random[..], &high[..1000], &[]] {
+                    let mut d = CompressorOxide::with_mem_level(flags, mem_level);
+                    d.set_rsyncable(strategy == 1);
+                    let mut out = vec![0; d.compress_bound(data.len())];
+                    let (status, in_pos, _) = compress(&mut d, data, &mut out, TDEFLFlush::Finish);
+                    assert_eq!((status, in_pos), (TDEFLStatus::Done, data.len()));
+                }
+            }
+        }
+
+        // Stored blocks only take a few bytes more than the data.
+        let flags = create_comp_flags_from_zip_params(0, 15, 0);
+        let bound = CompressorOxide::new(flags).compress_bound(100_000);
+        assert_eq!(bound, 100_000 + 4 * 5 + 1 + 6);
+        let flags = create_comp_flags_from_zip_params(6, 15, 0);
+        assert!(CompressorOxide::new(flags).compress_bound(100_000) < 100_500);
+
+        // The same bound as zlib in zlib compatible mode.
+        #[cfg(feature = "zlib-compat")]
+        {
+            let mut d = CompressorOxide::new(flags);
+            d.set_zlib_compatible(true);
+            assert_eq!(d.compress_bound(100_000), 100_043);
+        }
+    }
+
+    #[test]
+    fn rsyncable() {
+        let text = include_bytes!("../../tests/test_data/numbers.txt");
+        let mut data = text[..].to_vec();
+        data.extend_from_slice(&test_data(100_000));
+        data.extend_from_slice(&random_data(50_000));
+        data.extend_from_slice(&[0; 20_000]);
+        data.extend_from_slice(&text[..30_000]);
+        let mut changed = data.clone();
+        changed[1000] ^= 1;
+
+        for &level in &[0, 1, 6, 9] {
+            let flags = create_comp_flags_from_zip_params(level, 15, 0);
+            let mut d = CompressorOxide::new(flags);
+            d.set_rsyncable(true);
+            assert!(d.rsyncable());
+            let encoded = compress_chunked(&mut d, &data, data.len(), TDEFLFlush::None);
+            assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), data);
+            if level != 0 {
+                let flushes = encoded.windows(4).filter(|w| w == &[0, 0, 0xFF, 0xFF]);
+                assert!(flushes.count() > data.len() / 16384);
+            }
+
+            // The chunks don't depend on how the input and output are split up.
+            for &(chunk_size, flush) in &[(777, TDEFLFlush::None), (10_000, TDEFLFlush::Sync)] {
+                d.reset();
+                let chunked = compress_chunked(&mut d, &data, chunk_size, flush);
+                assert_eq!(decompress_to_vec_zlib(&chunked).unwrap(), data);
+                if flush == TDEFLFlush::None {
+                    assert!(chunked == encoded);
+                }
+            }
+            d.reset();
+            let mut small_bufs = vec![];
+            let mut in_pos = 0;
+            loop {
+                let mut out = [0; 100];
+                let (status, bytes_in, bytes_out) =
+                    compress(&mut d, &data[in_pos..], &mut out, TDEFLFlush::Finish);
+                in_pos += bytes_in;
+                small_bufs.extend_from_slice(&out[..bytes_out]);
+                if status == TDEFLStatus::Done {
+                    break;
+                }
+                assert_eq!(status, TDEFLStatus::Okay);
+            }
+            assert!(small_bufs == encoded);
+
+            // Only the output of the changed chunk differs, apart from the adler32 checksum.
+            d.reset();
+            let encoded_changed =
+                compress_chunked(&mut d, &changed, changed.len(), TDEFLFlush::None);
+            assert_eq!(decompress_to_vec_zlib(&encoded_changed).unwrap(), changed);
+            let without_adler32 = |encoded: &[u8]| encoded[..encoded.len() - 4].to_vec();
+            let same_end = without_adler32(&encoded)
+                .iter()
+                .rev()
+                .zip(without_adler32(&encoded_changed).iter().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+            assert!(same_end > encoded.len() - 10_000);
+
+            d.set_rsyncable(false);
+            d.reset();
+            let plain = compress_chunked(&mut d, &data, data.len(), TDEFLFlush::None);
+            assert!(!d.rsyncable());
+            assert!(plain != encoded);
+        }
+    }
+
+    #[test]
+    fn match_finders() {
+        let text = include_bytes!("../../tests/test_data/numbers.txt");
+        let mut data = test_data(50_000);
+        data.extend_from_slice(&random_data(20_000));
+        data.extend_from_slice(&text[..]);
+        data.extend_from_slice(&[0; 1000]);
+        data.extend_from_slice(&text[..30_000]);
+
+        let tree = TDEFL_BINARY_TREE_MATCHES;
+        let finders = [
+            (2, 0, MatchFinder::Hash4),
+            (3, 0, MatchFinder::Hash3),
+            (6, 0, MatchFinder::Hash3),
+            (9, 0, MatchFinder::Hash3),
+            (10, 0, MatchFinder::Hash3),
+            (2, tree, MatchFinder::BinaryTree),
+            (6, tree, MatchFinder::BinaryTree),
+            (9, tree, MatchFinder::BinaryTree),
+            (10, tree, MatchFinder::BinaryTree),
+        ];
+        for &(level, extra_flags, finder) in &finders {
+            let flags = create_comp_flags_from_zip_params(level, 15, 0) | extra_flags;
+            assert_eq!(CompressorOxide::new(flags).dict.finder, finder);
+            for &(chunk_size, flush) in &[
+                (data.len(), TDEFLFlush::None),
+                (777, TDEFLFlush::None),
+                (10_000, TDEFLFlush::Sync),
+                (10_000, TDEFLFlush::Full),
+            ] {
+                let mut d = CompressorOxide::new(flags);
+                let encoded = compress_chunked(&mut d, &data, chunk_size, flush);
+                assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), data);
+            }
+        }
+
+        // Changing the settings mid-stream switches between them.
+        let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(9, 15, 0));
+        let mut encoded = vec![];
+        let settings = [
+            (9, true),
+            (2, false),
+            (6, false),
+            (10, true),
+            (2, true),
+            (3, false),
+        ];
+        for (chunk, &(level, tree)) in data.chunks(25_000).zip(settings.iter().cycle()) {
+            d.set_compression_level_raw(level);
+            d.set_binary_tree_matches(tree);
+            compress_to_output(&mut d, chunk, TDEFLFlush::None, |out: &[u8]| {
+                encoded.extend_from_slice(out);
+                true
+            });
+        }
+        compress_to_output(&mut d, &[], TDEFLFlush::Finish, |out: &[u8]| {
+            encoded.extend_from_slice(out);
+            true
+        });
+        assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn binary_tree_with_buffers() {
+        // The binary trees can be kept in the buffers of the caller as well, so the output
+        // doesn't depend on where the memory comes from.
+        let text = include_bytes!("../../tests/test_data/numbers.txt");
+        let mut data = test_data(50_000);
+        data.extend_from_slice(&text[..]);
+        for &level in &[9, 10] {
+            let flags = create_comp_flags_from_zip_params(level, 15, 0) | TDEFL_BINARY_TREE_MATCHES;
+            let expected = compress_chunked(
+                &mut CompressorOxide::new(flags),
+                &data,
+                data.len(),
+                TDEFLFlush::None,
+            );
+
+            let sizes = BufferSizes::MAX;
+            let mut buffers = Box::new(CompressorBuffers::new());
+            let (mut dict, mut next) = (vec![0; sizes.dict], vec![0; 3 * sizes.next]);
+            let (mut hash_table, mut lz_codes) =
+                (vec![0; sizes.hash_table], vec![0; sizes.lz_codes]);
+            let mut out_buf = vec![0; sizes.out_buf];
+            let mut d = CompressorOxide::with_buffers(
+                flags,
+                &mut buffers,
+                &mut dict,
+                &mut next,
+                &mut hash_table,
+                &mut lz_codes,
+                &mut out_buf,
+            )
+            .unwrap();
+            assert_eq!(d.dict.finder, MatchFinder::BinaryTree);
+            assert_eq!(d.buffer_sizes(), sizes);
+            let tree_ptr = d.dict.b.tree.as_ptr();
+            let encoded = compress_chunked(&mut d, &data, data.len(), TDEFLFlush::None);
+            assert_eq!(encoded, expected);
+            assert_eq!(d.dict.b.tree.as_ptr(), tree_ptr);
+        }
+
+        // Without room for them, the trees are allocated when first needed.
+        let sizes = BufferSizes::for_mem_level(4);
+        let flags = create_comp_flags_from_zip_params(6, 15, 0);
+        let mut d = CompressorOxide::with_owned_buffers(
+            flags,
+            Box::<CompressorBuffers>::default(),
+            vec![0; sizes.dict],
+            vec![0; sizes.next],
+            vec![0; sizes.hash_table],
+            vec![0; sizes.lz_codes],
+            vec![0; sizes.out_buf],
+        )
+        .unwrap();
+        assert!(d.dict.b.tree.is_empty());
+        d.set_binary_tree_matches(true);
+        assert_eq!(d.dict.finder, MatchFinder::BinaryTree);
+        assert_eq!(d.dict.b.tree.len(), 2 * sizes.next);
+        let encoded = compress_chunked(&mut d, &data, 10_000, TDEFLFlush::None);
+        assert_eq!(decompress_to_vec_zlib(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn preset_dictionary() {
+        let text = include_bytes!("../../tests/test_data/numbers.txt");
+        let mut dictionary = random_data(25_000);
+        dictionary.extend_from_slice(&text[..10_000]);
+        let mut data = dictionary[10_000..20_000].to_vec();
+        data.extend_from_slice(&text[10_000..15_000]);
+        let (dictionary, data) = (&dictionary[..], &data[..]);
+
+        let decode = |dictionary: &[u8], encoded: &[u8]| {
+            let mut r = DecompressorOxide::new();
+            let mut decoded = dictionary.to_vec();
+            decoded.resize(dictionary.len() + data.len() + 1, 0);
+            let (status, _, out_len) = crate::inflate::core::decompress(
+                &mut r,
+                encoded,
+                &mut decoded,
+                dictionary.len(),
+                inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+            );
+            assert_eq!(status, TINFLStatus::Done);
+            decoded[dictionary.len()..dictionary.len() + out_len].to_vec()
+        };
+
+        for &level in &[0, 1, 3, 6, 9, 10] {
+            let flags = create_comp_flags_from_zip_params(level, -15, 0);
+            let mut d = CompressorOxide::new(flags);
+            let plain = compress_chunked(&mut d, data, data.len(), TDEFLFlush::None);
+            d.reset();
+            d.set_dictionary(dictionary).unwrap();
+            let encoded = compress_chunked(&mut d, data, 5000, TDEFLFlush::None);
+            assert_eq!(decode(dictionary, &encoded), data);
+            if level > 0 {
+                assert!(encoded.len() < plain.len() / 2);
+            }
+
+            // Setting it in two parts appends the second to the first.
+            d.reset();
+            d.set_dictionary(&dictionary[..20_000]).unwrap();
+            d.set_dictionary(&dictionary[20_000..]).unwrap();
+            let encoded = compress_chunked(&mut d, data, 5000, TDEFLFlush::None);
+            assert_eq!(decode(dictionary, &encoded), data);
+        }
+
+        // Primed bits are kept, and rsyncable mode does not split up the dictionary.
+        let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(6, -15, 0));
+        d.set_rsyncable(true);
+        d.prime(3, 0).unwrap();
+        d.set_dictionary(dictionary).unwrap();
+        assert_eq!(d.pending_output(), (0, 3));
+        let encoded = compress_chunked(&mut d, data, data.len(), TDEFLFlush::None);
+        assert_eq!(encoded[0] & 0b111, 0);
+        let mut r = DecompressorOxide::new();
+        r.prime(5, u32::from(encoded[0] >> 3)).unwrap();
+        let mut decoded = dictionary.to_vec();
+        decoded.resize(dictionary.len() + data.len(), 0);
+        let (status, _, _) = crate::inflate::core::decompress(
+            &mut r,
+            &encoded[1..],
+            &mut decoded,
+            dictionary.len(),
+            inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+        );
+        assert_eq!(status, TINFLStatus::Done);
+        assert_eq!(&decoded[dictionary.len()..], data);
+
+        // Not after compression has started, or with a zlib wrapper.
+        let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(6, -15, 0));
+        compress(&mut d, &data[..100], &mut [0; 100], TDEFLFlush::None);
+        assert_eq!(d.set_dictionary(dictionary), Err(MZError::Param));
+        let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(6, 15, 0));
+        assert_eq!(d.set_dictionary(dictionary), Err(MZError::Param));
+    }
+
+    #[test]
+    fn compression_options() {
+        assert_eq!(
+            CompressionOptions::new().flags(),
+            Ok(create_comp_flags_from_zip_params(
+                6,
+                MZ_DEFAULT_WINDOW_BITS,
+                0
+            ))
+        );
+        let strategies = [
+            CompressionStrategy::Default,
+            CompressionStrategy::Filtered,
+            CompressionStrategy::HuffmanOnly,
+            CompressionStrategy::RLE,
+            CompressionStrategy::Fixed,
+        ];
+        for level in 1..=10 {
+            for &strategy in &strategies {
+                let options = CompressionOptions::new()
+                    .level(level)
+                    .strategy(strategy)
+                    .format(DataFormat::Raw);
+                assert_eq!(
+                    options.flags(),
+                    Ok(create_comp_flags_from_zip_params(
+                        level.into(),
+                        -MZ_DEFAULT_WINDOW_BITS,
+                        strategy as i32
+                    ))
+                );
+            }
+        }
+
+        let options = CompressionOptions::new()
+            .level(2)
+            .greedy_parsing(false)
+            .max_probes(200)
+            .stable_output(true);
+        let flags = options.flags().unwrap();
+        assert_eq!(flags & MAX_PROBES_MASK as u32, 200);
+        assert_eq!(flags & TDEFL_GREEDY_PARSING_FLAG, 0);
+        assert_ne!(flags & TDEFL_STABLE_OUTPUT_FLAG, 0);
+        let d = options.mem_level(4).build().unwrap();
+        assert_eq!(d.flags() as u32, flags);
+        assert_eq!(d.buffer_sizes(), BufferSizes::for_mem_level(4));
+        let flags = CompressionOptions::new()
+            .level(8)
+            .greedy_parsing(true)
+            .flags();
+        assert_ne!(flags.unwrap() & TDEFL_GREEDY_PARSING_FLAG, 0);
+
+        #[allow(unused_mut)]
+        let mut invalid = vec![
+            CompressionOptions::new().level(11),
+            CompressionOptions::new().window_bits(14),
+            CompressionOptions::new().max_probes(4096),
+            CompressionOptions::new().mem_level(0),
+            CompressionOptions::new().mem_level(10),
+            CompressionOptions::new()
+                .level(0)
+                .strategy(CompressionStrategy::Fixed),
+            CompressionOptions::new().level(0).greedy_parsing(true),
+            CompressionOptions::new()
+                .strategy(CompressionStrategy::HuffmanOnly)
+                .max_probes(10),
+            CompressionOptions::new()
+                .strategy(CompressionStrategy::RLE)
+                .max_probes(10),
+            CompressionOptions::new().dictionary(b"abc"),
+        ];
+        #[cfg(feature = "zlib-compat")]
+        invalid.extend_from_slice(&[
+            CompressionOptions::new()
+                .zlib_compatible(true)
+                .max_probes(10),
+            CompressionOptions::new()
+                .zlib_compatible(true)
+                .stable_output(true),
+            CompressionOptions::new()
+                .format(DataFormat::Raw)
+                .zlib_compatible(true)
+                .dictionary(b"abc"),
+        ]);
+        for options in &invalid {
+            assert_eq!(options.flags(), Err(MZError::Param), "{:?}", options);
+            assert!(options.build().is_err());
+        }
+
+        // Configuring an existing compressor, which has to have matching buffers.
+        let data = test_data(10_000);
+        let options = CompressionOptions::new()
+            .format(DataFormat::Raw)
+            .level(9)
+            .dictionary(&data[..5000]);
+        let mut d = CompressorOxide::with_mem_level(DEFAULT_FLAGS, 5);
+        assert_eq!(options.mem_level(6).configure(&mut d), Err(MZError::Param));
+        compress(&mut d, &data, &mut [0; 100], TDEFLFlush::None);
+        options.mem_level(5).configure(&mut d).unwrap();
+        assert_eq!(d.data_format(), DataFormat::Raw);
+        let encoded = compress_chunked(&mut d, &data[5000..], 5000, TDEFLFlush::None);
+        let mut built = options.mem_level(5).build().unwrap();
+        assert_eq!(
+            compress_chunked(&mut built, &data[5000..], 5000, TDEFLFlush::None),
+            encoded
+        );
+    }
 }
@@ -1,12 +1,19 @@
 //! This module contains functionality for compression.
 
+#[cfg(feature = "alloc")]
 use alloc::vec;
+#[cfg(feature = "alloc")]
 use alloc::vec::Vec;
 
 mod buffer;
 pub mod core;
+#[cfg(feature = "stats")]
+pub mod stats;
 pub mod stream;
+#[cfg(feature = "zlib-compat")]
+mod zlib_compat;
 use self::core::*;
+use crate::{DataFormat, MZError};
 
 /// How much processing the compressor should do to compress the data.
 /// `NoCompression` and `Bestspeed` have special meanings, the other levels determine the number
@@ -106,18 +113,71 @@ fn tdefl_compress_mem_to_mem(
     flags: c_int,
 ) -> usize*/
 
+/// Get an upper bound on the size of the output when compressing `input_len` bytes at
+/// the specified compression level (0-10) in the given format.
+///
+/// This covers storing the data in blocks, which is what the compressor falls back to
+/// for data that doesn't compress, and the zlib header and trailer. It applies to the
+/// compression functions in this module, and to a compressor created with the flags from
+/// [`create_comp_flags_from_zip_params`](core/fn.create_comp_flags_from_zip_params.html)
+/// with the default strategy that is only flushed when finished.
+/// [`CompressorOxide::compress_bound`](core/struct.CompressorOxide.html#method.compress_bound)
+/// gives the bound for other settings.
+pub fn compress_bound(input_len: usize, format: DataFormat, level: u8) -> usize {
+    let flags = create_comp_flags_from_zip_params(level.into(), format.to_window_bits(), 0);
+    compress_bound_from_flags(input_len, flags, BufferSizes::MAX)
+}
+
 /// Compress the input data to a vector, using the specified compression level (0-10).
+#[cfg(feature = "alloc")]
 pub fn compress_to_vec(input: &[u8], level: u8) -> Vec<u8> {
     compress_to_vec_inner(input, level, 0, 0)
 }
 
 /// Compress the input data to a vector, using the specified compression level (0-10), and with a
 /// zlib wrapper.
+#[cfg(feature = "alloc")]
 pub fn compress_to_vec_zlib(input: &[u8], level: u8) -> Vec<u8> {
     compress_to_vec_inner(input, level, 1, 0)
 }
 
+/// Compress the input data into `output`, using the specified compression level (0-10) and
+/// format.
+///
+/// `compressor` is reset and set to the level and format, keeping other settings like its
+/// buffer sizes, so the same one can be reused for many calls. An `output` of
+/// [`compress_bound`](fn.compress_bound.html) bytes is always large enough.
+///
+/// This doesn't allocate, except in zlib compatible mode, where the compressor allocates the
+/// state of the zlib port the first time it's used and keeps it for later calls. The binary
+/// trees of
+/// [`TDEFL_BINARY_TREE_MATCHES`](core/deflate_flags/constant.TDEFL_BINARY_TREE_MATCHES.html)
+/// are allocated when the flag is set on the compressor, not here. With the `stats` feature, the
+/// list of recorded blocks also grows until it's large enough for the blocks of a call.
+///
+/// Returns the number of bytes written to `output`.
+///
+/// # Errors
+/// Returns `MZError::Buf` if `output` is too small to hold all of the compressed data.
+pub fn compress_into(
+    compressor: &mut CompressorOxide,
+    input: &[u8],
+    output: &mut [u8],
+    level: u8,
+    format: DataFormat,
+) -> Result<usize, MZError> {
+    compressor.reset();
+    compressor.set_format_and_level(format, level);
+    let (status, _, bytes_out) = compress(compressor, input, output, TDEFLFlush::Finish);
+    match status {
+        TDEFLStatus::Done => Ok(bytes_out),
+        TDEFLStatus::Okay => Err(MZError::Buf),
+        _ => Err(MZError::Param),
+    }
+}
+
 /// Simple function to compress data to a vec.
+#[cfg(feature = "alloc")]
 fn compress_to_vec_inner(input: &[u8], level: u8, window_bits: i32, strategy: i32) -> Vec<u8> {
     // The comp flags function sets the zlib flag if the window_bits parameter is > 0.
     let flags = create_comp_flags_from_zip_params(level.into(), window_bits, strategy);
@@ -156,10 +216,16 @@ fn compress_to_vec_inner(input: &[u8], level: u8, window_bits: i32, strategy: i3
     output
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "alloc"))]
 mod test {
-    use super::{compress_to_vec, compress_to_vec_inner, CompressionStrategy};
+    use super::{
+        compress_bound, compress_into, compress_to_vec, compress_to_vec_inner,
+        compress_to_vec_zlib, CompressionStrategy, CompressorOxide,
+    };
     use crate::inflate::decompress_to_vec;
+    use crate::test_util::random_data;
+    use crate::{DataFormat, MZError};
+    use std::prelude::v1::*;
     use std::vec;
 
     /// Test deflate example.
@@ -224,4 +290,36 @@ mod test {
         // as neither checks matches against the byte at index 0.)
         assert!(c.len() <= 6);
     }
+
+    #[test]
+    fn compress_bound_random() {
+        let data = random_data(70_000);
+
+        for level in 0..=10 {
+            let raw = compress_bound(data.len(), DataFormat::Raw, level);
+            assert!(compress_to_vec(&data, level).len() <= raw);
+            let zlib = compress_bound(data.len(), DataFormat::Zlib, level);
+            assert!(compress_to_vec_zlib(&data, level).len() <= zlib);
+            assert_eq!(zlib, raw + 6);
+        }
+        assert_eq!(compress_bound(0, DataFormat::Raw, 0), 6);
+    }
+
+    #[test]
+    fn compress_into_slice() {
+        let data = b"Hello, hello, hello, hello! Goodbye, goodbye.";
+        let mut compressor = CompressorOxide::default();
+        let mut out = [0; 64];
+        for level in 0..=10 {
+            let len = compress_into(&mut compressor, data, &mut out, level, DataFormat::Raw);
+            assert_eq!(&out[..len.unwrap()], &compress_to_vec(data, level)[..]);
+            let len = compress_into(&mut compressor, data, &mut out, level, DataFormat::Zlib);
+            assert_eq!(&out[..len.unwrap()], &compress_to_vec_zlib(data, level)[..]);
+        }
+
+        let res = compress_into(&mut compressor, data, &mut out[..10], 6, DataFormat::Zlib);
+        assert_eq!(res, Err(MZError::Buf));
+        let len = compress_into(&mut compressor, &[], &mut out, 6, DataFormat::Zlib).unwrap();
+        assert_eq!(&out[..len], &compress_to_vec_zlib(&[], 6)[..]);
+    }
 }
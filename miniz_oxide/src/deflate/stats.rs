@@ -0,0 +1,132 @@
+//! Statistics about the output of the compressor.
+//!
+//! Only available with the `stats` feature, so there is no overhead when they are not needed.
+
+use alloc::collections::VecDeque;
+use core::mem;
+
+use crate::deflate::core::{LARGE_DIST_SYM, SMALL_DIST_SYM};
+use crate::BlockType;
+
+/// The number of most recent blocks kept in
+/// [`CompressionStats::blocks`](struct.CompressionStats.html#structfield.blocks).
+pub const MAX_RECORDED_BLOCKS: usize = 1024;
+
+/// Information about a single block output by the compressor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BlockStats {
+    /// The type of the block.
+    pub block_type: BlockType,
+    /// The number of uncompressed bytes in the block.
+    pub input_bytes: u32,
+    /// The size of the block in bits, including the block header.
+    pub output_bits: u64,
+}
+
+/// Statistics collected by the compressor, see
+/// [`CompressorOxide::stats`](../core/struct.CompressorOxide.html#method.stats).
+#[derive(Debug, Clone)]
+pub struct CompressionStats {
+    /// Information about the last `MAX_RECORDED_BLOCKS` blocks output, oldest first.
+    pub blocks: VecDeque<BlockStats>,
+    /// Number of blocks output of each type, indexed by `block_type_index`.
+    block_counts: [u64; 3],
+    /// Number of literals found by the compressor.
+    pub literals: u64,
+    /// Number of matches found by the compressor.
+    pub matches: u64,
+    /// Number of matches of each length, indexed by the match length (3-258).
+    pub match_lengths: [u64; 259],
+    /// Number of matches using each distance code (0-29), see section 3.2.5 of RFC 1951.
+    pub match_distance_codes: [u64; 30],
+    /// Time spent looking for matches, in the units of the timer set with
+    /// [`CompressorOxide::set_stats_timer`](../core/struct.CompressorOxide.html#method.set_stats_timer).
+    /// Always 0 if no timer is set.
+    pub match_finder_time: u64,
+}
+
+impl CompressionStats {
+    /// Number of blocks of the given type output so far, including the ones no longer kept
+    /// in `blocks`.
+    pub fn num_blocks(&self, block_type: BlockType) -> u64 {
+        self.block_counts[block_type_index(block_type)]
+    }
+
+    /// Reset all the statistics, keeping the memory of `blocks` so a reused compressor doesn't
+    /// allocate it again.
+    pub(crate) fn clear(&mut self) {
+        let mut blocks = mem::take(&mut self.blocks);
+        blocks.clear();
+        *self = CompressionStats {
+            blocks,
+            ..CompressionStats::default()
+        };
+    }
+
+    /// Record a block that was output, dropping the oldest one kept if there are already
+    /// `MAX_RECORDED_BLOCKS`.
+    pub(crate) fn record_block(&mut self, block: BlockStats) {
+        self.block_counts[block_type_index(block.block_type)] += 1;
+        if self.blocks.len() == MAX_RECORDED_BLOCKS {
+            self.blocks.pop_front();
+        }
+        self.blocks.push_back(block);
+    }
+
+    /// Count the literals and matches in the lz code buffer of a block.
+    ///
+    /// The buffer uses the same format as `LZOxide::codes`, a flag byte describing
+    /// whether each of the next 8 codes is a literal or a match followed by the codes.
+    pub(crate) fn record_lz_codes(&mut self, lz_code_buf: &[u8]) {
+        let mut flags = 1;
+        let mut i = 0;
+        while i < lz_code_buf.len() {
+            if flags == 1 {
+                flags = u32::from(lz_code_buf[i]) | 0x100;
+                i += 1;
+                continue;
+            }
+
+            if flags & 1 == 1 {
+                let match_len = usize::from(lz_code_buf[i]) + 3;
+                let match_dist =
+                    usize::from(lz_code_buf[i + 1]) | (usize::from(lz_code_buf[i + 2]) << 8);
+                let dist_code = if match_dist < 512 {
+                    SMALL_DIST_SYM[match_dist]
+                } else {
+                    LARGE_DIST_SYM[(match_dist >> 8) & 127]
+                };
+                self.matches += 1;
+                self.match_lengths[match_len] += 1;
+                self.match_distance_codes[usize::from(dist_code)] += 1;
+                i += 3;
+            } else {
+                self.literals += 1;
+                i += 1;
+            }
+            flags >>= 1;
+        }
+    }
+}
+
+fn block_type_index(block_type: BlockType) -> usize {
+    match block_type {
+        BlockType::Stored => 0,
+        BlockType::Static => 1,
+        BlockType::Dynamic => 2,
+    }
+}
+
+impl Default for CompressionStats {
+    fn default() -> Self {
+        CompressionStats {
+            blocks: VecDeque::new(),
+            block_counts: [0; 3],
+            literals: 0,
+            matches: 0,
+            match_lengths: [0; 259],
+            match_distance_codes: [0; 30],
+            match_finder_time: 0,
+        }
+    }
+}
@@ -94,7 +94,7 @@ pub fn deflate(
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "alloc"))]
 mod test {
     use super::deflate;
     use crate::deflate::CompressorOxide;
@@ -0,0 +1,1974 @@
+// Ported from deflate.c and trees.c of zlib, which carry the following notice:
+//
+// Copyright (C) 1995-2017 Jean-loup Gailly and Mark Adler
+//
+// This software is provided 'as-is', without any express or implied
+// warranty.  In no event will the authors be held liable for any damages
+// arising from the use of this software.
+//
+// Permission is granted to anyone to use this software for any purpose,
+// including commercial applications, and to alter it and redistribute it
+// freely, subject to the following restrictions:
+//
+// 1. The origin of this software must not be misrepresented; you must not
+//    claim that you wrote the original software. If you use this software
+//    in a product, an acknowledgment in the product documentation would be
+//    appreciated but is not required.
+// 2. Altered source versions must be plainly marked as such, and must not be
+//    misrepresented as being the original software.
+// 3. This notice may not be removed or altered from any source distribution.
+//
+// The port is altered from the original, and licensed under the same terms.
+
+//! A port of the compressor of zlib 1.2.x, used when `TDEFL_ZLIB_COMPATIBLE_FLAG` is set to
+//! produce the same output as zlib's `deflate`.
+//!
+//! The code follows `deflate.c` and `trees.c` of zlib closely, with the same names where
+//! possible, so that the two can be compared side by side. Only what is needed for the
+//! default window size (15 bits) and memory level (8) of zlib is included.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp;
+
+use super::core::deflate_flags::*;
+use super::core::TDEFLFlush;
+use crate::shared::{update_adler32, MZ_ADLER32_INIT};
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Minimum amount of lookahead, except at the end of the input.
+const MIN_LOOKAHEAD: usize = MAX_MATCH + MIN_MATCH + 1;
+
+const W_BITS: usize = 15;
+const W_SIZE: usize = 1 << W_BITS;
+const W_MASK: usize = W_SIZE - 1;
+const WINDOW_SIZE: usize = 2 * W_SIZE;
+/// Matches are limited to this distance so that the lookahead always fits in the window.
+const MAX_DIST: usize = W_SIZE - MIN_LOOKAHEAD;
+
+/// `memLevel + 7` for the default memory level of 8.
+const HASH_BITS: usize = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const HASH_MASK: usize = HASH_SIZE - 1;
+const HASH_SHIFT: usize = (HASH_BITS + MIN_MATCH - 1) / MIN_MATCH;
+
+/// `1 << (memLevel + 6)`, the number of symbols a block can hold.
+const LIT_BUFSIZE: usize = 1 << 14;
+const PENDING_BUF_SIZE: usize = LIT_BUFSIZE * 4;
+/// Blocks are ended when the symbol buffer has one entry left.
+const SYM_END: usize = (LIT_BUFSIZE - 1) * 3;
+const MAX_STORED: usize = 65535;
+/// Matches of length 3 are dropped if further away than this in the lazy parser.
+const TOO_FAR: usize = 4096;
+/// Hash chain entry that marks the end of the chain.
+const NIL: usize = 0;
+
+const LENGTH_CODES: usize = 29;
+const LITERALS: usize = 256;
+const L_CODES: usize = LITERALS + 1 + LENGTH_CODES;
+const D_CODES: usize = 30;
+const BL_CODES: usize = 19;
+const HEAP_SIZE: usize = 2 * L_CODES + 1;
+const MAX_BITS: usize = 15;
+const MAX_BL_BITS: usize = 7;
+const END_BLOCK: usize = 256;
+const REP_3_6: usize = 16;
+const REPZ_3_10: usize = 17;
+const REPZ_11_138: usize = 18;
+
+const STORED_BLOCK: u32 = 0;
+const STATIC_TREES: u32 = 1;
+const DYN_TREES: u32 = 2;
+
+const Z_NO_FLUSH: i32 = 0;
+const Z_FULL_FLUSH: i32 = 3;
+const Z_FINISH: i32 = 4;
+const Z_BLOCK: i32 = 5;
+
+#[rustfmt::skip]
+const EXTRA_LBITS: [u8; LENGTH_CODES] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0
+];
+#[rustfmt::skip]
+const EXTRA_DBITS: [u8; D_CODES] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13
+];
+#[rustfmt::skip]
+const EXTRA_BLBITS: [u8; BL_CODES] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 3, 7];
+#[rustfmt::skip]
+const BL_ORDER: [usize; BL_CODES] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15
+];
+
+/// Which parser a level uses.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Func {
+    Stored,
+    Fast,
+    Slow,
+}
+
+/// An entry of zlib's `configuration_table`.
+struct Config {
+    good_length: usize,
+    max_lazy: usize,
+    nice_length: usize,
+    max_chain: usize,
+    func: Func,
+}
+
+#[rustfmt::skip]
+const CONFIGURATION_TABLE: [Config; 10] = [
+    Config { good_length: 0, max_lazy: 0, nice_length: 0, max_chain: 0, func: Func::Stored },
+    Config { good_length: 4, max_lazy: 4, nice_length: 8, max_chain: 4, func: Func::Fast },
+    Config { good_length: 4, max_lazy: 5, nice_length: 16, max_chain: 8, func: Func::Fast },
+    Config { good_length: 4, max_lazy: 6, nice_length: 32, max_chain: 32, func: Func::Fast },
+    Config { good_length: 4, max_lazy: 4, nice_length: 16, max_chain: 16, func: Func::Slow },
+    Config { good_length: 8, max_lazy: 16, nice_length: 32, max_chain: 32, func: Func::Slow },
+    Config { good_length: 8, max_lazy: 16, nice_length: 128, max_chain: 128, func: Func::Slow },
+    Config { good_length: 8, max_lazy: 32, nice_length: 128, max_chain: 256, func: Func::Slow },
+    Config { good_length: 32, max_lazy: 128, nice_length: 258, max_chain: 1024, func: Func::Slow },
+    Config { good_length: 32, max_lazy: 258, nice_length: 258, max_chain: 4096, func: Func::Slow },
+];
+
+/// The compression strategies of zlib, in the same order.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub(crate) enum ZlibStrategy {
+    Default,
+    Filtered,
+    HuffmanOnly,
+    Rle,
+    Fixed,
+}
+
+/// Get the zlib level and strategy matching the compressor flags.
+///
+/// The flags of the standard levels map back to the same zlib level, other numbers of probes
+/// use the closest level below.
+pub(crate) fn params_from_flags(flags: u32) -> (u8, ZlibStrategy) {
+    if flags & TDEFL_FORCE_ALL_RAW_BLOCKS != 0 {
+        return (0, ZlibStrategy::Default);
+    }
+
+    let probes = flags & 0xFFF;
+    let level = if flags & TDEFL_GREEDY_PARSING_FLAG != 0 {
+        match probes {
+            0..=1 => 1,
+            2..=6 => 2,
+            _ => 3,
+        }
+    } else {
+        match probes {
+            0..=16 => 4,
+            17..=32 => 5,
+            33..=128 => 6,
+            129..=256 => 7,
+            257..=512 => 8,
+            _ => 9,
+        }
+    };
+    let strategy = if flags & TDEFL_RLE_MATCHES != 0 {
+        ZlibStrategy::Rle
+    } else if flags & TDEFL_FORCE_ALL_STATIC_BLOCKS != 0 {
+        ZlibStrategy::Fixed
+    } else if probes == 0 {
+        ZlibStrategy::HuffmanOnly
+    } else if flags & TDEFL_FILTER_MATCHES != 0 {
+        ZlibStrategy::Filtered
+    } else {
+        ZlibStrategy::Default
+    };
+    (level, strategy)
+}
+
+/// Return codes of `deflate`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum ZlibStatus {
+    Ok,
+    StreamEnd,
+    StreamError,
+    BufError,
+}
+
+/// Where the compressed data goes.
+pub(crate) enum Sink<'a> {
+    /// A buffer that limits the output like `avail_out` in zlib.
+    Buf { buf: &'a mut [u8], pos: usize },
+    /// A callback taking any amount of output.
+    Func {
+        func: &'a mut dyn FnMut(&[u8]) -> bool,
+        failed: bool,
+    },
+}
+
+impl<'a> Sink<'a> {
+    fn avail_out(&self) -> usize {
+        match *self {
+            Sink::Buf { ref buf, pos } => buf.len() - pos,
+            Sink::Func { .. } => u32::max_value() as usize,
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        match *self {
+            Sink::Buf {
+                ref mut buf,
+                ref mut pos,
+            } => {
+                buf[*pos..*pos + data.len()].copy_from_slice(data);
+                *pos += data.len();
+            }
+            Sink::Func {
+                ref mut func,
+                ref mut failed,
+            } => {
+                if !*failed && !data.is_empty() {
+                    *failed = !func(data);
+                }
+            }
+        }
+    }
+}
+
+/// The input and output of a call to `deflate`, like `z_stream`.
+struct Strm<'a, 'b, 'c> {
+    input: &'a [u8],
+    next_in: usize,
+    out: &'b mut Sink<'c>,
+}
+
+impl<'a, 'b, 'c> Strm<'a, 'b, 'c> {
+    fn avail_in(&self) -> usize {
+        self.input.len() - self.next_in
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum BlockState {
+    /// Block not completed, need more input or more output.
+    NeedMore,
+    /// Block flush performed.
+    BlockDone,
+    /// Finish started, need only more output at next deflate.
+    FinishStarted,
+    /// Finish done, accept no more input or output.
+    FinishDone,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Status {
+    Init,
+    Busy,
+    Finish,
+}
+
+/// Value of `last_flush` before anything has been compressed.
+const LAST_FLUSH_NONE: i32 = -2;
+
+fn rank(flush: i32) -> i32 {
+    flush * 2 - if flush > 4 { 9 } else { 0 }
+}
+
+/// The bits waiting to be output, `pending_buf` and `bi_buf` of zlib.
+#[derive(Clone)]
+struct BitWriter {
+    pending: Vec<u8>,
+    pending_out: usize,
+    bi_buf: u16,
+    bi_valid: u32,
+}
+
+impl BitWriter {
+    fn pending(&self) -> usize {
+        self.pending.len() - self.pending_out
+    }
+
+    fn put_short(&mut self, w: u16) {
+        self.pending.push(w as u8);
+        self.pending.push((w >> 8) as u8);
+    }
+
+    fn put_short_msb(&mut self, b: u16) {
+        self.pending.push((b >> 8) as u8);
+        self.pending.push(b as u8);
+    }
+
+    fn send_bits(&mut self, value: u32, length: u32) {
+        if self.bi_valid > 16 - length {
+            self.bi_buf |= (value << self.bi_valid) as u16;
+            let bi_buf = self.bi_buf;
+            self.put_short(bi_buf);
+            self.bi_buf = (value >> (16 - self.bi_valid)) as u16;
+            self.bi_valid = self.bi_valid + length - 16;
+        } else {
+            self.bi_buf |= (value << self.bi_valid) as u16;
+            self.bi_valid += length;
+        }
+    }
+
+    fn send_code(&mut self, c: usize, tree: &Tree) {
+        self.send_bits(u32::from(tree.fc[c]), u32::from(tree.dl[c]));
+    }
+
+    /// `bi_flush`, output whole bytes.
+    fn flush_bits(&mut self) {
+        if self.bi_valid == 16 {
+            let bi_buf = self.bi_buf;
+            self.put_short(bi_buf);
+            self.bi_buf = 0;
+            self.bi_valid = 0;
+        } else if self.bi_valid >= 8 {
+            self.pending.push(self.bi_buf as u8);
+            self.bi_buf >>= 8;
+            self.bi_valid -= 8;
+        }
+    }
+
+    /// `bi_windup`, output everything and align to a byte boundary.
+    fn windup(&mut self) {
+        if self.bi_valid > 8 {
+            let bi_buf = self.bi_buf;
+            self.put_short(bi_buf);
+        } else if self.bi_valid > 0 {
+            self.pending.push(self.bi_buf as u8);
+        }
+        self.bi_buf = 0;
+        self.bi_valid = 0;
+    }
+
+    /// `_tr_stored_block`.
+    fn stored_block(&mut self, buf: &[u8], last: bool) {
+        self.send_bits((STORED_BLOCK << 1) + last as u32, 3);
+        self.windup();
+        self.put_short(buf.len() as u16);
+        self.put_short(!buf.len() as u16);
+        self.pending.extend_from_slice(buf);
+    }
+}
+
+/// A huffman tree, an array of `ct_data` in zlib.
+///
+/// `fc` holds the frequency of a symbol while counting and its code once the tree is built,
+/// `dl` the parent of a node while building the tree and the code length afterwards.
+#[derive(Clone)]
+struct Tree {
+    fc: Vec<u16>,
+    dl: Vec<u16>,
+    max_code: usize,
+}
+
+impl Tree {
+    fn new(size: usize) -> Tree {
+        Tree {
+            fc: vec![0; size],
+            dl: vec![0; size],
+            max_code: 0,
+        }
+    }
+}
+
+/// The constant part of a `tree_desc`.
+struct StaticDesc<'a> {
+    static_tree: Option<&'a Tree>,
+    extra_bits: &'a [u8],
+    extra_base: usize,
+    elems: usize,
+    max_length: usize,
+}
+
+/// The tables zlib computes in `tr_static_init`.
+#[derive(Clone)]
+struct StaticTables {
+    ltree: Tree,
+    dtree: Tree,
+    dist_code: Vec<u8>,
+    length_code: Vec<u8>,
+    base_length: Vec<u16>,
+    base_dist: Vec<u16>,
+}
+
+impl StaticTables {
+    fn new() -> StaticTables {
+        let mut length_code = vec![0; 256];
+        let mut base_length = vec![0; LENGTH_CODES];
+        let mut length = 0;
+        let mut code = 0;
+        while code < LENGTH_CODES - 1 {
+            base_length[code] = length as u16;
+            for _ in 0..1 << EXTRA_LBITS[code] {
+                length_code[length] = code as u8;
+                length += 1;
+            }
+            code += 1;
+        }
+        // Length 258 can be coded as 284 + 5 bits or as 285, use the shorter one.
+        length_code[length - 1] = code as u8;
+
+        let mut dist_code = vec![0; 512];
+        let mut base_dist = vec![0; D_CODES];
+        let mut dist = 0;
+        for code in 0..16 {
+            base_dist[code] = dist as u16;
+            for _ in 0..1 << EXTRA_DBITS[code] {
+                dist_code[dist] = code as u8;
+                dist += 1;
+            }
+        }
+        // From now on, all distances are divided by 128.
+        dist >>= 7;
+        for code in 16..D_CODES {
+            base_dist[code] = (dist << 7) as u16;
+            for _ in 0..1 << (EXTRA_DBITS[code] - 7) {
+                dist_code[256 + dist] = code as u8;
+                dist += 1;
+            }
+        }
+
+        let mut ltree = Tree::new(L_CODES + 2);
+        let mut bl_count = [0; MAX_BITS + 1];
+        for n in 0..L_CODES + 2 {
+            let len = match n {
+                0..=143 => 8,
+                144..=255 => 9,
+                256..=279 => 7,
+                _ => 8,
+            };
+            ltree.dl[n] = len;
+            bl_count[len as usize] += 1;
+        }
+        gen_codes(&mut ltree, L_CODES + 1, &bl_count);
+
+        let mut dtree = Tree::new(D_CODES);
+        for n in 0..D_CODES {
+            dtree.dl[n] = 5;
+            dtree.fc[n] = bi_reverse(n as u16, 5);
+        }
+
+        StaticTables {
+            ltree,
+            dtree,
+            dist_code,
+            length_code,
+            base_length,
+            base_dist,
+        }
+    }
+
+    fn d_code(&self, dist: usize) -> usize {
+        if dist < 256 {
+            self.dist_code[dist] as usize
+        } else {
+            self.dist_code[256 + (dist >> 7)] as usize
+        }
+    }
+}
+
+/// Reverse the first `len` bits of `code`.
+fn bi_reverse(code: u16, len: usize) -> u16 {
+    let mut res = 0;
+    let mut code = code;
+    for _ in 0..len {
+        res = (res << 1) | (code & 1);
+        code >>= 1;
+    }
+    res
+}
+
+/// Generate the codes of a tree from the code lengths and the number of codes of each length.
+fn gen_codes(tree: &mut Tree, max_code: usize, bl_count: &[u16; MAX_BITS + 1]) {
+    let mut next_code = [0u16; MAX_BITS + 1];
+    let mut code = 0u16;
+    for bits in 1..=MAX_BITS {
+        code = (code + bl_count[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+    for n in 0..=max_code {
+        let len = tree.dl[n] as usize;
+        if len == 0 {
+            continue;
+        }
+        tree.fc[n] = bi_reverse(next_code[len], len);
+        next_code[len] = next_code[len].wrapping_add(1);
+    }
+}
+
+/// The state used while building huffman trees.
+#[derive(Clone)]
+struct TreeBuilder {
+    heap: Vec<usize>,
+    heap_len: usize,
+    heap_max: usize,
+    depth: Vec<u8>,
+    bl_count: [u16; MAX_BITS + 1],
+    /// Bit length of the current block with optimal trees.
+    opt_len: u64,
+    /// Bit length of the current block with static trees.
+    static_len: u64,
+}
+
+fn smaller(tree: &Tree, n: usize, m: usize, depth: &[u8]) -> bool {
+    tree.fc[n] < tree.fc[m] || (tree.fc[n] == tree.fc[m] && depth[n] <= depth[m])
+}
+
+impl TreeBuilder {
+    fn pqdownheap(&mut self, tree: &Tree, mut k: usize) {
+        let v = self.heap[k];
+        let mut j = k << 1;
+        while j <= self.heap_len {
+            if j < self.heap_len && smaller(tree, self.heap[j + 1], self.heap[j], &self.depth) {
+                j += 1;
+            }
+            if smaller(tree, v, self.heap[j], &self.depth) {
+                break;
+            }
+            self.heap[k] = self.heap[j];
+            k = j;
+            j <<= 1;
+        }
+        self.heap[k] = v;
+    }
+
+    fn gen_bitlen(&mut self, tree: &mut Tree, desc: &StaticDesc) {
+        let max_code = tree.max_code;
+        let max_length = desc.max_length;
+        let mut overflow = 0i32;
+
+        self.bl_count = [0; MAX_BITS + 1];
+
+        // The root of the heap has length 0.
+        tree.dl[self.heap[self.heap_max]] = 0;
+
+        let mut h = self.heap_max + 1;
+        while h < HEAP_SIZE {
+            let n = self.heap[h];
+            let mut bits = tree.dl[tree.dl[n] as usize] as usize + 1;
+            if bits > max_length {
+                bits = max_length;
+                overflow += 1;
+            }
+            tree.dl[n] = bits as u16;
+            h += 1;
+            if n > max_code {
+                // Not a leaf node.
+                continue;
+            }
+            self.bl_count[bits] += 1;
+            let xbits = if n >= desc.extra_base {
+                desc.extra_bits[n - desc.extra_base] as u64
+            } else {
+                0
+            };
+            let f = u64::from(tree.fc[n]);
+            self.opt_len = self.opt_len.wrapping_add(f * (bits as u64 + xbits));
+            if let Some(stree) = desc.static_tree {
+                self.static_len = self
+                    .static_len
+                    .wrapping_add(f * (u64::from(stree.dl[n]) + xbits));
+            }
+        }
+        if overflow == 0 {
+            return;
+        }
+
+        // Find the first bit length which could increase.
+        loop {
+            let mut bits = max_length - 1;
+            while self.bl_count[bits] == 0 {
+                bits -= 1;
+            }
+            // Move one leaf down the tree, and one overflow item as its brother.
+            self.bl_count[bits] -= 1;
+            self.bl_count[bits + 1] += 2;
+            self.bl_count[max_length] -= 1;
+            overflow -= 2;
+            if overflow <= 0 {
+                break;
+            }
+        }
+
+        // Recompute all bit lengths, scanning in increasing frequency.
+        let mut bits = max_length;
+        while bits != 0 {
+            let mut n = self.bl_count[bits];
+            while n != 0 {
+                h -= 1;
+                let m = self.heap[h];
+                if m > max_code {
+                    continue;
+                }
+                if tree.dl[m] as usize != bits {
+                    let diff = (bits as u64).wrapping_sub(u64::from(tree.dl[m]));
+                    self.opt_len = self
+                        .opt_len
+                        .wrapping_add(diff.wrapping_mul(u64::from(tree.fc[m])));
+                    tree.dl[m] = bits as u16;
+                }
+                n -= 1;
+            }
+            bits -= 1;
+        }
+    }
+
+    fn build_tree(&mut self, tree: &mut Tree, desc: &StaticDesc) {
+        let elems = desc.elems;
+        let mut max_code: isize = -1;
+
+        self.heap_len = 0;
+        self.heap_max = HEAP_SIZE;
+
+        for n in 0..elems {
+            if tree.fc[n] != 0 {
+                self.heap_len += 1;
+                self.heap[self.heap_len] = n;
+                max_code = n as isize;
+                self.depth[n] = 0;
+            } else {
+                tree.dl[n] = 0;
+            }
+        }
+
+        // Force at least two codes of non zero frequency, as at least one distance code has
+        // to exist and at least one bit should be sent for each code.
+        while self.heap_len < 2 {
+            let node = if max_code < 2 {
+                max_code += 1;
+                max_code as usize
+            } else {
+                0
+            };
+            self.heap_len += 1;
+            self.heap[self.heap_len] = node;
+            tree.fc[node] = 1;
+            self.depth[node] = 0;
+            self.opt_len = self.opt_len.wrapping_sub(1);
+            if let Some(stree) = desc.static_tree {
+                self.static_len = self.static_len.wrapping_sub(u64::from(stree.dl[node]));
+            }
+        }
+        tree.max_code = max_code as usize;
+
+        let mut n = self.heap_len / 2;
+        while n >= 1 {
+            self.pqdownheap(tree, n);
+            n -= 1;
+        }
+
+        // Combine the two least frequent nodes until only one is left.
+        let mut node = elems;
+        loop {
+            let n = self.heap[1];
+            self.heap[1] = self.heap[self.heap_len];
+            self.heap_len -= 1;
+            self.pqdownheap(tree, 1);
+            let m = self.heap[1];
+
+            // Keep the nodes sorted by frequency.
+            self.heap_max -= 1;
+            self.heap[self.heap_max] = n;
+            self.heap_max -= 1;
+            self.heap[self.heap_max] = m;
+
+            tree.fc[node] = tree.fc[n].wrapping_add(tree.fc[m]);
+            self.depth[node] = cmp::max(self.depth[n], self.depth[m]) + 1;
+            tree.dl[n] = node as u16;
+            tree.dl[m] = node as u16;
+
+            self.heap[1] = node;
+            node += 1;
+            self.pqdownheap(tree, 1);
+            if self.heap_len < 2 {
+                break;
+            }
+        }
+
+        self.heap_max -= 1;
+        self.heap[self.heap_max] = self.heap[1];
+
+        self.gen_bitlen(tree, desc);
+        let bl_count = self.bl_count;
+        gen_codes(tree, tree.max_code, &bl_count);
+    }
+}
+
+/// Count the code lengths of `tree` for the bit length tree.
+fn scan_tree(tree: &mut Tree, max_code: usize, bl_tree: &mut Tree) {
+    let mut prevlen: isize = -1;
+    let mut nextlen = tree.dl[0] as isize;
+    let mut count = 0;
+    let (mut max_count, mut min_count) = if nextlen == 0 { (138, 3) } else { (7, 4) };
+    // Guard.
+    tree.dl[max_code + 1] = 0xffff;
+
+    for n in 0..=max_code {
+        let curlen = nextlen;
+        nextlen = tree.dl[n + 1] as isize;
+        count += 1;
+        if count < max_count && curlen == nextlen {
+            continue;
+        } else if count < min_count {
+            bl_tree.fc[curlen as usize] += count as u16;
+        } else if curlen != 0 {
+            if curlen != prevlen {
+                bl_tree.fc[curlen as usize] += 1;
+            }
+            bl_tree.fc[REP_3_6] += 1;
+        } else if count <= 10 {
+            bl_tree.fc[REPZ_3_10] += 1;
+        } else {
+            bl_tree.fc[REPZ_11_138] += 1;
+        }
+        count = 0;
+        prevlen = curlen;
+        if nextlen == 0 {
+            max_count = 138;
+            min_count = 3;
+        } else if curlen == nextlen {
+            max_count = 6;
+            min_count = 3;
+        } else {
+            max_count = 7;
+            min_count = 4;
+        }
+    }
+}
+
+/// Send the code lengths of `tree` using the bit length tree.
+fn send_tree(bits: &mut BitWriter, tree: &Tree, max_code: usize, bl_tree: &Tree) {
+    let mut prevlen: isize = -1;
+    let mut nextlen = tree.dl[0] as isize;
+    let mut count = 0;
+    let (mut max_count, mut min_count) = if nextlen == 0 { (138, 3) } else { (7, 4) };
+
+    for n in 0..=max_code {
+        let curlen = nextlen;
+        nextlen = tree.dl[n + 1] as isize;
+        count += 1;
+        if count < max_count && curlen == nextlen {
+            continue;
+        } else if count < min_count {
+            while count != 0 {
+                bits.send_code(curlen as usize, bl_tree);
+                count -= 1;
+            }
+        } else if curlen != 0 {
+            if curlen != prevlen {
+                bits.send_code(curlen as usize, bl_tree);
+                count -= 1;
+            }
+            bits.send_code(REP_3_6, bl_tree);
+            bits.send_bits(count - 3, 2);
+        } else if count <= 10 {
+            bits.send_code(REPZ_3_10, bl_tree);
+            bits.send_bits(count - 3, 3);
+        } else {
+            bits.send_code(REPZ_11_138, bl_tree);
+            bits.send_bits(count - 11, 7);
+        }
+        count = 0;
+        prevlen = curlen;
+        if nextlen == 0 {
+            max_count = 138;
+            min_count = 3;
+        } else if curlen == nextlen {
+            max_count = 6;
+            min_count = 3;
+        } else {
+            max_count = 7;
+            min_count = 4;
+        }
+    }
+}
+
+/// Send the symbols of the current block with the given trees.
+fn compress_block(
+    bits: &mut BitWriter,
+    syms: &[u8],
+    tables: &StaticTables,
+    ltree: &Tree,
+    dtree: &Tree,
+) {
+    for sym in syms.chunks(3) {
+        let mut dist = sym[0] as usize | (sym[1] as usize) << 8;
+        let mut lc = sym[2] as usize;
+        if dist == 0 {
+            bits.send_code(lc, ltree);
+        } else {
+            // Here, lc is the match length - MIN_MATCH.
+            let code = tables.length_code[lc] as usize;
+            bits.send_code(code + LITERALS + 1, ltree);
+            let extra = u32::from(EXTRA_LBITS[code]);
+            if extra != 0 {
+                lc -= tables.base_length[code] as usize;
+                bits.send_bits(lc as u32, extra);
+            }
+            dist -= 1;
+            let code = tables.d_code(dist);
+            bits.send_code(code, dtree);
+            let extra = u32::from(EXTRA_DBITS[code]);
+            if extra != 0 {
+                dist -= tables.base_dist[code] as usize;
+                bits.send_bits(dist as u32, extra);
+            }
+        }
+    }
+    bits.send_code(END_BLOCK, ltree);
+}
+
+/// The state of the zlib compressor, `deflate_state` in zlib.
+#[derive(Clone)]
+pub(crate) struct ZlibState {
+    status: Status,
+    /// Whether to write the zlib header and trailer.
+    zlib_header: bool,
+    trailer_written: bool,
+    last_flush: i32,
+    adler: u32,
+    bits: BitWriter,
+
+    /// The sliding window, of twice the window size.
+    window: Box<[u8]>,
+    /// Link to an older string with the same hash, indexed by position in the window.
+    prev: Box<[u16]>,
+    /// Heads of the hash chains.
+    head: Box<[u16]>,
+    ins_h: usize,
+
+    /// Window position of the beginning of the current block, negative if the window was
+    /// moved past it.
+    block_start: isize,
+    match_length: usize,
+    prev_match: usize,
+    match_available: bool,
+    strstart: usize,
+    match_start: usize,
+    lookahead: usize,
+    prev_length: usize,
+    max_chain_length: usize,
+    max_lazy_match: usize,
+    good_match: usize,
+    nice_match: usize,
+    level: u8,
+    strategy: ZlibStrategy,
+    /// Bytes at the end of the window left to insert in the hash chains.
+    insert: usize,
+    /// Pending hash updates after the window was moved by the stored parser, 2 to clear.
+    matches: u8,
+
+    dyn_ltree: Tree,
+    dyn_dtree: Tree,
+    bl_tree: Tree,
+    builder: TreeBuilder,
+    tables: StaticTables,
+    sym_buf: Box<[u8]>,
+    sym_next: usize,
+}
+
+impl ZlibState {
+    pub(crate) fn new(level: u8, strategy: ZlibStrategy, zlib_header: bool) -> Box<ZlibState> {
+        let mut s = Box::new(ZlibState {
+            status: Status::Init,
+            zlib_header,
+            trailer_written: false,
+            last_flush: LAST_FLUSH_NONE,
+            adler: MZ_ADLER32_INIT,
+            bits: BitWriter {
+                pending: Vec::with_capacity(PENDING_BUF_SIZE),
+                pending_out: 0,
+                bi_buf: 0,
+                bi_valid: 0,
+            },
+            window: vec![0; WINDOW_SIZE].into_boxed_slice(),
+            prev: vec![0; W_SIZE].into_boxed_slice(),
+            head: vec![0; HASH_SIZE].into_boxed_slice(),
+            ins_h: 0,
+            block_start: 0,
+            match_length: 0,
+            prev_match: 0,
+            match_available: false,
+            strstart: 0,
+            match_start: 0,
+            lookahead: 0,
+            prev_length: 0,
+            max_chain_length: 0,
+            max_lazy_match: 0,
+            good_match: 0,
+            nice_match: 0,
+            level: cmp::min(level, 9),
+            strategy,
+            insert: 0,
+            matches: 0,
+            dyn_ltree: Tree::new(HEAP_SIZE),
+            dyn_dtree: Tree::new(2 * D_CODES + 1),
+            bl_tree: Tree::new(2 * BL_CODES + 1),
+            builder: TreeBuilder {
+                heap: vec![0; HEAP_SIZE],
+                heap_len: 0,
+                heap_max: 0,
+                depth: vec![0; HEAP_SIZE],
+                bl_count: [0; MAX_BITS + 1],
+                opt_len: 0,
+                static_len: 0,
+            },
+            tables: StaticTables::new(),
+            sym_buf: vec![0; LIT_BUFSIZE * 3].into_boxed_slice(),
+            sym_next: 0,
+        });
+        s.reset();
+        s
+    }
+
+    /// Start a new stream with the same parameters, like `deflateReset`.
+    ///
+    /// As in zlib, the contents of the window are kept, which can make a difference for
+    /// matches compared past the end of the input.
+    pub(crate) fn reset(&mut self) {
+        self.bits.pending.clear();
+        self.bits.pending_out = 0;
+        self.bits.bi_buf = 0;
+        self.bits.bi_valid = 0;
+        self.trailer_written = false;
+        self.status = Status::Init;
+        self.adler = MZ_ADLER32_INIT;
+        self.last_flush = LAST_FLUSH_NONE;
+        self.init_block();
+        self.lm_init();
+    }
+
+    /// Set whether to write a zlib header and trailer, only possible before starting.
+    pub(crate) fn set_zlib_header(&mut self, zlib_header: bool) {
+        if self.status == Status::Init {
+            self.zlib_header = zlib_header;
+        }
+    }
+
+    pub(crate) fn adler32(&self) -> u32 {
+        self.adler
+    }
+
+    /// The number of bytes and bits that have not been output yet.
+    pub(crate) fn pending_output(&self) -> (usize, u32) {
+        (self.bits.pending(), self.bits.bi_valid)
+    }
+
+    fn lm_init(&mut self) {
+        self.clear_hash();
+        self.set_config();
+        self.strstart = 0;
+        self.block_start = 0;
+        self.lookahead = 0;
+        self.insert = 0;
+        self.match_length = MIN_MATCH - 1;
+        self.prev_length = MIN_MATCH - 1;
+        self.match_available = false;
+        self.ins_h = 0;
+    }
+
+    fn set_config(&mut self) {
+        let config = &CONFIGURATION_TABLE[self.level as usize];
+        self.max_lazy_match = config.max_lazy;
+        self.good_match = config.good_length;
+        self.nice_match = config.nice_length;
+        self.max_chain_length = config.max_chain;
+    }
+
+    /// Override the parameters of the match finding from the configuration table, like
+    /// `deflateTune`. They are reset when the level is changed.
+    pub(crate) fn tune(
+        &mut self,
+        good_length: usize,
+        max_lazy: usize,
+        nice_length: usize,
+        max_chain: usize,
+    ) {
+        self.good_match = good_length;
+        self.max_lazy_match = max_lazy;
+        self.nice_match = nice_length;
+        self.max_chain_length = max_chain;
+    }
+
+    fn clear_hash(&mut self) {
+        for h in self.head.iter_mut() {
+            *h = NIL as u16;
+        }
+    }
+
+    /// Compress as much of `input` as possible, like `deflate` with `avail_out` given by
+    /// `out`.
+    ///
+    /// Changes of level and strategy are handled like `deflateParams` called before
+    /// `deflate`. Returns the status and the number of bytes of `input` consumed.
+    pub(crate) fn deflate(
+        &mut self,
+        input: &[u8],
+        out: &mut Sink,
+        flush: TDEFLFlush,
+        level: u8,
+        strategy: ZlibStrategy,
+    ) -> (ZlibStatus, usize) {
+        let level = cmp::min(level, 9);
+        if (level != self.level || strategy != self.strategy) && !self.params(out, level, strategy)
+        {
+            return (ZlibStatus::BufError, 0);
+        }
+
+        let mut strm = Strm {
+            input,
+            next_in: 0,
+            out,
+        };
+        let status = self.deflate_inner(&mut strm, flush as i32);
+        (status, strm.next_in)
+    }
+
+    /// `deflateParams`, without any new input. Returns false if the data compressed with the
+    /// previous parameters could not be flushed.
+    fn params(&mut self, out: &mut Sink, level: u8, strategy: ZlibStrategy) -> bool {
+        let func = CONFIGURATION_TABLE[level as usize].func;
+        let old_func = CONFIGURATION_TABLE[self.level as usize].func;
+        if (strategy != self.strategy || func != old_func) && self.last_flush != LAST_FLUSH_NONE {
+            let mut strm = Strm {
+                input: &[],
+                next_in: 0,
+                out,
+            };
+            if self.deflate_inner(&mut strm, Z_BLOCK) == ZlibStatus::StreamError
+                || (self.strstart as isize - self.block_start) as usize + self.lookahead != 0
+            {
+                return false;
+            }
+        }
+        if self.level != level {
+            if self.level == 0 && self.matches != 0 {
+                if self.matches == 1 {
+                    self.slide_hash();
+                } else {
+                    self.clear_hash();
+                }
+                self.matches = 0;
+            }
+            self.level = level;
+            self.set_config();
+        }
+        self.strategy = strategy;
+        true
+    }
+
+    fn deflate_inner(&mut self, strm: &mut Strm, flush: i32) -> ZlibStatus {
+        if self.status == Status::Finish && flush != Z_FINISH {
+            return ZlibStatus::StreamError;
+        }
+        if strm.out.avail_out() == 0 {
+            return ZlibStatus::BufError;
+        }
+        let old_flush = self.last_flush;
+        self.last_flush = flush;
+
+        // Flush as much pending output as possible.
+        if self.bits.pending() != 0 {
+            self.flush_pending(strm);
+            if strm.out.avail_out() == 0 {
+                // Make sure the next call does not return a buffer error when there is
+                // nothing new to do.
+                self.last_flush = -1;
+                return ZlibStatus::Ok;
+            }
+        } else if strm.avail_in() == 0 && rank(flush) <= rank(old_flush) && flush != Z_FINISH {
+            return ZlibStatus::BufError;
+        }
+
+        // No more input after the first finish.
+        if self.status == Status::Finish && strm.avail_in() != 0 {
+            return ZlibStatus::BufError;
+        }
+
+        if self.status == Status::Init && !self.zlib_header {
+            self.status = Status::Busy;
+        }
+        if self.status == Status::Init {
+            let mut header = (8 + ((W_BITS as u16 - 8) << 4)) << 8;
+            let level_flags = if self.strategy >= ZlibStrategy::HuffmanOnly || self.level < 2 {
+                0
+            } else if self.level < 6 {
+                1
+            } else if self.level == 6 {
+                2
+            } else {
+                3
+            };
+            header |= level_flags << 6;
+            header += 31 - (header % 31);
+            self.bits.put_short_msb(header);
+            self.adler = MZ_ADLER32_INIT;
+            self.status = Status::Busy;
+
+            self.flush_pending(strm);
+            if self.bits.pending() != 0 {
+                self.last_flush = -1;
+                return ZlibStatus::Ok;
+            }
+        }
+
+        if strm.avail_in() != 0
+            || self.lookahead != 0
+            || (flush != Z_NO_FLUSH && self.status != Status::Finish)
+        {
+            let bstate = if self.level == 0 {
+                self.deflate_stored(strm, flush)
+            } else if self.strategy == ZlibStrategy::HuffmanOnly {
+                self.deflate_huff(strm, flush)
+            } else if self.strategy == ZlibStrategy::Rle {
+                self.deflate_rle(strm, flush)
+            } else if CONFIGURATION_TABLE[self.level as usize].func == Func::Fast {
+                self.deflate_fast(strm, flush)
+            } else {
+                self.deflate_slow(strm, flush)
+            };
+
+            if bstate == BlockState::FinishStarted || bstate == BlockState::FinishDone {
+                self.status = Status::Finish;
+            }
+            if bstate == BlockState::NeedMore || bstate == BlockState::FinishStarted {
+                if strm.out.avail_out() == 0 {
+                    self.last_flush = -1;
+                }
+                return ZlibStatus::Ok;
+            }
+            if bstate == BlockState::BlockDone {
+                if flush != Z_BLOCK {
+                    // Sync or full flush, end with an empty stored block.
+                    self.bits.stored_block(&[], false);
+                    if flush == Z_FULL_FLUSH {
+                        // Forget the history.
+                        self.clear_hash();
+                        if self.lookahead == 0 {
+                            self.strstart = 0;
+                            self.block_start = 0;
+                            self.insert = 0;
+                        }
+                    }
+                }
+                self.flush_pending(strm);
+                if strm.out.avail_out() == 0 {
+                    self.last_flush = -1;
+                    return ZlibStatus::Ok;
+                }
+            }
+        }
+
+        if flush != Z_FINISH {
+            return ZlibStatus::Ok;
+        }
+        if !self.zlib_header || self.trailer_written {
+            return ZlibStatus::StreamEnd;
+        }
+
+        let adler = self.adler;
+        self.bits.put_short_msb((adler >> 16) as u16);
+        self.bits.put_short_msb(adler as u16);
+        self.flush_pending(strm);
+        self.trailer_written = true;
+        if self.bits.pending() != 0 {
+            ZlibStatus::Ok
+        } else {
+            ZlibStatus::StreamEnd
+        }
+    }
+
+    /// Copy as much pending output as possible to the output.
+    fn flush_pending(&mut self, strm: &mut Strm) {
+        self.bits.flush_bits();
+        let len = cmp::min(self.bits.pending(), strm.out.avail_out());
+        if len == 0 {
+            return;
+        }
+        let start = self.bits.pending_out;
+        strm.out.write(&self.bits.pending[start..start + len]);
+        self.bits.pending_out += len;
+        if self.bits.pending() == 0 {
+            self.bits.pending.clear();
+            self.bits.pending_out = 0;
+        }
+    }
+
+    /// Read up to `size` bytes of input into the window at `pos`, updating the checksum.
+    fn read_buf(&mut self, strm: &mut Strm, pos: usize, size: usize) -> usize {
+        let len = cmp::min(strm.avail_in(), size);
+        if len == 0 {
+            return 0;
+        }
+        let data = &strm.input[strm.next_in..strm.next_in + len];
+        self.window[pos..pos + len].copy_from_slice(data);
+        if self.zlib_header {
+            self.adler = update_adler32(self.adler, data);
+        }
+        strm.next_in += len;
+        len
+    }
+
+    fn update_hash(&mut self, c: u8) {
+        self.ins_h = ((self.ins_h << HASH_SHIFT) ^ c as usize) & HASH_MASK;
+    }
+
+    /// Insert the string at `pos` in the hash chains, returning the previous head of the
+    /// chain.
+    fn insert_string(&mut self, pos: usize) -> usize {
+        let c = self.window[pos + MIN_MATCH - 1];
+        self.update_hash(c);
+        let head = self.head[self.ins_h];
+        self.prev[pos & W_MASK] = head;
+        self.head[self.ins_h] = pos as u16;
+        head as usize
+    }
+
+    /// Update the hash chains after the window was moved down by `W_SIZE`.
+    fn slide_hash(&mut self) {
+        for p in self.head.iter_mut().chain(self.prev.iter_mut()) {
+            let m = *p as usize;
+            *p = if m >= W_SIZE {
+                (m - W_SIZE) as u16
+            } else {
+                NIL as u16
+            };
+        }
+    }
+
+    /// Move the upper half of the window down by `W_SIZE`, keeping `len` bytes.
+    fn slide_window(&mut self, len: usize) {
+        let (lower, upper) = self.window.split_at_mut(W_SIZE);
+        lower[..len].copy_from_slice(&upper[..len]);
+    }
+
+    /// Fill the window when the lookahead becomes insufficient, and update the hash of the
+    /// strings that could not be inserted before.
+    fn fill_window(&mut self, strm: &mut Strm) {
+        loop {
+            let mut more = WINDOW_SIZE - self.lookahead - self.strstart;
+
+            // If the window is almost full, move the upper half to the lower one.
+            if self.strstart >= W_SIZE + MAX_DIST {
+                self.slide_window(W_SIZE - more);
+                self.match_start = self.match_start.wrapping_sub(W_SIZE);
+                self.strstart -= W_SIZE;
+                self.block_start -= W_SIZE as isize;
+                if self.insert > self.strstart {
+                    self.insert = self.strstart;
+                }
+                self.slide_hash();
+                more += W_SIZE;
+            }
+            if strm.avail_in() == 0 {
+                break;
+            }
+
+            let pos = self.strstart + self.lookahead;
+            let n = self.read_buf(strm, pos, more);
+            self.lookahead += n;
+
+            // Initialize the hash value now that we have some input.
+            if self.lookahead + self.insert >= MIN_MATCH {
+                let mut s = self.strstart - self.insert;
+                self.ins_h = self.window[s] as usize;
+                let c = self.window[s + 1];
+                self.update_hash(c);
+                while self.insert != 0 {
+                    self.insert_string(s);
+                    s += 1;
+                    self.insert -= 1;
+                    if self.lookahead + self.insert < MIN_MATCH {
+                        break;
+                    }
+                }
+            }
+
+            if self.lookahead >= MIN_LOOKAHEAD || strm.avail_in() == 0 {
+                break;
+            }
+        }
+    }
+
+    /// Find the longest match starting at `strstart` in the hash chain starting at
+    /// `cur_match`.
+    ///
+    /// The third byte is not compared, as it is always equal when the first two are for
+    /// strings with the same hash.
+    fn longest_match(&mut self, mut cur_match: usize) -> usize {
+        let mut chain_length = self.max_chain_length;
+        let scan = self.strstart;
+        let mut best_len = self.prev_length;
+        let mut nice_match = self.nice_match;
+        let limit = if self.strstart > MAX_DIST {
+            self.strstart - MAX_DIST
+        } else {
+            NIL
+        };
+        let mut match_start = self.match_start;
+        let w = &self.window;
+        let mut scan_end1 = w[scan + best_len - 1];
+        let mut scan_end = w[scan + best_len];
+
+        // Do not waste too much time if we already have a good match.
+        if self.prev_length >= self.good_match {
+            chain_length >>= 2;
+        }
+        // Do not look for matches beyond the end of the input.
+        if nice_match > self.lookahead {
+            nice_match = self.lookahead;
+        }
+
+        loop {
+            let m = cur_match;
+            if w[m + best_len] == scan_end
+                && w[m + best_len - 1] == scan_end1
+                && w[m] == w[scan]
+                && w[m + 1] == w[scan + 1]
+            {
+                let mut len = MIN_MATCH;
+                while len < MAX_MATCH && w[scan + len] == w[m + len] {
+                    len += 1;
+                }
+                if len > best_len {
+                    match_start = cur_match;
+                    best_len = len;
+                    if len >= nice_match {
+                        break;
+                    }
+                    scan_end1 = w[scan + best_len - 1];
+                    scan_end = w[scan + best_len];
+                }
+            }
+
+            cur_match = self.prev[cur_match & W_MASK] as usize;
+            if cur_match <= limit {
+                break;
+            }
+            // zlib's unsigned counter wraps around if it starts at 0, which only happens with
+            // tuned parameters. Stop after the first position checked instead.
+            if chain_length <= 1 {
+                break;
+            }
+            chain_length -= 1;
+        }
+
+        self.match_start = match_start;
+        cmp::min(best_len, self.lookahead)
+    }
+
+    fn tally_lit(&mut self, c: u8) -> bool {
+        self.sym_buf[self.sym_next] = 0;
+        self.sym_buf[self.sym_next + 1] = 0;
+        self.sym_buf[self.sym_next + 2] = c;
+        self.sym_next += 3;
+        self.dyn_ltree.fc[c as usize] += 1;
+        self.sym_next == SYM_END
+    }
+
+    /// Add a match of `length - MIN_MATCH` at `distance` to the block.
+    fn tally_dist(&mut self, distance: usize, length: usize) -> bool {
+        self.sym_buf[self.sym_next] = distance as u8;
+        self.sym_buf[self.sym_next + 1] = (distance >> 8) as u8;
+        self.sym_buf[self.sym_next + 2] = length as u8;
+        self.sym_next += 3;
+        let lcode = self.tables.length_code[length] as usize;
+        self.dyn_ltree.fc[lcode + LITERALS + 1] += 1;
+        let dcode = self.tables.d_code(distance - 1);
+        self.dyn_dtree.fc[dcode] += 1;
+        self.sym_next == SYM_END
+    }
+
+    fn init_block(&mut self) {
+        for f in &mut self.dyn_ltree.fc[..L_CODES] {
+            *f = 0;
+        }
+        for f in &mut self.dyn_dtree.fc[..D_CODES] {
+            *f = 0;
+        }
+        for f in &mut self.bl_tree.fc[..BL_CODES] {
+            *f = 0;
+        }
+        self.dyn_ltree.fc[END_BLOCK] = 1;
+        self.builder.opt_len = 0;
+        self.builder.static_len = 0;
+        self.sym_next = 0;
+        self.matches = 0;
+    }
+
+    /// Build the bit length tree and return the index in `BL_ORDER` of the last bit length
+    /// code to send.
+    fn build_bl_tree(&mut self) -> usize {
+        let lmax = self.dyn_ltree.max_code;
+        let dmax = self.dyn_dtree.max_code;
+        scan_tree(&mut self.dyn_ltree, lmax, &mut self.bl_tree);
+        scan_tree(&mut self.dyn_dtree, dmax, &mut self.bl_tree);
+
+        let desc = StaticDesc {
+            static_tree: None,
+            extra_bits: &EXTRA_BLBITS,
+            extra_base: 0,
+            elems: BL_CODES,
+            max_length: MAX_BL_BITS,
+        };
+        self.builder.build_tree(&mut self.bl_tree, &desc);
+
+        // At least 4 bit length codes are sent.
+        let mut max_blindex = BL_CODES - 1;
+        while max_blindex >= 3 {
+            if self.bl_tree.dl[BL_ORDER[max_blindex]] != 0 {
+                break;
+            }
+            max_blindex -= 1;
+        }
+        self.builder.opt_len = self
+            .builder
+            .opt_len
+            .wrapping_add(3 * (max_blindex as u64 + 1) + 5 + 5 + 4);
+        max_blindex
+    }
+
+    fn send_all_trees(&mut self, lcodes: usize, dcodes: usize, blcodes: usize) {
+        let bits = &mut self.bits;
+        bits.send_bits(lcodes as u32 - 257, 5);
+        bits.send_bits(dcodes as u32 - 1, 5);
+        bits.send_bits(blcodes as u32 - 4, 4);
+        for &order in &BL_ORDER[..blcodes] {
+            bits.send_bits(u32::from(self.bl_tree.dl[order]), 3);
+        }
+        send_tree(bits, &self.dyn_ltree, lcodes - 1, &self.bl_tree);
+        send_tree(bits, &self.dyn_dtree, dcodes - 1, &self.bl_tree);
+    }
+
+    /// Output the current block as a stored, static or dynamic block, whichever is
+    /// smallest. `buf` is the position of the input of the block in the window, if still
+    /// there.
+    fn tr_flush_block(&mut self, buf: Option<usize>, stored_len: usize, last: bool) {
+        let mut max_blindex = 0;
+        let mut opt_lenb;
+        let static_lenb;
+
+        if self.level > 0 {
+            let ldesc = StaticDesc {
+                static_tree: Some(&self.tables.ltree),
+                extra_bits: &EXTRA_LBITS,
+                extra_base: LITERALS + 1,
+                elems: L_CODES,
+                max_length: MAX_BITS,
+            };
+            self.builder.build_tree(&mut self.dyn_ltree, &ldesc);
+            let ddesc = StaticDesc {
+                static_tree: Some(&self.tables.dtree),
+                extra_bits: &EXTRA_DBITS,
+                extra_base: 0,
+                elems: D_CODES,
+                max_length: MAX_BITS,
+            };
+            self.builder.build_tree(&mut self.dyn_dtree, &ddesc);
+            max_blindex = self.build_bl_tree();
+
+            opt_lenb = self.builder.opt_len.wrapping_add(3 + 7) >> 3;
+            static_lenb = self.builder.static_len.wrapping_add(3 + 7) >> 3;
+            if static_lenb <= opt_lenb || self.strategy == ZlibStrategy::Fixed {
+                opt_lenb = static_lenb;
+            }
+        } else {
+            // Force a stored block.
+            opt_lenb = stored_len as u64 + 5;
+            static_lenb = opt_lenb;
+        }
+
+        let syms = &self.sym_buf[..self.sym_next];
+        match buf {
+            Some(start) if stored_len as u64 + 4 <= opt_lenb => {
+                self.bits
+                    .stored_block(&self.window[start..start + stored_len], last);
+            }
+            _ if static_lenb == opt_lenb => {
+                self.bits.send_bits((STATIC_TREES << 1) + last as u32, 3);
+                let tables = &self.tables;
+                compress_block(&mut self.bits, syms, tables, &tables.ltree, &tables.dtree);
+            }
+            _ => {
+                self.bits.send_bits((DYN_TREES << 1) + last as u32, 3);
+                let (lcodes, dcodes) = (self.dyn_ltree.max_code + 1, self.dyn_dtree.max_code + 1);
+                self.send_all_trees(lcodes, dcodes, max_blindex + 1);
+                let syms = &self.sym_buf[..self.sym_next];
+                compress_block(
+                    &mut self.bits,
+                    syms,
+                    &self.tables,
+                    &self.dyn_ltree,
+                    &self.dyn_dtree,
+                );
+            }
+        }
+        self.init_block();
+        if last {
+            self.bits.windup();
+        }
+    }
+
+    /// `FLUSH_BLOCK_ONLY`, end the current block and output it as far as possible.
+    fn flush_block_only(&mut self, strm: &mut Strm, last: bool) {
+        let buf = if self.block_start >= 0 {
+            Some(self.block_start as usize)
+        } else {
+            None
+        };
+        let stored_len = (self.strstart as isize - self.block_start) as usize;
+        self.tr_flush_block(buf, stored_len, last);
+        self.block_start = self.strstart as isize;
+        self.flush_pending(strm);
+    }
+
+    /// The end of the parsers after all input was used, flushing the block as requested.
+    fn finish_parse(&mut self, strm: &mut Strm, flush: i32) -> BlockState {
+        if flush == Z_FINISH {
+            self.flush_block_only(strm, true);
+            if strm.out.avail_out() == 0 {
+                return BlockState::FinishStarted;
+            }
+            return BlockState::FinishDone;
+        }
+        if self.sym_next != 0 {
+            self.flush_block_only(strm, false);
+            if strm.out.avail_out() == 0 {
+                return BlockState::NeedMore;
+            }
+        }
+        BlockState::BlockDone
+    }
+
+    /// Copy without compression as much as possible from the input to the output, level 0.
+    ///
+    /// Stored blocks are written directly to the output when there is room for a block of
+    /// at least the window size or all the input when flushing, and otherwise the input is
+    /// collected in the window.
+    fn deflate_stored(&mut self, strm: &mut Strm, flush: i32) -> BlockState {
+        // Smallest worthy block size when not flushing or finishing.
+        let mut min_block = cmp::min(PENDING_BUF_SIZE - 5, W_SIZE);
+
+        let mut last = false;
+        let used = strm.avail_in();
+        loop {
+            let mut len = MAX_STORED;
+            // Number of header bytes.
+            let mut have = (self.bits.bi_valid as usize + 42) >> 3;
+            if strm.out.avail_out() < have {
+                break;
+            }
+            have = strm.out.avail_out() - have;
+            let mut left = (self.strstart as isize - self.block_start) as usize;
+            if len > left + strm.avail_in() {
+                len = left + strm.avail_in();
+            }
+            if len > have {
+                len = have;
+            }
+
+            // Only write a block shorter than the minimum when flushing with all the input
+            // included.
+            if len < min_block
+                && ((len == 0 && flush != Z_FINISH)
+                    || flush == Z_NO_FLUSH
+                    || len != left + strm.avail_in())
+            {
+                break;
+            }
+
+            last = flush == Z_FINISH && len == left + strm.avail_in();
+            self.bits.stored_block(&[], last);
+
+            // Replace the lengths in the dummy stored block with len.
+            let p = self.bits.pending.len();
+            self.bits.pending[p - 4] = len as u8;
+            self.bits.pending[p - 3] = (len >> 8) as u8;
+            self.bits.pending[p - 2] = !len as u8;
+            self.bits.pending[p - 1] = (!len >> 8) as u8;
+
+            // Write the stored block header bytes.
+            self.flush_pending(strm);
+
+            // Copy uncompressed bytes from the window to the output.
+            if left != 0 {
+                if left > len {
+                    left = len;
+                }
+                let start = self.block_start as usize;
+                strm.out.write(&self.window[start..start + left]);
+                self.block_start += left as isize;
+                len -= left;
+            }
+
+            // Copy uncompressed bytes directly from the input to the output.
+            if len != 0 {
+                let data = &strm.input[strm.next_in..strm.next_in + len];
+                if self.zlib_header {
+                    self.adler = update_adler32(self.adler, data);
+                }
+                strm.out.write(data);
+                strm.next_in += len;
+            }
+
+            if last {
+                break;
+            }
+        }
+
+        // Update the sliding window with the last W_SIZE bytes of the copied data, or append
+        // all of it to the window if less was copied.
+        let used = used - strm.avail_in();
+        if used != 0 {
+            if used >= W_SIZE {
+                // Supplant the previous history.
+                self.matches = 2;
+                let end = strm.next_in;
+                self.window[..W_SIZE].copy_from_slice(&strm.input[end - W_SIZE..end]);
+                self.strstart = W_SIZE;
+                self.insert = self.strstart;
+            } else {
+                if WINDOW_SIZE - self.strstart <= used {
+                    // Slide the window down.
+                    self.strstart -= W_SIZE;
+                    let strstart = self.strstart;
+                    self.slide_window(strstart);
+                    if self.matches < 2 {
+                        self.matches += 1;
+                    }
+                    if self.insert > self.strstart {
+                        self.insert = self.strstart;
+                    }
+                }
+                let end = strm.next_in;
+                self.window[self.strstart..self.strstart + used]
+                    .copy_from_slice(&strm.input[end - used..end]);
+                self.strstart += used;
+                self.insert += cmp::min(used, W_SIZE - self.insert);
+            }
+            self.block_start = self.strstart as isize;
+        }
+
+        // If the last block was written to the output, then done.
+        if last {
+            return BlockState::FinishDone;
+        }
+
+        // If flushing and all input has been consumed, then done.
+        if flush != Z_NO_FLUSH
+            && flush != Z_FINISH
+            && strm.avail_in() == 0
+            && self.strstart as isize == self.block_start
+        {
+            return BlockState::BlockDone;
+        }
+
+        // Fill the window with any remaining input.
+        let mut have = WINDOW_SIZE - self.strstart;
+        if strm.avail_in() > have && self.block_start >= W_SIZE as isize {
+            // Slide the window down.
+            self.block_start -= W_SIZE as isize;
+            self.strstart -= W_SIZE;
+            let strstart = self.strstart;
+            self.slide_window(strstart);
+            if self.matches < 2 {
+                self.matches += 1;
+            }
+            have += W_SIZE;
+            if self.insert > self.strstart {
+                self.insert = self.strstart;
+            }
+        }
+        if have > strm.avail_in() {
+            have = strm.avail_in();
+        }
+        if have != 0 {
+            let pos = self.strstart;
+            self.read_buf(strm, pos, have);
+            self.strstart += have;
+            self.insert += cmp::min(have, W_SIZE - self.insert);
+        }
+
+        // There was not enough room in the output for a complete worthy or flushed stored
+        // block. Write a stored block to the pending output instead if there is enough input
+        // for a worthy block, or if flushing and there is room for the remaining input.
+        let have = (self.bits.bi_valid as usize + 42) >> 3;
+        // The maximum stored block length that will fit in the pending buffer.
+        let have = cmp::min(PENDING_BUF_SIZE - have, MAX_STORED);
+        min_block = cmp::min(have, W_SIZE);
+        let left = (self.strstart as isize - self.block_start) as usize;
+        if left >= min_block
+            || ((left != 0 || flush == Z_FINISH)
+                && flush != Z_NO_FLUSH
+                && strm.avail_in() == 0
+                && left <= have)
+        {
+            let len = cmp::min(left, have);
+            last = flush == Z_FINISH && strm.avail_in() == 0 && len == left;
+            let start = self.block_start as usize;
+            self.bits
+                .stored_block(&self.window[start..start + len], last);
+            self.block_start += len as isize;
+            self.flush_pending(strm);
+        }
+
+        // We've done all we can with the available input and output.
+        if last {
+            BlockState::FinishStarted
+        } else {
+            BlockState::NeedMore
+        }
+    }
+
+    /// Compress without lazy matching, levels 1 to 3. New strings are only inserted in the
+    /// hash chains for matches up to `max_lazy_match` long.
+    fn deflate_fast(&mut self, strm: &mut Strm, flush: i32) -> BlockState {
+        loop {
+            // Make sure that we always have enough lookahead.
+            if self.lookahead < MIN_LOOKAHEAD {
+                self.fill_window(strm);
+                if self.lookahead < MIN_LOOKAHEAD && flush == Z_NO_FLUSH {
+                    return BlockState::NeedMore;
+                }
+                if self.lookahead == 0 {
+                    break;
+                }
+            }
+
+            let mut hash_head = NIL;
+            if self.lookahead >= MIN_MATCH {
+                hash_head = self.insert_string(self.strstart);
+            }
+
+            if hash_head != NIL && self.strstart.wrapping_sub(hash_head) <= MAX_DIST {
+                self.match_length = self.longest_match(hash_head);
+            }
+            let bflush;
+            if self.match_length >= MIN_MATCH {
+                bflush = self.tally_dist(
+                    self.strstart - self.match_start,
+                    self.match_length - MIN_MATCH,
+                );
+                self.lookahead -= self.match_length;
+
+                // Insert new strings in the hash table only if the match length is not too
+                // large. This saves time but degrades compression.
+                if self.match_length <= self.max_lazy_match && self.lookahead >= MIN_MATCH {
+                    // The string at strstart is already in the table.
+                    self.match_length -= 1;
+                    loop {
+                        self.strstart += 1;
+                        self.insert_string(self.strstart);
+                        self.match_length -= 1;
+                        if self.match_length == 0 {
+                            break;
+                        }
+                    }
+                    self.strstart += 1;
+                } else {
+                    self.strstart += self.match_length;
+                    self.match_length = 0;
+                    self.ins_h = self.window[self.strstart] as usize;
+                    let c = self.window[self.strstart + 1];
+                    self.update_hash(c);
+                }
+            } else {
+                let c = self.window[self.strstart];
+                bflush = self.tally_lit(c);
+                self.lookahead -= 1;
+                self.strstart += 1;
+            }
+            if bflush {
+                self.flush_block_only(strm, false);
+                if strm.out.avail_out() == 0 {
+                    return BlockState::NeedMore;
+                }
+            }
+        }
+        self.insert = cmp::min(self.strstart, MIN_MATCH - 1);
+        self.finish_parse(strm, flush)
+    }
+
+    /// Compress with lazy matching, levels 4 to 9: a match is only used if there is no
+    /// longer one at the next position.
+    fn deflate_slow(&mut self, strm: &mut Strm, flush: i32) -> BlockState {
+        loop {
+            // Make sure that we always have enough lookahead.
+            if self.lookahead < MIN_LOOKAHEAD {
+                self.fill_window(strm);
+                if self.lookahead < MIN_LOOKAHEAD && flush == Z_NO_FLUSH {
+                    return BlockState::NeedMore;
+                }
+                if self.lookahead == 0 {
+                    break;
+                }
+            }
+
+            let mut hash_head = NIL;
+            if self.lookahead >= MIN_MATCH {
+                hash_head = self.insert_string(self.strstart);
+            }
+
+            // Find the longest match, discarding those <= prev_length.
+            self.prev_length = self.match_length;
+            self.prev_match = self.match_start;
+            self.match_length = MIN_MATCH - 1;
+
+            if hash_head != NIL
+                && self.prev_length < self.max_lazy_match
+                && self.strstart.wrapping_sub(hash_head) <= MAX_DIST
+            {
+                self.match_length = self.longest_match(hash_head);
+
+                if self.match_length <= 5
+                    && (self.strategy == ZlibStrategy::Filtered
+                        || (self.match_length == MIN_MATCH
+                            && self.strstart - self.match_start > TOO_FAR))
+                {
+                    // If prev_match is also MIN_MATCH, match_start is garbage but we will
+                    // ignore the current match anyway.
+                    self.match_length = MIN_MATCH - 1;
+                }
+            }
+
+            // If there was a match at the previous step and the current match is not
+            // better, output the previous match.
+            if self.prev_length >= MIN_MATCH && self.match_length <= self.prev_length {
+                let max_insert = self.strstart + self.lookahead - MIN_MATCH;
+                let bflush = self.tally_dist(
+                    self.strstart - 1 - self.prev_match,
+                    self.prev_length - MIN_MATCH,
+                );
+
+                // Insert in the hash table all strings up to the end of the match.
+                self.lookahead -= self.prev_length - 1;
+                self.prev_length -= 2;
+                loop {
+                    self.strstart += 1;
+                    if self.strstart <= max_insert {
+                        self.insert_string(self.strstart);
+                    }
+                    self.prev_length -= 1;
+                    if self.prev_length == 0 {
+                        break;
+                    }
+                }
+                self.match_available = false;
+                self.match_length = MIN_MATCH - 1;
+                self.strstart += 1;
+
+                if bflush {
+                    self.flush_block_only(strm, false);
+                    if strm.out.avail_out() == 0 {
+                        return BlockState::NeedMore;
+                    }
+                }
+            } else if self.match_available {
+                // No better match, output the previous byte as a literal.
+                let c = self.window[self.strstart - 1];
+                if self.tally_lit(c) {
+                    self.flush_block_only(strm, false);
+                }
+                self.strstart += 1;
+                self.lookahead -= 1;
+                if strm.out.avail_out() == 0 {
+                    return BlockState::NeedMore;
+                }
+            } else {
+                // There is no previous match to compare with, wait for the next step to
+                // decide.
+                self.match_available = true;
+                self.strstart += 1;
+                self.lookahead -= 1;
+            }
+        }
+
+        if self.match_available {
+            let c = self.window[self.strstart - 1];
+            self.tally_lit(c);
+            self.match_available = false;
+        }
+        self.insert = cmp::min(self.strstart, MIN_MATCH - 1);
+        self.finish_parse(strm, flush)
+    }
+
+    /// Only look for runs of the same byte, the `Z_RLE` strategy.
+    fn deflate_rle(&mut self, strm: &mut Strm, flush: i32) -> BlockState {
+        loop {
+            // Make sure that we always have enough lookahead for the longest run.
+            if self.lookahead <= MAX_MATCH {
+                self.fill_window(strm);
+                if self.lookahead <= MAX_MATCH && flush == Z_NO_FLUSH {
+                    return BlockState::NeedMore;
+                }
+                if self.lookahead == 0 {
+                    break;
+                }
+            }
+
+            // See how many times the previous byte repeats.
+            self.match_length = 0;
+            if self.lookahead >= MIN_MATCH && self.strstart > 0 {
+                let scan = self.strstart;
+                let w = &self.window;
+                let prev = w[scan - 1];
+                if prev == w[scan] && prev == w[scan + 1] && prev == w[scan + 2] {
+                    let mut len = MIN_MATCH;
+                    while len < MAX_MATCH && w[scan + len] == prev {
+                        len += 1;
+                    }
+                    self.match_length = cmp::min(len, self.lookahead);
+                }
+            }
+
+            let bflush;
+            if self.match_length >= MIN_MATCH {
+                bflush = self.tally_dist(1, self.match_length - MIN_MATCH);
+                self.lookahead -= self.match_length;
+                self.strstart += self.match_length;
+                self.match_length = 0;
+            } else {
+                let c = self.window[self.strstart];
+                bflush = self.tally_lit(c);
+                self.lookahead -= 1;
+                self.strstart += 1;
+            }
+            if bflush {
+                self.flush_block_only(strm, false);
+                if strm.out.avail_out() == 0 {
+                    return BlockState::NeedMore;
+                }
+            }
+        }
+        self.insert = 0;
+        self.finish_parse(strm, flush)
+    }
+
+    /// Only huffman encode the input, the `Z_HUFFMAN_ONLY` strategy.
+    fn deflate_huff(&mut self, strm: &mut Strm, flush: i32) -> BlockState {
+        loop {
+            if self.lookahead == 0 {
+                self.fill_window(strm);
+                if self.lookahead == 0 {
+                    if flush == Z_NO_FLUSH {
+                        return BlockState::NeedMore;
+                    }
+                    break;
+                }
+            }
+
+            self.match_length = 0;
+            let c = self.window[self.strstart];
+            let bflush = self.tally_lit(c);
+            self.lookahead -= 1;
+            self.strstart += 1;
+            if bflush {
+                self.flush_block_only(strm, false);
+                if strm.out.avail_out() == 0 {
+                    return BlockState::NeedMore;
+                }
+            }
+        }
+        self.insert = 0;
+        self.finish_parse(strm, flush)
+    }
+}
@@ -0,0 +1,502 @@
+//! PNG-style predictor filters, for use as a transform before compression.
+//!
+//! Data made up of rows of fixed-size elements, such as images, sensor readings or columns of
+//! numbers, often compresses a lot better if each byte is replaced by its difference to a
+//! prediction made from the neighbouring bytes. The filters are the ones defined by the
+//! [PNG specification](https://www.w3.org/TR/PNG/#9Filters): each filtered row starts with a
+//! byte giving the filter type, followed by the filtered bytes.
+//!
+//! `RowFilter` and `RowUnfilter` take data in chunks of any size, so they can be placed in front
+//! of a `CompressorOxide` and behind an `InflateState`:
+//!
+//! ``` rust
+//! use miniz_oxide::deflate::compress_to_vec_zlib;
+//! use miniz_oxide::filter::{Filter, RowFilter, RowUnfilter};
+//! use miniz_oxide::inflate::decompress_to_vec_zlib;
+//!
+//! // 16 rows of 8 little-endian u32 values.
+//! let data: Vec<u8> = (0..128u32).flat_map(|n| (n * 1000).to_le_bytes().to_vec()).collect();
+//!
+//! let mut filter = RowFilter::new(Filter::Adaptive, 4, 32);
+//! let mut filtered = Vec::new();
+//! filter.push(&data, |row| filtered.extend_from_slice(row));
+//! let compressed = compress_to_vec_zlib(&filtered, 6);
+//!
+//! let mut unfilter = RowUnfilter::new(4, 32);
+//! let mut unfiltered = Vec::new();
+//! let decompressed = decompress_to_vec_zlib(&compressed).unwrap();
+//! unfilter.push(&decompressed, |row| unfiltered.extend_from_slice(row)).unwrap();
+//! assert_eq!(unfiltered, data);
+//! ```
+
+#[cfg(feature = "alloc")]
+use alloc::vec;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+#[cfg(feature = "alloc")]
+use core::{cmp, mem};
+
+use crate::MZError;
+
+/// Predictor filter applied to a single row, stored in the filter type byte in front of it.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FilterType {
+    /// Store the row as it is.
+    None = 0,
+    /// Store the difference to the byte `bytes_per_pixel` to the left.
+    Sub = 1,
+    /// Store the difference to the byte above.
+    Up = 2,
+    /// Store the difference to the average of the bytes to the left and above.
+    Average = 3,
+    /// Store the difference to the Paeth predictor of the bytes to the left, above and
+    /// above left.
+    Paeth = 4,
+}
+
+impl FilterType {
+    /// All the filter types, in the order of their values.
+    pub const ALL: [FilterType; 5] = [
+        FilterType::None,
+        FilterType::Sub,
+        FilterType::Up,
+        FilterType::Average,
+        FilterType::Paeth,
+    ];
+
+    /// Get the filter stored in a filter type byte, or `None` if it's not a valid filter type.
+    #[inline]
+    pub fn from_u8(value: u8) -> Option<FilterType> {
+        FilterType::ALL.get(usize::from(value)).cloned()
+    }
+}
+
+/// Predictor filter for `RowFilter` to apply to the rows.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Filter {
+    /// Use `FilterType::None` for every row.
+    None,
+    /// Use `FilterType::Sub` for every row.
+    Sub,
+    /// Use `FilterType::Up` for every row.
+    Up,
+    /// Use `FilterType::Average` for every row.
+    Average,
+    /// Use `FilterType::Paeth` for every row.
+    Paeth,
+    /// Pick the filter type for each row, using the minimum sum of absolute differences
+    /// heuristic suggested by the PNG specification.
+    Adaptive,
+}
+
+impl Filter {
+    /// The filter type used for every row, or `None` for `Filter::Adaptive`.
+    #[inline]
+    pub fn filter_type(self) -> Option<FilterType> {
+        match self {
+            Filter::None => Some(FilterType::None),
+            Filter::Sub => Some(FilterType::Sub),
+            Filter::Up => Some(FilterType::Up),
+            Filter::Average => Some(FilterType::Average),
+            Filter::Paeth => Some(FilterType::Paeth),
+            Filter::Adaptive => None,
+        }
+    }
+}
+
+impl From<FilterType> for Filter {
+    #[inline]
+    fn from(filter_type: FilterType) -> Filter {
+        match filter_type {
+            FilterType::None => Filter::None,
+            FilterType::Sub => Filter::Sub,
+            FilterType::Up => Filter::Up,
+            FilterType::Average => Filter::Average,
+            FilterType::Paeth => Filter::Paeth,
+        }
+    }
+}
+
+#[inline]
+fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let pa = (p - i16::from(a)).abs();
+    let pb = (p - i16::from(b)).abs();
+    let pc = (p - i16::from(c)).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Filter `row` into `out` using `filter`.
+///
+/// `prev` is the previous, unfiltered, row, which should be all zeros for the first row.
+/// `bpp` is the distance in bytes to the byte considered to be to the left, e.g. the number of
+/// bytes per pixel for images. The filter type byte is not written to `out`.
+///
+/// # Panics
+/// Panics if `bpp` is 0 or if `prev` or `out` are shorter than `row`.
+pub fn filter_row(filter: FilterType, bpp: usize, prev: &[u8], row: &[u8], out: &mut [u8]) {
+    assert!(bpp > 0);
+    let len = row.len();
+    let (prev, out) = (&prev[..len], &mut out[..len]);
+    let first = bpp.min(len);
+    match filter {
+        FilterType::None => out.copy_from_slice(row),
+        FilterType::Sub => {
+            out[..first].copy_from_slice(&row[..first]);
+            for i in first..len {
+                out[i] = row[i].wrapping_sub(row[i - bpp]);
+            }
+        }
+        FilterType::Up => {
+            for i in 0..len {
+                out[i] = row[i].wrapping_sub(prev[i]);
+            }
+        }
+        FilterType::Average => {
+            for i in 0..first {
+                out[i] = row[i].wrapping_sub(prev[i] / 2);
+            }
+            for i in first..len {
+                let avg = (u16::from(row[i - bpp]) + u16::from(prev[i])) / 2;
+                out[i] = row[i].wrapping_sub(avg as u8);
+            }
+        }
+        FilterType::Paeth => {
+            for i in 0..first {
+                out[i] = row[i].wrapping_sub(prev[i]);
+            }
+            for i in first..len {
+                let pred = paeth_predictor(row[i - bpp], prev[i], prev[i - bpp]);
+                out[i] = row[i].wrapping_sub(pred);
+            }
+        }
+    }
+}
+
+/// Undo `filter_row`, unfiltering `row` in place.
+///
+/// `prev` is the previous, already unfiltered, row, or all zeros for the first row.
+///
+/// # Panics
+/// Panics if `bpp` is 0 or if `prev` is shorter than `row`.
+pub fn unfilter_row(filter: FilterType, bpp: usize, prev: &[u8], row: &mut [u8]) {
+    assert!(bpp > 0);
+    let len = row.len();
+    let prev = &prev[..len];
+    let first = bpp.min(len);
+    match filter {
+        FilterType::None => (),
+        FilterType::Sub => {
+            for i in first..len {
+                row[i] = row[i].wrapping_add(row[i - bpp]);
+            }
+        }
+        FilterType::Up => {
+            for i in 0..len {
+                row[i] = row[i].wrapping_add(prev[i]);
+            }
+        }
+        FilterType::Average => {
+            for i in 0..first {
+                row[i] = row[i].wrapping_add(prev[i] / 2);
+            }
+            for i in first..len {
+                let avg = (u16::from(row[i - bpp]) + u16::from(prev[i])) / 2;
+                row[i] = row[i].wrapping_add(avg as u8);
+            }
+        }
+        FilterType::Paeth => {
+            for i in 0..first {
+                row[i] = row[i].wrapping_add(prev[i]);
+            }
+            for i in first..len {
+                let pred = paeth_predictor(row[i - bpp], prev[i], prev[i - bpp]);
+                row[i] = row[i].wrapping_add(pred);
+            }
+        }
+    }
+}
+
+/// Sum of the filtered bytes seen as signed values, used to pick a filter for
+/// `Filter::Adaptive`.
+#[cfg(feature = "alloc")]
+fn filtered_row_cost(filtered: &[u8]) -> u64 {
+    filtered
+        .iter()
+        .map(|&b| u64::from((b as i8).wrapping_abs() as u8))
+        .sum()
+}
+
+/// Streaming filter, splitting the data pushed into it into rows and filtering them.
+#[cfg(feature = "alloc")]
+#[derive(Clone)]
+pub struct RowFilter {
+    filter: Filter,
+    bpp: usize,
+    /// The previous unfiltered row.
+    prev: Vec<u8>,
+    /// Buffered part of the current row, if it was split between calls to `push`.
+    row: Vec<u8>,
+    row_pos: usize,
+    /// The filter type byte followed by the filtered row.
+    out: Vec<u8>,
+    /// Scratch space for trying out filters with `Filter::Adaptive`.
+    candidate: Vec<u8>,
+}
+
+#[cfg(feature = "alloc")]
+impl RowFilter {
+    /// Create a filter for rows of `row_len` bytes, where the byte to the left is `bpp` bytes
+    /// back.
+    ///
+    /// # Panics
+    /// Panics if `bpp` or `row_len` is 0.
+    pub fn new(filter: Filter, bpp: usize, row_len: usize) -> RowFilter {
+        assert!(bpp > 0 && row_len > 0);
+        RowFilter {
+            filter,
+            bpp,
+            prev: vec![0; row_len],
+            row: vec![0; row_len],
+            row_pos: 0,
+            out: vec![0; row_len + 1],
+            candidate: if filter == Filter::Adaptive {
+                vec![0; row_len + 1]
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    /// Filter `data`, calling `row_func` with each completed row, which is one byte longer
+    /// than the rows that were pushed as it starts with the filter type.
+    ///
+    /// Data not making up a complete row is buffered until the next call.
+    pub fn push(&mut self, mut data: &[u8], mut row_func: impl FnMut(&[u8])) {
+        let row_len = self.prev.len();
+        while !data.is_empty() {
+            if self.row_pos == 0 && data.len() >= row_len {
+                // Filter straight from the input if we have a whole row.
+                let (row, rest) = data.split_at(row_len);
+                self.filter_row(row);
+                self.prev.copy_from_slice(row);
+                data = rest;
+            } else {
+                let n = cmp::min(row_len - self.row_pos, data.len());
+                self.row[self.row_pos..self.row_pos + n].copy_from_slice(&data[..n]);
+                self.row_pos += n;
+                data = &data[n..];
+                if self.row_pos < row_len {
+                    break;
+                }
+                self.row_pos = 0;
+                let row = mem::replace(&mut self.row, Vec::new());
+                self.filter_row(&row);
+                self.prev.copy_from_slice(&row);
+                self.row = row;
+            }
+            row_func(&self.out);
+        }
+    }
+
+    fn filter_row(&mut self, row: &[u8]) {
+        if let Some(filter) = self.filter.filter_type() {
+            self.out[0] = filter as u8;
+            filter_row(filter, self.bpp, &self.prev, row, &mut self.out[1..]);
+        } else {
+            let mut best_cost = u64::max_value();
+            for &filter in FilterType::ALL.iter() {
+                self.candidate[0] = filter as u8;
+                filter_row(filter, self.bpp, &self.prev, row, &mut self.candidate[1..]);
+                let cost = filtered_row_cost(&self.candidate[1..]);
+                if cost < best_cost {
+                    best_cost = cost;
+                    mem::swap(&mut self.out, &mut self.candidate);
+                }
+            }
+        }
+    }
+
+    /// Number of bytes buffered waiting for the rest of the row.
+    pub fn pending(&self) -> usize {
+        self.row_pos
+    }
+
+    /// Reset the filter to start filtering a new set of rows, dropping any buffered data.
+    pub fn reset(&mut self) {
+        self.prev.iter_mut().for_each(|b| *b = 0);
+        self.row_pos = 0;
+    }
+}
+
+/// Streaming unfilter, reversing what `RowFilter` does.
+#[cfg(feature = "alloc")]
+#[derive(Clone)]
+pub struct RowUnfilter {
+    bpp: usize,
+    /// The previous unfiltered row.
+    prev: Vec<u8>,
+    /// The filter type byte followed by the current row.
+    row: Vec<u8>,
+    row_pos: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl RowUnfilter {
+    /// Create an unfilter for rows that were `row_len` bytes long before filtering, where the
+    /// byte to the left is `bpp` bytes back.
+    ///
+    /// # Panics
+    /// Panics if `bpp` or `row_len` is 0.
+    pub fn new(bpp: usize, row_len: usize) -> RowUnfilter {
+        assert!(bpp > 0 && row_len > 0);
+        RowUnfilter {
+            bpp,
+            prev: vec![0; row_len],
+            row: vec![0; row_len + 1],
+            row_pos: 0,
+        }
+    }
+
+    /// Unfilter `data`, calling `row_func` with each completed row.
+    ///
+    /// Data not making up a complete row is buffered until the next call.
+    ///
+    /// # Errors
+    /// Returns `MZError::Data` if a row starts with an invalid filter type.
+    pub fn push(
+        &mut self,
+        mut data: &[u8],
+        mut row_func: impl FnMut(&[u8]),
+    ) -> Result<(), MZError> {
+        while !data.is_empty() {
+            let n = cmp::min(self.row.len() - self.row_pos, data.len());
+            self.row[self.row_pos..self.row_pos + n].copy_from_slice(&data[..n]);
+            self.row_pos += n;
+            data = &data[n..];
+            if self.row_pos < self.row.len() {
+                break;
+            }
+            self.row_pos = 0;
+
+            let filter = FilterType::from_u8(self.row[0]).ok_or(MZError::Data)?;
+            unfilter_row(filter, self.bpp, &self.prev, &mut self.row[1..]);
+            self.prev.copy_from_slice(&self.row[1..]);
+            row_func(&self.prev);
+        }
+        Ok(())
+    }
+
+    /// Number of bytes buffered waiting for the rest of the row.
+    pub fn pending(&self) -> usize {
+        self.row_pos
+    }
+
+    /// Reset the unfilter to start on a new set of rows, dropping any buffered data.
+    pub fn reset(&mut self) {
+        self.prev.iter_mut().for_each(|b| *b = 0);
+        self.row_pos = 0;
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod test {
+    use super::*;
+    use crate::test_util::test_data;
+
+    #[test]
+    fn filter_unfilter_row() {
+        let prev = test_data(50);
+        let row: Vec<u8> = test_data(51)[1..].to_vec();
+        for &bpp in &[1, 3, 4, 8, 60] {
+            for &filter in FilterType::ALL.iter() {
+                let mut filtered = vec![0; row.len()];
+                filter_row(filter, bpp, &prev, &row, &mut filtered);
+                if filter == FilterType::None {
+                    assert_eq!(filtered, row);
+                }
+                unfilter_row(filter, bpp, &prev, &mut filtered);
+                assert_eq!(filtered, row);
+            }
+        }
+    }
+
+    #[test]
+    fn paeth_filter() {
+        let prev = [10, 20, 38, 40];
+        let row = [12, 25, 31, 200];
+        let mut out = [0; 4];
+        filter_row(FilterType::Paeth, 1, &prev, &row, &mut out);
+        // The first byte only has the byte above, then the predictor picks the byte above
+        // twice and the byte to the left last.
+        assert_eq!(out, [2, 5, 249, 169]);
+    }
+
+    #[test]
+    fn row_filter_streaming() {
+        let (bpp, row_len) = (3, 30);
+        let data = test_data(row_len * 20);
+        let filters = FilterType::ALL.iter().map(|&t| Filter::from(t));
+        for filter in filters.chain(Some(Filter::Adaptive)) {
+            let mut whole = Vec::new();
+            RowFilter::new(filter, bpp, row_len).push(&data, |row| whole.extend_from_slice(row));
+            assert_eq!(whole.len(), (row_len + 1) * 20);
+            if let Some(filter_type) = filter.filter_type() {
+                assert!(whole.chunks(row_len + 1).all(|r| r[0] == filter_type as u8));
+            }
+
+            // Splitting the input up shouldn't change the output.
+            for &chunk_size in &[1, 7, 29, 31, 100] {
+                let mut f = RowFilter::new(filter, bpp, row_len);
+                let mut filtered = Vec::new();
+                for chunk in data.chunks(chunk_size) {
+                    f.push(chunk, |row| filtered.extend_from_slice(row));
+                }
+                assert_eq!(f.pending(), 0);
+                assert_eq!(filtered, whole);
+
+                let mut u = RowUnfilter::new(bpp, row_len);
+                let mut unfiltered = Vec::new();
+                for chunk in filtered.chunks(chunk_size) {
+                    u.push(chunk, |row| unfiltered.extend_from_slice(row))
+                        .unwrap();
+                }
+                assert_eq!(u.pending(), 0);
+                assert_eq!(unfiltered, data);
+            }
+        }
+    }
+
+    #[test]
+    fn row_filter_pending_and_reset() {
+        let data = test_data(10);
+        let mut f = RowFilter::new(Filter::Up, 1, 4);
+        let mut rows = 0;
+        f.push(&data, |_| rows += 1);
+        assert_eq!((rows, f.pending()), (2, 2));
+
+        f.reset();
+        let mut first = Vec::new();
+        f.push(&data[..4], |row| first.extend_from_slice(row));
+        // After a reset, the previous row is zero again.
+        assert_eq!(first, [2, data[0], data[1], data[2], data[3]]);
+    }
+
+    #[test]
+    fn row_unfilter_invalid_filter() {
+        let mut u = RowUnfilter::new(1, 2);
+        let mut rows = 0;
+        assert_eq!(
+            u.push(&[0, 1, 2, 5, 1, 2], |_| rows += 1),
+            Err(MZError::Data)
+        );
+        assert_eq!(rows, 1);
+    }
+}
@@ -1,5 +1,6 @@
 //! Streaming decompression functionality.
 
+use super::huffman::*;
 use super::*;
 use crate::shared::{update_adler32, HUFFMAN_LENGTH_ORDER};
 
@@ -7,84 +8,11 @@ use ::core::convert::TryInto;
 use ::core::{cmp, slice};
 
 use self::output_buffer::OutputBuffer;
+pub use crate::BlockType;
+use crate::{LZToken, MZError};
 
 pub const TINFL_LZ_DICT_SIZE: usize = 32_768;
 
-/// A struct containing huffman code lengths and the huffman code tree used by the decompressor.
-struct HuffmanTable {
-    /// Length of the code at each index.
-    pub code_size: [u8; MAX_HUFF_SYMBOLS_0],
-    /// Fast lookup table for shorter huffman codes.
-    ///
-    /// See `HuffmanTable::fast_lookup`.
-    pub look_up: [i16; FAST_LOOKUP_SIZE as usize],
-    /// Full huffman tree.
-    ///
-    /// Positive values are edge nodes/symbols, negative values are
-    /// parent nodes/references to other nodes.
-    pub tree: [i16; MAX_HUFF_TREE_SIZE],
-}
-
-impl HuffmanTable {
-    fn new() -> HuffmanTable {
-        HuffmanTable {
-            code_size: [0; MAX_HUFF_SYMBOLS_0],
-            look_up: [0; FAST_LOOKUP_SIZE as usize],
-            tree: [0; MAX_HUFF_TREE_SIZE],
-        }
-    }
-
-    /// Look for a symbol in the fast lookup table.
-    /// The symbol is stored in the lower 9 bits, the length in the next 6.
-    /// If the returned value is negative, the code wasn't found in the
-    /// fast lookup table and the full tree has to be traversed to find the code.
-    #[inline]
-    fn fast_lookup(&self, bit_buf: BitBuffer) -> i16 {
-        self.look_up[(bit_buf & BitBuffer::from(FAST_LOOKUP_SIZE - 1)) as usize]
-    }
-
-    /// Get the symbol and the code length from the huffman tree.
-    #[inline]
-    fn tree_lookup(&self, fast_symbol: i32, bit_buf: BitBuffer, mut code_len: u32) -> (i32, u32) {
-        let mut symbol = fast_symbol;
-        // We step through the tree until we encounter a positive value, which indicates a
-        // symbol.
-        loop {
-            // symbol here indicates the position of the left (0) node, if the next bit is 1
-            // we add 1 to the lookup position to get the right node.
-            symbol = i32::from(self.tree[(!symbol + ((bit_buf >> code_len) & 1) as i32) as usize]);
-            code_len += 1;
-            if symbol >= 0 {
-                break;
-            }
-        }
-        (symbol, code_len)
-    }
-
-    #[inline]
-    /// Look up a symbol and code length from the bits in the provided bit buffer.
-    ///
-    /// Returns Some(symbol, length) on success,
-    /// None if the length is 0.
-    ///
-    /// It's possible we could avoid checking for 0 if we can guarantee a sane table.
-    /// TODO: Check if a smaller type for code_len helps performance.
-    fn lookup(&self, bit_buf: BitBuffer) -> Option<(i32, u32)> {
-        let symbol = self.fast_lookup(bit_buf).into();
-        if symbol >= 0 {
-            if (symbol >> 9) as u32 != 0 {
-                Some((symbol, (symbol >> 9) as u32))
-            } else {
-                // Zero-length code.
-                None
-            }
-        } else {
-            // We didn't get a symbol from the fast lookup table, so check the tree instead.
-            Some(self.tree_lookup(symbol, bit_buf, FAST_LOOKUP_BITS.into()))
-        }
-    }
-}
-
 /// The number of huffman tables used.
 const MAX_HUFF_TABLES: usize = 3;
 /// The length of the first (literal/length) huffman table.
@@ -93,11 +21,6 @@ const MAX_HUFF_SYMBOLS_0: usize = 288;
 const MAX_HUFF_SYMBOLS_1: usize = 32;
 /// The length of the last (huffman code length) huffman table.
 const _MAX_HUFF_SYMBOLS_2: usize = 19;
-/// The maximum length of a code that can be looked up in the fast lookup table.
-const FAST_LOOKUP_BITS: u8 = 10;
-/// The size of the fast lookup table.
-const FAST_LOOKUP_SIZE: u32 = 1 << FAST_LOOKUP_BITS;
-const MAX_HUFF_TREE_SIZE: usize = MAX_HUFF_SYMBOLS_0 * 2;
 const LITLEN_TABLE: usize = 0;
 const DIST_TABLE: usize = 1;
 const HUFFLEN_TABLE: usize = 2;
@@ -148,22 +71,94 @@ pub struct DecompressorOxide {
     counter: u32,
     /// Number of extra bits for the last length or distance code.
     num_extra: u32,
+    /// Number of bits read of the current literal, or length and distance codes including
+    /// their extra bits.
+    code_bits: u32,
+    /// Length of the match being copied in `WriteLenBytesToEnd`.
+    match_len: u32,
     /// Number of entries in each huffman table.
     table_sizes: [u32; MAX_HUFF_TABLES],
     /// Buffer of input data.
     bit_buf: BitBuffer,
-    /// Huffman tables.
-    tables: [HuffmanTable; MAX_HUFF_TABLES],
+    /// Code lengths of the literal/length, distance and code length huffman codes.
+    code_sizes: [[u8; MAX_HUFF_SYMBOLS_0]; MAX_HUFF_TABLES],
+    /// Lookup table for the literal/length huffman code, see `DecompressorOxide::lookup`.
+    litlen_table: [u32; LITLEN_TABLE_SIZE],
+    /// Lookup table for the distance huffman code.
+    dist_table: [u32; DIST_TABLE_SIZE],
+    /// Lookup table for the huffman code used to send the code lengths of the other two.
+    hufflen_table: [u32; PRECODE_TABLE_SIZE],
     /// Raw block header.
     raw_header: [u8; 4],
     /// Huffman length codes.
     len_codes: [u8; MAX_HUFF_SYMBOLS_0 + MAX_HUFF_SYMBOLS_1 + 137],
+    /// Number of input bytes consumed since the start of the stream.
+    total_in: u64,
+}
+
+impl Clone for DecompressorOxide {
+    fn clone(&self) -> Self {
+        let mut clone = DecompressorOxide::new();
+        clone.clone_from(self);
+        clone
+    }
+
+    /// Copy in place, avoiding a large temporary on the stack.
+    fn clone_from(&mut self, source: &Self) {
+        self.state = source.state;
+        self.num_bits = source.num_bits;
+        self.z_header0 = source.z_header0;
+        self.z_header1 = source.z_header1;
+        self.z_adler32 = source.z_adler32;
+        self.finish = source.finish;
+        self.block_type = source.block_type;
+        self.check_adler32 = source.check_adler32;
+        self.dist = source.dist;
+        self.counter = source.counter;
+        self.num_extra = source.num_extra;
+        self.code_bits = source.code_bits;
+        self.match_len = source.match_len;
+        self.table_sizes = source.table_sizes;
+        self.bit_buf = source.bit_buf;
+        self.code_sizes.copy_from_slice(&source.code_sizes);
+        self.litlen_table.copy_from_slice(&source.litlen_table);
+        self.dist_table.copy_from_slice(&source.dist_table);
+        self.hufflen_table.copy_from_slice(&source.hufflen_table);
+        self.raw_header = source.raw_header;
+        self.len_codes.copy_from_slice(&source.len_codes);
+        self.total_in = source.total_in;
+    }
 }
 
 impl DecompressorOxide {
     /// Create a new tinfl_decompressor with all fields set to 0.
-    pub fn new() -> DecompressorOxide {
-        DecompressorOxide::default()
+    ///
+    /// This is a `const fn`, so the decompressor can be put in a `static`.
+    pub const fn new() -> DecompressorOxide {
+        DecompressorOxide {
+            state: core::State::Start,
+            num_bits: 0,
+            z_header0: 0,
+            z_header1: 0,
+            z_adler32: 0,
+            finish: 0,
+            block_type: 0,
+            check_adler32: 0,
+            dist: 0,
+            counter: 0,
+            num_extra: 0,
+            code_bits: 0,
+            match_len: 0,
+            table_sizes: [0; MAX_HUFF_TABLES],
+            bit_buf: 0,
+            code_sizes: [[0; MAX_HUFF_SYMBOLS_0]; MAX_HUFF_TABLES],
+            litlen_table: [0; LITLEN_TABLE_SIZE],
+            dist_table: [0; DIST_TABLE_SIZE],
+            hufflen_table: [0; PRECODE_TABLE_SIZE],
+            raw_header: [0; 4],
+            len_codes: [0; MAX_HUFF_SYMBOLS_0 + MAX_HUFF_SYMBOLS_1 + 137],
+            total_in: 0,
+        }
     }
 
     /// Set the current state to `Start`.
@@ -182,35 +177,171 @@ impl DecompressorOxide {
             None
         }
     }
+
+    /// Returns why decompression failed, if the decompressor is in a failure state.
+    ///
+    /// This does not cover checksum mismatches, which are reported with
+    /// `TINFLStatus::Adler32Mismatch` once the whole stream has been decompressed.
+    pub fn failure_reason(&self) -> Option<FailureReason> {
+        match self.state {
+            BadZlibHeader => Some(FailureReason::BadZlibHeader),
+            BlockTypeUnexpected => Some(FailureReason::BlockTypeUnexpected),
+            BadRawLength => Some(FailureReason::BadRawLength),
+            BadTotalSymbols => Some(FailureReason::BadTotalSymbols),
+            BadCodeSizeDistPrevLookup => Some(FailureReason::BadCodeSizeDistPrevLookup),
+            BadCodeSizeSum => Some(FailureReason::BadCodeSizeSum),
+            InvalidLitlen => Some(FailureReason::InvalidLitlen),
+            InvalidDist => Some(FailureReason::InvalidDist),
+            InvalidCodeLen => Some(FailureReason::InvalidCodeLen),
+            DistanceOutOfBounds => Some(FailureReason::DistanceOutOfBounds),
+            _ => None,
+        }
+    }
+
+    /// Insert `bits` bits of `value` into the input bit buffer, to be read before the
+    /// remaining input.
+    ///
+    /// This is useful to start decompressing a raw deflate stream that does not start at a
+    /// byte boundary. Priming a decompressor that has not started yet skips the zlib header,
+    /// so it should only be done for raw streams.
+    ///
+    /// # Errors
+    /// Returns `MZError::Stream` if `bits` is larger than 16, or if there is not enough space
+    /// left in the bit buffer (at most 32 bits can be buffered).
+    pub fn prime(&mut self, bits: u32, value: u32) -> Result<(), MZError> {
+        if bits > 16 || self.num_bits + bits > 32 {
+            return Err(MZError::Stream);
+        }
+        if bits == 0 {
+            return Ok(());
+        }
+        if self.state == State::Start {
+            // The start state clears the bit buffer, so do its work here and go straight to
+            // the first block header.
+            self.bit_buf = 0;
+            self.num_bits = 0;
+            self.dist = 0;
+            self.counter = 0;
+            self.num_extra = 0;
+            self.z_header0 = 0;
+            self.z_header1 = 0;
+            self.z_adler32 = 1;
+            self.check_adler32 = 1;
+            self.total_in = 0;
+            self.state = State::ReadBlockHeader;
+        }
+        let value = value & ((1 << bits) - 1);
+        self.bit_buf |= BitBuffer::from(value) << self.num_bits;
+        self.num_bits += bits;
+        Ok(())
+    }
+
+    /// Returns where in the deflate stream the decompressor currently is.
+    pub fn block_position(&self) -> BlockPosition {
+        match self.state {
+            BlockTypeNoCompression | RawHeader => BlockPosition::Stored { remaining: 0 },
+            RawReadFirstByte | RawStoreFirstByte | RawMemcpy1 | RawMemcpy2 => {
+                BlockPosition::Stored {
+                    remaining: self.counter,
+                }
+            }
+            ReadTableSizes
+            | ReadHufflenTableCodeSize
+            | ReadLitlenDistTablesCodeSize
+            | ReadExtraBitsCodeSize => BlockPosition::Tables,
+            DecodeLitlen => BlockPosition::Compressed {
+                code_bits: 0,
+                written: 0,
+            },
+            WriteSymbol
+            | ReadExtraBitsLitlen
+            | DecodeDistance
+            | ReadExtraBitsDistance
+            | HuffDecodeOuterLoop2 => BlockPosition::Compressed {
+                code_bits: self.code_bits,
+                written: 0,
+            },
+            WriteLenBytesToEnd => BlockPosition::Compressed {
+                code_bits: self.code_bits,
+                written: self.match_len - self.counter,
+            },
+            _ => BlockPosition::Outside,
+        }
+    }
+
+    /// Whether the decompressor is about to read a block header at the start of the next byte
+    /// of input, with nothing left over in the bit buffer.
+    ///
+    /// This is the case after a sync or full flush, where the next block can also be found by
+    /// a decompressor starting at that byte.
+    pub(crate) fn at_byte_aligned_block_start(&self) -> bool {
+        self.state == State::ReadBlockHeader && self.num_bits == 0
+    }
+
+    /// Look up the entry for the next code of the given huffman table in the bit buffer.
+    ///
+    /// Codes of up to 11 bits for literal/lengths (8 for distances) are resolved with a single
+    /// lookup, longer ones with one more lookup in a subtable. The entries for literal/length
+    /// and distance codes carry the base value and number of extra bits of the symbol, see the
+    /// `huffman` module for the layout.
+    #[inline(always)]
+    fn lookup(&self, table: usize, bit_buf: BitBuffer) -> u32 {
+        let bit_buf = bit_buf as u64;
+        match table {
+            LITLEN_TABLE => lookup(&self.litlen_table, LITLEN_TABLE_BITS, bit_buf),
+            DIST_TABLE => lookup(&self.dist_table, DIST_TABLE_BITS, bit_buf),
+            _ => lookup(&self.hufflen_table, PRECODE_TABLE_BITS, bit_buf),
+        }
+    }
+}
+
+/// Where in the deflate stream a decompressor is, see
+/// [`DecompressorOxide::block_position`](struct.DecompressorOxide.html#method.block_position).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BlockPosition {
+    /// Not inside a block, e.g in the zlib header or trailer, or between two blocks.
+    Outside,
+    /// Inside a stored block, with `remaining` bytes left to copy from the input.
+    Stored { remaining: u32 },
+    /// Reading the huffman table definitions at the start of a dynamic block.
+    Tables,
+    /// Inside the data of a compressed block, `code_bits` bits into the current literal, or
+    /// length and distance codes including their extra bits, of which `written` bytes have
+    /// been output.
+    Compressed { code_bits: u32, written: u32 },
+}
+
+/// Why decompression failed, see
+/// [`DecompressorOxide::failure_reason`](struct.DecompressorOxide.html#method.failure_reason).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FailureReason {
+    /// The zlib header is invalid, or uses an unsupported compression method or window size.
+    BadZlibHeader,
+    /// A block uses the reserved block type.
+    BlockTypeUnexpected,
+    /// The length of a stored block doesn't match its ones complement.
+    BadRawLength,
+    /// The code lengths of a huffman table don't form a valid code.
+    BadTotalSymbols,
+    /// The code lengths of a dynamic block start with a repeat of the previous length.
+    BadCodeSizeDistPrevLookup,
+    /// A repeated code length goes past the end of the literal/length and distance codes.
+    BadCodeSizeSum,
+    /// A literal/length symbol is outside of the valid range (286 and 287 are invalid).
+    InvalidLitlen,
+    /// A distance symbol is outside of the valid range (30 and 31 are invalid).
+    InvalidDist,
+    /// The input contains a code that isn't used in the huffman table.
+    InvalidCodeLen,
+    /// A match refers to data before the start of the output.
+    DistanceOutOfBounds,
 }
 
 impl Default for DecompressorOxide {
     /// Create a new tinfl_decompressor with all fields set to 0.
     #[inline(always)]
     fn default() -> Self {
-        DecompressorOxide {
-            state: core::State::Start,
-            num_bits: 0,
-            z_header0: 0,
-            z_header1: 0,
-            z_adler32: 0,
-            finish: 0,
-            block_type: 0,
-            check_adler32: 0,
-            dist: 0,
-            counter: 0,
-            num_extra: 0,
-            table_sizes: [0; MAX_HUFF_TABLES],
-            bit_buf: 0,
-            // TODO:(oyvindln) Check that copies here are optimized out in release mode.
-            tables: [
-                HuffmanTable::new(),
-                HuffmanTable::new(),
-                HuffmanTable::new(),
-            ],
-            raw_header: [0; 4],
-            len_codes: [0; MAX_HUFF_SYMBOLS_0 + MAX_HUFF_SYMBOLS_1 + 137],
-        }
+        DecompressorOxide::new()
     }
 }
 
@@ -237,7 +368,6 @@ enum State {
     RawStoreFirstByte,
     WriteLenBytesToEnd,
     BlockDone,
-    HuffDecodeOuterLoop1,
     HuffDecodeOuterLoop2,
     ReadAdler32,
 
@@ -291,21 +421,21 @@ use self::State::*;
 /// The base is used together with the value of the extra bits to decode the actual
 /// length/distance values in a match.
 #[rustfmt::skip]
-const LENGTH_BASE: [u16; 32] = [
+pub(crate) const LENGTH_BASE: [u16; 32] = [
     3,  4,  5,  6,  7,  8,  9,  10,  11,  13,  15,  17,  19,  23,  27,  31,
     35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258, 512, 512, 512
 ];
 
 /// Number of extra bits for each length code.
 #[rustfmt::skip]
-const LENGTH_EXTRA: [u8; 32] = [
+pub(crate) const LENGTH_EXTRA: [u8; 32] = [
     0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
     3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0, 0, 0, 0
 ];
 
 /// Base length for each distance code.
 #[rustfmt::skip]
-const DIST_BASE: [u16; 32] = [
+pub(crate) const DIST_BASE: [u16; 32] = [
     1,    2,    3,    4,    5,    7,      9,      13,     17,     25,    33,
     49,   65,   97,   129,  193,  257,    385,    513,    769,    1025,  1537,
     2049, 3073, 4097, 6145, 8193, 12_289, 16_385, 24_577, 32_768, 32_768
@@ -313,14 +443,11 @@ const DIST_BASE: [u16; 32] = [
 
 /// Number of extra bits for each distance code.
 #[rustfmt::skip]
-const DIST_EXTRA: [u8; 32] = [
+pub(crate) const DIST_EXTRA: [u8; 32] = [
     0, 0, 0, 0, 1, 1, 2,  2,  3,  3,  4,  4,  5,  5,  6,  6,
     7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13, 13, 13
 ];
 
-/// The mask used when indexing the base/extra arrays.
-const BASE_EXTRA_MASK: usize = 32 - 1;
-
 /// Sets the value of all the elements of the slice to `val`.
 #[inline]
 fn memset<T: Copy>(slice: &mut [T], val: T) {
@@ -422,8 +549,7 @@ enum Action {
     End(TINFLStatus),
 }
 
-/// Try to decode the next huffman code, and puts it in the counter field of the decompressor
-/// if successful.
+/// Try to decode the next huffman code, and pass its lookup table entry to `f` if successful.
 ///
 /// # Returns
 /// The specified action returned from `f` on success,
@@ -437,7 +563,7 @@ fn decode_huffman_code<F>(
     f: F,
 ) -> Action
 where
-    F: FnOnce(&mut DecompressorOxide, &mut LocalVars, i32) -> Action,
+    F: FnOnce(&mut DecompressorOxide, &mut LocalVars, u32) -> Action,
 {
     // As the huffman codes can be up to 15 bits long we need at least 15 bits
     // ready in the bit buffer to start decoding the next huffman code.
@@ -457,28 +583,11 @@ where
             //  * until the */
             // /* bit buffer contains >=15 bits (deflate's max. Huffman code size). */
             loop {
-                let mut temp = i32::from(r.tables[table].fast_lookup(l.bit_buf));
-
-                if temp >= 0 {
-                    let code_len = (temp >> 9) as u32;
-                    if (code_len != 0) && (l.num_bits >= code_len) {
-                        break;
-                    }
-                } else if l.num_bits > FAST_LOOKUP_BITS.into() {
-                    let mut code_len = u32::from(FAST_LOOKUP_BITS);
-                    loop {
-                        temp = i32::from(
-                            r.tables[table].tree
-                                [(!temp + ((l.bit_buf >> code_len) & 1) as i32) as usize],
-                        );
-                        code_len += 1;
-                        if temp >= 0 || l.num_bits < code_len + 1 {
-                            break;
-                        }
-                    }
-                    if temp >= 0 {
-                        break;
-                    }
+                // The code can be decoded if all of its bits are available. Unused codes take up
+                // the maximum code length, so they are only reported once 15 bits are available.
+                let code_len = r.lookup(table, l.bit_buf) & ENTRY_LEN_MASK;
+                if code_len <= l.num_bits {
+                    break;
                 }
 
                 // TODO: miniz jumps straight to here after getting here again after failing to read
@@ -512,29 +621,15 @@ where
     }
 
     // We now have at least 15 bits in the input buffer.
-    let mut symbol = i32::from(r.tables[table].fast_lookup(l.bit_buf));
-    let code_len;
-    // If the symbol was found in the fast lookup table.
-    if symbol >= 0 {
-        // Get the length value from the top bits.
-        // As we shift down the sign bit, converting to an unsigned value
-        // shouldn't overflow.
-        code_len = (symbol >> 9) as u32;
-        // Mask out the length value.
-        symbol &= 511;
-    } else {
-        let res = r.tables[table].tree_lookup(symbol, l.bit_buf, u32::from(FAST_LOOKUP_BITS));
-        symbol = res.0;
-        code_len = res.1 as u32;
-    };
-
-    if code_len == 0 {
+    let entry = r.lookup(table, l.bit_buf);
+    if entry & ENTRY_UNUSED_CODE != 0 {
         return Action::Jump(InvalidCodeLen);
     }
 
-    l.bit_buf >>= code_len as u32;
+    let code_len = entry & ENTRY_LEN_MASK;
+    l.bit_buf >>= code_len;
     l.num_bits -= code_len;
-    f(r, l, symbol)
+    f(r, l, entry)
 }
 
 /// Try to read one byte from `in_iter` and call `f` with the read byte as an argument,
@@ -616,89 +711,37 @@ fn undo_bytes(l: &mut LocalVars, max: u32) -> u32 {
 fn start_static_table(r: &mut DecompressorOxide) {
     r.table_sizes[LITLEN_TABLE] = 288;
     r.table_sizes[DIST_TABLE] = 32;
-    memset(&mut r.tables[LITLEN_TABLE].code_size[0..144], 8);
-    memset(&mut r.tables[LITLEN_TABLE].code_size[144..256], 9);
-    memset(&mut r.tables[LITLEN_TABLE].code_size[256..280], 7);
-    memset(&mut r.tables[LITLEN_TABLE].code_size[280..288], 8);
-    memset(&mut r.tables[DIST_TABLE].code_size[0..32], 5);
+    memset(&mut r.code_sizes[LITLEN_TABLE][0..144], 8);
+    memset(&mut r.code_sizes[LITLEN_TABLE][144..256], 9);
+    memset(&mut r.code_sizes[LITLEN_TABLE][256..280], 7);
+    memset(&mut r.code_sizes[LITLEN_TABLE][280..288], 8);
+    memset(&mut r.code_sizes[DIST_TABLE][0..32], 5);
 }
 
 fn init_tree(r: &mut DecompressorOxide, l: &mut LocalVars) -> Action {
     loop {
-        let table = &mut r.tables[r.block_type as usize];
         let table_size = r.table_sizes[r.block_type as usize] as usize;
-        let mut total_symbols = [0u32; 16];
-        let mut next_code = [0u32; 17];
-        memset(&mut table.look_up[..], 0);
-        memset(&mut table.tree[..], 0);
-
-        for &code_size in &table.code_size[..table_size] {
-            total_symbols[code_size as usize] += 1;
-        }
-
-        let mut used_symbols = 0;
-        let mut total = 0;
-        for i in 1..16 {
-            used_symbols += total_symbols[i];
-            total += total_symbols[i];
-            total <<= 1;
-            next_code[i + 1] = total;
-        }
+        let code_sizes = &r.code_sizes[r.block_type as usize][..table_size];
+        let valid = match r.block_type as usize {
+            LITLEN_TABLE => build_table(
+                &mut r.litlen_table,
+                LITLEN_TABLE_BITS,
+                code_sizes,
+                litlen_entry,
+            ),
+            DIST_TABLE => build_table(&mut r.dist_table, DIST_TABLE_BITS, code_sizes, dist_entry),
+            _ => build_table(
+                &mut r.hufflen_table,
+                PRECODE_TABLE_BITS,
+                code_sizes,
+                precode_entry,
+            ),
+        };
 
-        if total != 65_536 && used_symbols > 1 {
+        if !valid {
             return Action::Jump(BadTotalSymbols);
         }
 
-        let mut tree_next = -1;
-        for symbol_index in 0..table_size {
-            let mut rev_code = 0;
-            let code_size = table.code_size[symbol_index];
-            if code_size == 0 {
-                continue;
-            }
-
-            let mut cur_code = next_code[code_size as usize];
-            next_code[code_size as usize] += 1;
-
-            for _ in 0..code_size {
-                rev_code = (rev_code << 1) | (cur_code & 1);
-                cur_code >>= 1;
-            }
-
-            if code_size <= FAST_LOOKUP_BITS {
-                let k = (i16::from(code_size) << 9) | symbol_index as i16;
-                while rev_code < FAST_LOOKUP_SIZE {
-                    table.look_up[rev_code as usize] = k;
-                    rev_code += 1 << code_size;
-                }
-                continue;
-            }
-
-            let mut tree_cur = table.look_up[(rev_code & (FAST_LOOKUP_SIZE - 1)) as usize];
-            if tree_cur == 0 {
-                table.look_up[(rev_code & (FAST_LOOKUP_SIZE - 1)) as usize] = tree_next as i16;
-                tree_cur = tree_next;
-                tree_next -= 2;
-            }
-
-            rev_code >>= FAST_LOOKUP_BITS - 1;
-            for _ in FAST_LOOKUP_BITS + 1..code_size {
-                rev_code >>= 1;
-                tree_cur -= (rev_code & 1) as i16;
-                if table.tree[(-tree_cur - 1) as usize] == 0 {
-                    table.tree[(-tree_cur - 1) as usize] = tree_next as i16;
-                    tree_cur = tree_next;
-                    tree_next -= 2;
-                } else {
-                    tree_cur = table.tree[(-tree_cur - 1) as usize];
-                }
-            }
-
-            rev_code >>= 1;
-            tree_cur -= (rev_code & 1) as i16;
-            table.tree[(-tree_cur - 1) as usize] = symbol_index as i16;
-        }
-
         if r.block_type == 2 {
             l.counter = 0;
             return Action::Jump(ReadLitlenDistTablesCodeSize);
@@ -714,6 +757,29 @@ fn init_tree(r: &mut DecompressorOxide, l: &mut LocalVars) -> Action {
     Action::Jump(DecodeLitlen)
 }
 
+/// Continue with the entry of a decoded literal/length code that isn't a literal.
+#[inline]
+fn start_length(l: &mut LocalVars, entry: u32) -> Action {
+    if entry & ENTRY_END_OF_BLOCK != 0 {
+        // We hit the end of block symbol.
+        Action::Jump(BlockDone)
+    } else if entry & ENTRY_INVALID != 0 {
+        // Invalid code (286 or 287).
+        Action::Jump(InvalidLitlen)
+    } else {
+        // Length and distance codes have a number of extra bits depending on
+        // the base, which together with the base gives us the exact value.
+        l.code_bits = entry & ENTRY_LEN_MASK;
+        l.counter = entry >> ENTRY_VALUE_SHIFT;
+        l.num_extra = (entry >> ENTRY_EXTRA_SHIFT) & ENTRY_EXTRA_MASK;
+        if l.num_extra != 0 {
+            Action::Jump(ReadExtraBitsLitlen)
+        } else {
+            Action::Jump(DecodeDistance)
+        }
+    }
+}
+
 // A helper macro for generating the state machine.
 //
 // As Rust doesn't have fallthrough on matches, we have to return to the match statement
@@ -733,6 +799,55 @@ macro_rules! generate_state {
     };
 }
 
+/// Receiver of events describing the structure of a stream as it is being decompressed.
+///
+/// Used with [`decompress_with_visitor`](fn.decompress_with_visitor.html), mainly to help debug
+/// problems with streams produced by other encoders. All methods have empty default
+/// implementations, so only the ones of interest need to be implemented.
+///
+/// Bit positions are counted from the start of the stream (including the zlib header if any),
+/// and stay correct when the stream is decompressed over several calls.
+pub trait InflateVisitor {
+    /// Called with the two zlib header bytes, before they are validated.
+    fn zlib_header(&mut self, _cmf: u8, _flg: u8) {}
+
+    /// Called after reading the 3-bit header of a block starting at bit `bit_pos`.
+    fn block_header(&mut self, _bit_pos: u64, _is_final: bool, _block_type: BlockType) {}
+
+    /// Called with the length of a stored block once its length has been validated.
+    fn stored_block_len(&mut self, _len: u16) {}
+
+    /// Called with the code lengths of the literal/length and distance huffman tables of a
+    /// static or dynamic block, before the tables are built.
+    fn huffman_tables(&mut self, _litlen_code_sizes: &[u8], _dist_code_sizes: &[u8]) {}
+
+    /// Called for every decoded LZ77 token, see
+    /// [`decompress_with_tokens`](fn.decompress_with_tokens.html).
+    fn token(&mut self, _token: LZToken) {}
+
+    /// Called at the end of a block, `bit_pos` being the position just after the end of block
+    /// code (or the stored data).
+    fn block_end(&mut self, _bit_pos: u64) {}
+
+    /// Called with the adler32 checksum stored at the end of a zlib stream.
+    fn zlib_trailer(&mut self, _adler32: u32) {}
+}
+
+/// Visitor used for normal decompression, which ignores everything.
+struct NoVisitor;
+
+impl InflateVisitor for NoVisitor {}
+
+/// Visitor passing the decoded tokens to a closure.
+struct TokenVisitor<F>(F);
+
+impl<F: FnMut(LZToken)> InflateVisitor for TokenVisitor<F> {
+    #[inline(always)]
+    fn token(&mut self, token: LZToken) {
+        (self.0)(token)
+    }
+}
+
 #[derive(Copy, Clone)]
 struct LocalVars {
     pub bit_buf: BitBuffer,
@@ -740,6 +855,7 @@ struct LocalVars {
     pub dist: u32,
     pub counter: u32,
     pub num_extra: u32,
+    pub code_bits: u32,
 }
 
 #[inline]
@@ -827,13 +943,14 @@ fn apply_match(
 /// Currently we don't do this here, but this function does avoid having to jump through the
 /// big match loop on each state change(as rust does not have fallthrough or gotos at the moment),
 /// and already improves decompression speed a fair bit.
-fn decompress_fast(
+fn decompress_fast<V: InflateVisitor>(
     r: &mut DecompressorOxide,
     mut in_iter: &mut slice::Iter<u8>,
     out_buf: &mut OutputBuffer,
     flags: u32,
     local_vars: &mut LocalVars,
     out_buf_size_mask: usize,
+    visitor: &mut V,
 ) -> (TINFLStatus, State) {
     // Make a local copy of the most used variables, to avoid having to update and read from values
     // in a random memory location and to encourage more register use.
@@ -842,7 +959,7 @@ fn decompress_fast(
 
     let status: TINFLStatus = 'o: loop {
         state = State::DecodeLitlen;
-        loop {
+        let entry = loop {
             // This function assumes that there is at least 259 bytes left in the output buffer,
             // and that there is at least 14 bytes left in the input buffer. 14 input bytes:
             // 15 (prev lit) + 15 (length) + 5 (length extra) + 15 (dist)
@@ -856,67 +973,60 @@ fn decompress_fast(
 
             fill_bit_buffer(&mut l, &mut in_iter);
 
-            if let Some((symbol, code_len)) = r.tables[LITLEN_TABLE].lookup(l.bit_buf) {
-                l.counter = symbol as u32;
-                l.bit_buf >>= code_len;
-                l.num_bits -= code_len;
+            let entry = r.lookup(LITLEN_TABLE, l.bit_buf);
+            if entry & ENTRY_UNUSED_CODE != 0 {
+                state.begin(InvalidCodeLen);
+                break 'o TINFLStatus::Failed;
+            }
+            l.bit_buf >>= entry & ENTRY_LEN_MASK;
+            l.num_bits -= entry & ENTRY_LEN_MASK;
 
-                if (l.counter & 256) != 0 {
-                    // The symbol is not a literal.
-                    break;
-                } else {
-                    // If we have a 32-bit buffer we need to read another two bytes now
-                    // to have enough bits to keep going.
-                    if cfg!(not(target_pointer_width = "64")) {
-                        fill_bit_buffer(&mut l, &mut in_iter);
-                    }
+            if entry & ENTRY_LITERAL == 0 {
+                break entry;
+            }
 
-                    if let Some((symbol, code_len)) = r.tables[LITLEN_TABLE].lookup(l.bit_buf) {
-                        l.bit_buf >>= code_len;
-                        l.num_bits -= code_len;
-                        // The previous symbol was a literal, so write it directly and check
-                        // the next one.
-                        out_buf.write_byte(l.counter as u8);
-                        if (symbol & 256) != 0 {
-                            l.counter = symbol as u32;
-                            // The symbol is a length value.
-                            break;
-                        } else {
-                            // The symbol is a literal, so write it directly and continue.
-                            out_buf.write_byte(symbol as u8);
-                        }
-                    } else {
-                        state.begin(InvalidCodeLen);
-                        break 'o TINFLStatus::Failed;
-                    }
-                }
-            } else {
+            // If we have a 32-bit buffer we need to read another two bytes now
+            // to have enough bits to keep going.
+            if cfg!(not(target_pointer_width = "64")) {
+                fill_bit_buffer(&mut l, &mut in_iter);
+            }
+
+            let next_entry = r.lookup(LITLEN_TABLE, l.bit_buf);
+            if next_entry & ENTRY_UNUSED_CODE != 0 {
                 state.begin(InvalidCodeLen);
                 break 'o TINFLStatus::Failed;
             }
-        }
+            l.bit_buf >>= next_entry & ENTRY_LEN_MASK;
+            l.num_bits -= next_entry & ENTRY_LEN_MASK;
+
+            // The previous symbol was a literal, so write it directly and check
+            // the next one.
+            let literal = (entry >> ENTRY_VALUE_SHIFT) as u8;
+            out_buf.write_byte(literal);
+            visitor.token(LZToken::Literal(literal));
+            if next_entry & ENTRY_LITERAL == 0 {
+                break next_entry;
+            }
+
+            // The symbol is a literal, so write it directly and continue.
+            let literal = (next_entry >> ENTRY_VALUE_SHIFT) as u8;
+            out_buf.write_byte(literal);
+            visitor.token(LZToken::Literal(literal));
+        };
 
-        // Mask the top bits since they may contain length info.
-        l.counter &= 511;
-        if l.counter == 256 {
+        if entry & ENTRY_END_OF_BLOCK != 0 {
             // We hit the end of block symbol.
             state.begin(BlockDone);
             break 'o TINFLStatus::Done;
-        } else if l.counter > 285 {
-            // Invalid code.
-            // We already verified earlier that the code is > 256.
+        } else if entry & ENTRY_INVALID != 0 {
+            // Invalid code (286 or 287).
             state.begin(InvalidLitlen);
             break 'o TINFLStatus::Failed;
         } else {
-            // The symbol was a length code.
-            // # Optimization
-            // Mask the value to avoid bounds checks
-            // We could use get_unchecked later if can statically verify that
-            // this will never go out of bounds.
-            l.num_extra = u32::from(LENGTH_EXTRA[(l.counter - 257) as usize & BASE_EXTRA_MASK]);
-            l.counter = u32::from(LENGTH_BASE[(l.counter - 257) as usize & BASE_EXTRA_MASK]);
-            // Length and distance codes have a number of extra bits depending on
-            // the base, which together with the base gives us the exact value.
+            // The symbol was a length code, the entry gives us the base length and the number
+            // of extra bits that together with the base gives us the exact value.
+            l.counter = entry >> ENTRY_VALUE_SHIFT;
+            l.num_extra = (entry >> ENTRY_EXTRA_SHIFT) & ENTRY_EXTRA_MASK;
 
             fill_bit_buffer(&mut l, &mut in_iter);
             if l.num_extra != 0 {
@@ -932,21 +1042,20 @@ fn decompress_fast(
                 fill_bit_buffer(&mut l, &mut in_iter);
             }
 
-            if let Some((mut symbol, code_len)) = r.tables[DIST_TABLE].lookup(l.bit_buf) {
-                symbol &= 511;
-                l.bit_buf >>= code_len;
-                l.num_bits -= code_len;
-                if symbol > 29 {
-                    state.begin(InvalidDist);
-                    break 'o TINFLStatus::Failed;
-                }
-
-                l.num_extra = u32::from(DIST_EXTRA[symbol as usize]);
-                l.dist = u32::from(DIST_BASE[symbol as usize]);
-            } else {
+            let entry = r.lookup(DIST_TABLE, l.bit_buf);
+            if entry & ENTRY_UNUSED_CODE != 0 {
                 state.begin(InvalidCodeLen);
                 break 'o TINFLStatus::Failed;
             }
+            l.bit_buf >>= entry & ENTRY_LEN_MASK;
+            l.num_bits -= entry & ENTRY_LEN_MASK;
+            if entry & ENTRY_INVALID != 0 {
+                state.begin(InvalidDist);
+                break 'o TINFLStatus::Failed;
+            }
+
+            l.num_extra = (entry >> ENTRY_EXTRA_SHIFT) & ENTRY_EXTRA_MASK;
+            l.dist = entry >> ENTRY_VALUE_SHIFT;
 
             if l.num_extra != 0 {
                 fill_bit_buffer(&mut l, &mut in_iter);
@@ -966,6 +1075,10 @@ fn decompress_fast(
                 break TINFLStatus::Failed;
             }
 
+            visitor.token(LZToken::Match {
+                len: l.counter as u16,
+                dist: l.dist as u16,
+            });
             apply_match(
                 out_buf.get_mut(),
                 position,
@@ -1023,6 +1136,102 @@ pub fn decompress(
     out: &mut [u8],
     out_pos: usize,
     flags: u32,
+) -> (TINFLStatus, usize, usize) {
+    decompress_inner(r, in_buf, out, out_pos, flags, &mut NoVisitor)
+}
+
+/// Decompress like [`decompress`](fn.decompress.html), additionally passing the LZ77 tokens
+/// the stream decodes into to `token_func` as they are decoded.
+///
+/// Every decoded literal and match is reported once the bytes it represents have been written
+/// to (or, for matches that didn't fit, started being written to) the output buffer, and
+/// `LZToken::EndOfBlock` is reported at the end of each block. Bytes of raw/stored blocks
+/// are reported as literals.
+///
+/// The decompressed data is still written to `out`, as it is needed to resolve matches.
+///
+/// # Returns
+/// Same as [`decompress`](fn.decompress.html).
+pub fn decompress_with_tokens(
+    r: &mut DecompressorOxide,
+    in_buf: &[u8],
+    out: &mut [u8],
+    out_pos: usize,
+    flags: u32,
+    token_func: impl FnMut(LZToken),
+) -> (TINFLStatus, usize, usize) {
+    decompress_inner(
+        r,
+        in_buf,
+        out,
+        out_pos,
+        flags,
+        &mut TokenVisitor(token_func),
+    )
+}
+
+/// Decompress like [`decompress`](fn.decompress.html), additionally reporting the structure of
+/// the stream (headers, block boundaries, huffman tables and tokens) to `visitor`.
+///
+/// # Returns
+/// Same as [`decompress`](fn.decompress.html).
+pub fn decompress_with_visitor<V: InflateVisitor>(
+    r: &mut DecompressorOxide,
+    in_buf: &[u8],
+    out: &mut [u8],
+    out_pos: usize,
+    flags: u32,
+    visitor: &mut V,
+) -> (TINFLStatus, usize, usize) {
+    decompress_inner(r, in_buf, out, out_pos, flags, visitor)
+}
+
+/// Decompress the whole of `in_buf` in one go, passing the output to `callback_func` in chunks
+/// as it is produced.
+///
+/// Decompression is done into an internal wrapping buffer of `TINFL_LZ_DICT_SIZE` bytes, so
+/// each chunk passed to the callback is at most that size. The
+/// `TINFL_FLAG_HAS_MORE_INPUT` and `TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF` flags are ignored.
+/// If the callback returns `false`, decompression stops and `TINFLStatus::Failed` is returned.
+///
+/// # Returns
+/// Returns a tuple containing the final status of the decompressor and the number of input
+/// bytes read.
+pub fn decompress_to_output(
+    in_buf: &[u8],
+    flags: u32,
+    mut callback_func: impl FnMut(&[u8]) -> bool,
+) -> (TINFLStatus, usize) {
+    let flags = flags & !(TINFL_FLAG_HAS_MORE_INPUT | TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF);
+    let mut r = DecompressorOxide::new();
+    let mut dict = [0u8; TINFL_LZ_DICT_SIZE];
+    let mut in_pos = 0;
+    let mut dict_pos = 0;
+
+    loop {
+        let (status, in_consumed, out_consumed) =
+            decompress(&mut r, &in_buf[in_pos..], &mut dict, dict_pos, flags);
+        in_pos += in_consumed;
+
+        if out_consumed != 0 && !callback_func(&dict[dict_pos..dict_pos + out_consumed]) {
+            return (TINFLStatus::Failed, in_pos);
+        }
+
+        if status != TINFLStatus::HasMoreOutput {
+            return (status, in_pos);
+        }
+
+        dict_pos = (dict_pos + out_consumed) & (TINFL_LZ_DICT_SIZE - 1);
+    }
+}
+
+fn decompress_inner<V: InflateVisitor>(
+    r: &mut DecompressorOxide,
+    in_buf: &[u8],
+    out: &mut [u8],
+    out_pos: usize,
+    flags: u32,
+    visitor: &mut V,
 ) -> (TINFLStatus, usize, usize) {
     let out_buf_size_mask = if flags & TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF != 0 {
         usize::max_value()
@@ -1054,8 +1263,18 @@ pub fn decompress(
         dist: r.dist,
         counter: r.counter,
         num_extra: r.num_extra,
+        code_bits: r.code_bits,
     };
 
+    // Current position in the stream in bits, used when reporting to the visitor.
+    macro_rules! bit_pos {
+        () => {
+            // Bits inserted with `prime` come before the input, they are counted as position 0.
+            ((r.total_in + (in_buf.len() - in_iter.len()) as u64) * 8)
+                .saturating_sub(u64::from(l.num_bits))
+        };
+    }
+
     let mut status = 'state_machine: loop {
         match state {
             Start => generate_state!(state, 'state_machine, {
@@ -1068,6 +1287,7 @@ pub fn decompress(
                 r.z_header1 = 0;
                 r.z_adler32 = 1;
                 r.check_adler32 = 1;
+                r.total_in = 0;
                 if flags & TINFL_FLAG_PARSE_ZLIB_HEADER != 0 {
                     Action::Jump(State::ReadZlibCmf)
                 } else {
@@ -1085,19 +1305,33 @@ pub fn decompress(
             ReadZlibFlg => generate_state!(state, 'state_machine, {
                 read_byte(&mut in_iter, flags, |flg| {
                     r.z_header1 = u32::from(flg);
+                    visitor.zlib_header(r.z_header0 as u8, flg);
                     validate_zlib_header(r.z_header0, r.z_header1, flags, out_buf_size_mask)
                 })
             }),
 
             // Read the block header and jump to the relevant section depending on the block type.
             ReadBlockHeader => generate_state!(state, 'state_machine, {
+                let block_start = bit_pos!();
                 read_bits(&mut l, 3, &mut in_iter, flags, |l, bits| {
                     r.finish = (bits & 1) as u32;
                     r.block_type = (bits >> 1) as u32 & 3;
+                    let block_type = match r.block_type {
+                        0 => BlockType::Stored,
+                        1 => BlockType::Static,
+                        _ => BlockType::Dynamic,
+                    };
+                    if r.block_type != 3 {
+                        visitor.block_header(block_start, r.finish != 0, block_type);
+                    }
                     match r.block_type {
                         0 => Action::Jump(BlockTypeNoCompression),
                         1 => {
                             start_static_table(r);
+                            visitor.huffman_tables(
+                                &r.code_sizes[LITLEN_TABLE][..288],
+                                &r.code_sizes[DIST_TABLE][..32],
+                            );
                             init_tree(r, l)
                         },
                         2 => {
@@ -1144,6 +1378,10 @@ pub fn decompress(
                     let valid = length == !check;
                     l.counter = length.into();
 
+                    if valid {
+                        visitor.stored_block_len(length);
+                    }
+
                     if !valid {
                         Action::Jump(BadRawLength)
                     } else if l.counter == 0 {
@@ -1174,6 +1412,7 @@ pub fn decompress(
                     Action::End(TINFLStatus::HasMoreOutput)
                 } else {
                     out_buf.write_byte(l.dist as u8);
+                    visitor.token(LZToken::Literal(l.dist as u8));
                     l.counter -= 1;
                     if l.counter == 0 || l.num_bits == 0 {
                         Action::Jump(RawMemcpy1)
@@ -1210,6 +1449,9 @@ pub fn decompress(
                     );
 
                     out_buf.write_slice(&in_iter.as_slice()[..bytes_to_copy]);
+                    for &byte in &in_iter.as_slice()[..bytes_to_copy] {
+                        visitor.token(LZToken::Literal(byte));
+                    }
 
                     (&mut in_iter).nth(bytes_to_copy - 1);
                     l.counter -= bytes_to_copy as u32;
@@ -1230,7 +1472,7 @@ pub fn decompress(
                         Action::None
                     })
                 } else {
-                    memset(&mut r.tables[HUFFLEN_TABLE].code_size[..], 0);
+                    memset(&mut r.code_sizes[HUFFLEN_TABLE][..], 0);
                     l.counter = 0;
                     Action::Jump(ReadHufflenTableCodeSize)
                 }
@@ -1244,8 +1486,7 @@ pub fn decompress(
                         // These lengths are not stored in a normal ascending order, but rather one
                         // specified by the deflate specification intended to put the most used
                         // values at the front as trailing zero lengths do not have to be stored.
-                        r.tables[HUFFLEN_TABLE]
-                            .code_size[HUFFMAN_LENGTH_ORDER[l.counter as usize] as usize] =
+                        r.code_sizes[HUFFLEN_TABLE][HUFFMAN_LENGTH_ORDER[l.counter as usize] as usize] =
                                 bits as u8;
                         l.counter += 1;
                         Action::None
@@ -1260,8 +1501,8 @@ pub fn decompress(
                 if l.counter < r.table_sizes[LITLEN_TABLE] + r.table_sizes[DIST_TABLE] {
                     decode_huffman_code(
                         r, &mut l, HUFFLEN_TABLE,
-                        flags, &mut in_iter, |r, l, symbol| {
-                            l.dist = symbol as u32;
+                        flags, &mut in_iter, |r, l, entry| {
+                            l.dist = entry >> ENTRY_VALUE_SHIFT;
                             if l.dist < 16 {
                                 r.len_codes[l.counter as usize] = l.dist as u8;
                                 l.counter += 1;
@@ -1277,15 +1518,20 @@ pub fn decompress(
                 } else if l.counter != r.table_sizes[LITLEN_TABLE] + r.table_sizes[DIST_TABLE] {
                     Action::Jump(BadCodeSizeSum)
                 } else {
-                    r.tables[LITLEN_TABLE].code_size[..r.table_sizes[LITLEN_TABLE] as usize]
+                    r.code_sizes[LITLEN_TABLE][..r.table_sizes[LITLEN_TABLE] as usize]
                         .copy_from_slice(&r.len_codes[..r.table_sizes[LITLEN_TABLE] as usize]);
 
                     let dist_table_start = r.table_sizes[LITLEN_TABLE] as usize;
                     let dist_table_end = (r.table_sizes[LITLEN_TABLE] +
                                           r.table_sizes[DIST_TABLE]) as usize;
-                    r.tables[DIST_TABLE].code_size[..r.table_sizes[DIST_TABLE] as usize]
+                    r.code_sizes[DIST_TABLE][..r.table_sizes[DIST_TABLE] as usize]
                         .copy_from_slice(&r.len_codes[dist_table_start..dist_table_end]);
 
+                    visitor.huffman_tables(
+                        &r.len_codes[..dist_table_start],
+                        &r.len_codes[dist_table_start..dist_table_end],
+                    );
+
                     r.block_type -= 1;
                     init_tree(r, &mut l)
                 }
@@ -1323,9 +1569,14 @@ pub fn decompress(
                         LITLEN_TABLE,
                         flags,
                         &mut in_iter,
-                        |_r, l, symbol| {
-                            l.counter = symbol as u32;
-                            Action::Jump(WriteSymbol)
+                        |_r, l, entry| {
+                            if entry & ENTRY_LITERAL != 0 {
+                                l.code_bits = entry & ENTRY_LEN_MASK;
+                                l.counter = entry >> ENTRY_VALUE_SHIFT;
+                                Action::Jump(WriteSymbol)
+                            } else {
+                                start_length(l, entry)
+                            }
                         },
                     )
                 } else if
@@ -1341,6 +1592,7 @@ pub fn decompress(
                         flags,
                         &mut l,
                         out_buf_size_mask,
+                        visitor,
                     );
 
                     state = new_state;
@@ -1352,91 +1604,64 @@ pub fn decompress(
                 } else {
                     fill_bit_buffer(&mut l, &mut in_iter);
 
-                    if let Some((symbol, code_len)) = r.tables[LITLEN_TABLE].lookup(l.bit_buf) {
-
-                    l.counter = symbol as u32;
-                    l.bit_buf >>= code_len;
-                    l.num_bits -= code_len;
-
-                    if (l.counter & 256) != 0 {
-                        // The symbol is not a literal.
-                        Action::Jump(HuffDecodeOuterLoop1)
+                    let entry = r.lookup(LITLEN_TABLE, l.bit_buf);
+                    if entry & ENTRY_UNUSED_CODE != 0 {
+                        Action::Jump(InvalidCodeLen)
                     } else {
-                        // If we have a 32-bit buffer we need to read another two bytes now
-                        // to have enough bits to keep going.
-                        if cfg!(not(target_pointer_width = "64")) {
-                            fill_bit_buffer(&mut l, &mut in_iter);
-                        }
+                        l.bit_buf >>= entry & ENTRY_LEN_MASK;
+                        l.num_bits -= entry & ENTRY_LEN_MASK;
 
-                        if let Some((symbol, code_len)) = r.tables[LITLEN_TABLE].lookup(l.bit_buf) {
-
-                            l.bit_buf >>= code_len;
-                            l.num_bits -= code_len;
-                            // The previous symbol was a literal, so write it directly and check
-                            // the next one.
-                            out_buf.write_byte(l.counter as u8);
-                            if (symbol & 256) != 0 {
-                                l.counter = symbol as u32;
-                                // The symbol is a length value.
-                                Action::Jump(HuffDecodeOuterLoop1)
+                        if entry & ENTRY_LITERAL == 0 {
+                            // The symbol is not a literal.
+                            start_length(&mut l, entry)
+                        } else {
+                            // If we have a 32-bit buffer we need to read another two bytes now
+                            // to have enough bits to keep going.
+                            if cfg!(not(target_pointer_width = "64")) {
+                                fill_bit_buffer(&mut l, &mut in_iter);
+                            }
+
+                            let next_entry = r.lookup(LITLEN_TABLE, l.bit_buf);
+                            if next_entry & ENTRY_UNUSED_CODE != 0 {
+                                Action::Jump(InvalidCodeLen)
                             } else {
-                                // The symbol is a literal, so write it directly and continue.
-                                out_buf.write_byte(symbol as u8);
-                                Action::None
+                                l.bit_buf >>= next_entry & ENTRY_LEN_MASK;
+                                l.num_bits -= next_entry & ENTRY_LEN_MASK;
+                                // The previous symbol was a literal, so write it directly and
+                                // check the next one.
+                                let literal = (entry >> ENTRY_VALUE_SHIFT) as u8;
+                                out_buf.write_byte(literal);
+                                visitor.token(LZToken::Literal(literal));
+                                if next_entry & ENTRY_LITERAL == 0 {
+                                    // The symbol is a length value.
+                                    start_length(&mut l, next_entry)
+                                } else {
+                                    // The symbol is a literal, so write it directly and continue.
+                                    let literal = (next_entry >> ENTRY_VALUE_SHIFT) as u8;
+                                    out_buf.write_byte(literal);
+                                    visitor.token(LZToken::Literal(literal));
+                                    Action::None
+                                }
                             }
-                        } else {
-                            Action::Jump(InvalidCodeLen)
                         }
                     }
-                    } else {
-                        Action::Jump(InvalidCodeLen)
-                    }
                 }
             }),
 
             WriteSymbol => generate_state!(state, 'state_machine, {
-                if l.counter >= 256 {
-                    Action::Jump(HuffDecodeOuterLoop1)
-                } else if out_buf.bytes_left() > 0 {
+                if out_buf.bytes_left() > 0 {
                     out_buf.write_byte(l.counter as u8);
+                    visitor.token(LZToken::Literal(l.counter as u8));
                     Action::Jump(DecodeLitlen)
                 } else {
                     Action::End(TINFLStatus::HasMoreOutput)
                 }
             }),
 
-            HuffDecodeOuterLoop1 => generate_state!(state, 'state_machine, {
-                // Mask the top bits since they may contain length info.
-                l.counter &= 511;
-
-                if l.counter == 256 {
-                    // We hit the end of block symbol.
-                    Action::Jump(BlockDone)
-                } else if l.counter > 285 {
-                    // Invalid code.
-                    // We already verified earlier that the code is > 256.
-                    Action::Jump(InvalidLitlen)
-                } else {
-                    // # Optimization
-                    // Mask the value to avoid bounds checks
-                    // We could use get_unchecked later if can statically verify that
-                    // this will never go out of bounds.
-                    l.num_extra =
-                        u32::from(LENGTH_EXTRA[(l.counter - 257) as usize & BASE_EXTRA_MASK]);
-                    l.counter = u32::from(LENGTH_BASE[(l.counter - 257) as usize & BASE_EXTRA_MASK]);
-                    // Length and distance codes have a number of extra bits depending on
-                    // the base, which together with the base gives us the exact value.
-                    if l.num_extra != 0 {
-                        Action::Jump(ReadExtraBitsLitlen)
-                    } else {
-                        Action::Jump(DecodeDistance)
-                    }
-                }
-            }),
-
             ReadExtraBitsLitlen => generate_state!(state, 'state_machine, {
                 let num_extra = l.num_extra;
                 read_bits(&mut l, num_extra, &mut in_iter, flags, |l, extra_bits| {
+                    l.code_bits += num_extra;
                     l.counter += extra_bits as u32;
                     Action::Jump(DecodeDistance)
                 })
@@ -1445,17 +1670,14 @@ pub fn decompress(
             DecodeDistance => generate_state!(state, 'state_machine, {
                 // Try to read a huffman code from the input buffer and look up what
                 // length code the decoded symbol refers to.
-                decode_huffman_code(r, &mut l, DIST_TABLE, flags, &mut in_iter, |_r, l, symbol| {
-                    if symbol > 29 {
+                decode_huffman_code(r, &mut l, DIST_TABLE, flags, &mut in_iter, |_r, l, entry| {
+                    if entry & ENTRY_INVALID != 0 {
                         // Invalid distance code.
                         return Action::Jump(InvalidDist)
                     }
-                    // # Optimization
-                    // Mask the value to avoid bounds checks
-                    // We could use get_unchecked later if can statically verify that
-                    // this will never go out of bounds.
-                    l.num_extra = u32::from(DIST_EXTRA[symbol as usize & BASE_EXTRA_MASK]);
-                    l.dist = u32::from(DIST_BASE[symbol as usize & BASE_EXTRA_MASK]);
+                    l.code_bits += entry & ENTRY_LEN_MASK;
+                    l.num_extra = (entry >> ENTRY_EXTRA_SHIFT) & ENTRY_EXTRA_MASK;
+                    l.dist = entry >> ENTRY_VALUE_SHIFT;
                     if l.num_extra != 0 {
                         // ReadEXTRA_BITS_DISTACNE
                         Action::Jump(ReadExtraBitsDistance)
@@ -1468,6 +1690,7 @@ pub fn decompress(
             ReadExtraBitsDistance => generate_state!(state, 'state_machine, {
                 let num_extra = l.num_extra;
                 read_bits(&mut l, num_extra, &mut in_iter, flags, |l, extra_bits| {
+                    l.code_bits += num_extra;
                     l.dist += extra_bits as u32;
                     Action::Jump(HuffDecodeOuterLoop2)
                 })
@@ -1481,6 +1704,10 @@ pub fn decompress(
                     // the start of the decoded data, so we can't continue.
                     Action::Jump(DistanceOutOfBounds)
                 } else {
+                    visitor.token(LZToken::Match {
+                        len: l.counter as u16,
+                        dist: l.dist as u16,
+                    });
                     let out_pos = out_buf.position();
                     let source_pos = out_buf.position()
                         .wrapping_sub(l.dist as usize) & out_buf_size_mask;
@@ -1498,6 +1725,7 @@ pub fn decompress(
                         if l.counter == 0 {
                             Action::Jump(DecodeLitlen)
                         } else {
+                            r.match_len = l.counter;
                             Action::Jump(WriteLenBytesToEnd)
                         }
                     } else {
@@ -1538,6 +1766,8 @@ pub fn decompress(
             }),
 
             BlockDone => generate_state!(state, 'state_machine, {
+                visitor.token(LZToken::EndOfBlock);
+                visitor.block_end(bit_pos!());
                 // End once we've read the last block.
                 if r.finish != 0 {
                     pad_to_bytes(&mut l, &mut in_iter, flags, |_| Action::None);
@@ -1578,6 +1808,7 @@ pub fn decompress(
                         })
                     }
                 } else {
+                    visitor.zlib_trailer(r.z_adler32);
                     Action::Jump(DoneForever)
                 }
             }),
@@ -1611,6 +1842,10 @@ pub fn decompress(
     r.dist = l.dist;
     r.counter = l.counter;
     r.num_extra = l.num_extra;
+    r.code_bits = l.code_bits;
+    r.total_in = r
+        .total_in
+        .wrapping_add((in_buf.len() - in_iter.len() - in_undo) as u64);
 
     r.bit_buf &= ((1 as BitBuffer) << r.num_bits) - 1;
 
@@ -1641,9 +1876,13 @@ pub fn decompress(
     )
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "alloc"))]
 mod test {
     use super::*;
+    use crate::deflate::core::deflate_flags::TDEFL_FORCE_ALL_STATIC_BLOCKS;
+    use crate::deflate::core::{
+        compress_to_output, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush,
+    };
 
     //TODO: Fix these.
 
@@ -1715,9 +1954,10 @@ mod test {
         assert_eq!(b_status.0, TINFLStatus::Done);
     }
 
-    fn masked_lookup(table: &HuffmanTable, bit_buf: BitBuffer) -> (i32, u32) {
-        let ret = table.lookup(bit_buf).unwrap();
-        (ret.0 & 511, ret.1)
+    /// Look up a code, returning the entry without the code length, and the code length.
+    fn masked_lookup(d: &DecompressorOxide, table: usize, bit_buf: BitBuffer) -> (u32, u32) {
+        let entry = d.lookup(table, bit_buf);
+        (entry & !ENTRY_LEN_MASK, entry & ENTRY_LEN_MASK)
     }
 
     #[test]
@@ -1731,23 +1971,33 @@ mod test {
             dist: d.dist,
             counter: d.counter,
             num_extra: d.num_extra,
+            code_bits: d.code_bits,
         };
         init_tree(&mut d, &mut l);
-        let llt = &d.tables[LITLEN_TABLE];
-        let dt = &d.tables[DIST_TABLE];
-        assert_eq!(masked_lookup(llt, 0b00001100), (0, 8));
-        assert_eq!(masked_lookup(llt, 0b00011110), (72, 8));
-        assert_eq!(masked_lookup(llt, 0b01011110), (74, 8));
-        assert_eq!(masked_lookup(llt, 0b11111101), (143, 8));
-        assert_eq!(masked_lookup(llt, 0b000010011), (144, 9));
-        assert_eq!(masked_lookup(llt, 0b111111111), (255, 9));
-        assert_eq!(masked_lookup(llt, 0b00000000), (256, 7));
-        assert_eq!(masked_lookup(llt, 0b1110100), (279, 7));
-        assert_eq!(masked_lookup(llt, 0b00000011), (280, 8));
-        assert_eq!(masked_lookup(llt, 0b11100011), (287, 8));
-
-        assert_eq!(masked_lookup(dt, 0), (0, 5));
-        assert_eq!(masked_lookup(dt, 20), (5, 5));
+        let llt = |bits| masked_lookup(&d, LITLEN_TABLE, bits);
+        let dt = |bits| masked_lookup(&d, DIST_TABLE, bits);
+        assert_eq!(llt(0b00001100), (litlen_entry(0), 8));
+        assert_eq!(llt(0b00011110), (litlen_entry(72), 8));
+        assert_eq!(llt(0b01011110), (litlen_entry(74), 8));
+        assert_eq!(llt(0b11111101), (litlen_entry(143), 8));
+        assert_eq!(llt(0b000010011), (litlen_entry(144), 9));
+        assert_eq!(llt(0b111111111), (litlen_entry(255), 9));
+        assert_eq!(llt(0b00000000), (litlen_entry(256), 7));
+        assert_eq!(llt(0b1110100), (litlen_entry(279), 7));
+        assert_eq!(llt(0b00000011), (litlen_entry(280), 8));
+        assert_eq!(llt(0b11100011), (litlen_entry(287), 8));
+
+        assert_eq!(dt(0), (dist_entry(0), 5));
+        assert_eq!(dt(20), (dist_entry(5), 5));
+
+        // Length and distance entries carry the base and number of extra bits.
+        assert_eq!(litlen_entry(265) >> ENTRY_VALUE_SHIFT, 11);
+        assert_eq!(
+            (litlen_entry(265) >> ENTRY_EXTRA_SHIFT) & ENTRY_EXTRA_MASK,
+            1
+        );
+        assert_eq!(dist_entry(5) >> ENTRY_VALUE_SHIFT, 7);
+        assert_eq!((dist_entry(5) >> ENTRY_EXTRA_SHIFT) & ENTRY_EXTRA_MASK, 1);
     }
 
     fn check_result(input: &[u8], expected_status: TINFLStatus, expected_state: State, zlib: bool) {
@@ -1763,6 +2013,10 @@ mod test {
             decompress(&mut r, input, &mut output_buf, 0, flags);
         assert_eq!(expected_status, d_status);
         assert_eq!(expected_state, r.state);
+        assert_eq!(
+            r.failure_reason().is_some(),
+            d_status == TINFLStatus::Failed
+        );
     }
 
     #[test]
@@ -1841,6 +2095,105 @@ mod test {
         //cr(&[0x1f, 0x8b, 0x08 ,0 ,0 ,0 ,0 ,0 ,0 ,0 ,0x03, 0, 0, 0, 0, 0, 0, 0, 0, 0x01], F, State::BadCRC, false)
     }
 
+    #[test]
+    fn failure_reason() {
+        let fail = |input: &[u8]| {
+            let mut r = DecompressorOxide::default();
+            let mut output_buf = vec![0; 1024 * 32];
+            let flags = TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+            let (status, _, _) = decompress(&mut r, input, &mut output_buf, 0, flags);
+            assert_eq!(status, TINFLStatus::Failed);
+            r.failure_reason().unwrap()
+        };
+        assert_eq!(fail(&[6]), FailureReason::BlockTypeUnexpected);
+        assert_eq!(fail(&[0, 0, 0, 0, 0]), FailureReason::BadRawLength);
+        assert_eq!(
+            fail(&[0x0c, 0xc0, 0x81, 0, 0, 0, 0, 0, 0x90, 0xff, 0x6b, 0x4, 0]),
+            FailureReason::DistanceOutOfBounds
+        );
+
+        let mut r = DecompressorOxide::default();
+        let mut output_buf = vec![0; 1024];
+        let (status, _, _) = decompress(&mut r, &[3, 0], &mut output_buf, 0, 0);
+        assert_eq!(status, TINFLStatus::Done);
+        assert_eq!(r.failure_reason(), None);
+    }
+
+    #[test]
+    fn block_position() {
+        let stored = [1, 5, 0, 0xfa, 0xff, b'h', b'e', b'l', b'l', b'o'];
+        let mut r = DecompressorOxide::default();
+        assert_eq!(r.block_position(), BlockPosition::Outside);
+        let mut output_buf = vec![0; 1024];
+        let flags = TINFL_FLAG_HAS_MORE_INPUT | TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+        let (status, in_pos, out_pos) = decompress(&mut r, &stored[..7], &mut output_buf, 0, flags);
+        assert_eq!(status, TINFLStatus::NeedsMoreInput);
+        assert_eq!(r.block_position(), BlockPosition::Stored { remaining: 3 });
+        let (status, _, _) = decompress(&mut r, &stored[in_pos..], &mut output_buf, out_pos, flags);
+        assert_eq!(status, TINFLStatus::Done);
+        assert_eq!(r.block_position(), BlockPosition::Outside);
+
+        let fixed = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+        let mut r = DecompressorOxide::default();
+        let (status, _, _) = decompress(&mut r, &fixed[..3], &mut output_buf, 0, flags);
+        assert_eq!(status, TINFLStatus::NeedsMoreInput);
+        assert_eq!(
+            r.block_position(),
+            BlockPosition::Compressed {
+                code_bits: 0,
+                written: 0
+            }
+        );
+
+        // Three literals followed by a match of length 57 (code 275 with 3 extra bits) and
+        // distance 3 (code 2) in a fixed block.
+        let flags = create_comp_flags_from_zip_params(1, -15, 0) | TDEFL_FORCE_ALL_STATIC_BLOCKS;
+        let mut d = CompressorOxide::new(flags);
+        let mut encoded = vec![];
+        compress_to_output(&mut d, &b"abc".repeat(20), TDEFLFlush::Finish, |out| {
+            encoded.extend_from_slice(out);
+            true
+        });
+        for &(out_len, position) in &[
+            (
+                2,
+                BlockPosition::Compressed {
+                    code_bits: 8,
+                    written: 0,
+                },
+            ),
+            (
+                10,
+                BlockPosition::Compressed {
+                    code_bits: 7 + 3 + 5,
+                    written: 7,
+                },
+            ),
+        ] {
+            let mut r = DecompressorOxide::default();
+            let mut output_buf = vec![0; out_len];
+            let flags = TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+            let (status, _, _) = decompress(&mut r, &encoded, &mut output_buf, 0, flags);
+            assert_eq!(status, TINFLStatus::HasMoreOutput);
+            assert_eq!(r.block_position(), position);
+        }
+    }
+
+    #[test]
+    fn prime() {
+        // The header of a final stored block, the padding up to the next byte is skipped as the
+        // bit buffer is empty afterwards.
+        let mut r = DecompressorOxide::default();
+        r.prime(3, 0b001).unwrap();
+        assert_eq!(r.prime(17, 0), Err(MZError::Stream));
+        let mut output_buf = vec![0; 1024];
+        let flags = TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+        let stored = [5, 0, 0xfa, 0xff, b'h', b'e', b'l', b'l', b'o'];
+        let (status, _, out_pos) = decompress(&mut r, &stored, &mut output_buf, 0, flags);
+        assert_eq!(status, TINFLStatus::Done);
+        assert_eq!(&output_buf[..out_pos], b"hello");
+    }
+
     #[test]
     fn empty_output_buffer_non_wrapping() {
         let encoded = [
@@ -1870,4 +2223,152 @@ mod test {
         let res = decompress(&mut r, &encoded, &mut output_buf, 0, flags);
         assert_eq!(res, (TINFLStatus::HasMoreOutput, 2, 0));
     }
+
+    #[test]
+    fn decompress_tokens() {
+        // "Deflate late" from the static huffman example by Mark Adler.
+        let encoded = [
+            0x73, 0x49, 0x4d, 0xcb, 0x49, 0x2c, 0x49, 0x55, 0x00, 0x11, 0x00,
+        ];
+        let mut r = DecompressorOxide::new();
+        let mut output_buf = vec![0; 32];
+        let mut tokens = Vec::new();
+        let res = decompress_with_tokens(
+            &mut r,
+            &encoded,
+            &mut output_buf,
+            0,
+            TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+            |token| tokens.push(token),
+        );
+        assert_eq!(res, (TINFLStatus::Done, encoded.len(), 12));
+        assert_eq!(&output_buf[..12], b"Deflate late");
+
+        let mut expected: Vec<LZToken> = b"Deflate ".iter().map(|&b| LZToken::Literal(b)).collect();
+        expected.push(LZToken::Match { len: 4, dist: 5 });
+        expected.push(LZToken::EndOfBlock);
+        assert_eq!(tokens, expected);
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        ZlibHeader(u8, u8),
+        BlockHeader(u64, bool, BlockType),
+        StoredBlockLen(u16),
+        HuffmanTables(usize, usize),
+        BlockEnd(u64),
+        ZlibTrailer(u32),
+    }
+
+    impl InflateVisitor for Vec<Event> {
+        fn zlib_header(&mut self, cmf: u8, flg: u8) {
+            self.push(Event::ZlibHeader(cmf, flg));
+        }
+        fn block_header(&mut self, bit_pos: u64, is_final: bool, block_type: BlockType) {
+            self.push(Event::BlockHeader(bit_pos, is_final, block_type));
+        }
+        fn stored_block_len(&mut self, len: u16) {
+            self.push(Event::StoredBlockLen(len));
+        }
+        fn huffman_tables(&mut self, litlen_code_sizes: &[u8], dist_code_sizes: &[u8]) {
+            self.push(Event::HuffmanTables(
+                litlen_code_sizes.len(),
+                dist_code_sizes.len(),
+            ));
+        }
+        fn block_end(&mut self, bit_pos: u64) {
+            self.push(Event::BlockEnd(bit_pos));
+        }
+        fn zlib_trailer(&mut self, adler32: u32) {
+            self.push(Event::ZlibTrailer(adler32));
+        }
+    }
+
+    #[test]
+    fn visit_stream() {
+        // A stored block containing "ab" followed by the static block from the test above.
+        let encoded = [
+            0x78, 0x01, 0x00, 0x02, 0x00, 0xfd, 0xff, b'a', b'b', 0x73, 0x49, 0x4d, 0xcb, 0x49,
+            0x2c, 0x49, 0x55, 0x00, 0x11, 0x00, 0x26, 0xf7, 0x05, 0x3f,
+        ];
+        let expected = [
+            Event::ZlibHeader(0x78, 0x01),
+            Event::BlockHeader(16, false, BlockType::Stored),
+            Event::StoredBlockLen(2),
+            Event::BlockEnd(72),
+            Event::BlockHeader(72, true, BlockType::Static),
+            Event::HuffmanTables(288, 32),
+            Event::BlockEnd(159),
+            Event::ZlibTrailer(0x26f7053f),
+        ];
+        let flags = TINFL_FLAG_PARSE_ZLIB_HEADER | TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+
+        let mut events = Vec::new();
+        let mut r = DecompressorOxide::new();
+        let mut output_buf = vec![0; 32];
+        let res = decompress_with_visitor(&mut r, &encoded, &mut output_buf, 0, flags, &mut events);
+        assert_eq!(res, (TINFLStatus::Done, encoded.len(), 14));
+        assert_eq!(&output_buf[..14], b"abDeflate late");
+        assert_eq!(events, expected);
+
+        // Bit positions should not depend on how the input is split up.
+        let mut events = Vec::new();
+        let mut r = DecompressorOxide::new();
+        let mut in_pos = 0;
+        let mut out_pos = 0;
+        for end in 1..=encoded.len() {
+            let (status, in_consumed, out_consumed) = decompress_with_visitor(
+                &mut r,
+                &encoded[in_pos..end],
+                &mut output_buf,
+                out_pos,
+                flags | TINFL_FLAG_HAS_MORE_INPUT,
+                &mut events,
+            );
+            in_pos += in_consumed;
+            out_pos += out_consumed;
+            if status == TINFLStatus::Done {
+                break;
+            }
+            assert_eq!(status, TINFLStatus::NeedsMoreInput);
+        }
+        assert_eq!(out_pos, 14);
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn decompress_to_output_chunks() {
+        use crate::deflate::compress_to_vec_zlib;
+
+        let data: Vec<u8> = (0..100_000u32)
+            .map(|n| (n % 251 * (n % 13)) as u8)
+            .collect();
+        let mut encoded = compress_to_vec_zlib(&data, 6);
+        let flags = TINFL_FLAG_COMPUTE_ADLER32 | TINFL_FLAG_PARSE_ZLIB_HEADER;
+
+        let mut output = Vec::new();
+        let (status, in_consumed) = decompress_to_output(&encoded, flags, |chunk| {
+            assert!(chunk.len() <= TINFL_LZ_DICT_SIZE);
+            output.extend_from_slice(chunk);
+            true
+        });
+        assert_eq!(status, TINFLStatus::Done);
+        assert_eq!(in_consumed, encoded.len());
+        assert_eq!(output, data);
+
+        // Stopping in the callback should abort decompression.
+        let mut calls = 0;
+        let (status, _) = decompress_to_output(&encoded, flags, |_| {
+            calls += 1;
+            false
+        });
+        assert_eq!(status, TINFLStatus::Failed);
+        assert_eq!(calls, 1);
+
+        // Truncated input can't be completed as more input is never expected.
+        encoded.truncate(encoded.len() / 2);
+        let (status, in_consumed) = decompress_to_output(&encoded, flags, |_| true);
+        assert_eq!(status, TINFLStatus::FailedCannotMakeProgress);
+        assert_eq!(in_consumed, encoded.len());
+    }
 }
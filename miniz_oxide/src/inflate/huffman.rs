@@ -0,0 +1,204 @@
+//! Lookup tables used to decode the huffman codes of deflate blocks.
+//!
+//! Each table is indexed by the next bits of input, and resolves codes of up to `*_TABLE_BITS`
+//! bits in a single lookup. Longer codes point to a subtable indexed by the bits following the
+//! first `*_TABLE_BITS`. Entries for literal/length and distance codes also carry the base value
+//! and the number of extra bits of the symbol, so it doesn't have to be looked up separately.
+
+use super::core::{DIST_BASE, DIST_EXTRA, LENGTH_BASE, LENGTH_EXTRA};
+
+// Layout of the lookup table entries:
+// bits 0-7: number of bits to consume for the code.
+// bits 8-11: number of extra bits following the code, or index bits of a subtable.
+// bits 12-16: flags.
+// bits 17-31: literal byte, base length or distance, precode symbol or subtable start.
+pub(super) const ENTRY_LEN_MASK: u32 = 0xFF;
+pub(super) const ENTRY_EXTRA_SHIFT: u32 = 8;
+pub(super) const ENTRY_EXTRA_MASK: u32 = 0xF;
+pub(super) const ENTRY_LITERAL: u32 = 1 << 12;
+pub(super) const ENTRY_END_OF_BLOCK: u32 = 1 << 13;
+pub(super) const ENTRY_SUBTABLE: u32 = 1 << 14;
+/// Set for symbols that are not allowed in the data, and for codes not assigned to any symbol.
+pub(super) const ENTRY_INVALID: u32 = 1 << 15;
+/// Set, along with `ENTRY_INVALID`, for codes not assigned to any symbol.
+pub(super) const ENTRY_UNUSED_CODE: u32 = 1 << 16;
+pub(super) const ENTRY_VALUE_SHIFT: u32 = 17;
+
+pub(super) const MAX_CODE_LEN: usize = 15;
+
+/// Number of bits resolved by the main table of each code, longer codes go through a subtable.
+pub(super) const LITLEN_TABLE_BITS: u32 = 11;
+pub(super) const DIST_TABLE_BITS: u32 = 8;
+pub(super) const PRECODE_TABLE_BITS: u32 = 7;
+
+/// Largest size of the tables, counting the subtables, for any valid code.
+///
+/// These are the maximums found by the `enough` utility from zlib, for 288 and 32 symbols
+/// respectively, with a maximum code length of 15.
+pub(super) const LITLEN_TABLE_SIZE: usize = 2342;
+pub(super) const DIST_TABLE_SIZE: usize = 402;
+pub(super) const PRECODE_TABLE_SIZE: usize = 1 << PRECODE_TABLE_BITS;
+
+#[inline]
+fn reverse_bits(mut code: u32, len: usize) -> u32 {
+    let mut rev = 0;
+    for _ in 0..len {
+        rev = (rev << 1) | (code & 1);
+        code >>= 1;
+    }
+    rev
+}
+
+/// Build the lookup table for the code with the given code lengths, where `entry` gives the
+/// value and flags of the entry for each symbol.
+///
+/// Returns false if the code is oversubscribed, or incomplete while using more than one symbol.
+pub(super) fn build_table(
+    table: &mut [u32],
+    table_bits: u32,
+    code_lens: &[u8],
+    entry: impl Fn(usize) -> u32,
+) -> bool {
+    let mut counts = [0u32; MAX_CODE_LEN + 1];
+    for &len in code_lens {
+        counts[usize::from(len)] += 1;
+    }
+    counts[0] = 0;
+
+    let mut next_code = [0u32; MAX_CODE_LEN + 1];
+    let mut code = 0;
+    let mut used_symbols = 0;
+    for len in 1..=MAX_CODE_LEN {
+        code = (code + counts[len - 1]) << 1;
+        next_code[len] = code;
+        used_symbols += counts[len];
+    }
+    let total = code + counts[MAX_CODE_LEN];
+    if total != 1 << MAX_CODE_LEN {
+        if used_symbols > 1 {
+            return false;
+        }
+        // Same as miniz, a code with a single symbol (or none) is accepted, and the unused
+        // codes are an error when they show up in the data. They take up the maximum code
+        // length, so running into them in the padding past the end of the input counts as the
+        // input being truncated.
+        let (main, subtables) = table.split_at_mut(1 << table_bits);
+        for e in main {
+            *e = ENTRY_INVALID | ENTRY_UNUSED_CODE | MAX_CODE_LEN as u32;
+        }
+        for e in subtables {
+            *e = ENTRY_INVALID | ENTRY_UNUSED_CODE | (MAX_CODE_LEN as u32 - table_bits);
+        }
+    }
+
+    let main_size = 1 << table_bits;
+    let main_mask = main_size - 1;
+    let mut codes = [0u16; 288];
+    let mut has_long_codes = false;
+    for (symbol, &len) in code_lens.iter().enumerate() {
+        let len = usize::from(len);
+        if len == 0 {
+            continue;
+        }
+        let rev_code = reverse_bits(next_code[len], len) as usize;
+        next_code[len] += 1;
+        codes[symbol] = rev_code as u16;
+
+        if len <= table_bits as usize {
+            let e = entry(symbol) | len as u32;
+            let mut i = rev_code;
+            while i < main_size {
+                table[i] = e;
+                i += 1 << len;
+            }
+        } else {
+            // Start tracking the longest code sharing this prefix.
+            table[rev_code & main_mask] = 0;
+            has_long_codes = true;
+        }
+    }
+
+    if !has_long_codes {
+        return true;
+    }
+
+    let long_codes = || {
+        code_lens
+            .iter()
+            .enumerate()
+            .filter(|&(_, &len)| u32::from(len) > table_bits)
+    };
+    for (symbol, &len) in long_codes() {
+        let e = &mut table[codes[symbol] as usize & main_mask];
+        *e = (*e).max(u32::from(len));
+    }
+
+    let mut next_subtable = main_size;
+    for (symbol, &len) in long_codes() {
+        let rev_code = codes[symbol] as usize;
+        let prefix = rev_code & main_mask;
+        if table[prefix] & ENTRY_SUBTABLE == 0 {
+            let sub_bits = table[prefix] - table_bits;
+            if next_subtable + (1 << sub_bits) > table.len() {
+                return false;
+            }
+            table[prefix] = ENTRY_SUBTABLE
+                | (sub_bits << ENTRY_EXTRA_SHIFT)
+                | ((next_subtable as u32) << ENTRY_VALUE_SHIFT);
+            next_subtable += 1 << sub_bits;
+        }
+
+        let start = (table[prefix] >> ENTRY_VALUE_SHIFT) as usize;
+        let sub_size = 1 << ((table[prefix] >> ENTRY_EXTRA_SHIFT) & ENTRY_EXTRA_MASK);
+        let sub_len = u32::from(len) - table_bits;
+        let e = entry(symbol) | sub_len;
+        let mut i = rev_code >> table_bits;
+        while i < sub_size {
+            table[start + i] = e;
+            i += 1 << sub_len;
+        }
+    }
+
+    true
+}
+
+pub(super) fn litlen_entry(symbol: usize) -> u32 {
+    match symbol {
+        0..=255 => ENTRY_LITERAL | ((symbol as u32) << ENTRY_VALUE_SHIFT),
+        256 => ENTRY_END_OF_BLOCK,
+        257..=285 => {
+            let i = symbol - 257;
+            (u32::from(LENGTH_BASE[i]) << ENTRY_VALUE_SHIFT)
+                | (u32::from(LENGTH_EXTRA[i]) << ENTRY_EXTRA_SHIFT)
+        }
+        _ => ENTRY_INVALID,
+    }
+}
+
+pub(super) fn dist_entry(symbol: usize) -> u32 {
+    if symbol < 30 {
+        (u32::from(DIST_BASE[symbol]) << ENTRY_VALUE_SHIFT)
+            | (u32::from(DIST_EXTRA[symbol]) << ENTRY_EXTRA_SHIFT)
+    } else {
+        ENTRY_INVALID
+    }
+}
+
+pub(super) fn precode_entry(symbol: usize) -> u32 {
+    (symbol as u32) << ENTRY_VALUE_SHIFT
+}
+
+/// Look up the entry for the next code in the bit buffer, following subtable links.
+///
+/// The number of bits to consume in the returned entry includes the main table bits
+/// for codes resolved in a subtable.
+#[inline(always)]
+pub(super) fn lookup(table: &[u32], table_bits: u32, bit_buf: u64) -> u32 {
+    let entry = table[(bit_buf & ((1 << table_bits) - 1)) as usize];
+    if entry & ENTRY_SUBTABLE == 0 {
+        return entry;
+    }
+    let sub_bits = (entry >> ENTRY_EXTRA_SHIFT) & ENTRY_EXTRA_MASK;
+    let index = ((bit_buf >> table_bits) & ((1 << sub_bits) - 1)) as usize;
+    table[(entry >> ENTRY_VALUE_SHIFT) as usize + index] + table_bits
+}
@@ -2,12 +2,19 @@
 
 use ::core::cmp::min;
 use ::core::usize;
+#[cfg(feature = "alloc")]
 use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
 use alloc::vec;
+#[cfg(feature = "alloc")]
 use alloc::vec::Vec;
 
 pub mod core;
+mod huffman;
+mod one_shot;
 mod output_buffer;
+#[cfg(feature = "parallel")]
+mod parallel;
 pub mod stream;
 use self::core::*;
 
@@ -61,6 +68,7 @@ impl TINFLStatus {
 ///
 /// Returns a status and an integer representing where the decompressor failed on failure.
 #[inline]
+#[cfg(feature = "alloc")]
 pub fn decompress_to_vec(input: &[u8]) -> Result<Vec<u8>, TINFLStatus> {
     decompress_to_vec_inner(input, 0, usize::max_value())
 }
@@ -69,6 +77,7 @@ pub fn decompress_to_vec(input: &[u8]) -> Result<Vec<u8>, TINFLStatus> {
 ///
 /// Returns a status and an integer representing where the decompressor failed on failure.
 #[inline]
+#[cfg(feature = "alloc")]
 pub fn decompress_to_vec_zlib(input: &[u8]) -> Result<Vec<u8>, TINFLStatus> {
     decompress_to_vec_inner(
         input,
@@ -83,6 +92,7 @@ pub fn decompress_to_vec_zlib(input: &[u8]) -> Result<Vec<u8>, TINFLStatus> {
 ///
 /// Returns a status and an integer representing where the decompressor failed on failure.
 #[inline]
+#[cfg(feature = "alloc")]
 pub fn decompress_to_vec_with_limit(input: &[u8], max_size: usize) -> Result<Vec<u8>, TINFLStatus> {
     decompress_to_vec_inner(input, 0, max_size)
 }
@@ -93,6 +103,7 @@ pub fn decompress_to_vec_with_limit(input: &[u8], max_size: usize) -> Result<Vec
 ///
 /// Returns a status and an integer representing where the decompressor failed on failure.
 #[inline]
+#[cfg(feature = "alloc")]
 pub fn decompress_to_vec_zlib_with_limit(
     input: &[u8],
     max_size: usize,
@@ -100,6 +111,121 @@ pub fn decompress_to_vec_zlib_with_limit(
     decompress_to_vec_inner(input, inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER, max_size)
 }
 
+/// Decompress the deflate-encoded data in `input` into `output`, which must be large enough
+/// to hold all of the decompressed data.
+///
+/// This uses a faster decoder than `decompress_to_vec` and does not need to allocate, but
+/// can't stop partway through the stream, so it is best suited for when the size of the
+/// decompressed data is known in advance.
+///
+/// Returns the number of bytes written to `output`. The contents of `output` past that are
+/// unspecified, as they may have been used as scratch space.
+///
+/// # Errors
+/// Returns `TINFLStatus::HasMoreOutput` if `output` is too small,
+/// `TINFLStatus::FailedCannotMakeProgress` if the input is truncated and
+/// `TINFLStatus::Failed` if it is invalid.
+#[inline]
+pub fn decompress_slice(input: &[u8], output: &mut [u8]) -> Result<usize, TINFLStatus> {
+    one_shot::decompress(input, output, false)
+}
+
+/// Decompress the deflate-encoded data (with a zlib wrapper) in `input` into `output`, which
+/// must be large enough to hold all of the decompressed data.
+///
+/// See `decompress_slice`. Additionally returns `TINFLStatus::Adler32Mismatch` if the checksum
+/// of the decompressed data doesn't match the one in the stream.
+#[inline]
+pub fn decompress_slice_zlib(input: &[u8], output: &mut [u8]) -> Result<usize, TINFLStatus> {
+    one_shot::decompress(input, output, true)
+}
+
+/// Decompress the deflate-encoded data in `input` into `output` using `decompressor`,
+/// without allocating.
+///
+/// `decompressor` is reset first, so the same one can be reused for many calls. Unlike
+/// `decompress_slice`, this uses the regular decompressor, which checks the stream as it goes
+/// and leaves `output` past the decompressed data untouched.
+///
+/// Returns the number of bytes written to `output`.
+///
+/// # Errors
+/// Returns `TINFLStatus::HasMoreOutput` if `output` is too small,
+/// `TINFLStatus::FailedCannotMakeProgress` if the input is truncated and
+/// `TINFLStatus::Failed` if it is invalid.
+#[inline]
+pub fn decompress_into(
+    decompressor: &mut DecompressorOxide,
+    input: &[u8],
+    output: &mut [u8],
+) -> Result<usize, TINFLStatus> {
+    decompress_into_inner(decompressor, input, output, 0)
+}
+
+/// Decompress the deflate-encoded data (with a zlib wrapper) in `input` into `output` using
+/// `decompressor`, without allocating.
+///
+/// See `decompress_into`. Additionally returns `TINFLStatus::Adler32Mismatch` if the checksum
+/// of the decompressed data doesn't match the one in the stream.
+#[inline]
+pub fn decompress_into_zlib(
+    decompressor: &mut DecompressorOxide,
+    input: &[u8],
+    output: &mut [u8],
+) -> Result<usize, TINFLStatus> {
+    decompress_into_inner(
+        decompressor,
+        input,
+        output,
+        inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER,
+    )
+}
+
+/// Decompress the deflate-encoded data in `input` to a vector, using up to `num_threads`
+/// threads for streams split into independent parts by full flushes (`MZFlush::Full`).
+///
+/// Each full flush ends with an empty stored block, which is where the input is split to be
+/// decompressed in parallel. The parts are put back together in order, and those that turn out
+/// to depend on the data before them, like the ones following sync flushes, are decompressed
+/// again sequentially. The result is the same as for `decompress_to_vec`, but only streams
+/// with full flushes every few hundred KiB or so get faster.
+#[cfg(feature = "parallel")]
+pub fn decompress_to_vec_parallel(
+    input: &[u8],
+    num_threads: usize,
+) -> Result<Vec<u8>, TINFLStatus> {
+    parallel::decompress_to_vec(input, false, num_threads, parallel::MIN_PART_SIZE)
+}
+
+/// Decompress the deflate-encoded data (with a zlib wrapper) in `input` to a vector, using up
+/// to `num_threads` threads for streams split into independent parts by full flushes.
+///
+/// See `decompress_to_vec_parallel`. The checksum of the whole decompressed data is checked
+/// against the one at the end of the stream.
+#[cfg(feature = "parallel")]
+pub fn decompress_to_vec_zlib_parallel(
+    input: &[u8],
+    num_threads: usize,
+) -> Result<Vec<u8>, TINFLStatus> {
+    parallel::decompress_to_vec(input, true, num_threads, parallel::MIN_PART_SIZE)
+}
+
+fn decompress_into_inner(
+    decompressor: &mut DecompressorOxide,
+    input: &[u8],
+    output: &mut [u8],
+    flags: u32,
+) -> Result<usize, TINFLStatus> {
+    // All of the output is in `output`, so matches never have to wrap around.
+    let flags = flags | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    decompressor.init();
+    match decompress(decompressor, input, output, 0, flags) {
+        (TINFLStatus::Done, _, out_pos) => Ok(out_pos),
+        (status, _, _) => Err(status),
+    }
+}
+
+#[cfg(feature = "alloc")]
 fn decompress_to_vec_inner(
     input: &[u8],
     flags: u32,
@@ -143,10 +269,13 @@ fn decompress_to_vec_inner(
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "alloc"))]
 mod test {
     use super::TINFLStatus;
-    use super::{decompress_to_vec_zlib, decompress_to_vec_zlib_with_limit};
+    use super::{
+        decompress_into, decompress_into_zlib, decompress_to_vec_zlib,
+        decompress_to_vec_zlib_with_limit, DecompressorOxide,
+    };
     const encoded: [u8; 20] = [
         120, 156, 243, 72, 205, 201, 201, 215, 81, 168, 202, 201, 76, 82, 4, 0, 27, 101, 4, 19,
     ];
@@ -171,4 +300,26 @@ mod test {
             _ => panic!("Decompression output size limit was not enforced"),
         }
     }
+
+    #[test]
+    fn decompress_into_slice() {
+        let mut decompressor = DecompressorOxide::new();
+        let mut out = [0; 16];
+        let res = decompress_into_zlib(&mut decompressor, &encoded[..], &mut out);
+        assert_eq!(res, Ok(12));
+        assert_eq!(&out[..12], &b"Hello, zlib!"[..]);
+        assert_eq!(&out[12..], &[0; 4]);
+
+        // The decompressor is reset for each call.
+        let res = decompress_into_zlib(&mut decompressor, &encoded[..], &mut out[..8]);
+        assert_eq!(res, Err(TINFLStatus::HasMoreOutput));
+        let res = decompress_into_zlib(&mut decompressor, &encoded[..10], &mut out);
+        assert_eq!(res, Err(TINFLStatus::FailedCannotMakeProgress));
+        let mut bad_checksum = encoded;
+        bad_checksum[19] ^= 1;
+        let res = decompress_into_zlib(&mut decompressor, &bad_checksum[..], &mut out);
+        assert_eq!(res, Err(TINFLStatus::Adler32Mismatch));
+        let res = decompress_into(&mut decompressor, &encoded[2..16], &mut out);
+        assert_eq!(res, Ok(12));
+    }
 }
@@ -0,0 +1,643 @@
+//! Non-resumable decompressor for when the whole input and output are available at once.
+//!
+//! Since it never has to stop halfway through a stream and save its state, this decoder can
+//! keep everything in local variables, refill its bit buffer 8 bytes at a time, resolve
+//! symbols with two-level lookup tables and copy matches 8 bytes at a time.
+
+use super::huffman::*;
+use super::TINFLStatus;
+use crate::shared::{update_adler32, HUFFMAN_LENGTH_ORDER, MZ_ADLER32_INIT};
+
+/// Free space needed in the output buffer to take the fast path for a match, which may write
+/// up to 15 bytes past the end of a match of the maximum length.
+const FAST_OUTPUT_SLACK: usize = 258 + 16;
+
+struct BitReader<'a> {
+    input: &'a [u8],
+    /// Position of the next byte of input to add to the bit buffer.
+    pos: usize,
+    /// Bits read from the input but not consumed yet, starting from the lowest bit.
+    ///
+    /// The bits above `num_bits` may hold the start of the following input bytes.
+    bit_buf: u64,
+    num_bits: u32,
+    /// Number of zero bytes added to the bit buffer past the end of the input.
+    overread: usize,
+}
+
+impl<'a> BitReader<'a> {
+    /// Make sure the bit buffer holds at least 56 bits.
+    #[inline(always)]
+    fn refill(&mut self) -> Result<(), TINFLStatus> {
+        if let Some(bytes) = self.input.get(self.pos..self.pos + 8) {
+            let mut word = [0; 8];
+            word.copy_from_slice(bytes);
+            self.bit_buf |= u64::from_le_bytes(word) << self.num_bits;
+            self.pos += (63 - self.num_bits as usize) >> 3;
+            self.num_bits |= 56;
+            Ok(())
+        } else {
+            self.refill_slow()
+        }
+    }
+
+    /// Refill byte by byte near the end of the input, padding with zeroes past the end.
+    #[inline(never)]
+    fn refill_slow(&mut self) -> Result<(), TINFLStatus> {
+        while self.num_bits < 56 {
+            let byte = match self.input.get(self.pos) {
+                Some(&byte) => {
+                    self.pos += 1;
+                    byte
+                }
+                None => {
+                    self.overread += 1;
+                    0
+                }
+            };
+            self.bit_buf |= u64::from(byte) << self.num_bits;
+            self.num_bits += 8;
+        }
+        // Once more padding has been added than fits in the bit buffer, some of it has been
+        // consumed, so the input was truncated.
+        if self.overread > 8 {
+            Err(TINFLStatus::FailedCannotMakeProgress)
+        } else {
+            Ok(())
+        }
+    }
+
+    #[inline(always)]
+    fn consume(&mut self, n: u32) {
+        self.bit_buf >>= n;
+        self.num_bits -= n;
+    }
+
+    #[inline(always)]
+    fn take(&mut self, n: u32) -> u32 {
+        let bits = (self.bit_buf & ((1 << n) - 1)) as u32;
+        self.consume(n);
+        bits
+    }
+
+    /// Skip to the next byte boundary and hand the whole bytes left in the bit buffer back to
+    /// the input, so the input can be read directly from `pos`.
+    fn align_to_byte(&mut self) -> Result<(), TINFLStatus> {
+        let whole_bytes = (self.num_bits >> 3) as usize;
+        if self.overread > whole_bytes {
+            return Err(TINFLStatus::FailedCannotMakeProgress);
+        }
+        self.pos -= whole_bytes - self.overread;
+        self.overread = 0;
+        self.bit_buf = 0;
+        self.num_bits = 0;
+        Ok(())
+    }
+}
+
+struct Tables {
+    litlen: [u32; LITLEN_TABLE_SIZE],
+    dist: [u32; DIST_TABLE_SIZE],
+}
+
+impl Tables {
+    fn build_fixed(&mut self) {
+        let mut lens = [8u8; 288];
+        for len in &mut lens[144..256] {
+            *len = 9;
+        }
+        for len in &mut lens[256..280] {
+            *len = 7;
+        }
+        build_table(&mut self.litlen, LITLEN_TABLE_BITS, &lens, litlen_entry);
+        build_table(&mut self.dist, DIST_TABLE_BITS, &[5; 32], dist_entry);
+    }
+
+    /// Read the code lengths of a dynamic block and build the tables for them.
+    fn read_dynamic(&mut self, r: &mut BitReader) -> Result<(), TINFLStatus> {
+        r.refill()?;
+        let num_litlen = r.take(5) as usize + 257;
+        let num_dist = r.take(5) as usize + 1;
+        let num_precode = r.take(4) as usize + 4;
+
+        let mut precode_lens = [0u8; 19];
+        for &symbol in &HUFFMAN_LENGTH_ORDER[..num_precode] {
+            r.refill()?;
+            precode_lens[usize::from(symbol)] = r.take(3) as u8;
+        }
+        let mut precode = [0u32; PRECODE_TABLE_SIZE];
+        if !build_table(
+            &mut precode,
+            PRECODE_TABLE_BITS,
+            &precode_lens,
+            precode_entry,
+        ) {
+            return Err(TINFLStatus::Failed);
+        }
+
+        let total = num_litlen + num_dist;
+        let mut lens = [0u8; 288 + 32];
+        let mut i = 0;
+        while i < total {
+            r.refill()?;
+            let entry = lookup(&precode, PRECODE_TABLE_BITS, r.bit_buf);
+            r.consume(entry & ENTRY_LEN_MASK);
+            if entry & ENTRY_INVALID != 0 {
+                return Err(TINFLStatus::Failed);
+            }
+            let symbol = entry >> ENTRY_VALUE_SHIFT;
+            if symbol < 16 {
+                lens[i] = symbol as u8;
+                i += 1;
+                continue;
+            }
+
+            let (len, repeat) = match symbol {
+                16 if i == 0 => return Err(TINFLStatus::Failed),
+                16 => (lens[i - 1], 3 + r.take(2)),
+                17 => (0, 3 + r.take(3)),
+                _ => (0, 11 + r.take(7)),
+            };
+            let end = i + repeat as usize;
+            if end > total {
+                return Err(TINFLStatus::Failed);
+            }
+            for l in &mut lens[i..end] {
+                *l = len;
+            }
+            i = end;
+        }
+
+        if build_table(
+            &mut self.litlen,
+            LITLEN_TABLE_BITS,
+            &lens[..num_litlen],
+            litlen_entry,
+        ) && build_table(
+            &mut self.dist,
+            DIST_TABLE_BITS,
+            &lens[num_litlen..total],
+            dist_entry,
+        ) {
+            Ok(())
+        } else {
+            Err(TINFLStatus::Failed)
+        }
+    }
+}
+
+#[inline(always)]
+fn copy_word(out: &mut [u8], src: usize, dst: usize) {
+    let mut word = [0; 8];
+    word.copy_from_slice(&out[src..src + 8]);
+    out[dst..dst + 8].copy_from_slice(&word);
+}
+
+#[inline(always)]
+fn copy_double_word(out: &mut [u8], src: usize, dst: usize) {
+    let mut word = [0; 16];
+    word.copy_from_slice(&out[src..src + 16]);
+    out[dst..dst + 16].copy_from_slice(&word);
+}
+
+/// Copy a match in whole words, possibly writing garbage past its end.
+///
+/// Requires `FAST_OUTPUT_SLACK` bytes of space after `out_pos`.
+#[inline(always)]
+fn copy_match_fast(out: &mut [u8], out_pos: usize, dist: usize, length: usize) {
+    let end = out_pos + length;
+    let mut src = out_pos - dist;
+    let mut dst = out_pos;
+    if dist >= 16 {
+        // Each word only reads bytes that have already been written, since the words are no
+        // longer than the distance.
+        loop {
+            copy_double_word(out, src, dst);
+            src += 16;
+            dst += 16;
+            if dst >= end {
+                break;
+            }
+        }
+    } else if dist >= 8 {
+        loop {
+            copy_word(out, src, dst);
+            src += 8;
+            dst += 8;
+            if dst >= end {
+                break;
+            }
+        }
+    } else {
+        // Repeat the last `dist` bytes over a word, then write it out, moving forward by a
+        // multiple of the distance each time so the pattern stays aligned.
+        let mut pattern = [0; 8];
+        pattern[..dist].copy_from_slice(&out[src..out_pos]);
+        for i in dist..8 {
+            pattern[i] = pattern[i - dist];
+        }
+        let step = [8, 8, 8, 6, 8, 5, 6, 7][dist];
+        while dst < end {
+            out[dst..dst + 8].copy_from_slice(&pattern);
+            dst += step;
+        }
+    }
+}
+
+#[inline]
+fn copy_match(out: &mut [u8], out_pos: usize, dist: usize, length: usize) {
+    for i in out_pos..out_pos + length {
+        out[i] = out[i - dist];
+    }
+}
+
+/// Decode symbols while there is enough input and space in the output that neither can run out
+/// within a symbol, returning the new output position and whether the end of the block was
+/// reached.
+fn decode_fast(
+    r: &mut BitReader,
+    tables: &Tables,
+    out: &mut [u8],
+    mut out_pos: usize,
+) -> Result<(usize, bool), TINFLStatus> {
+    // Keep the reader state in locals so it can stay in registers.
+    let input = r.input;
+    let mut pos = r.pos;
+    let mut bit_buf = r.bit_buf;
+    let mut num_bits = r.num_bits;
+
+    macro_rules! refill {
+        () => {{
+            let mut word = [0; 8];
+            word.copy_from_slice(&input[pos..pos + 8]);
+            bit_buf |= u64::from_le_bytes(word) << num_bits;
+            pos += (63 - num_bits as usize) >> 3;
+            num_bits |= 56;
+        }};
+    }
+    macro_rules! consume {
+        ($n:expr) => {{
+            let n = $n;
+            bit_buf >>= n;
+            num_bits -= n;
+        }};
+    }
+
+    if input.len() - pos < 8 || out.len() - out_pos < FAST_OUTPUT_SLACK {
+        return Ok((out_pos, false));
+    }
+    refill!();
+    // A literal/length code with its extra bits and a distance code with its extra bits take at
+    // most 15 + 5 + 15 + 13 = 48 bits, so one refill is enough for each symbol. A refill also
+    // leaves the following input bytes above `num_bits`, so there are still at least 16 valid
+    // bits after consuming a symbol, and the next code can be looked up before refilling.
+    let mut entry = lookup(&tables.litlen, LITLEN_TABLE_BITS, bit_buf);
+
+    let result = loop {
+        if entry & ENTRY_LITERAL != 0 {
+            // Up to three literals fit in the bits from one refill.
+            for _ in 0..3 {
+                consume!(entry & ENTRY_LEN_MASK);
+                out[out_pos] = (entry >> ENTRY_VALUE_SHIFT) as u8;
+                out_pos += 1;
+                entry = lookup(&tables.litlen, LITLEN_TABLE_BITS, bit_buf);
+                if entry & ENTRY_LITERAL == 0 {
+                    break;
+                }
+            }
+        } else {
+            consume!(entry & ENTRY_LEN_MASK);
+            if entry & (ENTRY_END_OF_BLOCK | ENTRY_INVALID) != 0 {
+                break if entry & ENTRY_INVALID != 0 {
+                    Err(TINFLStatus::Failed)
+                } else {
+                    Ok((out_pos, true))
+                };
+            }
+
+            let extra = (entry >> ENTRY_EXTRA_SHIFT) & ENTRY_EXTRA_MASK;
+            let length =
+                (entry >> ENTRY_VALUE_SHIFT) as usize + (bit_buf & ((1 << extra) - 1)) as usize;
+            consume!(extra);
+
+            let dist_entry = lookup(&tables.dist, DIST_TABLE_BITS, bit_buf);
+            consume!(dist_entry & ENTRY_LEN_MASK);
+            if dist_entry & ENTRY_INVALID != 0 {
+                break Err(TINFLStatus::Failed);
+            }
+            let extra = (dist_entry >> ENTRY_EXTRA_SHIFT) & ENTRY_EXTRA_MASK;
+            let dist = (dist_entry >> ENTRY_VALUE_SHIFT) as usize
+                + (bit_buf & ((1 << extra) - 1)) as usize;
+            consume!(extra);
+
+            if dist > out_pos {
+                break Err(TINFLStatus::Failed);
+            }
+            entry = lookup(&tables.litlen, LITLEN_TABLE_BITS, bit_buf);
+            copy_match_fast(out, out_pos, dist, length);
+            out_pos += length;
+        }
+
+        if input.len() - pos < 8 || out.len() - out_pos < FAST_OUTPUT_SLACK {
+            break Ok((out_pos, false));
+        }
+        refill!();
+    };
+
+    r.pos = pos;
+    r.bit_buf = bit_buf;
+    r.num_bits = num_bits;
+    result
+}
+
+/// Decode the data of a huffman block, returning the new output position.
+fn decode_block(
+    r: &mut BitReader,
+    tables: &Tables,
+    out: &mut [u8],
+    mut out_pos: usize,
+) -> Result<usize, TINFLStatus> {
+    loop {
+        let (new_pos, done) = decode_fast(r, tables, out, out_pos)?;
+        out_pos = new_pos;
+        if done {
+            return Ok(out_pos);
+        }
+
+        // Close to the end of the input or output, decode one symbol with all the checks.
+        r.refill()?;
+        let entry = lookup(&tables.litlen, LITLEN_TABLE_BITS, r.bit_buf);
+        r.consume(entry & ENTRY_LEN_MASK);
+
+        if entry & ENTRY_LITERAL != 0 {
+            match out.get_mut(out_pos) {
+                Some(b) => *b = (entry >> ENTRY_VALUE_SHIFT) as u8,
+                None => return Err(TINFLStatus::HasMoreOutput),
+            }
+            out_pos += 1;
+            continue;
+        }
+
+        if entry & (ENTRY_END_OF_BLOCK | ENTRY_INVALID) != 0 {
+            return if entry & ENTRY_INVALID != 0 {
+                Err(TINFLStatus::Failed)
+            } else {
+                Ok(out_pos)
+            };
+        }
+
+        let length = (entry >> ENTRY_VALUE_SHIFT) as usize
+            + r.take((entry >> ENTRY_EXTRA_SHIFT) & ENTRY_EXTRA_MASK) as usize;
+
+        let entry = lookup(&tables.dist, DIST_TABLE_BITS, r.bit_buf);
+        r.consume(entry & ENTRY_LEN_MASK);
+        if entry & ENTRY_INVALID != 0 {
+            return Err(TINFLStatus::Failed);
+        }
+        let dist = (entry >> ENTRY_VALUE_SHIFT) as usize
+            + r.take((entry >> ENTRY_EXTRA_SHIFT) & ENTRY_EXTRA_MASK) as usize;
+
+        if dist > out_pos {
+            return Err(TINFLStatus::Failed);
+        }
+        if out.len() - out_pos < length {
+            return Err(TINFLStatus::HasMoreOutput);
+        }
+        copy_match(out, out_pos, dist, length);
+        out_pos += length;
+    }
+}
+
+fn stored_block(r: &mut BitReader, out: &mut [u8], out_pos: usize) -> Result<usize, TINFLStatus> {
+    let header = r
+        .input
+        .get(r.pos..r.pos + 4)
+        .ok_or(TINFLStatus::FailedCannotMakeProgress)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err(TINFLStatus::Failed);
+    }
+
+    let start = r.pos + 4;
+    let len = usize::from(len);
+    let data = r
+        .input
+        .get(start..start + len)
+        .ok_or(TINFLStatus::FailedCannotMakeProgress)?;
+    out.get_mut(out_pos..out_pos + len)
+        .ok_or(TINFLStatus::HasMoreOutput)?
+        .copy_from_slice(data);
+    r.pos = start + len;
+    Ok(out_pos + len)
+}
+
+/// Decode all the blocks of a deflate stream, returning the number of bytes written to `out`.
+fn decode_blocks(r: &mut BitReader, out: &mut [u8]) -> Result<usize, TINFLStatus> {
+    let mut tables = Tables {
+        litlen: [0; LITLEN_TABLE_SIZE],
+        dist: [0; DIST_TABLE_SIZE],
+    };
+    let mut have_fixed_tables = false;
+    let mut out_pos = 0;
+    loop {
+        r.refill()?;
+        let final_block = r.take(1) != 0;
+        match r.take(2) {
+            0 => {
+                r.align_to_byte()?;
+                out_pos = stored_block(r, out, out_pos)?;
+            }
+            1 => {
+                if !have_fixed_tables {
+                    tables.build_fixed();
+                    have_fixed_tables = true;
+                }
+                out_pos = decode_block(r, &tables, out, out_pos)?;
+            }
+            2 => {
+                tables.read_dynamic(r)?;
+                have_fixed_tables = false;
+                out_pos = decode_block(r, &tables, out, out_pos)?;
+            }
+            _ => return Err(TINFLStatus::Failed),
+        }
+        if final_block {
+            return Ok(out_pos);
+        }
+    }
+}
+
+/// Decompress a whole deflate stream, optionally with a zlib wrapper, returning the number of
+/// bytes written to `out`.
+pub(crate) fn decompress(input: &[u8], out: &mut [u8], zlib: bool) -> Result<usize, TINFLStatus> {
+    let mut r = BitReader {
+        input,
+        pos: 0,
+        bit_buf: 0,
+        num_bits: 0,
+        overread: 0,
+    };
+
+    if zlib {
+        if input.len() < 2 {
+            return Err(TINFLStatus::FailedCannotMakeProgress);
+        }
+        let (cmf, flg) = (u32::from(input[0]), u32::from(input[1]));
+        // Same checks as `core::decompress`: valid check bits, no preset dictionary, the deflate
+        // method and a window size of at most 32 KiB.
+        if (cmf * 256 + flg) % 31 != 0 || flg & 0b0010_0000 != 0 || cmf & 15 != 8 || cmf >> 4 > 7 {
+            return Err(TINFLStatus::Failed);
+        }
+        r.pos = 2;
+    }
+
+    let out_pos = match decode_blocks(&mut r, out) {
+        // Invalid data decoded from the zeroes past the end of the input means the input was
+        // truncated rather than corrupt.
+        Err(TINFLStatus::Failed) if r.overread * 8 > r.num_bits as usize => {
+            return Err(TINFLStatus::FailedCannotMakeProgress)
+        }
+        result => result?,
+    };
+    r.align_to_byte()?;
+
+    if zlib {
+        let trailer = input
+            .get(r.pos..r.pos + 4)
+            .ok_or(TINFLStatus::FailedCannotMakeProgress)?;
+        let adler = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        if !cfg!(fuzzing) && adler != update_adler32(MZ_ADLER32_INIT, &out[..out_pos]) {
+            return Err(TINFLStatus::Adler32Mismatch);
+        }
+    }
+
+    Ok(out_pos)
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod test {
+    use super::decompress;
+    use crate::deflate::compress_to_vec;
+    use crate::deflate::core::{compress_to_output, CompressorOxide, TDEFLFlush};
+    use crate::inflate::{decompress_to_vec, decompress_to_vec_zlib, TINFLStatus};
+    use crate::test_util::Lcg;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn test_data() -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut lcg = Lcg(1);
+        for i in 0..200_000u32 {
+            let x = lcg.next_u32();
+            let byte = match i / 20_000 % 4 {
+                0 => (x >> 24) as u8,
+                1 => b"abcabcabd"[(x >> 16) as usize % 9],
+                2 => (i % 7) as u8,
+                _ => (i / 300) as u8,
+            };
+            data.push(byte);
+        }
+        data
+    }
+
+    fn check(compressed: &[u8], zlib: bool, expected: &[u8]) {
+        let mut out = vec![0; expected.len()];
+        assert_eq!(decompress(compressed, &mut out, zlib), Ok(expected.len()));
+        assert!(out == expected);
+
+        // Works the same with extra space, and with too little.
+        let mut out = vec![0; expected.len() + 1000];
+        assert_eq!(decompress(compressed, &mut out, zlib), Ok(expected.len()));
+        assert!(out[..expected.len()] == *expected);
+        if !expected.is_empty() {
+            let mut out = vec![0; expected.len() - 1];
+            assert_eq!(
+                decompress(compressed, &mut out, zlib),
+                Err(TINFLStatus::HasMoreOutput)
+            );
+        }
+    }
+
+    #[test]
+    fn matches_decompress_to_vec() {
+        let data = test_data();
+        for &len in &[0, 1, 13, 1000, data.len()] {
+            let data = &data[..len];
+            for level in 0..=10 {
+                let compressed = compress_to_vec(data, level);
+                assert_eq!(decompress_to_vec(&compressed).unwrap(), data);
+                check(&compressed, false, data);
+
+                let compressed = crate::deflate::compress_to_vec_zlib(data, level);
+                assert_eq!(decompress_to_vec_zlib(&compressed).unwrap(), data);
+                check(&compressed, true, data);
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_and_mixed_blocks() {
+        // Fixed codes only.
+        let data = test_data();
+        let flags = crate::deflate::core::create_comp_flags_from_zip_params(6, -15, 0)
+            | crate::deflate::core::deflate_flags::TDEFL_FORCE_ALL_STATIC_BLOCKS;
+        let mut compressor = CompressorOxide::new(flags);
+        let mut compressed = Vec::new();
+        // Sync flushes in between add empty stored blocks.
+        for chunk in data.chunks(30_000) {
+            compress_to_output(&mut compressor, chunk, TDEFLFlush::Sync, |d| {
+                compressed.extend_from_slice(d);
+                true
+            });
+        }
+        compress_to_output(&mut compressor, &[], TDEFLFlush::Finish, |d| {
+            compressed.extend_from_slice(d);
+            true
+        });
+        check(&compressed, false, &data);
+    }
+
+    #[test]
+    fn invalid_streams() {
+        let data = test_data();
+        let compressed = compress_to_vec(&data[..50_000], 6);
+        let mut out = vec![0; 60_000];
+
+        // Truncated anywhere.
+        for &len in &[0, 1, 10, compressed.len() / 2, compressed.len() - 1] {
+            assert_eq!(
+                decompress(&compressed[..len], &mut out, false),
+                Err(TINFLStatus::FailedCannotMakeProgress)
+            );
+        }
+
+        // Reserved block type.
+        assert_eq!(
+            decompress(&[0x07], &mut out, false),
+            Err(TINFLStatus::Failed)
+        );
+        // Bad stored block length.
+        assert_eq!(
+            decompress(&[0x01, 0x05, 0x00, 0x00, 0x00], &mut out, false),
+            Err(TINFLStatus::Failed)
+        );
+        // Distance going back past the start of the output.
+        assert_eq!(
+            decompress(&[0x03, 0x02, 0x00, 0x00], &mut out, false),
+            Err(TINFLStatus::Failed)
+        );
+
+        // Bad zlib header and checksum.
+        let mut zlib = crate::deflate::compress_to_vec_zlib(&data[..1000], 6);
+        zlib[0] = 0x79;
+        assert_eq!(decompress(&zlib, &mut out, true), Err(TINFLStatus::Failed));
+        zlib[0] = 0x78;
+        let last = zlib.len() - 1;
+        zlib[last] ^= 1;
+        assert_eq!(
+            decompress(&zlib, &mut out, true),
+            Err(TINFLStatus::Adler32Mismatch)
+        );
+    }
+}
@@ -0,0 +1,437 @@
+//! Decompression of streams split by full flushes, with each part decompressed in its own thread.
+//!
+//! A full flush (`MZFlush::Full`) ends the current block with an empty stored block and
+//! forgets the previous data, so the following data can be decompressed without it. The empty
+//! stored block ends with the bytes `00 00 FF FF`, which are used to guess where the parts
+//! start. These guesses are decompressed in parallel, and then checked in order: the previous
+//! part has to end exactly there, between two blocks, and the part must not refer to data before
+//! it. Anything that doesn't check out, like the parts following sync flushes, which do refer
+//! to the previous data, or a `00 00 FF FF` that happened to be part of the compressed data,
+//! is decompressed again sequentially, so the result is always the same as for
+//! `decompress_to_vec`.
+
+use ::core::cmp::{max, min};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use super::core::inflate_flags::*;
+use super::core::{decompress, DecompressorOxide, TINFL_LZ_DICT_SIZE};
+use super::TINFLStatus;
+use crate::shared::{update_adler32, MZ_ADLER32_INIT};
+
+/// The end of the empty stored block written by a sync or full flush: its length and the ones
+/// complement of it.
+const FLUSH_MARKER: [u8; 4] = [0, 0, 0xFF, 0xFF];
+
+/// Smallest part worth handing to a thread when splitting the input.
+pub(super) const MIN_PART_SIZE: usize = 64 * 1024;
+
+/// A range of the input decompressed on its own, starting with a fresh decompressor.
+struct Part {
+    output: Vec<u8>,
+    decomp: Box<DecompressorOxide>,
+    /// How the decompression ended, `Done` if the part ends the stream.
+    status: TINFLStatus,
+    /// Number of input bytes used by the decompressor, only known when done.
+    in_consumed: usize,
+}
+
+/// Decompress `input` into the end of `buf`, where `buf[..out_pos]` holds the data before it.
+///
+/// `last` tells if the input ends with the stream, or if the stream continues after it.
+/// Returns the status and the number of bytes of input used, with `out_pos` updated to the end
+/// of the output.
+fn decompress_into_vec(
+    decomp: &mut DecompressorOxide,
+    input: &[u8],
+    buf: &mut Vec<u8>,
+    out_pos: &mut usize,
+    last: bool,
+) -> (TINFLStatus, usize) {
+    let mut flags = TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    if !last {
+        flags |= TINFL_FLAG_HAS_MORE_INPUT;
+    }
+    let mut in_pos = 0;
+    let mut grow_by = max(input.len() * 2, 1024);
+    loop {
+        buf.resize(*out_pos + grow_by, 0);
+        let (status, in_consumed, out_consumed) =
+            decompress(decomp, &input[in_pos..], buf, *out_pos, flags);
+        in_pos += in_consumed;
+        *out_pos += out_consumed;
+        if status != TINFLStatus::HasMoreOutput {
+            return (status, in_pos);
+        }
+        grow_by = max(grow_by, *out_pos);
+    }
+}
+
+fn decompress_part(input: &[u8], last: bool) -> Part {
+    let mut decomp = Box::<DecompressorOxide>::default();
+    let mut output = Vec::new();
+    let mut out_pos = 0;
+    let (status, in_consumed) =
+        decompress_into_vec(&mut decomp, input, &mut output, &mut out_pos, last);
+    output.truncate(out_pos);
+    Part {
+        output,
+        decomp,
+        status,
+        in_consumed,
+    }
+}
+
+/// Split `input` into ranges starting after flush markers, each at least `min_size` long
+/// except for the last one, and return where they start.
+pub(super) fn split_at_flushes(input: &[u8], start: usize, min_size: usize) -> Vec<usize> {
+    let mut starts = vec![start];
+    let mut part_start = start;
+    loop {
+        // Look for a marker ending at least `min_size` bytes into the part.
+        let from = max(part_start + min_size, FLUSH_MARKER.len()) - FLUSH_MARKER.len();
+        let found = input.get(from..).and_then(|rest| {
+            rest.windows(FLUSH_MARKER.len())
+                .position(|w| w == FLUSH_MARKER)
+        });
+        match found {
+            Some(i) if from + i + FLUSH_MARKER.len() < input.len() => {
+                part_start = from + i + FLUSH_MARKER.len();
+                starts.push(part_start);
+            }
+            _ => return starts,
+        }
+    }
+}
+
+/// Decompress the parts of `input` starting at `starts` using `num_threads` threads.
+fn decompress_parts(input: &[u8], starts: &[usize], num_threads: usize) -> Vec<Part> {
+    let input: Arc<[u8]> = Arc::from(input);
+    let ranges: Arc<Vec<(usize, usize)>> = Arc::new(
+        starts
+            .iter()
+            .zip(starts[1..].iter().chain(Some(&input.len())))
+            .map(|(&start, &end)| (start, end))
+            .collect(),
+    );
+    let next = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = mpsc::channel();
+
+    let handles: Vec<_> = (0..num_threads)
+        .map(|_| {
+            let input = Arc::clone(&input);
+            let ranges = Arc::clone(&ranges);
+            let next = Arc::clone(&next);
+            let sender = sender.clone();
+            thread::spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= ranges.len() {
+                    break;
+                }
+                let (start, end) = ranges[i];
+                let part = decompress_part(&input[start..end], end == input.len());
+                if sender.send((i, part)).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+
+    let mut parts: Vec<Option<Part>> = (0..ranges.len()).map(|_| None).collect();
+    for (i, part) in receiver {
+        parts[i] = Some(part);
+    }
+    for handle in handles {
+        if let Err(panic) = handle.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+    parts.into_iter().map(|part| part.unwrap()).collect()
+}
+
+/// Decompressor continuing from the end of the data decompressed so far.
+struct Sequential {
+    decomp: Box<DecompressorOxide>,
+    /// The last `TINFL_LZ_DICT_SIZE` bytes of output, followed by space for more.
+    buf: Vec<u8>,
+    out_pos: usize,
+}
+
+impl Sequential {
+    fn new(decomp: Box<DecompressorOxide>, output: &[u8]) -> Sequential {
+        let history = &output[output.len().saturating_sub(TINFL_LZ_DICT_SIZE)..];
+        Sequential {
+            decomp,
+            buf: history.to_vec(),
+            out_pos: history.len(),
+        }
+    }
+
+    /// Decompress `input`, appending the data to `output`.
+    fn decompress(
+        &mut self,
+        input: &[u8],
+        last: bool,
+        output: &mut Vec<u8>,
+    ) -> (TINFLStatus, usize) {
+        let start = self.out_pos;
+        let res = decompress_into_vec(
+            &mut self.decomp,
+            input,
+            &mut self.buf,
+            &mut self.out_pos,
+            last,
+        );
+        output.extend_from_slice(&self.buf[start..self.out_pos]);
+        // Only keep what can still be referred to.
+        let keep_from = self.out_pos.saturating_sub(TINFL_LZ_DICT_SIZE);
+        self.buf.truncate(self.out_pos);
+        self.buf.drain(..keep_from);
+        self.out_pos -= keep_from;
+        res
+    }
+}
+
+/// Decompress `input` with `num_threads` threads, splitting it into parts of at least
+/// `min_part_size` bytes where possible.
+pub(super) fn decompress_to_vec(
+    input: &[u8],
+    zlib: bool,
+    num_threads: usize,
+    min_part_size: usize,
+) -> Result<Vec<u8>, TINFLStatus> {
+    let start = if zlib {
+        // Let the decompressor check the header.
+        let flags = TINFL_FLAG_PARSE_ZLIB_HEADER
+            | TINFL_FLAG_HAS_MORE_INPUT
+            | TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+        let header = &input[..min(input.len(), 2)];
+        let mut out = [0];
+        let (status, _, _) = decompress(&mut DecompressorOxide::new(), header, &mut out, 0, flags);
+        match status {
+            TINFLStatus::NeedsMoreInput if header.len() == 2 => 2,
+            TINFLStatus::NeedsMoreInput => return Err(TINFLStatus::FailedCannotMakeProgress),
+            _ => return Err(status),
+        }
+    } else {
+        0
+    };
+
+    let starts = split_at_flushes(input, start, min_part_size);
+    let parts = decompress_parts(input, &starts, max(num_threads, 1));
+
+    let mut output = Vec::new();
+    let mut sequential: Option<Sequential> = None;
+    let mut end = None;
+    for (i, part) in parts.into_iter().enumerate() {
+        let part_end = starts.get(i + 1).cloned().unwrap_or(input.len());
+        let last = part_end == input.len();
+        let fits = match part.status {
+            TINFLStatus::Done => true,
+            TINFLStatus::NeedsMoreInput => !last,
+            _ => false,
+        };
+        let (status, in_consumed) = match sequential.take() {
+            // The previous part ended where this one starts, so it can be used as is.
+            None if fits => {
+                output.extend_from_slice(&part.output);
+                if part.status != TINFLStatus::Done && !part.decomp.at_byte_aligned_block_start() {
+                    // The next part isn't at the start of a block after all, so carry on.
+                    sequential = Some(Sequential::new(part.decomp, &output));
+                }
+                (part.status, part.in_consumed)
+            }
+            // The part refers to earlier data, or didn't decompress at all, so start over
+            // with the data before it available.
+            None => {
+                let mut seq = Sequential::new(Box::default(), &output);
+                let res = seq.decompress(&input[starts[i]..part_end], last, &mut output);
+                sequential = Some(seq);
+                res
+            }
+            Some(mut seq) => {
+                let res = seq.decompress(&input[starts[i]..part_end], last, &mut output);
+                sequential = Some(seq);
+                res
+            }
+        };
+        match status {
+            TINFLStatus::Done => {
+                end = Some(starts[i] + in_consumed);
+                break;
+            }
+            TINFLStatus::NeedsMoreInput if !last => {
+                if sequential
+                    .as_ref()
+                    .map_or(false, |seq| seq.decomp.at_byte_aligned_block_start())
+                {
+                    // Back at the start of a block, so the next part may be used.
+                    sequential = None;
+                }
+            }
+            _ => return Err(status),
+        }
+    }
+
+    let end = end.ok_or(TINFLStatus::FailedCannotMakeProgress)?;
+    if zlib {
+        let trailer = input
+            .get(end..end + 4)
+            .ok_or(TINFLStatus::FailedCannotMakeProgress)?;
+        let expected = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        if update_adler32(MZ_ADLER32_INIT, &output) != expected {
+            return Err(TINFLStatus::Adler32Mismatch);
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decompress_to_vec, split_at_flushes};
+    use crate::deflate::core::{
+        compress_to_output, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush,
+    };
+    use crate::inflate::{self, TINFLStatus};
+    use crate::test_util::{compress_chunked, Lcg};
+    use alloc::vec::Vec;
+
+    fn test_data() -> Vec<u8> {
+        let text = include_bytes!("../../tests/test_data/numbers.txt");
+        let mut data = Vec::new();
+        let mut lcg = Lcg(1);
+        for i in 0..300_000u32 {
+            let x = lcg.next_u32();
+            data.push(if i % 3 == 0 {
+                (x >> 16) as u8
+            } else {
+                text[i as usize % text.len()]
+            });
+        }
+        data
+    }
+
+    /// Compress `data`, flushing with `flush` every `chunk_size` bytes.
+    fn compress(
+        data: &[u8],
+        level: i32,
+        window_bits: i32,
+        chunk_size: usize,
+        flush: TDEFLFlush,
+    ) -> Vec<u8> {
+        let flags = create_comp_flags_from_zip_params(level, window_bits, 0);
+        compress_chunked(&mut CompressorOxide::new(flags), data, chunk_size, flush)
+    }
+
+    #[test]
+    fn full_flushes() {
+        let data = test_data();
+        for &level in &[0, 1, 6, 9] {
+            let encoded = compress(&data, level, -15, 20_000, TDEFLFlush::Full);
+            // Every flush is found, one after each chunk but the last.
+            assert_eq!(split_at_flushes(&encoded, 0, 1).len(), data.len() / 20_000);
+            for &threads in &[1, 4] {
+                let decoded = decompress_to_vec(&encoded, false, threads, 1000).unwrap();
+                assert!(decoded == data);
+            }
+            assert!(inflate::decompress_to_vec_parallel(&encoded, 4).unwrap() == data);
+
+            let encoded = compress(&data, level, 15, 20_000, TDEFLFlush::Full);
+            let decoded = decompress_to_vec(&encoded, true, 4, 1000).unwrap();
+            assert!(decoded == data);
+            assert!(inflate::decompress_to_vec_zlib_parallel(&encoded, 4).unwrap() == data);
+        }
+    }
+
+    #[test]
+    fn sync_flushes() {
+        // The parts depend on the data before them, so they have to be decompressed again.
+        let data = test_data();
+        let encoded = compress(&data, 6, 15, 20_000, TDEFLFlush::Sync);
+        assert_eq!(decompress_to_vec(&encoded, true, 4, 1000).unwrap(), data);
+        // Mixed with full flushes.
+        let mut encoded = compress(&data[..150_000], 6, 15, 10_000, TDEFLFlush::Sync);
+        encoded.truncate(encoded.len() - 4);
+        let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(6, 15, 0));
+        let mut mixed = Vec::new();
+        for (i, chunk) in data.chunks(7_000).enumerate() {
+            let flush = match i % 3 {
+                0 => TDEFLFlush::Full,
+                1 => TDEFLFlush::Sync,
+                _ => TDEFLFlush::None,
+            };
+            compress_to_output(&mut d, chunk, flush, |out: &[u8]| {
+                mixed.extend_from_slice(out);
+                true
+            });
+        }
+        compress_to_output(&mut d, &[], TDEFLFlush::Finish, |out: &[u8]| {
+            mixed.extend_from_slice(out);
+            true
+        });
+        for &min_part_size in &[1, 1000, 50_000] {
+            assert_eq!(
+                decompress_to_vec(&mixed, true, 3, min_part_size).unwrap(),
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn markers_in_data() {
+        // Stored blocks holding flush markers, which aren't flushes.
+        let mut data = test_data();
+        for i in (0..data.len() - 4).step_by(997) {
+            data[i..i + 4].copy_from_slice(&[0, 0, 0xFF, 0xFF]);
+        }
+        for &level in &[0, 6] {
+            let encoded = compress(&data, level, 15, 30_000, TDEFLFlush::None);
+            for &min_part_size in &[1, 100, 1000] {
+                assert!(decompress_to_vec(&encoded, true, 4, min_part_size).unwrap() == data);
+            }
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let data = test_data();
+        let encoded = compress(&data, 6, 15, 20_000, TDEFLFlush::Full);
+        let len = encoded.len();
+
+        let mut bad_checksum = encoded.clone();
+        bad_checksum[len - 1] ^= 1;
+        assert_eq!(
+            decompress_to_vec(&bad_checksum, true, 4, 1000),
+            Err(TINFLStatus::Adler32Mismatch)
+        );
+
+        for &cut in &[1, 4, 10, len / 2, len - 2] {
+            assert_eq!(
+                decompress_to_vec(&encoded[..cut], true, 4, 1000),
+                inflate::decompress_to_vec_zlib(&encoded[..cut]).map(|_| Vec::new()),
+                "cut at {}",
+                cut
+            );
+        }
+
+        let mut bad_header = encoded.clone();
+        bad_header[1] ^= 1;
+        assert_eq!(
+            decompress_to_vec(&bad_header, true, 4, 1000),
+            Err(TINFLStatus::Failed)
+        );
+
+        // Corrupt data in one of the parts.
+        let mut corrupt = encoded.clone();
+        for b in &mut corrupt[len / 2..len / 2 + 100] {
+            *b = 0xFF;
+        }
+        assert!(decompress_to_vec(&corrupt, true, 4, 1000).is_err());
+        assert!(decompress_to_vec(&corrupt[2..len - 4], false, 4, 1000).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 //! Extra streaming decompression functionality.
 //!
 //! As of now this is mainly inteded for use to build a higher-level wrapper.
+#[cfg(feature = "alloc")]
 use alloc::boxed::Box;
 use core::{cmp, mem};
 
@@ -10,6 +11,8 @@ use crate::{DataFormat, MZError, MZFlush, MZResult, MZStatus, StreamResult};
 
 /// A struct that compbines a decompressor with extra data for streaming decompression.
 ///
+/// Cloning copies the output window as well, so the clone can continue decompressing the
+/// same stream independently of the original.
 pub struct InflateState {
     /// Inner decompressor struct
     decomp: DecompressorOxide,
@@ -34,33 +37,55 @@ pub struct InflateState {
     last_status: TINFLStatus,
 }
 
-impl Default for InflateState {
-    fn default() -> Self {
+impl Clone for InflateState {
+    fn clone(&self) -> Self {
         InflateState {
-            decomp: DecompressorOxide::default(),
-            dict: [0; TINFL_LZ_DICT_SIZE],
-            dict_ofs: 0,
-            dict_avail: 0,
-            first_call: true,
-            has_flushed: false,
-            data_format: DataFormat::Raw,
-            last_status: TINFLStatus::NeedsMoreInput,
+            decomp: self.decomp.clone(),
+            dict: self.dict,
+            ..*self
         }
     }
+
+    /// Copy in place, avoiding a large temporary on the stack.
+    fn clone_from(&mut self, source: &Self) {
+        self.decomp.clone_from(&source.decomp);
+        self.dict.copy_from_slice(&source.dict);
+        self.dict_ofs = source.dict_ofs;
+        self.dict_avail = source.dict_avail;
+        self.first_call = source.first_call;
+        self.has_flushed = source.has_flushed;
+        self.data_format = source.data_format;
+        self.last_status = source.last_status;
+    }
+}
+
+impl Default for InflateState {
+    fn default() -> Self {
+        InflateState::new(DataFormat::Raw)
+    }
 }
 impl InflateState {
     /// Create a new state.
     ///
     /// Note that this struct is quite large due to internal buffers, and as such storing it on
-    /// the stack is not recommended.
+    /// the stack is not recommended. Without the `alloc` feature, it can be put in a `static`
+    /// as this is a `const fn`. Memory with all bytes set to zero also holds a valid state,
+    /// which can be set up in place with [`reset`](#method.reset) instead.
     ///
     /// # Parameters
     /// `data_format`: Determines whether the compressed data is assumed to wrapped with zlib
     /// metadata.
-    pub fn new(data_format: DataFormat) -> InflateState {
-        let mut b = InflateState::default();
-        b.data_format = data_format;
-        b
+    pub const fn new(data_format: DataFormat) -> InflateState {
+        InflateState {
+            decomp: DecompressorOxide::new(),
+            dict: [0; TINFL_LZ_DICT_SIZE],
+            dict_ofs: 0,
+            dict_avail: 0,
+            first_call: true,
+            has_flushed: false,
+            data_format,
+            last_status: TINFLStatus::NeedsMoreInput,
+        }
     }
 
     /// Create a new state on the heap.
@@ -68,6 +93,7 @@ impl InflateState {
     /// # Parameters
     /// `data_format`: Determines whether the compressed data is assumed to wrapped with zlib
     /// metadata.
+    #[cfg(feature = "alloc")]
     pub fn new_boxed(data_format: DataFormat) -> Box<InflateState> {
         let mut b: Box<InflateState> = Box::default();
         b.data_format = data_format;
@@ -79,16 +105,28 @@ impl InflateState {
         &mut self.decomp
     }
 
+    /// Returns whether the decompressor expects a zlib header or raw deflate data.
+    pub fn data_format(&self) -> DataFormat {
+        self.data_format
+    }
+
     /// Return the status of the last call to `inflate` with this `InflateState`.
     pub fn last_status(&self) -> TINFLStatus {
         self.last_status
     }
 
+    /// Returns the number of decompressed bytes waiting in the internal buffer for space in
+    /// the output.
+    pub fn pending_output(&self) -> usize {
+        self.dict_avail
+    }
+
     /// Create a new state using miniz/zlib style window bits parameter.
     ///
     /// The decompressor does not support different window sizes. As such,
     /// any positive (>0) value will set the zlib header flag, while a negative one
     /// will not.
+    #[cfg(feature = "alloc")]
     pub fn new_boxed_with_window_bits(window_bits: i32) -> Box<InflateState> {
         let mut b: Box<InflateState> = Box::default();
         b.data_format = DataFormat::from_window_bits(window_bits);
@@ -301,7 +339,7 @@ fn push_dict_out(state: &mut InflateState, next_out: &mut &mut [u8]) -> usize {
     n
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "alloc"))]
 mod test {
     use super::{inflate, InflateState};
     use crate::{DataFormat, MZFlush, MZStatus};
@@ -327,4 +365,50 @@ mod test {
         assert_eq!(out[..res.bytes_written as usize], b"Hello, zlib!"[..]);
         assert_eq!(res.bytes_consumed, encoded.len());
     }
+
+    #[test]
+    fn const_new() {
+        // Can be put in a static without the `alloc` feature.
+        static STATE: InflateState = InflateState::new(DataFormat::Zlib);
+        assert_eq!(STATE.data_format(), DataFormat::Zlib);
+        assert_eq!(STATE.decomp.adler32(), None);
+    }
+
+    #[test]
+    fn clone_state() {
+        let data = include_bytes!("../../tests/test_data/numbers.txt");
+        let encoded = crate::deflate::compress_to_vec_zlib(data, 6);
+        let mut state = InflateState::new_boxed(DataFormat::Zlib);
+        let mut out = vec![0; data.len()];
+        // Leave data in the output window.
+        let res = inflate(
+            &mut state,
+            &encoded[..5000],
+            &mut out[..1000],
+            MZFlush::None,
+        );
+        assert_eq!(res.status, Ok(MZStatus::Ok));
+        let (consumed, written) = (res.bytes_consumed, res.bytes_written);
+
+        let mut copy = state.clone();
+        for state in &mut [&mut state, &mut copy] {
+            let mut out = out.clone();
+            let (mut consumed, mut written) = (consumed, written);
+            loop {
+                let res = inflate(
+                    state,
+                    &encoded[consumed..],
+                    &mut out[written..],
+                    MZFlush::None,
+                );
+                consumed += res.bytes_consumed;
+                written += res.bytes_written;
+                if res.status != Ok(MZStatus::Ok) {
+                    assert_eq!(res.status, Ok(MZStatus::StreamEnd));
+                    break;
+                }
+            }
+            assert_eq!(&out[..], &data[..]);
+        }
+    }
 }
@@ -22,20 +22,32 @@
 //! ```
 
 #![allow(warnings)]
-#![forbid(unsafe_code)]
+// Only the SIMD checksums in the `simd` module, enabled by the feature of the same name, need
+// unsafe code.
+#![cfg_attr(not(feature = "simd"), forbid(unsafe_code))]
+#![cfg_attr(feature = "simd", deny(unsafe_code))]
 #![no_std]
 
+#[cfg(all(feature = "alloc", not(feature = "rustc-dep-of-std")))]
 extern crate alloc;
+#[cfg(feature = "rustc-dep-of-std")]
+extern crate rustc_std_workspace_alloc as alloc;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "simd", feature = "parallel"))]
 extern crate std;
 
 pub mod deflate;
+pub mod filter;
 pub mod inflate;
 mod shared;
+#[cfg(feature = "simd")]
+mod simd;
+#[cfg(all(test, feature = "alloc"))]
+mod test_util;
 
 pub use crate::shared::update_adler32 as mz_adler32_oxide;
-pub use crate::shared::{MZ_ADLER32_INIT, MZ_DEFAULT_WINDOW_BITS};
+pub use crate::shared::update_crc32 as mz_crc32_oxide;
+pub use crate::shared::{MZ_ADLER32_INIT, MZ_CRC32_INIT, MZ_DEFAULT_WINDOW_BITS};
 
 /// A list of flush types.
 ///
@@ -125,6 +137,34 @@ impl DataFormat {
     }
 }
 
+/// A single LZ77 symbol of a deflate stream.
+///
+/// Used to feed pre-parsed data to the compressor with
+/// [`compress_tokens`](deflate/core/fn.compress_tokens.html), and to observe
+/// the symbols of a stream with
+/// [`decompress_with_tokens`](inflate/core/fn.decompress_with_tokens.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LZToken {
+    /// A single literal byte.
+    Literal(u8),
+    /// A back-reference copying `len` (3-258) bytes, starting `dist` (1-32768) bytes back
+    /// from the current position.
+    Match { len: u16, dist: u16 },
+    /// The end of the current block.
+    EndOfBlock,
+}
+
+/// The type of a deflate block.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BlockType {
+    /// Raw/stored/uncompressed block.
+    Stored,
+    /// Block compressed using the fixed huffman tables from the deflate specification.
+    Static,
+    /// Block compressed using huffman tables stored in the block header.
+    Dynamic,
+}
+
 /// `Result` alias for all miniz status codes both successful and failed.
 pub type MZResult = Result<MZStatus, MZError>;
 
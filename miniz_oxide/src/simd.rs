@@ -0,0 +1,387 @@
+//! SIMD implementations of the Adler-32 and CRC-32 checksums, used when the `simd` feature is
+//! enabled.
+//!
+//! This is the only module allowed to use `unsafe`, as the intrinsics require it. The
+//! implementations are picked at runtime based on what the CPU supports, and the functions
+//! return `None` to fall back to the scalar code if there is no suitable one. There are
+//! implementations for x86, and of Adler-32 for aarch64 with NEON. Other architectures always use
+//! the scalar code.
+
+#![allow(unsafe_code)]
+
+/// Largest prime smaller than 65536.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+const ADLER_MOD: u64 = 65521;
+
+/// Number of bytes summed into 32-bit vector lanes between reductions of the Adler-32 sums.
+///
+/// This is zlib's limit making sure none of the sums can overflow (5552), rounded down to a
+/// multiple of the block sizes used.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+const ADLER_CHUNK_LEN: usize = 5536;
+
+/// Inputs shorter than this aren't worth setting up the vector registers for.
+const ADLER_MIN_LEN: usize = 64;
+const CRC_MIN_LEN: usize = 128;
+
+#[allow(unreachable_code)]
+#[cfg_attr(
+    not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")),
+    allow(unused_variables)
+)]
+pub fn update_adler32(adler: u32, data: &[u8]) -> Option<u32> {
+    if data.len() < ADLER_MIN_LEN {
+        return None;
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("avx2") {
+            return Some(unsafe { x86::adler32_avx2(adler, data) });
+        }
+        if std::is_x86_feature_detected!("sse2") {
+            return Some(unsafe { x86::adler32_sse2(adler, data) });
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            return Some(unsafe { aarch64::adler32_neon(adler, data) });
+        }
+    }
+
+    None
+}
+
+#[allow(unreachable_code)]
+#[cfg_attr(
+    not(any(target_arch = "x86", target_arch = "x86_64")),
+    allow(unused_variables)
+)]
+pub fn update_crc32(crc: u32, data: &[u8]) -> Option<u32> {
+    if data.len() < CRC_MIN_LEN {
+        return None;
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("pclmulqdq") && std::is_x86_feature_detected!("sse4.1") {
+            return Some(unsafe { x86::crc32_pclmulqdq(crc, data) });
+        }
+    }
+
+    None
+}
+
+/// Combine the sums of a run of `len` bytes, starting from the checksum `adler`.
+///
+/// `sum` is the sum of the bytes, `prefix_sums` is the sum over all blocks of the bytes in the
+/// blocks before it, and `weighted_sum` is the sum over all blocks of each byte multiplied by
+/// its distance from the end of the block.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+#[inline(always)]
+fn adler32_combine(
+    adler: u32,
+    len: usize,
+    block_len: usize,
+    sum: u32,
+    prefix_sums: u32,
+    weighted_sum: u32,
+) -> u32 {
+    let a = u64::from(adler & 0xFFFF);
+    let b = u64::from(adler >> 16);
+    let b =
+        b + a * len as u64 + u64::from(prefix_sums) * block_len as u64 + u64::from(weighted_sum);
+    let a = a + u64::from(sum);
+    (((b % ADLER_MOD) << 16) | (a % ADLER_MOD)) as u32
+}
+
+/// Add the bytes that don't make up a whole block to the checksum.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+#[inline(always)]
+fn adler32_tail(adler: u32, data: &[u8]) -> u32 {
+    let mut a = adler & 0xFFFF;
+    let mut b = adler >> 16;
+    for &byte in data {
+        a += u32::from(byte);
+        b += a;
+    }
+    ((b % ADLER_MOD as u32) << 16) | (a % ADLER_MOD as u32)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86 {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::*;
+
+    use super::{adler32_combine, adler32_tail, ADLER_CHUNK_LEN};
+    use crate::shared::update_crc32_scalar;
+
+    #[inline(always)]
+    unsafe fn sum_epi32(v: __m128i) -> u32 {
+        let mut lanes = [0u32; 4];
+        _mm_storeu_si128(lanes.as_mut_ptr() as *mut __m128i, v);
+        lanes.iter().fold(0, |acc, &l| acc.wrapping_add(l))
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn adler32_sse2(mut adler: u32, data: &[u8]) -> u32 {
+        const BLOCK_LEN: usize = 16;
+        let zero = _mm_setzero_si128();
+        // Weights of the bytes in the first and second half of a block.
+        let weights_lo = _mm_set_epi16(9, 10, 11, 12, 13, 14, 15, 16);
+        let weights_hi = _mm_set_epi16(1, 2, 3, 4, 5, 6, 7, 8);
+
+        let (blocks, tail) = data.split_at(data.len() - data.len() % BLOCK_LEN);
+        for chunk in blocks.chunks(ADLER_CHUNK_LEN) {
+            let mut sum = zero;
+            let mut prefix_sums = zero;
+            let mut weighted_sum = zero;
+            for block in chunk.chunks_exact(BLOCK_LEN) {
+                let v = _mm_loadu_si128(block.as_ptr() as *const __m128i);
+                prefix_sums = _mm_add_epi32(prefix_sums, sum);
+                sum = _mm_add_epi32(sum, _mm_sad_epu8(v, zero));
+                let lo = _mm_madd_epi16(_mm_unpacklo_epi8(v, zero), weights_lo);
+                let hi = _mm_madd_epi16(_mm_unpackhi_epi8(v, zero), weights_hi);
+                weighted_sum = _mm_add_epi32(weighted_sum, _mm_add_epi32(lo, hi));
+            }
+            adler = adler32_combine(
+                adler,
+                chunk.len(),
+                BLOCK_LEN,
+                sum_epi32(sum),
+                sum_epi32(prefix_sums),
+                sum_epi32(weighted_sum),
+            );
+        }
+        adler32_tail(adler, tail)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn sum_epi32_256(v: __m256i) -> u32 {
+        sum_epi32(_mm_add_epi32(
+            _mm256_castsi256_si128(v),
+            _mm256_extracti128_si256(v, 1),
+        ))
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn adler32_avx2(mut adler: u32, data: &[u8]) -> u32 {
+        const BLOCK_LEN: usize = 32;
+        let zero = _mm256_setzero_si256();
+        let ones = _mm256_set1_epi16(1);
+        let weights = _mm256_set_epi8(
+            1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+            25, 26, 27, 28, 29, 30, 31, 32,
+        );
+
+        let (blocks, tail) = data.split_at(data.len() - data.len() % BLOCK_LEN);
+        for chunk in blocks.chunks(ADLER_CHUNK_LEN) {
+            let mut sum = zero;
+            let mut prefix_sums = zero;
+            let mut weighted_sum = zero;
+            for block in chunk.chunks_exact(BLOCK_LEN) {
+                let v = _mm256_loadu_si256(block.as_ptr() as *const __m256i);
+                prefix_sums = _mm256_add_epi32(prefix_sums, sum);
+                sum = _mm256_add_epi32(sum, _mm256_sad_epu8(v, zero));
+                let products = _mm256_madd_epi16(_mm256_maddubs_epi16(v, weights), ones);
+                weighted_sum = _mm256_add_epi32(weighted_sum, products);
+            }
+            adler = adler32_combine(
+                adler,
+                chunk.len(),
+                BLOCK_LEN,
+                sum_epi32_256(sum),
+                sum_epi32_256(prefix_sums),
+                sum_epi32_256(weighted_sum),
+            );
+        }
+        adler32_tail(adler, tail)
+    }
+
+    // Constants for folding the reflected CRC-32 polynomial, from "Fast CRC Computation for
+    // Generic Polynomials Using PCLMULQDQ Instruction" by Intel.
+    const K1: i64 = 0x1_5444_2bd4;
+    const K2: i64 = 0x1_c6e4_1596;
+    const K3: i64 = 0x1_7519_97d0;
+    const K4: i64 = 0x0_ccaa_009e;
+    const K5: i64 = 0x1_63cd_6124;
+    const P_X: i64 = 0x1_DB71_0641;
+    const U_PRIME: i64 = 0x1_F701_1641;
+
+    #[inline(always)]
+    unsafe fn load(data: &mut &[u8]) -> __m128i {
+        let v = _mm_loadu_si128(data.as_ptr() as *const __m128i);
+        *data = &data[16..];
+        v
+    }
+
+    /// Fold the 128 bits in `a` into `b`.
+    #[inline(always)]
+    unsafe fn fold(a: __m128i, b: __m128i, keys: __m128i) -> __m128i {
+        let lo = _mm_clmulepi64_si128(a, keys, 0x00);
+        let hi = _mm_clmulepi64_si128(a, keys, 0x11);
+        _mm_xor_si128(_mm_xor_si128(b, lo), hi)
+    }
+
+    /// Needs at least 64 bytes of data.
+    #[target_feature(enable = "pclmulqdq", enable = "sse2", enable = "sse4.1")]
+    pub unsafe fn crc32_pclmulqdq(crc: u32, mut data: &[u8]) -> u32 {
+        debug_assert!(data.len() >= 64);
+
+        // Fold 4 x 128 bits at a time, starting with the initial crc xored into the first
+        // bits.
+        let mut x3 = _mm_xor_si128(load(&mut data), _mm_cvtsi32_si128(!crc as i32));
+        let mut x2 = load(&mut data);
+        let mut x1 = load(&mut data);
+        let mut x0 = load(&mut data);
+
+        let k1k2 = _mm_set_epi64x(K2, K1);
+        while data.len() >= 64 {
+            x3 = fold(x3, load(&mut data), k1k2);
+            x2 = fold(x2, load(&mut data), k1k2);
+            x1 = fold(x1, load(&mut data), k1k2);
+            x0 = fold(x0, load(&mut data), k1k2);
+        }
+
+        // Then fold those into a single 128 bit value, and fold in the rest 128 bits at a time.
+        let k3k4 = _mm_set_epi64x(K4, K3);
+        let mut x = fold(x3, x2, k3k4);
+        x = fold(x, x1, k3k4);
+        x = fold(x, x0, k3k4);
+        while data.len() >= 16 {
+            x = fold(x, load(&mut data), k3k4);
+        }
+
+        // Reduce 128 bits to 96, then to 64.
+        let low_32 = _mm_set_epi32(0, 0, 0, !0);
+        let x = _mm_xor_si128(_mm_clmulepi64_si128(x, k3k4, 0x10), _mm_srli_si128(x, 8));
+        let x = _mm_xor_si128(
+            _mm_clmulepi64_si128(_mm_and_si128(x, low_32), _mm_set_epi64x(0, K5), 0x00),
+            _mm_srli_si128(x, 4),
+        );
+
+        // Barrett reduction from 64 to 32 bits.
+        let pu = _mm_set_epi64x(U_PRIME, P_X);
+        let t1 = _mm_clmulepi64_si128(_mm_and_si128(x, low_32), pu, 0x10);
+        let t2 = _mm_clmulepi64_si128(_mm_and_si128(t1, low_32), pu, 0x00);
+        let crc = !(_mm_extract_epi32(_mm_xor_si128(x, t2), 1) as u32);
+
+        update_crc32_scalar(crc, data)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64 {
+    use core::arch::aarch64::*;
+
+    use super::{adler32_combine, adler32_tail, ADLER_CHUNK_LEN};
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn adler32_neon(mut adler: u32, data: &[u8]) -> u32 {
+        const BLOCK_LEN: usize = 16;
+        // Weights of the bytes in the first and second half of a block.
+        let weights_lo = vld1_u8([16, 15, 14, 13, 12, 11, 10, 9].as_ptr());
+        let weights_hi = vld1_u8([8, 7, 6, 5, 4, 3, 2, 1].as_ptr());
+
+        let (blocks, tail) = data.split_at(data.len() - data.len() % BLOCK_LEN);
+        for chunk in blocks.chunks(ADLER_CHUNK_LEN) {
+            let mut sum = vdupq_n_u32(0);
+            let mut prefix_sums = vdupq_n_u32(0);
+            let mut weighted_sum = vdupq_n_u32(0);
+            for block in chunk.chunks_exact(BLOCK_LEN) {
+                let v = vld1q_u8(block.as_ptr());
+                prefix_sums = vaddq_u32(prefix_sums, sum);
+                sum = vpadalq_u16(sum, vpaddlq_u8(v));
+                let products = vmlal_u8(
+                    vmull_u8(vget_low_u8(v), weights_lo),
+                    vget_high_u8(v),
+                    weights_hi,
+                );
+                weighted_sum = vpadalq_u16(weighted_sum, products);
+            }
+            adler = adler32_combine(
+                adler,
+                chunk.len(),
+                BLOCK_LEN,
+                vaddvq_u32(sum),
+                vaddvq_u32(prefix_sums),
+                vaddvq_u32(weighted_sum),
+            );
+        }
+        adler32_tail(adler, tail)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::shared::update_crc32_scalar;
+    use adler::Adler32;
+    use std::vec::Vec;
+
+    fn adler32_scalar(adler: u32, data: &[u8]) -> u32 {
+        let mut hash = Adler32::from_checksum(adler);
+        hash.write_slice(data);
+        hash.checksum()
+    }
+
+    /// Check every implementation the CPU supports, not just the one picked at runtime.
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn x86_checksums() {
+        let data: Vec<u8> = (0..30_000u32).map(|n| (n * 31 + n / 97) as u8).collect();
+        for &len in &[64, 100, 128, 1000, 5536, 5537, 11_100, 30_000] {
+            for &start in &[1, 0xFFF0_FFF0] {
+                let expected = adler32_scalar(start, &data[..len]);
+                if std::is_x86_feature_detected!("sse2") {
+                    assert_eq!(
+                        unsafe { super::x86::adler32_sse2(start, &data[..len]) },
+                        expected
+                    );
+                }
+                if std::is_x86_feature_detected!("avx2") {
+                    assert_eq!(
+                        unsafe { super::x86::adler32_avx2(start, &data[..len]) },
+                        expected
+                    );
+                }
+            }
+            if std::is_x86_feature_detected!("pclmulqdq") && std::is_x86_feature_detected!("sse4.1")
+            {
+                for &start in &[0, 0xDEAD_BEEF] {
+                    assert_eq!(
+                        unsafe { super::x86::crc32_pclmulqdq(start, &data[..len]) },
+                        update_crc32_scalar(start, &data[..len])
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn aarch64_checksums() {
+        let data: Vec<u8> = (0..30_000u32).map(|n| (n * 31 + n / 97) as u8).collect();
+        if !std::arch::is_aarch64_feature_detected!("neon") {
+            return;
+        }
+        for &len in &[64, 100, 128, 1000, 5536, 5537, 11_100, 30_000] {
+            for &start in &[1, 0xFFF0_FFF0] {
+                assert_eq!(
+                    unsafe { super::aarch64::adler32_neon(start, &data[..len]) },
+                    adler32_scalar(start, &data[..len])
+                );
+            }
+        }
+        // All bytes set is the worst case for overflowing the sums.
+        let data = [0xFF; 30_000];
+        assert_eq!(
+            unsafe { super::aarch64::adler32_neon(0xFFF0_FFF0, &data) },
+            adler32_scalar(0xFFF0_FFF0, &data)
+        );
+    }
+}
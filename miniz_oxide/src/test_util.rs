@@ -0,0 +1,56 @@
+//! Data generators and helpers shared by the unit tests.
+
+use alloc::vec::Vec;
+
+use crate::deflate::core::{compress_to_output, CompressorOxide, TDEFLFlush, TDEFLStatus};
+
+/// Linear congruential generator, for test data that is the same on every run.
+pub struct Lcg(pub u32);
+
+impl Lcg {
+    /// Advance the generator and return its new state, of which the upper bits are the most
+    /// random.
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 = self.0.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        self.0
+    }
+}
+
+/// Pseudo-random data using only the values 0-15.
+pub fn test_data(len: usize) -> Vec<u8> {
+    let mut lcg = Lcg(12_345);
+    (0..len)
+        .map(|_| (lcg.next_u32() >> 16) as u8 & 0xF)
+        .collect()
+}
+
+/// Pseudo-random data that doesn't compress.
+pub fn random_data(len: usize) -> Vec<u8> {
+    let mut lcg = Lcg(98_765);
+    (0..len).map(|_| (lcg.next_u32() >> 16) as u8).collect()
+}
+
+/// Compress `data` in chunks of `chunk_size`, using `flush` after each chunk and finishing the
+/// stream after the last one.
+pub fn compress_chunked(
+    d: &mut CompressorOxide,
+    data: &[u8],
+    chunk_size: usize,
+    flush: TDEFLFlush,
+) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut chunks = data.chunks(chunk_size).peekable();
+    while let Some(chunk) = chunks.next() {
+        let flush = if chunks.peek().is_none() {
+            TDEFLFlush::Finish
+        } else {
+            flush
+        };
+        let (status, _) = compress_to_output(d, chunk, flush, |out: &[u8]| {
+            encoded.extend_from_slice(out);
+            true
+        });
+        assert!(status == TDEFLStatus::Okay || status == TDEFLStatus::Done);
+    }
+    encoded
+}
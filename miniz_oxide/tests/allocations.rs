@@ -0,0 +1,113 @@
+//! Checks of when the compressor allocates, with an allocator that counts the allocations made
+//! by each thread.
+#![cfg(feature = "alloc")]
+
+extern crate miniz_oxide;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use miniz_oxide::deflate::compress_into;
+use miniz_oxide::deflate::core::{
+    create_comp_flags_from_zip_params, BufferSizes, CompressorBuffers, CompressorOxide,
+};
+use miniz_oxide::inflate::decompress_to_vec_zlib;
+use miniz_oxide::DataFormat;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // `try_with` as the thread local can't be used while the thread is being torn down.
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Number of allocations made by `f` on this thread.
+fn count_allocations<F: FnOnce()>(f: F) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+/// Compress `data` into `out` with every level and format, returning the number of allocations
+/// made.
+fn compress_all_levels(d: &mut CompressorOxide, data: &[u8], out: &mut [u8]) -> usize {
+    // With the `stats` feature, the list of recorded blocks grows the first time.
+    if cfg!(feature = "stats") {
+        compress_all_levels_once(d, data, out);
+    }
+    compress_all_levels_once(d, data, out)
+}
+
+fn compress_all_levels_once(d: &mut CompressorOxide, data: &[u8], out: &mut [u8]) -> usize {
+    count_allocations(|| {
+        for level in 0..=10 {
+            for &format in &[DataFormat::Raw, DataFormat::Zlib] {
+                compress_into(d, data, out, level, format).unwrap();
+            }
+        }
+    })
+}
+
+#[test]
+fn compress_into_doesnt_allocate() {
+    let data = include_bytes!("test_data/numbers.txt");
+    let mut out = vec![0; 2 * data.len()];
+    let flags = create_comp_flags_from_zip_params(6, 15, 0);
+
+    let mut d = CompressorOxide::new(flags);
+    assert_eq!(compress_all_levels(&mut d, data, &mut out), 0);
+    let mut d = CompressorOxide::with_mem_level(flags, 1);
+    assert_eq!(compress_all_levels(&mut d, data, &mut out), 0);
+
+    // The binary trees are allocated when the flag is set.
+    let mut d = CompressorOxide::new(flags);
+    assert_eq!(count_allocations(|| d.set_binary_tree_matches(true)), 1);
+    assert_eq!(compress_all_levels(&mut d, data, &mut out), 0);
+
+    // Or provided with the other buffers.
+    let sizes = BufferSizes::MAX;
+    let mut buffers = Box::new(CompressorBuffers::new());
+    let (mut dict, mut next) = (vec![0; sizes.dict], vec![0; 3 * sizes.next]);
+    let (mut hash_table, mut lz_codes) = (vec![0; sizes.hash_table], vec![0; sizes.lz_codes]);
+    let mut out_buf = vec![0; sizes.out_buf];
+    let mut d = CompressorOxide::with_buffers(
+        flags,
+        &mut buffers,
+        &mut dict,
+        &mut next,
+        &mut hash_table,
+        &mut lz_codes,
+        &mut out_buf,
+    )
+    .unwrap();
+    assert_eq!(count_allocations(|| d.set_binary_tree_matches(true)), 0);
+    assert_eq!(compress_all_levels(&mut d, data, &mut out), 0);
+    let len = compress_into(&mut d, data, &mut out, 9, DataFormat::Zlib).unwrap();
+    assert_eq!(decompress_to_vec_zlib(&out[..len]).unwrap(), &data[..]);
+}
+
+#[cfg(feature = "zlib-compat")]
+#[test]
+fn compress_into_zlib_compatible() {
+    // The state of the zlib port is allocated the first time it's used, and then kept.
+    let data = include_bytes!("test_data/numbers.txt");
+    let mut out = vec![0; 2 * data.len()];
+    let mut d = CompressorOxide::new(create_comp_flags_from_zip_params(6, 15, 0));
+    d.set_zlib_compatible(true);
+    assert!(compress_all_levels_once(&mut d, data, &mut out) > 0);
+    assert_eq!(compress_all_levels_once(&mut d, data, &mut out), 0);
+}
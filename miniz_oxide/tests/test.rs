@@ -1,9 +1,21 @@
 extern crate miniz_oxide;
 
+use std::cmp;
 use std::io::Read;
 
+#[cfg(feature = "zlib-compat")]
+use miniz_oxide::deflate::core::compress_to_output;
+#[cfg(feature = "alloc")]
+use miniz_oxide::deflate::core::CompressionStrategy;
+use miniz_oxide::deflate::core::{
+    compress, create_comp_flags_from_zip_params, BufferSizes, CompressorBuffers, CompressorOxide,
+    TDEFLFlush, TDEFLStatus,
+};
+#[cfg(feature = "alloc")]
 use miniz_oxide::deflate::{compress_to_vec, compress_to_vec_zlib};
-use miniz_oxide::inflate::{decompress_to_vec, decompress_to_vec_zlib, TINFLStatus};
+use miniz_oxide::inflate::TINFLStatus;
+#[cfg(feature = "alloc")]
+use miniz_oxide::inflate::{decompress_to_vec, decompress_to_vec_zlib};
 
 fn get_test_file_data(name: &str) -> Vec<u8> {
     use std::fs::File;
@@ -15,6 +27,7 @@ fn get_test_file_data(name: &str) -> Vec<u8> {
 }
 
 /// Fuzzed file that caused issues for the inflate library.
+#[cfg(feature = "alloc")]
 #[test]
 fn inf_issue_14() {
     let data = get_test_file_data("tests/test_data/issue_14.zlib");
@@ -25,6 +38,7 @@ fn inf_issue_14() {
 }
 
 /// Fuzzed file that causes panics (subtract-with-overflow in debug, out-of-bounds in release)
+#[cfg(feature = "alloc")]
 #[test]
 fn inf_issue_19() {
     let data = get_test_file_data("tests/test_data/issue_19.deflate");
@@ -33,6 +47,7 @@ fn inf_issue_19() {
 
 /// Fuzzed (invalid )file that resulted in an infinite loop as inflate read a code as having 0
 /// length.
+#[cfg(feature = "alloc")]
 #[test]
 fn decompress_zero_code_len_oom() {
     let data = get_test_file_data("tests/test_data/invalid_code_len_oom");
@@ -41,21 +56,24 @@ fn decompress_zero_code_len_oom() {
 
 /// Same problem as previous test but in the end of input huffman decode part of
 /// `decode_huffman_code`
+#[cfg(feature = "alloc")]
 #[test]
 fn decompress_zero_code_len_2() {
     let data = get_test_file_data("tests/test_data/invalid_code_len_oom");
     let _ = decompress_to_vec(data.as_slice());
 }
 
+#[cfg(feature = "alloc")]
 fn get_test_data() -> Vec<u8> {
     use std::env;
     let path = env::var("TEST_FILE").unwrap_or_else(|_| "../miniz/miniz.c".to_string());
     get_test_file_data(&path)
 }
 
+#[cfg(feature = "alloc")]
 fn roundtrip(level: u8) {
     let data = get_test_data();
-    let enc = compress_to_vec(&data.as_slice()[..], level);
+    let enc = compress_to_vec(data.as_slice(), level);
     println!(
         "Input len: {}, compressed len: {}, level: {}",
         data.len(),
@@ -66,21 +84,25 @@ fn roundtrip(level: u8) {
     assert!(data == dec);
 }
 
+#[cfg(feature = "alloc")]
 #[test]
 fn roundtrip_lvl_9() {
     roundtrip(9);
 }
 
+#[cfg(feature = "alloc")]
 #[test]
 fn roundtrip_lvl_1() {
     roundtrip(1);
 }
 
+#[cfg(feature = "alloc")]
 #[test]
 fn roundtrip_lvl_0() {
     roundtrip(0);
 }
 
+#[cfg(feature = "alloc")]
 #[test]
 fn zlib_header_level() {
     let level = 6;
@@ -147,6 +169,7 @@ fn need_more_input_has_more_output_at_same_time() {
     decomp(&input[..11728]); // Fail: NeedsMoreInput even if the output buffer is also full!
 }
 
+#[cfg(feature = "alloc")]
 #[test]
 fn issue_75_empty_input_infinite_loop() {
     // Make sure compression works with empty input,
@@ -157,7 +180,263 @@ fn issue_75_empty_input_infinite_loop() {
     assert_eq!(d.len(), 0);
     let c = miniz_oxide::deflate::compress_to_vec(&[0], 6);
     let d = miniz_oxide::inflate::decompress_to_vec(&c).expect("decompression failed!");
-    assert!(&d == &[0]);
+    assert!(d == [0]);
+}
+
+/// Round trip using only caller provided buffers, which works without the `alloc` feature.
+#[test]
+fn roundtrip_with_buffers() {
+    use miniz_oxide::inflate::core::{decompress, inflate_flags, DecompressorOxide};
+
+    let data = include_bytes!("test_data/numbers.txt");
+    let mut buffers = CompressorBuffers::new();
+    let mut dict = [0; BufferSizes::MAX.dict];
+    let mut next = [0; BufferSizes::MAX.next];
+    let mut hash_table = [0; BufferSizes::MAX.hash_table];
+    let mut lz_codes = [0; BufferSizes::MAX.lz_codes];
+    let mut out_buf = [0; BufferSizes::MAX.out_buf];
+    let mut d = CompressorOxide::with_buffers(
+        create_comp_flags_from_zip_params(6, 15, 0),
+        &mut buffers,
+        &mut dict,
+        &mut next,
+        &mut hash_table,
+        &mut lz_codes,
+        &mut out_buf,
+    )
+    .unwrap();
+
+    let mut encoded = [0; 32 * 1024];
+    let (mut in_pos, mut out_pos) = (0, 0);
+    loop {
+        let in_end = cmp::min(in_pos + 10_000, data.len());
+        let flush = if in_end == data.len() {
+            TDEFLFlush::Finish
+        } else {
+            TDEFLFlush::None
+        };
+        let (status, bytes_in, bytes_out) = compress(
+            &mut d,
+            &data[in_pos..in_end],
+            &mut encoded[out_pos..],
+            flush,
+        );
+        in_pos += bytes_in;
+        out_pos += bytes_out;
+        match status {
+            TDEFLStatus::Done => break,
+            TDEFLStatus::Okay => (),
+            _ => panic!("compression failed: {:?}", status),
+        }
+    }
+    assert_eq!(in_pos, data.len());
+
+    let mut r = DecompressorOxide::new();
+    let mut decoded = [0; 64 * 1024];
+    let flags = inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
+        | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    let (status, bytes_in, bytes_out) =
+        decompress(&mut r, &encoded[..out_pos], &mut decoded, 0, flags);
+    assert_eq!((status, bytes_in), (TINFLStatus::Done, out_pos));
+    assert!(decoded[..bytes_out] == data[..]);
+}
+
+/// Where the input of the golden files in `tests/test_data/golden` is split up, with the flush
+/// used at the end of each part.
+#[cfg(feature = "alloc")]
+const GOLDEN_PARTS: [(usize, TDEFLFlush); 5] = [
+    (6000, TDEFLFlush::None),
+    (22000, TDEFLFlush::Sync),
+    (24000, TDEFLFlush::Sync),
+    (24300, TDEFLFlush::Full),
+    (28300, TDEFLFlush::Finish),
+];
+
+/// Compress `data` with stable output, passing in at most `in_chunk` bytes at a time and using
+/// an output buffer of `out_size` bytes.
+#[cfg(feature = "alloc")]
+fn compress_stable(data: &[u8], flags: u32, in_chunk: usize, out_size: usize) -> Vec<u8> {
+    let mut d = CompressorOxide::new(flags);
+    d.set_stable_output(true);
+    let mut encoded = Vec::new();
+    let mut out = vec![0; out_size];
+    let mut pos = 0;
+    for &(end, part_flush) in GOLDEN_PARTS.iter() {
+        while pos < end {
+            let in_end = cmp::min(pos + in_chunk, end);
+            let flush = if in_end == end {
+                part_flush
+            } else {
+                TDEFLFlush::None
+            };
+            loop {
+                let (status, bytes_in, bytes_out) =
+                    compress(&mut d, &data[pos..in_end], &mut out, flush);
+                pos += bytes_in;
+                encoded.extend_from_slice(&out[..bytes_out]);
+                match status {
+                    TDEFLStatus::Done => break,
+                    TDEFLStatus::Okay if pos == in_end && d.pending_output().0 == 0 => break,
+                    TDEFLStatus::Okay => (),
+                    _ => panic!("compression failed: {:?}", status),
+                }
+            }
+        }
+    }
+    encoded
+}
+
+/// The golden files were made with miniz_oxide 0.4.0. The output with
+/// `CompressorOxide::set_stable_output` must stay the same as in them, on all platforms.
+#[cfg(feature = "alloc")]
+#[test]
+fn stable_output_golden_files() {
+    let data = get_test_file_data("tests/test_data/golden/input.bin");
+    assert_eq!(data.len(), GOLDEN_PARTS[GOLDEN_PARTS.len() - 1].0);
+
+    let mut golden: Vec<_> = (0..=10)
+        .map(|level| {
+            let flags = create_comp_flags_from_zip_params(level, 15, 0);
+            (flags, format!("level_{}.zlib", level))
+        })
+        .collect();
+    for &(strategy, name) in &[
+        (CompressionStrategy::Filtered, "filtered"),
+        (CompressionStrategy::HuffmanOnly, "huffman_only"),
+        (CompressionStrategy::RLE, "rle"),
+        (CompressionStrategy::Fixed, "fixed"),
+    ] {
+        let flags = create_comp_flags_from_zip_params(6, -15, strategy as i32);
+        golden.push((flags, format!("{}.deflate", name)));
+    }
+
+    for (flags, name) in golden {
+        let expected = get_test_file_data(&format!("tests/test_data/golden/{}", name));
+        for &(in_chunk, out_size) in &[(data.len(), 64 * 1024), (1000, 97)] {
+            let encoded = compress_stable(&data, flags, in_chunk, out_size);
+            assert!(encoded == expected, "output differs from {}", name);
+        }
+
+        let decoded = if name.ends_with(".zlib") {
+            decompress_to_vec_zlib(&expected)
+        } else {
+            decompress_to_vec(&expected)
+        };
+        assert!(decoded.unwrap() == data);
+    }
+}
+
+/// Compress `data` with zlib compatible output and the flushes in `parts`, passing in at most
+/// `in_chunk` bytes at a time and using an output buffer of `out_size` bytes.
+///
+/// Like with zlib, the compressor is called again as long as it fills the output buffer.
+#[cfg(feature = "zlib-compat")]
+fn compress_zlib_compatible(
+    data: &[u8],
+    flags: u32,
+    parts: &[(usize, TDEFLFlush)],
+    in_chunk: usize,
+    out_size: usize,
+) -> Vec<u8> {
+    let mut d = CompressorOxide::new(flags);
+    d.set_zlib_compatible(true);
+    let mut encoded = Vec::new();
+    let mut out = vec![0; out_size];
+    let mut pos = 0;
+    for &(end, part_flush) in parts {
+        while pos < end {
+            let in_end = cmp::min(pos + in_chunk, end);
+            let flush = if in_end == end {
+                part_flush
+            } else {
+                TDEFLFlush::None
+            };
+            loop {
+                let (status, bytes_in, bytes_out) =
+                    compress(&mut d, &data[pos..in_end], &mut out, flush);
+                pos += bytes_in;
+                encoded.extend_from_slice(&out[..bytes_out]);
+                match status {
+                    TDEFLStatus::Done => break,
+                    TDEFLStatus::Okay if bytes_out < out_size => break,
+                    TDEFLStatus::Okay => (),
+                    _ => panic!("compression failed: {:?}", status),
+                }
+            }
+            assert_eq!(pos, in_end);
+        }
+    }
+    encoded
+}
+
+/// The files in `tests/test_data/zlib` were made with zlib 1.2.13, passing in each part of
+/// the input at once and using an output buffer of 16 KiB.
+#[cfg(feature = "zlib-compat")]
+#[test]
+fn zlib_compatible_golden_files() {
+    let data = get_test_file_data("tests/test_data/golden/input.bin");
+    let repeated: Vec<u8> = data.iter().cycle().take(4 * data.len()).cloned().collect();
+    let repeated_parts = [(repeated.len(), TDEFLFlush::Finish)];
+
+    let mut golden: Vec<_> = (0..=9)
+        .map(|level| {
+            let flags = create_comp_flags_from_zip_params(level, 15, 0);
+            (
+                flags,
+                format!("level_{}.zlib", level),
+                &data[..],
+                &GOLDEN_PARTS[..],
+            )
+        })
+        .collect();
+    for &(strategy, name) in &[
+        (CompressionStrategy::Filtered, "filtered"),
+        (CompressionStrategy::HuffmanOnly, "huffman_only"),
+        (CompressionStrategy::RLE, "rle"),
+        (CompressionStrategy::Fixed, "fixed"),
+    ] {
+        let flags = create_comp_flags_from_zip_params(6, -15, strategy as i32);
+        golden.push((flags, format!("{}.deflate", name), &data, &GOLDEN_PARTS));
+    }
+    for &level in &[1, 6, 9] {
+        let flags = create_comp_flags_from_zip_params(level, 15, 0);
+        let name = format!("repeated_level_{}.zlib", level);
+        golden.push((flags, name, &repeated, &repeated_parts));
+    }
+
+    for (flags, name, input, parts) in golden {
+        let expected = get_test_file_data(&format!("tests/test_data/zlib/{}", name));
+        let encoded = compress_zlib_compatible(input, flags, parts, input.len(), 16 * 1024);
+        assert!(encoded == expected, "output differs from {}", name);
+
+        // Only the stored blocks of level 0 depend on how the input and output are split up.
+        if !name.starts_with("level_0") {
+            let encoded = compress_zlib_compatible(input, flags, parts, 1000, 97);
+            assert!(encoded == expected, "chunked output differs from {}", name);
+
+            let mut d = CompressorOxide::new(flags);
+            d.set_zlib_compatible(true);
+            let mut encoded = Vec::new();
+            let mut pos = 0;
+            for &(end, flush) in parts {
+                let (status, bytes_in) =
+                    compress_to_output(&mut d, &input[pos..end], flush, |out| {
+                        encoded.extend_from_slice(out);
+                        true
+                    });
+                assert_ne!(status, TDEFLStatus::BadParam);
+                pos += bytes_in;
+            }
+            assert!(encoded == expected, "callback output differs from {}", name);
+        }
+
+        let decoded = if name.ends_with(".zlib") {
+            decompress_to_vec_zlib(&expected)
+        } else {
+            decompress_to_vec(&expected)
+        };
+        assert!(decoded.unwrap() == input);
+    }
 }
 
 /*
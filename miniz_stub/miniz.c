@@ -173,11 +173,6 @@ void mz_free(void *p)
 //    return MZ_REALLOC(address, items * size);
 //}
 
-const char *mz_version(void)
-{
-    return MZ_VERSION;
-}
-
 #ifndef MINIZ_NO_ZLIB_APIS
 
 //int mz_deflateInit(mz_streamp pStream, int level)
@@ -3,10 +3,14 @@ use std::marker::PhantomData;
 use std::{ptr, slice};
 
 pub use tinfl::{
-    tinfl_decompress, tinfl_decompress_mem_to_heap, tinfl_decompress_mem_to_mem, tinfl_decompressor,
+    tinfl_decompress, tinfl_decompress_mem_to_callback, tinfl_decompress_mem_to_heap,
+    tinfl_decompress_mem_to_mem, tinfl_decompressor, tinfl_put_buf_func_ptr,
 };
 
 use libc::*;
+pub use png::{
+    tdefl_write_image_to_png_file_in_memory, tdefl_write_image_to_png_file_in_memory_ex,
+};
 pub use tdef::{
     tdefl_allocate, tdefl_compress, tdefl_compress_buffer, tdefl_compress_mem_to_heap,
     tdefl_compress_mem_to_mem, tdefl_compress_mem_to_output,
@@ -14,7 +18,10 @@ pub use tdef::{
     tdefl_get_prev_return_status, tdefl_init,
 };
 
-use lib_oxide::{InternalState, StateType, StateTypeEnum, StreamOxide, MZ_ADLER32_INIT};
+use lib_oxide::{
+    InternalState, StateType, StateTypeEnum, StreamAllocator, StreamBox, StreamOxide,
+    MZ_ADLER32_INIT,
+};
 
 use miniz_oxide::{mz_adler32_oxide, MZError};
 
@@ -60,10 +67,22 @@ pub enum CAPICompressionStrategy {
 
 pub const MZ_CRC32_INIT: c_ulong = 0;
 
+/// Version of the miniz API, matching `MZ_VERSION` in `miniz.h`.
+const MZ_VERSION: &[u8] = b"10.0.1\0";
+
+const MZ_OK: c_int = CAPIReturnStatus::MZ_OK as c_int;
+const MZ_STREAM_END: c_int = CAPIReturnStatus::MZ_STREAM_END as c_int;
+const MZ_NEED_DICT: c_int = CAPIReturnStatus::MZ_NEED_DICT as c_int;
+const MZ_ERRNO: c_int = CAPIReturnStatus::MZ_ERRNO as c_int;
+const MZ_STREAM_ERROR: c_int = CAPIReturnStatus::MZ_STREAM_ERROR as c_int;
+const MZ_DATA_ERROR: c_int = CAPIReturnStatus::MZ_DATA_ERROR as c_int;
+const MZ_MEM_ERROR: c_int = CAPIReturnStatus::MZ_MEM_ERROR as c_int;
+const MZ_BUF_ERROR: c_int = CAPIReturnStatus::MZ_BUF_ERROR as c_int;
+const MZ_VERSION_ERROR: c_int = CAPIReturnStatus::MZ_VERSION_ERROR as c_int;
+const MZ_PARAM_ERROR: c_int = CAPIReturnStatus::MZ_PARAM_ERROR as c_int;
+
 pub fn mz_crc32_oxide(crc32: c_uint, data: &[u8]) -> c_uint {
-    let mut digest = crc32fast::Hasher::new_with_initial(crc32);
-    digest.update(data);
-    digest.finalize()
+    miniz_oxide::mz_crc32_oxide(crc32, data)
 }
 
 /// Signature of function used to allocate the compressor/decompressor structs.
@@ -73,6 +92,39 @@ pub type mz_alloc_func = unsafe extern "C" fn(*mut c_void, size_t, size_t) -> *m
 #[allow(bad_style)]
 pub type mz_free_func = unsafe extern "C" fn(*mut c_void, *mut c_void);
 
+/// Signature of the function `mz_inflateBack` calls to get more input.
+///
+/// Sets the pointer to the input and returns its length, 0 at the end of the input.
+#[allow(bad_style)]
+pub type mz_in_func = unsafe extern "C" fn(*mut c_void, *mut *const u8) -> c_uint;
+/// Signature of the function `mz_inflateBack` calls to write output.
+///
+/// Returns non-zero on error.
+#[allow(bad_style)]
+pub type mz_out_func = unsafe extern "C" fn(*mut c_void, *mut u8, c_uint) -> c_int;
+
+/// gzip header information, as used by zlib's `deflateSetHeader` and `inflateGetHeader`.
+///
+/// gzip streams are not supported, this is only defined for compatibility.
+#[repr(C)]
+#[allow(bad_style)]
+#[derive(Debug)]
+pub struct mz_gz_header {
+    pub text: c_int,
+    pub time: c_ulong,
+    pub xflags: c_int,
+    pub os: c_int,
+    pub extra: *mut u8,
+    pub extra_len: c_uint,
+    pub extra_max: c_uint,
+    pub name: *mut u8,
+    pub name_max: c_uint,
+    pub comment: *mut u8,
+    pub comm_max: c_uint,
+    pub hcrc: c_int,
+    pub done: c_int,
+}
+
 /// Inner stream state containing pointers to the used buffers and internal state.
 #[repr(C)]
 #[allow(bad_style)]
@@ -92,10 +144,12 @@ pub struct mz_stream {
     /// The total number of bytes output so far.
     pub total_out: c_ulong,
 
+    /// Description of the last error, or null if there is none.
+    /// Points to a static string.
     pub msg: *const c_char,
     /// Compressor or decompressor, if it exists.
-    /// This is boxed to work with the current C API.
-    pub state: Option<Box<InternalState>>,
+    /// Allocated with `zalloc` and freed with `zfree`.
+    pub state: *mut InternalState,
 
     /// Allocation function to use for allocating the internal compressor/decompressor.
     /// Uses `mz_default_alloc_func` if set to `None`.
@@ -127,7 +181,7 @@ impl Default for mz_stream {
             total_out: 0,
 
             msg: ptr::null(),
-            state: None,
+            state: ptr::null_mut(),
 
             zalloc: None,
             zfree: None,
@@ -159,12 +213,15 @@ impl<'io, ST: StateType> StreamOxide<'io, ST> {
                 .map_or(0, |out_slice| out_slice.len() as c_uint),
             total_out: self.total_out,
 
-            msg: ptr::null(),
+            msg: self.msg,
 
-            zalloc: None,
-            zfree: None,
-            opaque: ptr::null_mut(),
-            state: self.state.take(),
+            zalloc: self.allocator.zalloc,
+            zfree: self.allocator.zfree,
+            opaque: self.allocator.opaque,
+            state: self
+                .state
+                .take()
+                .map_or(ptr::null_mut(), StreamBox::into_raw),
 
             data_type: ST::STATE_TYPE,
             adler: self.adler as c_ulong,
@@ -173,26 +230,25 @@ impl<'io, ST: StateType> StreamOxide<'io, ST> {
     }
 
     /// Create a new StreamOxide wrapper from a [mz_stream] object.
-    /// Custom allocation functions are not supported, supplying an mz_stream with allocation
-    /// function will cause creation to fail.
+    /// Panics if the stream holds the wrong type of state.
     ///
-    /// Unsafe as the mz_stream object is not guaranteed to be valid. It is up to the
-    /// caller to ensure it is.
+    /// # Safety
+    /// The mz_stream object has to be valid, see `try_new`.
     pub unsafe fn new(stream: &mut mz_stream) -> Self {
-        Self::try_new(stream).expect(
-            "Failed to create StreamOxide, wrong state type or tried to specify allocators.",
-        )
+        Self::try_new(stream).expect("Failed to create StreamOxide, wrong state type.")
     }
 
     /// Try to create a new StreamOxide wrapper from a [mz_stream] object.
-    /// Custom allocation functions are not supported, supplying an mz_stream with allocation
-    /// functions will cause creation to fail.
+    /// The internal state is allocated and freed using the `zalloc` and `zfree` functions
+    /// of the stream, which are passed `opaque`, or `malloc` and `free` if they are not set.
     ///
-    /// Unsafe as the mz_stream object is not guaranteed to be valid. It is up to the
-    /// caller to ensure it is.
+    /// # Safety
+    /// `next_in` and `next_out` of the mz_stream object have to be null or valid for `avail_in`
+    /// and `avail_out` bytes while the wrapper is used, and `state` has to be null or set up
+    /// by this library with the allocation functions of the stream.
     pub unsafe fn try_new(stream: &mut mz_stream) -> Result<Self, MZError> {
         // Make sure we don't make an inflate stream from a deflate stream and vice versa.
-        if stream.data_type != ST::STATE_TYPE || stream.zalloc.is_some() || stream.zfree.is_some() {
+        if stream.data_type != ST::STATE_TYPE {
             return Err(MZError::Param);
         }
 
@@ -206,21 +262,33 @@ impl<'io, ST: StateType> StreamOxide<'io, ST> {
             .as_mut()
             .map(|ptr| slice::from_raw_parts_mut(ptr, stream.avail_out as usize));
 
+        let allocator = StreamAllocator {
+            zalloc: stream.zalloc,
+            zfree: stream.zfree,
+            opaque: stream.opaque,
+        };
+        let state = StreamBox::from_raw(stream.state, allocator);
+        stream.state = ptr::null_mut();
+
         Ok(StreamOxide {
             next_in: in_slice,
             total_in: stream.total_in,
             next_out: out_slice,
             total_out: stream.total_out,
-            state: stream.state.take(),
+            state,
+            allocator,
             adler: stream.adler as u32,
+            msg: stream.msg,
             state_type: PhantomData,
         })
     }
 }
 
-#[cfg(not(no_c_export))]
 unmangle!(
     /// Default allocation function using `malloc`.
+    ///
+    /// # Safety
+    /// The returned memory has to be freed with `miniz_def_free_func`.
     pub unsafe extern "C" fn miniz_def_alloc_func(
         _opaque: *mut c_void,
         items: size_t,
@@ -230,10 +298,17 @@ unmangle!(
     }
 
     /// Default free function using `free`.
+    ///
+    /// # Safety
+    /// `address` has to be null or returned by `miniz_def_alloc_func` or
+    /// `miniz_def_realloc_func`, and not freed already.
     pub unsafe extern "C" fn miniz_def_free_func(_opaque: *mut c_void, address: *mut c_void) {
         libc::free(address)
     }
 
+    /// # Safety
+    /// `address` has to be null or returned by `miniz_def_alloc_func` or
+    /// `miniz_def_realloc_func`, and not freed already.
     pub unsafe extern "C" fn miniz_def_realloc_func(
         _opaque: *mut c_void,
         address: *mut c_void,
@@ -247,6 +322,9 @@ unmangle!(
     /// If c_ulong is wider than 32 bits, only the lower 32 bits will be used.
     ///
     /// Returns MZ_ADLER32_INIT if ptr is `ptr::null`.
+    ///
+    /// # Safety
+    /// `ptr` has to be null or valid for reads of `buf_len` bytes.
     pub unsafe extern "C" fn mz_adler32(adler: c_ulong, ptr: *const u8, buf_len: usize) -> c_ulong {
         ptr.as_ref().map_or(MZ_ADLER32_INIT as c_ulong, |r| {
             let data = slice::from_raw_parts(r, buf_len);
@@ -258,10 +336,49 @@ unmangle!(
     /// If c_ulong is wider than 32 bits, only the lower 32 bits will be used.
     ///
     /// Returns MZ_CRC32_INIT if ptr is `ptr::null`.
+    ///
+    /// # Safety
+    /// `ptr` has to be null or valid for reads of `buf_len` bytes.
     pub unsafe extern "C" fn mz_crc32(crc: c_ulong, ptr: *const u8, buf_len: size_t) -> c_ulong {
         ptr.as_ref().map_or(MZ_CRC32_INIT, |r| {
             let data = slice::from_raw_parts(r, buf_len);
             mz_crc32_oxide(crc as u32, data) as c_ulong
         })
     }
+
+    /// Returns a description of the status code `err`, or null if the code is unknown.
+    pub extern "C" fn mz_error(err: c_int) -> *const c_char {
+        let desc: &'static [u8] = match err {
+            MZ_OK => b"\0",
+            MZ_STREAM_END => b"stream end\0",
+            MZ_NEED_DICT => b"need dictionary\0",
+            MZ_ERRNO => b"file error\0",
+            MZ_STREAM_ERROR => b"stream error\0",
+            MZ_DATA_ERROR => b"data error\0",
+            MZ_MEM_ERROR => b"out of memory\0",
+            MZ_BUF_ERROR => b"buf error\0",
+            MZ_VERSION_ERROR => b"version error\0",
+            MZ_PARAM_ERROR => b"parameter error\0",
+            _ => return ptr::null(),
+        };
+        desc.as_ptr() as *const c_char
+    }
+
+    /// Alias of `mz_error` for zlib compatibility.
+    pub extern "C" fn zError(err: c_int) -> *const c_char {
+        mz_error(err)
+    }
+
+    /// Returns the version of the miniz API that is implemented, as a NUL-terminated string.
+    pub extern "C" fn mz_version() -> *const c_char {
+        MZ_VERSION.as_ptr() as *const c_char
+    }
+
+    /// Alias of `mz_version` for zlib compatibility.
+    ///
+    /// With the `zlib_names` feature, this returns the implemented zlib version instead.
+    #[cfg(not(feature = "zlib_names"))]
+    pub extern "C" fn zlibVersion() -> *const c_char {
+        mz_version()
+    }
 );
@@ -4,7 +4,6 @@
 //!
 //! The C API is in a bit of a rough shape currently.
 
-extern crate crc32fast;
 #[cfg(not(any(
     feature = "libc_stub",
     all(target_arch = "wasm32", not(target_os = "emscripten"))
@@ -25,6 +24,7 @@ mod libc {
     pub type c_void = u8;
     pub type c_int = i32;
     pub type c_uint = u32;
+    pub type c_long = i64;
     pub type c_ulong = u64;
     pub type c_char = i8;
     pub type size_t = usize;
@@ -61,12 +61,15 @@ mod libc {
 extern crate miniz_oxide;
 
 use std::panic::{catch_unwind, AssertUnwindSafe};
-use std::{cmp, ptr};
+use std::{cmp, ptr, slice};
 
-use libc::{c_int, c_uint, c_ulong};
+use libc::{c_int, c_long, c_uint, c_ulong, c_void};
 
-use miniz_oxide::deflate::core::CompressionStrategy;
-use miniz_oxide::deflate::CompressionLevel;
+use miniz_oxide::deflate::core::{
+    compress_bound_from_flags, deflate_flags, BufferSizes, CompressionStrategy,
+};
+use miniz_oxide::deflate::{compress_bound, CompressionLevel};
+use miniz_oxide::DataFormat;
 pub use miniz_oxide::{MZError, MZFlush, MZResult, MZStatus};
 
 pub mod lib_oxide;
@@ -74,12 +77,14 @@ use lib_oxide::*;
 
 #[macro_use]
 mod unmangle;
+pub mod png;
 mod tdef;
 mod tinfl;
 
 mod c_export;
 pub use c_export::*;
-#[allow(bad_style)]
+#[cfg(feature = "zlib_names")]
+pub mod zlib_names;
 pub use tdef::Compressor as tdefl_compressor;
 
 pub const MZ_DEFLATED: c_int = 8;
@@ -95,46 +100,111 @@ fn as_c_return_code(r: MZResult) -> c_int {
 macro_rules! oxidize {
     ($mz_func:ident, $mz_func_oxide:ident; $($arg_name:ident: $type_name:ident),*) => {
         unmangle!(
+        /// # Safety
+        /// `stream` has to be null or point to a valid `mz_stream`: `next_in` and `next_out` have
+        /// to be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or
+        /// set up by one of the init functions with the allocation functions of the stream.
         pub unsafe extern "C" fn $mz_func(stream: *mut mz_stream, $($arg_name: $type_name),*)
                                           -> c_int {
-            match stream.as_mut() {
-                None => MZError::Stream as c_int,
-                Some(stream) => {
-                    // Make sure we catch a potential panic, as
-                    // this is called from C.
-                    match catch_unwind(AssertUnwindSafe(|| {
-                        // Do some checks to see if the stream object has the right type.
-                        match StreamOxide::try_new(stream) {
-                            Ok(mut stream_oxide) => {
-                                let status = $mz_func_oxide(&mut stream_oxide, $($arg_name),*);
-                                *stream = stream_oxide.into_mz_stream();
-                                as_c_return_code(status) }
-                            Err(e) => {
-                                e as c_int
-                            }
-                        }
-                    })) {
-                        Ok(res) => res,
-                        Err(_) => {
-                            println!("FATAL ERROR: Caught panic!");
-                            MZError::Stream as c_int},
-                    }
-                }
-            }
+            with_stream_oxide(stream, |stream_oxide| $mz_func_oxide(stream_oxide, $($arg_name),*))
         });
     };
 }
 
+/// Wrap `stream` in a `StreamOxide` for the duration of `f`, writing any changes back to it.
+///
+/// Returns `MZError::Stream` if `stream` is null, or if `f` panics, which is caught as we are
+/// called from C.
+unsafe fn with_stream_oxide<ST, F>(stream: *mut mz_stream, f: F) -> c_int
+where
+    ST: StateType,
+    F: FnOnce(&mut StreamOxide<ST>) -> MZResult,
+{
+    match stream.as_mut() {
+        None => MZError::Stream as c_int,
+        Some(stream) => {
+            match catch_unwind(AssertUnwindSafe(|| match StreamOxide::try_new(stream) {
+                Ok(mut stream_oxide) => {
+                    let status = f(&mut stream_oxide);
+                    *stream = stream_oxide.into_mz_stream();
+                    as_c_return_code(status)
+                }
+                Err(e) => e as c_int,
+            })) {
+                Ok(res) => res,
+                Err(_) => {
+                    println!("FATAL ERROR: Caught panic!");
+                    MZError::Stream as c_int
+                }
+            }
+        }
+    }
+}
+
+/// Copy `source` to `dest`, giving `dest` its own copy of the internal state made by `copy`.
+///
+/// Like in zlib, all the fields are copied, including the allocation functions used for
+/// the copy. Returns `MZError::Stream` if either stream is null or they are the same.
+unsafe fn copy_stream<ST, F>(dest: *mut mz_stream, source: *mut mz_stream, copy: F) -> c_int
+where
+    ST: StateType,
+    F: FnOnce(&mut StreamOxide<ST>) -> Result<StreamBox<InternalState>, MZError>,
+{
+    if dest.is_null() || dest == source {
+        return MZError::Stream as c_int;
+    }
+    let mut state = None;
+    let ret = with_stream_oxide(source, |stream_oxide| {
+        state = Some(copy(stream_oxide)?);
+        Ok(MZStatus::Ok)
+    });
+    if let Some(state) = state {
+        ptr::copy_nonoverlapping(source, dest, 1);
+        (*dest).state = state.into_raw();
+    }
+    ret
+}
+
+/// Get an upper bound on the size of the output of compressing `source_len` bytes that holds
+/// for any settings accepted by `mz_deflateInit2`.
+fn deflate_bound_any_settings(source_len: usize) -> usize {
+    let zlib = deflate_flags::TDEFL_WRITE_ZLIB_HEADER;
+    cmp::max(
+        // Static blocks that are too large to be stored if that is smaller.
+        compress_bound_from_flags(
+            source_len,
+            zlib | deflate_flags::TDEFL_FORCE_ALL_STATIC_BLOCKS,
+            BufferSizes::MAX,
+        ),
+        // The smallest blocks.
+        compress_bound_from_flags(source_len, zlib, BufferSizes::for_mem_level(1)),
+    )
+}
+
 oxidize!(mz_deflate, mz_deflate_oxide;
          flush: c_int);
 oxidize!(mz_deflateEnd, mz_deflate_end_oxide;);
 oxidize!(mz_deflateReset, mz_deflate_reset_oxide;);
+oxidize!(mz_deflateTune, mz_deflate_tune_oxide;
+         good_length: c_int, max_lazy: c_int, nice_length: c_int, max_chain: c_int);
+oxidize!(mz_deflatePrime, mz_deflate_prime_oxide;
+         bits: c_int, value: c_int);
 
 oxidize!(mz_inflate, mz_inflate_oxide;
          flush: c_int);
 oxidize!(mz_inflateEnd, mz_inflate_end_oxide;);
+oxidize!(mz_inflateReset, mz_inflate_reset_oxide;);
+oxidize!(mz_inflateReset2, mz_inflate_reset2_oxide;
+         window_bits: c_int);
+oxidize!(mz_inflatePrime, mz_inflate_prime_oxide;
+         bits: c_int, value: c_int);
+oxidize!(mz_inflateBackEnd, mz_inflate_end_oxide;);
 
 unmangle!(
+    /// # Safety
+    /// `stream` has to be null or point to a valid `mz_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
     pub unsafe extern "C" fn mz_deflateInit(stream: *mut mz_stream, level: c_int) -> c_int {
         mz_deflateInit2(
             stream,
@@ -146,6 +216,10 @@ unmangle!(
         )
     }
 
+    /// # Safety
+    /// `stream` has to be null or point to a valid `mz_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
     pub unsafe extern "C" fn mz_deflateInit2(
         stream: *mut mz_stream,
         level: c_int,
@@ -185,6 +259,10 @@ unmangle!(
         }
     }
 
+    /// # Safety
+    /// `stream` has to be null or point to a valid `mz_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
     pub unsafe extern "C" fn mz_inflateInit2(stream: *mut mz_stream, window_bits: c_int) -> c_int {
         match stream.as_mut() {
             None => MZError::Stream as c_int,
@@ -210,6 +288,9 @@ unmangle!(
         }
     }
 
+    /// # Safety
+    /// `dest_len` has to be null or valid, `dest` has to be valid for writes of `*dest_len` bytes
+    /// and `source` for reads of `source_len` bytes.
     pub unsafe extern "C" fn mz_compress(
         dest: *mut u8,
         dest_len: *mut c_ulong,
@@ -225,6 +306,9 @@ unmangle!(
         )
     }
 
+    /// # Safety
+    /// `dest_len` has to be null or valid, `dest` has to be valid for writes of `*dest_len` bytes
+    /// and `source` for reads of `source_len` bytes.
     pub unsafe extern "C" fn mz_compress2(
         dest: *mut u8,
         dest_len: *mut c_ulong,
@@ -253,17 +337,39 @@ unmangle!(
             })
     }
 
-    pub extern "C" fn mz_deflateBound(_stream: *mut mz_stream, source_len: c_ulong) -> c_ulong {
-        cmp::max(
-            128 + (source_len * 110) / 100,
-            128 + source_len + ((source_len / (31 * 1024)) + 1) * 5,
-        )
+    /// Get an upper bound on the size of the output of compressing `source_len` bytes with
+    /// the current settings of `stream`, if it is flushed only when finished.
+    ///
+    /// If `stream` is null or not initialized for compression, the bound holds for any settings
+    /// `mz_deflateInit2` accepts.
+    ///
+    /// # Safety
+    /// `stream` has to be null or point to a valid `mz_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn mz_deflateBound(
+        stream: *mut mz_stream,
+        source_len: c_ulong,
+    ) -> c_ulong {
+        let mut bound = deflate_bound_any_settings(source_len as usize) as c_ulong;
+        with_stream_oxide(stream, |stream_oxide| {
+            bound = mz_deflate_bound_oxide(stream_oxide, source_len as usize)? as c_ulong;
+            Ok(MZStatus::Ok)
+        });
+        bound
     }
 
+    /// # Safety
+    /// `stream` has to be null or point to a valid `mz_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
     pub unsafe extern "C" fn mz_inflateInit(stream: *mut mz_stream) -> c_int {
         mz_inflateInit2(stream, MZ_DEFAULT_WINDOW_BITS)
     }
 
+    /// # Safety
+    /// `dest_len` has to be null or valid, `dest` has to be valid for writes of `*dest_len` bytes
+    /// and `source` for reads of `source_len` bytes.
     pub unsafe extern "C" fn mz_uncompress(
         dest: *mut u8,
         dest_len: *mut c_ulong,
@@ -292,18 +398,166 @@ unmangle!(
             })
     }
 
+    /// Get an upper bound on the size of the output of `mz_compress` and `mz_compress2`.
     pub extern "C" fn mz_compressBound(source_len: c_ulong) -> c_ulong {
-        mz_deflateBound(ptr::null_mut(), source_len)
+        let levels = 0..=CompressionLevel::UberCompression as u8;
+        levels
+            .map(|level| compress_bound(source_len as usize, DataFormat::Zlib, level))
+            .max()
+            .unwrap_or(0) as c_ulong
+    }
+
+    /// Make `dest` a copy of the compression stream `source`, with its own internal state.
+    ///
+    /// # Safety
+    /// `source` has to meet the requirements for `stream` of `mz_deflate`, and `dest` has to be
+    /// null or valid for writes of an `mz_stream`.
+    pub unsafe extern "C" fn mz_deflateCopy(dest: *mut mz_stream, source: *mut mz_stream) -> c_int {
+        copy_stream(dest, source, mz_deflate_copy_oxide)
+    }
+
+    /// Get the number of bytes and bits of compressed data that have not been output yet,
+    /// e.g because `avail_out` was too small. Either pointer may be null.
+    ///
+    /// # Safety
+    /// `stream` has to be null or point to a valid `mz_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    /// `pending` and `bits` have to be null or valid for writes.
+    pub unsafe extern "C" fn mz_deflatePending(
+        stream: *mut mz_stream,
+        pending: *mut c_uint,
+        bits: *mut c_int,
+    ) -> c_int {
+        with_stream_oxide(stream, |stream_oxide| {
+            let (pending_bytes, pending_bits) = mz_deflate_pending_oxide(stream_oxide)?;
+            if let Some(pending) = pending.as_mut() {
+                *pending = pending_bytes as c_uint;
+            }
+            if let Some(bits) = bits.as_mut() {
+                *bits = pending_bits as c_int;
+            }
+            Ok(MZStatus::Ok)
+        })
+    }
+
+    /// Set the gzip header to write. gzip streams are not supported, so this always returns
+    /// `MZ_STREAM_ERROR`, like zlib does for streams without a gzip wrapper.
+    pub extern "C" fn mz_deflateSetHeader(
+        _stream: *mut mz_stream,
+        _head: *mut mz_gz_header,
+    ) -> c_int {
+        MZError::Stream as c_int
+    }
+
+    /// Make `dest` a copy of the decompression stream `source`, with its own internal state.
+    ///
+    /// # Safety
+    /// `source` has to meet the requirements for `stream` of `mz_inflate`, and `dest` has to be
+    /// null or valid for writes of an `mz_stream`.
+    pub unsafe extern "C" fn mz_inflateCopy(dest: *mut mz_stream, source: *mut mz_stream) -> c_int {
+        copy_stream(dest, source, mz_inflate_copy_oxide)
+    }
+
+    /// Get the position of the decompressor in the input, see `mz_inflate_mark_oxide`.
+    ///
+    /// Returns -65536, like zlib does, if the stream is invalid.
+    ///
+    /// # Safety
+    /// `stream` has to be null or point to a valid `mz_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn mz_inflateMark(stream: *mut mz_stream) -> c_long {
+        let mut mark = -(1 << 16);
+        with_stream_oxide(stream, |stream_oxide| {
+            mark = mz_inflate_mark_oxide(stream_oxide)?;
+            Ok(MZStatus::Ok)
+        });
+        mark
+    }
+
+    /// Request the gzip header of the stream. gzip streams are not supported, so this always
+    /// returns `MZ_STREAM_ERROR`, like zlib does for streams without a gzip wrapper.
+    pub extern "C" fn mz_inflateGetHeader(
+        _stream: *mut mz_stream,
+        _head: *mut mz_gz_header,
+    ) -> c_int {
+        MZError::Stream as c_int
+    }
+
+    /// Initialize a decompressor for `mz_inflateBack`, which decompresses raw deflate data.
+    ///
+    /// `window_bits` has to be between 8 and 15 and `window` non-null for zlib compatibility,
+    /// but the window is not used, as the decompressor has a window of its own.
+    ///
+    /// # Safety
+    /// `stream` has to be null or point to a valid `mz_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn mz_inflateBackInit(
+        stream: *mut mz_stream,
+        window_bits: c_int,
+        window: *mut u8,
+    ) -> c_int {
+        if !(8..=15).contains(&window_bits) || window.is_null() {
+            return MZError::Stream as c_int;
+        }
+        mz_inflateInit2(stream, -MZ_DEFAULT_WINDOW_BITS)
+    }
+
+    /// Decompress a raw deflate stream in one call, reading input with `in_func` and writing
+    /// output with `out_func`.
+    ///
+    /// Input is first taken from `next_in` if it is not null. `in_func` is called with `in_desc`
+    /// to get more, and returns the number of bytes available, 0 meaning the end of the input.
+    /// `out_func` is called with `out_desc` and each chunk of output, and returns non-zero to
+    /// signal an error.
+    ///
+    /// Returns `MZ_STREAM_END` on success, `MZ_BUF_ERROR` if the input ended early or
+    /// `out_func` failed (`next_in` is null in the former case), `MZ_DATA_ERROR` on corrupt
+    /// data and `MZ_STREAM_ERROR` if the stream is invalid.
+    ///
+    /// # Safety
+    /// `stream` has to be null or point to a valid `mz_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    /// `in_func` and `out_func` have to be safe to call with `in_desc` and `out_desc`.
+    pub unsafe extern "C" fn mz_inflateBack(
+        stream: *mut mz_stream,
+        in_func: Option<mz_in_func>,
+        in_desc: *mut c_void,
+        out_func: Option<mz_out_func>,
+        out_desc: *mut c_void,
+    ) -> c_int {
+        let (in_func, out_func) = match (in_func, out_func) {
+            (Some(in_func), Some(out_func)) => (in_func, out_func),
+            _ => return MZError::Stream as c_int,
+        };
+        with_stream_oxide(stream, |stream_oxide| {
+            mz_inflate_back_oxide(
+                stream_oxide,
+                || {
+                    let mut buf: *const u8 = ptr::null();
+                    let len = in_func(in_desc, &mut buf);
+                    if len == 0 || buf.is_null() {
+                        &[]
+                    } else {
+                        slice::from_raw_parts(buf, len as usize)
+                    }
+                },
+                |out| out_func(out_desc, out.as_ptr() as *mut u8, out.len() as c_uint) != 0,
+            )
+        })
     }
 );
 
-#[cfg(target_bit_width = "64")]
+#[cfg(target_pointer_width = "64")]
 #[inline]
 fn buffer_too_large(source_len: c_ulong, dest_len: c_ulong) -> bool {
     (source_len | dest_len) > 0xFFFFFFFF
 }
 
-#[cfg(not(target_bit_width = "64"))]
+#[cfg(not(target_pointer_width = "64"))]
 #[inline]
 fn buffer_too_large(_source_len: c_ulong, _dest_len: c_ulong) -> bool {
     false
@@ -1,15 +1,22 @@
 //! This module mainly contains functionality replicating the miniz higher level API.
 
 use std::default::Default;
-use std::{fmt, mem};
+use std::ffi::CStr;
+use std::ops::{Deref, DerefMut};
+use std::ptr::{self, NonNull};
+use std::{cmp, fmt, mem};
 
-use libc::c_ulong;
+use libc::{c_char, c_long, c_ulong, c_void};
 
+use c_export::{miniz_def_alloc_func, miniz_def_free_func, mz_alloc_func, mz_free_func};
 use miniz_oxide::deflate::core::{
-    create_comp_flags_from_zip_params, deflate_flags, CompressionStrategy, CompressorOxide,
+    create_comp_flags_from_zip_params, deflate_flags, BufferSizes, CompressionStrategy,
+    CompressorBuffers, CompressorOxide,
 };
 use miniz_oxide::deflate::stream::deflate;
+use miniz_oxide::inflate::core::{BlockPosition, FailureReason, TINFL_LZ_DICT_SIZE};
 use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::inflate::TINFLStatus;
 use tdef::Compressor;
 
 use miniz_oxide::*;
@@ -18,10 +25,183 @@ const MZ_DEFLATED: i32 = 8;
 
 pub const MZ_ADLER32_INIT: u32 = 1;
 
+/// The allocation functions and opaque pointer of an `mz_stream`.
+///
+/// `None` means the default `malloc`/`free` based functions are used.
+#[derive(Copy, Clone)]
+pub struct StreamAllocator {
+    pub(crate) zalloc: Option<mz_alloc_func>,
+    pub(crate) zfree: Option<mz_free_func>,
+    pub(crate) opaque: *mut c_void,
+}
+
+impl StreamAllocator {
+    fn alloc(&self, size: usize) -> *mut c_void {
+        let zalloc = self.zalloc.unwrap_or(miniz_def_alloc_func);
+        // Safe as long as the functions supplied in the mz_stream behave like malloc.
+        unsafe { zalloc(self.opaque, 1, size) }
+    }
+
+    fn free(&self, address: *mut c_void) {
+        let zfree = self.zfree.unwrap_or(miniz_def_free_func);
+        unsafe { zfree(self.opaque, address) }
+    }
+}
+
+impl Default for StreamAllocator {
+    fn default() -> StreamAllocator {
+        StreamAllocator {
+            zalloc: None,
+            zfree: None,
+            opaque: ptr::null_mut(),
+        }
+    }
+}
+
+/// Allocate memory for `len` values of type `T` with `allocator`.
+///
+/// Returns `MZError::Mem` if the allocation function returns null or
+/// insufficiently aligned memory.
+fn stream_alloc<T>(allocator: StreamAllocator, len: usize) -> Result<*mut T, MZError> {
+    let ptr = allocator.alloc(mem::size_of::<T>() * len) as *mut T;
+    if ptr.is_null() {
+        return Err(MZError::Mem);
+    }
+    if ptr as usize & (mem::align_of::<T>() - 1) != 0 {
+        allocator.free(ptr as *mut c_void);
+        return Err(MZError::Mem);
+    }
+    Ok(ptr)
+}
+
+/// Types for which memory with all bytes set to zero holds a valid value.
+///
+/// # Safety
+/// Only implement this for types that are documented to allow it.
+pub unsafe trait Zeroable {}
+
+// Both are documented to be valid when zeroed.
+unsafe impl Zeroable for CompressorBuffers {}
+unsafe impl Zeroable for InflateState {}
+
+/// Owning pointer to a value allocated with the allocation functions of a stream,
+/// used in place of `Box` for the internal state.
+pub struct StreamBox<T: ?Sized> {
+    ptr: NonNull<T>,
+    allocator: StreamAllocator,
+}
+
+impl<T: Copy + Default> StreamBox<[T]> {
+    /// Allocate a slice of `len` default values with `allocator`.
+    pub fn new_slice(allocator: StreamAllocator, len: usize) -> Result<StreamBox<[T]>, MZError> {
+        let ptr = stream_alloc::<T>(allocator, len)?;
+        unsafe {
+            for i in 0..len {
+                ptr::write(ptr.add(i), T::default());
+            }
+            Ok(StreamBox {
+                ptr: NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(ptr, len)),
+                allocator,
+            })
+        }
+    }
+}
+
+impl<T: Zeroable> StreamBox<T> {
+    /// Allocate a value with `allocator` and set all of its bytes to zero.
+    ///
+    /// This initialises large values in place, where `new` might build them on the stack.
+    pub fn new_zeroed(allocator: StreamAllocator) -> Result<StreamBox<T>, MZError> {
+        let ptr = stream_alloc::<T>(allocator, 1)?;
+        unsafe {
+            ptr::write_bytes(ptr, 0, 1);
+            Ok(StreamBox {
+                ptr: NonNull::new_unchecked(ptr),
+                allocator,
+            })
+        }
+    }
+}
+
+impl<T> StreamBox<T> {
+    /// Move `value` into memory allocated with `allocator`.
+    pub fn new(allocator: StreamAllocator, value: T) -> Result<StreamBox<T>, MZError> {
+        let ptr = stream_alloc::<T>(allocator, 1)?;
+        unsafe {
+            ptr::write(ptr, value);
+            Ok(StreamBox {
+                ptr: NonNull::new_unchecked(ptr),
+                allocator,
+            })
+        }
+    }
+
+    /// Give up ownership of the value, returning the raw pointer.
+    pub fn into_raw(self) -> *mut T {
+        let ptr = self.ptr.as_ptr();
+        mem::forget(self);
+        ptr
+    }
+
+    /// Take ownership of a pointer previously returned by `into_raw`.
+    ///
+    /// Returns `None` if `ptr` is null.
+    ///
+    /// # Safety
+    /// `ptr` has to be null or returned by `into_raw` of a `StreamBox` using `allocator`.
+    pub unsafe fn from_raw(ptr: *mut T, allocator: StreamAllocator) -> Option<StreamBox<T>> {
+        NonNull::new(ptr).map(|ptr| StreamBox { ptr, allocator })
+    }
+}
+
+impl<T: ?Sized> Deref for StreamBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for StreamBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T: ?Sized> Drop for StreamBox<T> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.ptr.as_ptr()) };
+        self.allocator
+            .free(self.ptr.as_ptr() as *mut u8 as *mut c_void);
+    }
+}
+
+// The allocation is owned like with a `Box`, and the allocation functions can be called from
+// any thread as long as the stream is only used by one at a time, as in zlib.
+unsafe impl<T: ?Sized + Send> Send for StreamBox<T> {}
+unsafe impl<T: ?Sized + Sync> Sync for StreamBox<T> {}
+
+/// Create a compressor owning buffers allocated with the allocation functions of a stream.
+fn stream_compressor(
+    allocator: StreamAllocator,
+    flags: u32,
+    sizes: BufferSizes,
+) -> Result<CompressorOxide<'static>, MZError> {
+    CompressorOxide::with_owned_buffers(
+        flags,
+        StreamBox::<CompressorBuffers>::new_zeroed(allocator)?,
+        StreamBox::<[u8]>::new_slice(allocator, sizes.dict)?,
+        StreamBox::<[u16]>::new_slice(allocator, sizes.next)?,
+        StreamBox::<[u16]>::new_slice(allocator, sizes.hash_table)?,
+        StreamBox::<[u8]>::new_slice(allocator, sizes.lz_codes)?,
+        StreamBox::<[u8]>::new_slice(allocator, sizes.out_buf)?,
+    )
+}
+
 #[repr(C)]
 pub enum InternalState {
-    Inflate(Box<InflateState>),
-    Deflate(Box<Compressor>),
+    Inflate(StreamBox<InflateState>),
+    Deflate(StreamBox<Compressor>),
 }
 
 impl fmt::Debug for InternalState {
@@ -48,14 +228,14 @@ pub enum StateTypeEnum {
 /// Trait used for states that can be carried by BoxedState.
 pub trait StateType {
     const STATE_TYPE: StateTypeEnum;
-    fn from_enum(&mut InternalState) -> Option<&mut Self>;
+    fn from_enum(value: &mut InternalState) -> Option<&mut Self>;
 }
 
 impl StateType for InflateState {
     const STATE_TYPE: StateTypeEnum = StateTypeEnum::Inflate;
     fn from_enum(value: &mut InternalState) -> Option<&mut Self> {
         if let InternalState::Inflate(state) = value {
-            Some(state.as_mut())
+            Some(&mut **state)
         } else {
             None
         }
@@ -66,7 +246,7 @@ impl StateType for Compressor {
     const STATE_TYPE: StateTypeEnum = StateTypeEnum::Deflate;
     fn from_enum(value: &mut InternalState) -> Option<&mut Self> {
         if let InternalState::Deflate(state) = value {
-            Some(state.as_mut())
+            Some(&mut **state)
         } else {
             None
         }
@@ -81,18 +261,49 @@ pub struct StreamOxide<'io, ST: StateType> {
     pub next_out: Option<&'io mut [u8]>,
     pub total_out: c_ulong,
 
-    pub(crate) state: Option<Box<InternalState>>,
+    pub(crate) state: Option<StreamBox<InternalState>>,
+    pub(crate) allocator: StreamAllocator,
 
     pub adler: u32,
+    /// Description of the last error, or null. Only ever written, as the caller may not
+    /// have initialized it.
+    pub msg: *const c_char,
     pub(crate) state_type: std::marker::PhantomData<ST>,
 }
 
 impl<'io, ST: StateType> StreamOxide<'io, ST> {
     pub fn state(&mut self) -> Option<&mut ST> {
-        StateType::from_enum(self.state.as_mut()?.as_mut())
+        StateType::from_enum(&mut **self.state.as_mut()?)
     }
 }
 
+/// Turn a NUL-terminated byte string literal into a `CStr`.
+fn c_str(bytes: &'static [u8]) -> &'static CStr {
+    CStr::from_bytes_with_nul(bytes).expect("message is not NUL-terminated")
+}
+
+/// Describe why decompression failed, using the same messages as zlib where possible.
+///
+/// Returns null if there is no error to describe.
+fn inflate_error_msg(state: &mut InflateState) -> *const c_char {
+    let msg: &'static [u8] = match state.decompressor().failure_reason() {
+        Some(FailureReason::BadZlibHeader) => b"incorrect header check\0",
+        Some(FailureReason::BlockTypeUnexpected) => b"invalid block type\0",
+        Some(FailureReason::BadRawLength) => b"invalid stored block lengths\0",
+        Some(FailureReason::BadTotalSymbols) => b"invalid code lengths set\0",
+        Some(FailureReason::BadCodeSizeDistPrevLookup) | Some(FailureReason::BadCodeSizeSum) => {
+            b"invalid bit length repeat\0"
+        }
+        Some(FailureReason::InvalidLitlen) => b"invalid literal/length code\0",
+        Some(FailureReason::InvalidDist) => b"invalid distance code\0",
+        Some(FailureReason::InvalidCodeLen) => b"invalid code\0",
+        Some(FailureReason::DistanceOutOfBounds) => b"invalid distance too far back\0",
+        None if state.last_status() == TINFLStatus::Adler32Mismatch => b"incorrect data check\0",
+        None => return ptr::null(),
+    };
+    c_str(msg).as_ptr()
+}
+
 /// Returns true if the window_bits parameter is valid.
 fn invalid_window_bits(window_bits: i32) -> bool {
     (window_bits != MZ_DEFAULT_WINDOW_BITS) && (-window_bits != MZ_DEFAULT_WINDOW_BITS)
@@ -145,7 +356,10 @@ pub fn mz_deflate_init_oxide(stream_oxide: &mut StreamOxide<Compressor>, level:
 ///              Only `MZ_DEFAULT_WINDOW_BITS` is currently supported.
 ///              A negative value, i.e `-MZ_DEFAULT_WINDOW_BITS` indicates that the stream
 ///              should be not be wrapped in a zlib wrapper.
-/// mem_level: Currently unused. Only values from 1 to and including 9 are accepted.
+/// mem_level: Memory level (1-9), controlling the size of the hash table and blocks, and
+///            at levels 1 to 3 the window.
+///            Lower levels use less memory, at the cost of compression ratio.
+///            See `deflate::core::BufferSizes::for_mem_level`.
 /// strategy: Compression strategy. See `deflate::CompressionStrategy` for accepted options.
 ///           The default, which is used in most cases, is 0.
 pub fn mz_deflate_init2_oxide(
@@ -159,7 +373,7 @@ pub fn mz_deflate_init2_oxide(
     let comp_flags = deflate_flags::TDEFL_COMPUTE_ADLER32
         | create_comp_flags_from_zip_params(level, window_bits, strategy);
 
-    let invalid_level = (mem_level < 1) || (mem_level > 9);
+    let invalid_level = !(1..=9).contains(&mem_level);
     if (method != MZ_DEFLATED) || invalid_level || invalid_window_bits(window_bits) {
         return Err(MZError::Param);
     }
@@ -167,10 +381,22 @@ pub fn mz_deflate_init2_oxide(
     stream_oxide.adler = MZ_ADLER32_INIT;
     stream_oxide.total_in = 0;
     stream_oxide.total_out = 0;
-
-    let mut compr: Box<Compressor> = Box::default();
-    compr.inner = Some(CompressorOxide::new(comp_flags));
-    stream_oxide.state = Some(Box::new(InternalState::Deflate(compr)));
+    stream_oxide.msg = ptr::null();
+
+    let allocator = stream_oxide.allocator;
+    let inner = stream_compressor(
+        allocator,
+        comp_flags,
+        BufferSizes::for_mem_level(mem_level as u8),
+    )?;
+    let compr = StreamBox::new(
+        allocator,
+        Compressor {
+            inner: Some(inner),
+            callback: None,
+        },
+    )?;
+    stream_oxide.state = Some(StreamBox::new(allocator, InternalState::Deflate(compr))?);
 
     Ok(MZStatus::Ok)
 }
@@ -193,7 +419,7 @@ pub fn mz_deflate_oxide(stream_oxide: &mut StreamOxide<Compressor>, flush: i32)
     };
 
     *next_in = &next_in[ret.bytes_consumed as usize..];
-    *next_out = &mut mem::replace(next_out, &mut [])[ret.bytes_written as usize..];
+    *next_out = &mut mem::take(next_out)[ret.bytes_written as usize..];
     // Wrapping add to emulate miniz_behaviour, will wrap around >4 GiB on 32-bit.
     stream_oxide.total_in = stream_oxide
         .total_in
@@ -228,6 +454,94 @@ pub fn mz_deflate_reset_oxide(stream_oxide: &mut StreamOxide<Compressor>) -> MZR
     Ok(MZStatus::Ok)
 }
 
+/// Create a copy of the compression state, allocated with the allocation functions of the
+/// stream.
+///
+/// Returns `MZError::Stream` if the inner stream is missing, and `MZError::Mem` if the
+/// allocation fails.
+pub fn mz_deflate_copy_oxide(
+    stream_oxide: &mut StreamOxide<Compressor>,
+) -> Result<StreamBox<InternalState>, MZError> {
+    let allocator = stream_oxide.allocator;
+    let source = stream_oxide
+        .state()
+        .and_then(|state| state.inner.as_ref())
+        .ok_or(MZError::Stream)?;
+    let mut inner = stream_compressor(allocator, source.flags() as u32, source.buffer_sizes())?;
+    inner.clone_from(source);
+    let compr = StreamBox::new(
+        allocator,
+        Compressor {
+            inner: Some(inner),
+            callback: None,
+        },
+    )?;
+    StreamBox::new(allocator, InternalState::Deflate(compr))
+}
+
+/// Tune the match finding of the compressor, see `CompressorOxide::tune`.
+///
+/// Negative values are treated as 0. Changing the compression level resets them.
+///
+/// Returns `MZError::Stream` if the inner stream is missing, otherwise `MZStatus::Ok`.
+pub fn mz_deflate_tune_oxide(
+    stream_oxide: &mut StreamOxide<Compressor>,
+    good_length: i32,
+    max_lazy: i32,
+    nice_length: i32,
+    max_chain: i32,
+) -> MZResult {
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    let compressor = state.inner.as_mut().ok_or(MZError::Stream)?;
+    compressor.tune(
+        cmp::max(good_length, 0) as u32,
+        cmp::max(max_lazy, 0) as u32,
+        cmp::max(nice_length, 0) as u32,
+        cmp::max(max_chain, 0) as u32,
+    );
+    Ok(MZStatus::Ok)
+}
+
+/// Insert the lower `bits` bits of `value` into the compressed output.
+///
+/// Returns `MZError::Buf` if there is no space for the bits, and `MZError::Stream` if
+/// `bits` is negative, the inner stream is missing or the zlib header is not written yet.
+pub fn mz_deflate_prime_oxide(
+    stream_oxide: &mut StreamOxide<Compressor>,
+    bits: i32,
+    value: i32,
+) -> MZResult {
+    if bits < 0 {
+        return Err(MZError::Stream);
+    }
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    let compressor = state.inner.as_mut().ok_or(MZError::Stream)?;
+    match compressor.prime(bits as u32, value as u32) {
+        Err(MZError::Param) => Err(MZError::Stream),
+        res => res.map(|_| MZStatus::Ok),
+    }
+}
+
+/// Get the number of bytes and bits of compressed data that have not been output yet.
+pub fn mz_deflate_pending_oxide(
+    stream_oxide: &mut StreamOxide<Compressor>,
+) -> Result<(usize, u32), MZError> {
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    let compressor = state.inner.as_ref().ok_or(MZError::Stream)?;
+    Ok(compressor.pending_output())
+}
+
+/// Get an upper bound on the size of the output of compressing `source_len` bytes with the
+/// settings of the compressor.
+pub fn mz_deflate_bound_oxide(
+    stream_oxide: &mut StreamOxide<Compressor>,
+    source_len: usize,
+) -> Result<usize, MZError> {
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    let compressor = state.inner.as_ref().ok_or(MZError::Stream)?;
+    Ok(compressor.compress_bound(source_len))
+}
+
 pub fn mz_inflate_init_oxide(stream_oxide: &mut StreamOxide<InflateState>) -> MZResult {
     mz_inflate_init2_oxide(stream_oxide, MZ_DEFAULT_WINDOW_BITS)
 }
@@ -243,10 +557,17 @@ pub fn mz_inflate_init2_oxide(
     stream_oxide.adler = 0;
     stream_oxide.total_in = 0;
     stream_oxide.total_out = 0;
+    stream_oxide.msg = ptr::null();
 
-    stream_oxide.state = Some(Box::new(InternalState::Inflate(
-        InflateState::new_boxed_with_window_bits(window_bits),
-    )));
+    let data_format = if window_bits > 0 {
+        DataFormat::Zlib
+    } else {
+        DataFormat::Raw
+    };
+    let allocator = stream_oxide.allocator;
+    let mut decomp = StreamBox::<InflateState>::new_zeroed(allocator)?;
+    decomp.reset(data_format);
+    stream_oxide.state = Some(StreamBox::new(allocator, InternalState::Inflate(decomp))?);
 
     Ok(MZStatus::Ok)
 }
@@ -264,7 +585,7 @@ pub fn mz_inflate_oxide(stream_oxide: &mut StreamOxide<InflateState>, flush: i32
     let flush = MZFlush::new(flush)?;
     let ret = inflate(state, next_in, next_out, flush);
     *next_in = &next_in[ret.bytes_consumed as usize..];
-    *next_out = &mut mem::replace(next_out, &mut [])[ret.bytes_written as usize..];
+    *next_out = &mut mem::take(next_out)[ret.bytes_written as usize..];
     // Wrapping add to emulate miniz_behaviour, will wrap around >4 GiB on 32-bit.
     stream_oxide.total_in = stream_oxide
         .total_in
@@ -273,6 +594,9 @@ pub fn mz_inflate_oxide(stream_oxide: &mut StreamOxide<InflateState>, flush: i32
         .total_out
         .wrapping_add(ret.bytes_written as c_ulong);
     stream_oxide.adler = state.decompressor().adler32().unwrap_or(0);
+    if ret.status == Err(MZError::Data) {
+        stream_oxide.msg = inflate_error_msg(state);
+    }
     ret.into()
 }
 
@@ -284,9 +608,7 @@ pub fn mz_uncompress2_oxide(
     let status = mz_inflate_oxide(stream_oxide, MZFlush::Finish as i32);
     mz_inflate_end_oxide(stream_oxide)?;
 
-    let empty_in = stream_oxide
-        .next_in
-        .map_or(true, |next_in| next_in.is_empty());
+    let empty_in = stream_oxide.next_in.unwrap_or_default().is_empty();
     match (status, empty_in) {
         (Ok(MZStatus::StreamEnd), _) => {
             *dest_len = stream_oxide.total_out;
@@ -302,6 +624,168 @@ pub fn mz_inflate_end_oxide(stream_oxide: &mut StreamOxide<InflateState>) -> MZR
     Ok(MZStatus::Ok)
 }
 
+/// Create a copy of the decompression state, including the window of previous output,
+/// allocated with the allocation functions of the stream.
+///
+/// Returns `MZError::Stream` if the inner stream is missing, and `MZError::Mem` if the
+/// allocation fails.
+pub fn mz_inflate_copy_oxide(
+    stream_oxide: &mut StreamOxide<InflateState>,
+) -> Result<StreamBox<InternalState>, MZError> {
+    let allocator = stream_oxide.allocator;
+    let source = stream_oxide.state().ok_or(MZError::Stream)?;
+    let mut decomp = StreamBox::<InflateState>::new_zeroed(allocator)?;
+    decomp.clone_from(source);
+    StreamBox::new(allocator, InternalState::Inflate(decomp))
+}
+
+/// Reset the decompressor, so it can be used to decompress a new stream of the same format.
+///
+/// Returns `MZError::Stream` if the inner stream is missing, otherwise `MZStatus::Ok`.
+pub fn mz_inflate_reset_oxide(stream_oxide: &mut StreamOxide<InflateState>) -> MZResult {
+    let data_format = stream_oxide.state().ok_or(MZError::Stream)?.data_format();
+    let window_bits = match data_format {
+        DataFormat::Zlib => MZ_DEFAULT_WINDOW_BITS,
+        DataFormat::Raw => -MZ_DEFAULT_WINDOW_BITS,
+    };
+    mz_inflate_reset2_oxide(stream_oxide, window_bits)
+}
+
+/// Reset the decompressor, using `window_bits` to select the format of the next stream
+/// like `mz_inflate_init2_oxide`.
+///
+/// Returns `MZError::Param` if `window_bits` is invalid, and `MZError::Stream` if the inner
+/// stream is missing.
+pub fn mz_inflate_reset2_oxide(
+    stream_oxide: &mut StreamOxide<InflateState>,
+    window_bits: i32,
+) -> MZResult {
+    if invalid_window_bits(window_bits) {
+        return Err(MZError::Param);
+    }
+
+    let data_format = if window_bits > 0 {
+        DataFormat::Zlib
+    } else {
+        DataFormat::Raw
+    };
+    stream_oxide
+        .state()
+        .ok_or(MZError::Stream)?
+        .reset(data_format);
+    stream_oxide.adler = 0;
+    stream_oxide.total_in = 0;
+    stream_oxide.total_out = 0;
+    stream_oxide.msg = ptr::null();
+    Ok(MZStatus::Ok)
+}
+
+/// Insert the lower `bits` bits of `value` into the input, to be read before the
+/// remaining input.
+///
+/// Returns `MZError::Stream` if `bits` is negative or larger than 16, there is no space for
+/// the bits, or the inner stream is missing.
+pub fn mz_inflate_prime_oxide(
+    stream_oxide: &mut StreamOxide<InflateState>,
+    bits: i32,
+    value: i32,
+) -> MZResult {
+    if bits < 0 {
+        return Err(MZError::Stream);
+    }
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    state.decompressor().prime(bits as u32, value as u32)?;
+    Ok(MZStatus::Ok)
+}
+
+/// Get the position of the decompressor in the input, as returned by zlib's `inflateMark`.
+///
+/// The upper 16 bits are -1 outside of compressed data, in which case the lower 16 bits
+/// are the number of bytes left to copy from a stored block. Inside compressed data, the
+/// upper bits are the number of bits back from the current position in the input to the
+/// start of the current literal or match, and the lower 16 bits the number of bytes of it
+/// that have already been output.
+///
+/// The decompressor can be ahead of the output, with decompressed data waiting for space in
+/// the output buffer. The position is then still that of the decompressor, which matches the
+/// input consumed, and the bytes waiting count as output.
+pub fn mz_inflate_mark_oxide(
+    stream_oxide: &mut StreamOxide<InflateState>,
+) -> Result<c_long, MZError> {
+    let state = stream_oxide.state().ok_or(MZError::Stream)?;
+    Ok(match state.decompressor().block_position() {
+        BlockPosition::Outside | BlockPosition::Tables => -(1 << 16),
+        BlockPosition::Stored { remaining } => -(1 << 16) + c_long::from(remaining as u16),
+        BlockPosition::Compressed { code_bits, written } => {
+            (c_long::from(code_bits) << 16) + c_long::from(written)
+        }
+    })
+}
+
+/// Decompress a whole stream, reading input with `in_fn` and writing output with `out_fn`.
+///
+/// Input is taken from `next_in` first, `in_fn` is called for more once that is used up and
+/// returns an empty slice at the end of the input. The output is passed to `out_fn` in chunks
+/// of up to 32 KiB, it returns `true` to signal an error and stop decompressing.
+/// `next_in` is set to the unused input afterwards.
+///
+/// Returns `MZStatus::StreamEnd` once the stream is decompressed, `MZError::Buf` if the
+/// input ended early or `out_fn` failed, and `MZError::Data` if the data is corrupt.
+pub fn mz_inflate_back_oxide<'io, I, O>(
+    stream_oxide: &mut StreamOxide<'io, InflateState>,
+    mut in_fn: I,
+    mut out_fn: O,
+) -> MZResult
+where
+    I: FnMut() -> &'io [u8],
+    O: FnMut(&[u8]) -> bool,
+{
+    let state: &mut InflateState = {
+        let enum_ref = stream_oxide.state.as_mut().ok_or(MZError::Stream)?;
+        StateType::from_enum(enum_ref)
+    }
+    .ok_or(MZError::Stream)?;
+
+    let mut next_in = stream_oxide.next_in.unwrap_or_default();
+    let mut window = [0; TINFL_LZ_DICT_SIZE];
+    let status = loop {
+        let ret = inflate(state, next_in, &mut window, MZFlush::None);
+        next_in = &next_in[ret.bytes_consumed..];
+        stream_oxide.total_in = stream_oxide
+            .total_in
+            .wrapping_add(ret.bytes_consumed as c_ulong);
+        stream_oxide.total_out = stream_oxide
+            .total_out
+            .wrapping_add(ret.bytes_written as c_ulong);
+        if ret.bytes_written > 0 && out_fn(&window[..ret.bytes_written]) {
+            break Err(MZError::Buf);
+        }
+
+        match ret.status {
+            Ok(MZStatus::StreamEnd) => break Ok(MZStatus::StreamEnd),
+            Ok(_) => (),
+            // The decompressor needs more input.
+            Err(MZError::Buf) => {
+                next_in = in_fn();
+                if next_in.is_empty() {
+                    break Err(MZError::Buf);
+                }
+            }
+            Err(MZError::Data) => {
+                stream_oxide.msg = inflate_error_msg(state);
+                break Err(MZError::Data);
+            }
+            Err(e) => break Err(e),
+        }
+    };
+    stream_oxide.next_in = if next_in.is_empty() {
+        None
+    } else {
+        Some(next_in)
+    };
+    status
+}
+
 /*
 #[test]
 fn roundtrip_oxide() {
@@ -0,0 +1,322 @@
+//! Simple in-memory PNG writer, replicating `tdefl_write_image_to_png_file_in_memory` from miniz.
+
+use std::{cmp, ptr, slice};
+
+use libc::{c_int, c_uint, c_void, size_t};
+
+use c_export::{miniz_def_alloc_func, mz_crc32_oxide};
+use miniz_oxide::deflate::core::{
+    compress_to_output, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush, TDEFLStatus,
+};
+use miniz_oxide::filter::RowFilter;
+use miniz_oxide::MZError;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// PNG colour type to use for images with the given number of channels.
+const COLOR_TYPES: [u8; 5] = [0x00, 0x00, 0x04, 0x02, 0x06];
+
+/// Largest width, height or chunk length allowed by the PNG specification.
+const PNG_MAX_SIZE: usize = 0x7FFF_FFFF;
+
+/// Filter to apply to the rows of an image before compressing them.
+///
+/// `PngFilter::None` stores the rows unfiltered like miniz does, the other filters let
+/// gradients and photographic images compress a lot better, at some cost in speed.
+pub use miniz_oxide::filter::Filter as PngFilter;
+
+/// Append a PNG chunk with the given type and data to `out`.
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = mz_crc32_oxide(0, &out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Compress an 8-bit image to a PNG file in memory.
+///
+/// `image` holds `height` rows of `width` pixels with `num_chans` bytes each, stored
+/// top to bottom, or bottom to top if `flip` is set. 1 to 4 channels are supported,
+/// written as greyscale, greyscale with alpha, RGB and RGBA respectively.
+/// `level` is the compression level, clamped to 10.
+///
+/// # Errors
+/// Returns `MZError::Param` if the dimensions or number of channels are invalid, or if `image`
+/// is too short, and `MZError::Buf` if the compressed image doesn't fit in a single PNG chunk.
+pub fn write_image_to_png_in_memory(
+    image: &[u8],
+    width: u32,
+    height: u32,
+    num_chans: u32,
+    level: u8,
+    flip: bool,
+    filter: PngFilter,
+) -> Result<Vec<u8>, MZError> {
+    let (w, h, bpp) = (width as usize, height as usize, num_chans as usize);
+    if w == 0 || h == 0 || w > PNG_MAX_SIZE || h > PNG_MAX_SIZE || bpp == 0 || bpp > 4 {
+        return Err(MZError::Param);
+    }
+    let bpl = w.checked_mul(bpp).ok_or(MZError::Param)?;
+    match bpl.checked_mul(h) {
+        Some(len) if len <= image.len() => (),
+        _ => return Err(MZError::Param),
+    }
+
+    let flags = create_comp_flags_from_zip_params(cmp::min(level, 10).into(), 1, 0);
+    let mut compressor = CompressorOxide::new(flags);
+
+    let mut out = Vec::with_capacity(57 + cmp::max(64, (1 + bpl).saturating_mul(h)));
+    out.extend_from_slice(&PNG_SIGNATURE);
+
+    let mut ihdr = [0; 13];
+    ihdr[..4].copy_from_slice(&width.to_be_bytes());
+    ihdr[4..8].copy_from_slice(&height.to_be_bytes());
+    // Bit depth, the compression, filter and interlace methods are left at 0.
+    ihdr[8] = 8;
+    ihdr[9] = COLOR_TYPES[bpp];
+    write_chunk(&mut out, b"IHDR", &ihdr);
+
+    // Compress straight into the IDAT chunk and fill in the length afterwards.
+    let idat_start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(b"IDAT");
+
+    let mut row_filter = RowFilter::new(filter, bpp, bpl);
+    let mut status = TDEFLStatus::Okay;
+    for y in 0..h {
+        let row_index = if flip { h - 1 - y } else { y };
+        let row = &image[row_index * bpl..(row_index + 1) * bpl];
+        row_filter.push(row, |filtered| {
+            if status == TDEFLStatus::Okay {
+                status = compress_to_output(&mut compressor, filtered, TDEFLFlush::None, |data| {
+                    out.extend_from_slice(data);
+                    true
+                })
+                .0;
+            }
+        });
+    }
+    if status != TDEFLStatus::Okay {
+        return Err(MZError::Stream);
+    }
+
+    let (status, _) = compress_to_output(&mut compressor, &[], TDEFLFlush::Finish, |data| {
+        out.extend_from_slice(data);
+        true
+    });
+    if status != TDEFLStatus::Done {
+        return Err(MZError::Stream);
+    }
+
+    let idat_len = out.len() - idat_start - 8;
+    if idat_len > PNG_MAX_SIZE {
+        return Err(MZError::Buf);
+    }
+    out[idat_start..idat_start + 4].copy_from_slice(&(idat_len as u32).to_be_bytes());
+    let crc = mz_crc32_oxide(0, &out[idat_start + 4..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+
+    write_chunk(&mut out, b"IEND", &[]);
+
+    Ok(out)
+}
+
+unmangle!(
+    /// Compress an image to a PNG file in memory.
+    ///
+    /// Sets `p_len_out` to the size of the PNG file.
+    /// Returns `ptr::null()` if the parameters are invalid or compression or allocation fails.
+    /// The buffer should be freed with `miniz_def_free_func`.
+    ///
+    /// # Safety
+    /// `p_image` has to be null or valid for reads of `w * h * num_chans` bytes, and `p_len_out`
+    /// null or valid for writes.
+    pub unsafe extern "C" fn tdefl_write_image_to_png_file_in_memory_ex(
+        p_image: *const c_void,
+        w: c_int,
+        h: c_int,
+        num_chans: c_int,
+        p_len_out: *mut size_t,
+        level: c_uint,
+        flip: c_int,
+    ) -> *mut c_void {
+        let len_out = match p_len_out.as_mut() {
+            Some(len_out) => len_out,
+            None => return ptr::null_mut(),
+        };
+        *len_out = 0;
+
+        if p_image.is_null() || w <= 0 || h <= 0 || num_chans <= 0 || num_chans > 4 {
+            return ptr::null_mut();
+        }
+        let image_len = match (w as usize)
+            .checked_mul(h as usize)
+            .and_then(|n| n.checked_mul(num_chans as usize))
+        {
+            Some(len) => len,
+            None => return ptr::null_mut(),
+        };
+
+        let png = match write_image_to_png_in_memory(
+            slice::from_raw_parts(p_image as *const u8, image_len),
+            w as u32,
+            h as u32,
+            num_chans as u32,
+            cmp::min(level, 10) as u8,
+            flip != 0,
+            PngFilter::None,
+        ) {
+            Ok(png) => png,
+            Err(_) => return ptr::null_mut(),
+        };
+
+        let p_buf = miniz_def_alloc_func(ptr::null_mut(), png.len(), 1);
+        if !p_buf.is_null() {
+            ptr::copy_nonoverlapping(png.as_ptr(), p_buf as *mut u8, png.len());
+            *len_out = png.len();
+        }
+        p_buf
+    }
+
+    /// Compress an image to a PNG file in memory, using compression level 6.
+    ///
+    /// See `tdefl_write_image_to_png_file_in_memory_ex`.
+    ///
+    /// # Safety
+    /// `p_image` has to be null or valid for reads of `w * h * num_chans` bytes, and `p_len_out`
+    /// null or valid for writes.
+    pub unsafe extern "C" fn tdefl_write_image_to_png_file_in_memory(
+        p_image: *const c_void,
+        w: c_int,
+        h: c_int,
+        num_chans: c_int,
+        p_len_out: *mut size_t,
+    ) -> *mut c_void {
+        tdefl_write_image_to_png_file_in_memory_ex(p_image, w, h, num_chans, p_len_out, 6, 0)
+    }
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use miniz_oxide::filter::FilterType;
+    use miniz_oxide::inflate::decompress_to_vec_zlib;
+
+    /// Split a PNG file into its chunks, checking the signature and chunk CRCs.
+    fn parse_chunks(png: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let crc = &rest[8 + len..12 + len];
+            assert_eq!(crc, &mz_crc32_oxide(0, &rest[4..8 + len]).to_be_bytes()[..]);
+            chunks.push(([rest[4], rest[5], rest[6], rest[7]], &rest[8..8 + len]));
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+
+    fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+        let p = i16::from(a) + i16::from(b) - i16::from(c);
+        let pa = (p - i16::from(a)).abs();
+        let pb = (p - i16::from(b)).abs();
+        let pc = (p - i16::from(c)).abs();
+        if pa <= pb && pa <= pc {
+            a
+        } else if pb <= pc {
+            b
+        } else {
+            c
+        }
+    }
+
+    /// Reference unfilter following the PNG specification, independent of the filter module.
+    fn unfilter(bpp: usize, bpl: usize, data: &[u8]) -> Vec<u8> {
+        let mut image: Vec<u8> = Vec::new();
+        for (y, filtered) in data.chunks(bpl + 1).enumerate() {
+            let start = image.len();
+            for i in 0..bpl {
+                let left = if i >= bpp { image[start + i - bpp] } else { 0 };
+                let up = if y > 0 { image[start + i - bpl] } else { 0 };
+                let up_left = if y > 0 && i >= bpp {
+                    image[start + i - bpl - bpp]
+                } else {
+                    0
+                };
+                let predicted = match filtered[0] {
+                    0 => 0,
+                    1 => left,
+                    2 => up,
+                    3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+                    4 => paeth_predictor(left, up, up_left),
+                    f => panic!("invalid filter type {}", f),
+                };
+                image.push(filtered[1 + i].wrapping_add(predicted));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let (w, h) = (37, 23);
+        for &(num_chans, color_type) in &[(1, 0), (2, 4), (3, 2), (4, 6)] {
+            let bpl = w * num_chans;
+            let image: Vec<u8> = (0..bpl * h)
+                .map(|i| ((i % bpl) * 3 + (i / bpl) * 7 + i % num_chans) as u8)
+                .collect();
+            let mut flipped = Vec::new();
+            for row in image.chunks(bpl).rev() {
+                flipped.extend_from_slice(row);
+            }
+
+            let filters = FilterType::ALL.iter().map(|&t| PngFilter::from(t));
+            for filter in filters.chain(Some(PngFilter::Adaptive)) {
+                for &flip in &[false, true] {
+                    let source = if flip { &flipped } else { &image };
+                    let png = write_image_to_png_in_memory(
+                        source,
+                        w as u32,
+                        h as u32,
+                        num_chans as u32,
+                        6,
+                        flip,
+                        filter,
+                    )
+                    .unwrap();
+
+                    let chunks = parse_chunks(&png);
+                    assert_eq!(chunks.len(), 3);
+                    assert_eq!(&chunks[0].0, b"IHDR");
+                    assert_eq!(
+                        chunks[0].1,
+                        &[0, 0, 0, w as u8, 0, 0, 0, h as u8, 8, color_type, 0, 0, 0]
+                    );
+                    assert_eq!(&chunks[1].0, b"IDAT");
+                    assert_eq!(&chunks[2].0, b"IEND");
+
+                    let data = decompress_to_vec_zlib(chunks[1].1).unwrap();
+                    assert_eq!(data.len(), (bpl + 1) * h);
+                    if let Some(filter_type) = filter.filter_type() {
+                        assert!(data.chunks(bpl + 1).all(|row| row[0] == filter_type as u8));
+                    }
+                    assert_eq!(unfilter(num_chans, bpl, &data), image);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn png_invalid_params() {
+        let image = [0; 12];
+        for &(w, h, chans) in &[(0, 1, 1), (1, 0, 1), (2, 2, 0), (2, 2, 5), (4, 4, 1)] {
+            assert_eq!(
+                write_image_to_png_in_memory(&image, w, h, chans, 6, false, PngFilter::None),
+                Err(MZError::Param)
+            );
+        }
+    }
+}
@@ -19,20 +19,12 @@ pub struct CallbackFunc {
 
 /// Main compression struct. Not the same as `CompressorOxide`
 #[repr(C)]
+#[derive(Default)]
 pub struct Compressor {
-    pub(crate) inner: Option<CompressorOxide>,
+    pub(crate) inner: Option<CompressorOxide<'static>>,
     pub(crate) callback: Option<CallbackFunc>,
 }
 
-impl Default for Compressor {
-    fn default() -> Self {
-        Compressor {
-            inner: None,
-            callback: None,
-        }
-    }
-}
-
 #[repr(C)]
 #[allow(bad_style)]
 #[derive(PartialEq, Eq)]
@@ -108,6 +100,10 @@ impl Compressor {
 }
 
 unmangle!(
+    /// # Safety
+    /// `d` has to be null or point to a compressor from `tdefl_allocate`. `in_size` and
+    /// `out_size` have to be null or valid, and `in_buf` and `out_buf` null or valid for
+    /// `*in_size` and `*out_size` bytes.
     pub unsafe extern "C" fn tdefl_compress(
         d: Option<&mut Compressor>,
         in_buf: *const c_void,
@@ -119,8 +115,12 @@ unmangle!(
         let flush = i32_to_tdefl_flush(flush);
         match d {
             None => {
-                in_size.map(|size| *size = 0);
-                out_size.map(|size| *size = 0);
+                if let Some(size) = in_size {
+                    *size = 0;
+                }
+                if let Some(size) = out_size {
+                    *size = 0;
+                }
                 tdefl_status::TDEFL_STATUS_BAD_PARAM
             }
             Some(compressor_wrap) => {
@@ -129,8 +129,12 @@ unmangle!(
                     let out_buf_size = out_size.as_ref().map_or(0, |size| **size);
 
                     if in_buf_size > 0 && in_buf.is_null() {
-                        in_size.map(|size| *size = 0);
-                        out_size.map(|size| *size = 0);
+                        if let Some(size) = in_size {
+                            *size = 0;
+                        }
+                        if let Some(size) = out_size {
+                            *size = 0;
+                        }
                         return tdefl_status::TDEFL_STATUS_BAD_PARAM;
                     }
 
@@ -147,15 +151,23 @@ unmangle!(
                                 flush,
                             ),
                             None => {
-                                in_size.map(|size| *size = 0);
-                                out_size.map(|size| *size = 0);
+                                if let Some(size) = in_size {
+                                    *size = 0;
+                                }
+                                if let Some(size) = out_size {
+                                    *size = 0;
+                                }
                                 return tdefl_status::TDEFL_STATUS_BAD_PARAM;
                             }
                         },
                         Some(ref func) => {
                             if out_buf_size > 0 || !out_buf.is_null() {
-                                in_size.map(|size| *size = 0);
-                                out_size.map(|size| *size = 0);
+                                if let Some(size) = in_size {
+                                    *size = 0;
+                                }
+                                if let Some(size) = out_size {
+                                    *size = 0;
+                                }
                                 return tdefl_status::TDEFL_STATUS_BAD_PARAM;
                             }
                             let res =
@@ -170,8 +182,12 @@ unmangle!(
                         }
                     };
 
-                    in_size.map(|size| *size = res.1);
-                    out_size.map(|size| *size = res.2);
+                    if let Some(size) = in_size {
+                        *size = res.1;
+                    }
+                    if let Some(size) = out_size {
+                        *size = res.2;
+                    }
                     res.0.into()
                 } else {
                     tdefl_status::TDEFL_STATUS_BAD_PARAM
@@ -180,6 +196,9 @@ unmangle!(
         }
     }
 
+    /// # Safety
+    /// `d` has to be null or point to a compressor from `tdefl_allocate`.
+    /// `in_buf` has to be null or valid for reads of `in_size` bytes.
     pub unsafe extern "C" fn tdefl_compress_buffer(
         d: Option<&mut Compressor>,
         in_buf: *const c_void,
@@ -193,6 +212,9 @@ unmangle!(
     ///
     /// This does initialize the struct, but not the inner constructor,
     /// tdefl_init has to be called before doing anything with it.
+    ///
+    /// # Safety
+    /// The compressor has to be freed with `tdefl_deallocate`.
     pub unsafe extern "C" fn tdefl_allocate() -> *mut Compressor {
         Box::into_raw(Box::<Compressor>::new(Compressor {
             inner: None,
@@ -204,9 +226,12 @@ unmangle!(
     ///
     /// This also calles the compressor's destructor, freeing the internal memory
     /// allocated by it.
+    ///
+    /// # Safety
+    /// `c` has to be null or returned by `tdefl_allocate`, and not freed already.
     pub unsafe extern "C" fn tdefl_deallocate(c: *mut Compressor) {
         if !c.is_null() {
-            Box::from_raw(c);
+            drop(Box::from_raw(c));
         }
     }
 
@@ -216,6 +241,10 @@ unmangle!(
     /// Deinitialization is handled by tdefl_deallocate, and thus
     /// Compressor should not be allocated or freed manually, but only through
     /// tdefl_allocate and tdefl_deallocate
+    ///
+    /// # Safety
+    /// `d` has to be null or point to a compressor from `tdefl_allocate`.
+    /// `put_buf_func`, if set, has to be safe to call with `put_buf_user`.
     pub unsafe extern "C" fn tdefl_init(
         d: Option<&mut Compressor>,
         put_buf_func: PutBufFuncPtr,
@@ -228,7 +257,7 @@ unmangle!(
                 if let Some(f) = put_buf_func {
                     d.callback = Some(CallbackFunc {
                         put_buf_func: f,
-                        put_buf_user: put_buf_user,
+                        put_buf_user,
                     })
                 } else {
                     d.callback = None;
@@ -245,6 +274,8 @@ unmangle!(
         }
     }
 
+    /// # Safety
+    /// `d` has to be null or point to a compressor from `tdefl_allocate`.
     pub unsafe extern "C" fn tdefl_get_prev_return_status(
         d: Option<&mut Compressor>,
     ) -> tdefl_status {
@@ -253,10 +284,15 @@ unmangle!(
         })
     }
 
+    /// # Safety
+    /// `d` has to be null or point to a compressor from `tdefl_allocate`.
     pub unsafe extern "C" fn tdefl_get_adler32(d: Option<&mut Compressor>) -> c_uint {
-        d.map_or(::MZ_ADLER32_INIT as u32, |d| d.adler32())
+        d.map_or(::MZ_ADLER32_INIT, |d| d.adler32())
     }
 
+    /// # Safety
+    /// `buf` has to be valid for reads of `buf_len` bytes, and `put_buf_func` safe to call with
+    /// `put_buf_user`.
     pub unsafe extern "C" fn tdefl_compress_mem_to_output(
         buf: *const c_void,
         buf_len: usize,
@@ -274,8 +310,8 @@ unmangle!(
                 Compressor::new_with_callback(
                     flags as u32,
                     CallbackFunc {
-                        put_buf_func: put_buf_func,
-                        put_buf_user: put_buf_user,
+                        put_buf_func,
+                        put_buf_user,
                     },
                 ),
             );
@@ -283,7 +319,9 @@ unmangle!(
             let res =
                 tdefl_compress_buffer(compressor.as_mut(), buf, buf_len, TDEFLFlush::Finish as i32)
                     == tdefl_status::TDEFL_STATUS_DONE;
-            compressor.as_mut().map(|c| c.drop_inner());
+            if let Some(c) = compressor.as_mut() {
+                c.drop_inner();
+            }
             ::miniz_def_free_func(ptr::null_mut(), compressor as *mut c_void);
             res
         } else {
@@ -333,11 +371,7 @@ pub unsafe extern "C" fn output_buffer_putter(
                 user.capacity = new_capacity;
             }
 
-            ptr::copy_nonoverlapping(
-                buf as *const u8,
-                user.buf.offset(user.size as isize),
-                len as usize,
-            );
+            ptr::copy_nonoverlapping(buf as *const u8, user.buf.add(user.size), len as usize);
             user.size = new_size;
             true
         }
@@ -345,6 +379,9 @@ pub unsafe extern "C" fn output_buffer_putter(
 }
 
 unmangle!(
+    /// # Safety
+    /// `src_buf` has to be valid for reads of `src_buf_len` bytes, and `out_len` null or valid for
+    /// writes.
     pub unsafe extern "C" fn tdefl_compress_mem_to_heap(
         src_buf: *const c_void,
         src_buf_len: usize,
@@ -379,6 +416,9 @@ unmangle!(
         }
     }
 
+    /// # Safety
+    /// `out_buf` has to be null or valid for writes of `out_buf_len` bytes, and `src_buf` valid
+    /// for reads of `src_buf_len` bytes.
     pub unsafe extern "C" fn tdefl_compress_mem_to_mem(
         out_buf: *mut c_void,
         out_buf_len: usize,
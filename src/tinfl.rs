@@ -3,13 +3,22 @@
 use libc::*;
 use miniz_oxide::inflate::core::DecompressorOxide;
 pub use miniz_oxide::inflate::core::DecompressorOxide as tinfl_decompressor;
-pub use miniz_oxide::inflate::core::{decompress, inflate_flags};
+pub use miniz_oxide::inflate::core::{decompress, decompress_to_output, inflate_flags};
 use miniz_oxide::inflate::TINFLStatus;
-use std::{ptr, slice, usize};
+use std::{ptr, slice};
 
 pub const TINFL_DECOMPRESS_MEM_TO_MEM_FAILED: size_t = usize::MAX;
 
+/// Decompression callback function type.
+#[allow(non_camel_case_types)]
+pub type tinfl_put_buf_func_ptr =
+    Option<unsafe extern "C" fn(*const c_void, c_int, *mut c_void) -> c_int>;
+
 unmangle!(
+    /// # Safety
+    /// `r` has to point to a decompressor, `in_buf_size` and `out_buf_size` have to be valid and
+    /// `in_buf` valid for reads of `*in_buf_size` bytes. `out_buf_next` has to point into the
+    /// buffer at `out_buf_start`, with `*out_buf_size` bytes from it valid for writes.
     pub unsafe extern "C" fn tinfl_decompress(
         r: *mut DecompressorOxide,
         in_buf: *const u8,
@@ -34,6 +43,9 @@ unmangle!(
         status as i32
     }
 
+    /// # Safety
+    /// `p_out_buf` has to be valid for writes of `out_buf_len` bytes, and `p_src_buf` for reads of
+    /// `src_buf_len` bytes.
     pub unsafe extern "C" fn tinfl_decompress_mem_to_mem(
         p_out_buf: *mut c_void,
         out_buf_len: size_t,
@@ -65,6 +77,10 @@ unmangle!(
     /// Sets `p_out_len` to the length of the returned buffer.
     /// Returns `ptr::null()` if decompression or allocation fails.
     /// The buffer should be freed with `miniz_def_free_func`.
+    ///
+    /// # Safety
+    /// `p_src_buf` has to be valid for reads of `src_buf_len` bytes, and `p_out_len` null or valid
+    /// for writes.
     pub unsafe extern "C" fn tinfl_decompress_mem_to_heap(
         p_src_buf: *const c_void,
         src_buf_len: size_t,
@@ -91,7 +107,7 @@ unmangle!(
             let (status, in_consumed, out_consumed) = decompress(
                 &mut decomp,
                 slice::from_raw_parts(
-                    p_src_buf.offset(src_buf_ofs as isize) as *const u8,
+                    (p_src_buf as *const u8).add(src_buf_ofs),
                     src_buf_len - src_buf_ofs,
                 ),
                 slice::from_raw_parts_mut(p_buf as *mut u8, out_buf_capacity),
@@ -139,6 +155,42 @@ unmangle!(
 
         p_buf
     }
+
+    /// Decompress data from `p_in_buf` through an internal 32KiB buffer, calling
+    /// `put_buf_func` with `p_put_buf_user` each time output is produced.
+    ///
+    /// Sets `p_in_buf_size` to the number of input bytes consumed.
+    /// Returns 1 on success, and 0 if decompression fails or `put_buf_func` returns 0.
+    ///
+    /// # Safety
+    /// `p_in_buf_size` has to be valid and `p_in_buf` valid for reads of `*p_in_buf_size` bytes.
+    /// `put_buf_func`, if set, has to be safe to call with `p_put_buf_user`.
+    pub unsafe extern "C" fn tinfl_decompress_mem_to_callback(
+        p_in_buf: *const c_void,
+        p_in_buf_size: *mut size_t,
+        put_buf_func: tinfl_put_buf_func_ptr,
+        p_put_buf_user: *mut c_void,
+        flags: c_int,
+    ) -> c_int {
+        let put_buf_func = match put_buf_func {
+            Some(f) => f,
+            None => return 0,
+        };
+        let (status, in_consumed) = decompress_to_output(
+            slice::from_raw_parts(p_in_buf as *const u8, *p_in_buf_size),
+            flags as u32,
+            |out| {
+                put_buf_func(
+                    out.as_ptr() as *const c_void,
+                    out.len() as c_int,
+                    p_put_buf_user,
+                ) != 0
+            },
+        );
+
+        *p_in_buf_size = in_consumed;
+        (status == TINFLStatus::Done) as c_int
+    }
 );
 
 #[cfg(test)]
@@ -245,4 +297,38 @@ mod test {
         let out_buf = tinfl_decompress_mem_to_heap_wrapper(&mut encoded[..], flags as i32).unwrap();
         assert_eq!(out_buf.as_slice(), &b"Hello, zlib!"[..]);
     }
+
+    unsafe extern "C" fn put_buf_to_vec(
+        buf: *const c_void,
+        len: c_int,
+        user: *mut c_void,
+    ) -> c_int {
+        let out = &mut *(user as *mut Vec<u8>);
+        out.extend_from_slice(slice::from_raw_parts(buf as *const u8, len as usize));
+        1
+    }
+
+    #[test]
+    fn mem_to_callback() {
+        let encoded: [u8; 22] = [
+            120, 156, 243, 72, 205, 201, 201, 215, 81, 168, 202, 201, 76, 82, 4, 0, 27, 101, 4, 19,
+            0, 0,
+        ];
+        let flags = TINFL_FLAG_COMPUTE_ADLER32 | TINFL_FLAG_PARSE_ZLIB_HEADER;
+        let mut out = Vec::<u8>::new();
+        let mut in_size = encoded.len();
+        let res = unsafe {
+            tinfl_decompress_mem_to_callback(
+                encoded.as_ptr() as *const c_void,
+                &mut in_size,
+                Some(put_buf_to_vec),
+                &mut out as *mut Vec<u8> as *mut c_void,
+                flags as c_int,
+            )
+        };
+        assert_eq!(res, 1);
+        // Trailing data after the end of the stream is not consumed.
+        assert_eq!(in_size, encoded.len() - 2);
+        assert_eq!(out, b"Hello, zlib!");
+    }
 }
@@ -0,0 +1,557 @@
+//! Exports using the names and stream layout of zlib, enabled with the `zlib_names` feature.
+//!
+//! This allows using the library in place of `libz.so.1`, e.g with `LD_PRELOAD`, for programs
+//! that only use the parts of zlib we support. gzip streams, dictionaries and the `gz*` file
+//! functions are not supported.
+
+use std::mem;
+
+use libc::{c_char, c_int, c_long, c_uint, c_ulong, c_void};
+
+use c_export::{
+    mz_adler32, mz_alloc_func, mz_crc32, mz_free_func, mz_gz_header, mz_in_func, mz_out_func,
+    mz_stream,
+};
+use lib_oxide::{InternalState, StateTypeEnum};
+use miniz_oxide::{MZError, MZFlush, MZStatus};
+use {
+    mz_compress, mz_compress2, mz_compressBound, mz_deflate, mz_deflateBound, mz_deflateCopy,
+    mz_deflateEnd, mz_deflateInit2, mz_deflatePending, mz_deflatePrime, mz_deflateReset,
+    mz_deflateSetHeader, mz_deflateTune, mz_inflate, mz_inflateBack, mz_inflateBackEnd,
+    mz_inflateBackInit, mz_inflateCopy, mz_inflateEnd, mz_inflateGetHeader, mz_inflateInit2,
+    mz_inflateMark, mz_inflatePrime, mz_inflateReset, mz_inflateReset2, mz_uncompress,
+};
+
+/// The zlib version whose API is implemented, returned by `zlibVersion`.
+///
+/// Callers compiled against zlib check that the major version matches.
+const ZLIB_VERSION: &[u8] = b"1.2.13\0";
+
+/// Value of `data_type` when the type of the data is unknown.
+const Z_UNKNOWN: c_int = 2;
+
+/// Stream struct with the same layout as zlib's `z_stream`.
+///
+/// The only difference to `mz_stream` is `data_type`, which zlib uses for output only.
+/// zlib's allocation functions take `unsigned int` sizes rather than `size_t`, which are
+/// passed the same way on the platforms we support.
+#[repr(C)]
+#[allow(bad_style)]
+#[derive(Debug)]
+pub struct z_stream {
+    pub next_in: *const u8,
+    pub avail_in: c_uint,
+    pub total_in: c_ulong,
+
+    pub next_out: *mut u8,
+    pub avail_out: c_uint,
+    pub total_out: c_ulong,
+
+    pub msg: *const c_char,
+    pub state: *mut InternalState,
+
+    pub zalloc: Option<mz_alloc_func>,
+    pub zfree: Option<mz_free_func>,
+    pub opaque: *mut c_void,
+
+    /// Always set to `Z_UNKNOWN` (2).
+    pub data_type: c_int,
+    pub adler: c_ulong,
+    pub reserved: c_ulong,
+}
+
+/// Call `f` with an `mz_stream` holding the fields of `strm`, and copy the changes back.
+///
+/// Returns `Z_STREAM_ERROR` if `strm` is null.
+unsafe fn with_mz_stream<F>(strm: *mut z_stream, data_type: StateTypeEnum, f: F) -> c_int
+where
+    F: FnOnce(&mut mz_stream) -> c_int,
+{
+    let strm = match strm.as_mut() {
+        Some(strm) => strm,
+        None => return MZError::Stream as c_int,
+    };
+    let mut stream = mz_stream {
+        next_in: strm.next_in,
+        avail_in: strm.avail_in,
+        total_in: strm.total_in,
+        next_out: strm.next_out,
+        avail_out: strm.avail_out,
+        total_out: strm.total_out,
+        msg: strm.msg,
+        state: strm.state,
+        zalloc: strm.zalloc,
+        zfree: strm.zfree,
+        opaque: strm.opaque,
+        data_type,
+        adler: strm.adler,
+        reserved: strm.reserved,
+    };
+    let ret = f(&mut stream);
+
+    strm.next_in = stream.next_in;
+    strm.avail_in = stream.avail_in;
+    strm.total_in = stream.total_in;
+    strm.next_out = stream.next_out;
+    strm.avail_out = stream.avail_out;
+    strm.total_out = stream.total_out;
+    strm.msg = stream.msg;
+    strm.state = stream.state;
+    strm.data_type = Z_UNKNOWN;
+    strm.adler = stream.adler;
+    zlib_return_code(ret)
+}
+
+/// zlib has no parameter error, it reports invalid parameters as stream errors.
+fn zlib_return_code(ret: c_int) -> c_int {
+    if ret == MZError::Param as c_int {
+        MZError::Stream as c_int
+    } else {
+        ret
+    }
+}
+
+/// Check that the caller was compiled against a compatible version of zlib.
+unsafe fn compatible_version(version: *const c_char, stream_size: c_int) -> bool {
+    !version.is_null()
+        && *version as u8 == ZLIB_VERSION[0]
+        && stream_size as usize == mem::size_of::<z_stream>()
+}
+
+/// Map zlib's window bits to the window size we support, which is always the largest one.
+///
+/// `min` is the smallest accepted window size.
+fn supported_window_bits(window_bits: c_int, min: c_int) -> Option<c_int> {
+    if (min..=15).contains(&window_bits) {
+        Some(15)
+    } else if (-15..=-min).contains(&window_bits) {
+        Some(-15)
+    } else {
+        None
+    }
+}
+
+/// Copy `source` to `dest` like zlib, giving `dest` the state copied by `copy`, one of
+/// `mz_deflateCopy` and `mz_inflateCopy`.
+unsafe fn copy_z_stream(
+    dest: *mut z_stream,
+    source: *mut z_stream,
+    data_type: StateTypeEnum,
+    copy: unsafe extern "C" fn(*mut mz_stream, *mut mz_stream) -> c_int,
+) -> c_int {
+    if dest.is_null() || dest == source {
+        return MZError::Stream as c_int;
+    }
+    let mut state = std::ptr::null_mut();
+    let ret = with_mz_stream(source, data_type, |stream| {
+        let mut dest_stream = mz_stream::default();
+        let ret = copy(&mut dest_stream, stream);
+        state = dest_stream.state;
+        ret
+    });
+    if !state.is_null() {
+        std::ptr::copy_nonoverlapping(source, dest, 1);
+        (*dest).state = state;
+    }
+    ret
+}
+
+/// Prepare `strm` for initialization, as the caller doesn't have to set these fields.
+unsafe fn clear_for_init(strm: *mut z_stream) {
+    if let Some(strm) = strm.as_mut() {
+        strm.msg = std::ptr::null();
+        strm.state = std::ptr::null_mut();
+    }
+}
+
+unmangle!(
+    pub extern "C" fn zlibVersion() -> *const c_char {
+        ZLIB_VERSION.as_ptr() as *const c_char
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn deflateInit_(
+        strm: *mut z_stream,
+        level: c_int,
+        version: *const c_char,
+        stream_size: c_int,
+    ) -> c_int {
+        deflateInit2_(strm, level, 8, 15, 8, 0, version, stream_size)
+    }
+
+    /// Window sizes smaller than the default are accepted but the largest one is used, gzip
+    /// output (`window_bits` above 15) is not supported.
+    ///
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn deflateInit2_(
+        strm: *mut z_stream,
+        level: c_int,
+        method: c_int,
+        window_bits: c_int,
+        mem_level: c_int,
+        strategy: c_int,
+        version: *const c_char,
+        stream_size: c_int,
+    ) -> c_int {
+        if !compatible_version(version, stream_size) {
+            return MZError::Version as c_int;
+        }
+        let window_bits = match supported_window_bits(window_bits, 8) {
+            Some(window_bits) => window_bits,
+            None => return MZError::Stream as c_int,
+        };
+        clear_for_init(strm);
+        with_mz_stream(strm, StateTypeEnum::Deflate, |stream| {
+            mz_deflateInit2(stream, level, method, window_bits, mem_level, strategy)
+        })
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn deflate(strm: *mut z_stream, flush: c_int) -> c_int {
+        with_mz_stream(strm, StateTypeEnum::Deflate, |stream| {
+            mz_deflate(stream, flush)
+        })
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn deflateEnd(strm: *mut z_stream) -> c_int {
+        with_mz_stream(strm, StateTypeEnum::Deflate, |stream| mz_deflateEnd(stream))
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn deflateReset(strm: *mut z_stream) -> c_int {
+        with_mz_stream(strm, StateTypeEnum::Deflate, |stream| {
+            mz_deflateReset(stream)
+        })
+    }
+
+    /// # Safety
+    /// `source` has to meet the requirements for `stream` of `deflate`, and `dest` has to be
+    /// null or valid for writes of an `z_stream`.
+    pub unsafe extern "C" fn deflateCopy(dest: *mut z_stream, source: *mut z_stream) -> c_int {
+        copy_z_stream(dest, source, StateTypeEnum::Deflate, mz_deflateCopy)
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn deflateTune(
+        strm: *mut z_stream,
+        good_length: c_int,
+        max_lazy: c_int,
+        nice_length: c_int,
+        max_chain: c_int,
+    ) -> c_int {
+        with_mz_stream(strm, StateTypeEnum::Deflate, |stream| {
+            mz_deflateTune(stream, good_length, max_lazy, nice_length, max_chain)
+        })
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn deflatePrime(strm: *mut z_stream, bits: c_int, value: c_int) -> c_int {
+        with_mz_stream(strm, StateTypeEnum::Deflate, |stream| {
+            mz_deflatePrime(stream, bits, value)
+        })
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    /// `pending` and `bits` have to be null or valid for writes.
+    pub unsafe extern "C" fn deflatePending(
+        strm: *mut z_stream,
+        pending: *mut c_uint,
+        bits: *mut c_int,
+    ) -> c_int {
+        with_mz_stream(strm, StateTypeEnum::Deflate, |stream| {
+            mz_deflatePending(stream, pending, bits)
+        })
+    }
+
+    pub extern "C" fn deflateSetHeader(_strm: *mut z_stream, head: *mut mz_gz_header) -> c_int {
+        mz_deflateSetHeader(std::ptr::null_mut(), head)
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn deflateBound(strm: *mut z_stream, source_len: c_ulong) -> c_ulong {
+        let mut bound = mz_deflateBound(std::ptr::null_mut(), source_len);
+        with_mz_stream(strm, StateTypeEnum::Deflate, |stream| {
+            bound = mz_deflateBound(stream, source_len);
+            MZStatus::Ok as c_int
+        });
+        bound
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn inflateInit_(
+        strm: *mut z_stream,
+        version: *const c_char,
+        stream_size: c_int,
+    ) -> c_int {
+        inflateInit2_(strm, 15, version, stream_size)
+    }
+
+    /// Any window size is accepted as the largest one is always used. A `window_bits` of 0,
+    /// meaning the size from the zlib header, is accepted, as is automatic header detection
+    /// (32 added to `window_bits`), which only accepts zlib streams. gzip is not supported.
+    ///
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn inflateInit2_(
+        strm: *mut z_stream,
+        window_bits: c_int,
+        version: *const c_char,
+        stream_size: c_int,
+    ) -> c_int {
+        if !compatible_version(version, stream_size) {
+            return MZError::Version as c_int;
+        }
+        let window_bits = match window_bits {
+            0 | 40..=47 => 15,
+            window_bits => match supported_window_bits(window_bits, 8) {
+                Some(window_bits) => window_bits,
+                None => return MZError::Stream as c_int,
+            },
+        };
+        clear_for_init(strm);
+        with_mz_stream(strm, StateTypeEnum::Inflate, |stream| {
+            mz_inflateInit2(stream, window_bits)
+        })
+    }
+
+    /// zlib decompresses as much as fits in the output, where miniz returns early after
+    /// flushing its internal window. zlib also doesn't require `Z_FINISH` on the first call
+    /// to decompress the whole stream, it returns `Z_BUF_ERROR` if the stream isn't complete,
+    /// or if no progress could be made.
+    ///
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn inflate(strm: *mut z_stream, flush: c_int) -> c_int {
+        with_mz_stream(strm, StateTypeEnum::Inflate, |stream| {
+            let finish = flush == MZFlush::Finish as c_int;
+            let flush = if finish {
+                MZFlush::None as c_int
+            } else {
+                flush
+            };
+            let (orig_in, orig_out) = (stream.total_in, stream.total_out);
+            let mut ret;
+            loop {
+                let (total_in, total_out) = (stream.total_in, stream.total_out);
+                ret = mz_inflate(stream, flush);
+                let progress = stream.total_in != total_in || stream.total_out != total_out;
+                if ret != MZStatus::Ok as c_int || stream.avail_out == 0 || !progress {
+                    break;
+                }
+            }
+            let progress = stream.total_in != orig_in || stream.total_out != orig_out;
+            if ret == MZError::Buf as c_int && progress {
+                ret = MZStatus::Ok as c_int;
+            }
+            if ret == MZStatus::Ok as c_int && (finish || !progress) {
+                MZError::Buf as c_int
+            } else {
+                ret
+            }
+        })
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn inflateEnd(strm: *mut z_stream) -> c_int {
+        with_mz_stream(strm, StateTypeEnum::Inflate, |stream| mz_inflateEnd(stream))
+    }
+
+    /// # Safety
+    /// `source` has to meet the requirements for `stream` of `inflate`, and `dest` has to be
+    /// null or valid for writes of an `z_stream`.
+    pub unsafe extern "C" fn inflateCopy(dest: *mut z_stream, source: *mut z_stream) -> c_int {
+        copy_z_stream(dest, source, StateTypeEnum::Inflate, mz_inflateCopy)
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn inflateReset(strm: *mut z_stream) -> c_int {
+        with_mz_stream(strm, StateTypeEnum::Inflate, |stream| {
+            mz_inflateReset(stream)
+        })
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn inflateReset2(strm: *mut z_stream, window_bits: c_int) -> c_int {
+        let window_bits = match window_bits {
+            0 | 40..=47 => 15,
+            window_bits => match supported_window_bits(window_bits, 8) {
+                Some(window_bits) => window_bits,
+                None => return MZError::Stream as c_int,
+            },
+        };
+        with_mz_stream(strm, StateTypeEnum::Inflate, |stream| {
+            mz_inflateReset2(stream, window_bits)
+        })
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn inflatePrime(strm: *mut z_stream, bits: c_int, value: c_int) -> c_int {
+        with_mz_stream(strm, StateTypeEnum::Inflate, |stream| {
+            mz_inflatePrime(stream, bits, value)
+        })
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn inflateMark(strm: *mut z_stream) -> c_long {
+        let mut mark = -(1 << 16);
+        with_mz_stream(strm, StateTypeEnum::Inflate, |stream| {
+            mark = mz_inflateMark(stream);
+            MZStatus::Ok as c_int
+        });
+        mark
+    }
+
+    pub extern "C" fn inflateGetHeader(_strm: *mut z_stream, head: *mut mz_gz_header) -> c_int {
+        mz_inflateGetHeader(std::ptr::null_mut(), head)
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn inflateBackInit_(
+        strm: *mut z_stream,
+        window_bits: c_int,
+        window: *mut u8,
+        version: *const c_char,
+        stream_size: c_int,
+    ) -> c_int {
+        if !compatible_version(version, stream_size) {
+            return MZError::Version as c_int;
+        }
+        clear_for_init(strm);
+        with_mz_stream(strm, StateTypeEnum::Inflate, |stream| {
+            mz_inflateBackInit(stream, window_bits, window)
+        })
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    /// `in_func` and `out_func` have to be safe to call with `in_desc` and `out_desc`.
+    pub unsafe extern "C" fn inflateBack(
+        strm: *mut z_stream,
+        in_func: Option<mz_in_func>,
+        in_desc: *mut c_void,
+        out_func: Option<mz_out_func>,
+        out_desc: *mut c_void,
+    ) -> c_int {
+        with_mz_stream(strm, StateTypeEnum::Inflate, |stream| {
+            mz_inflateBack(stream, in_func, in_desc, out_func, out_desc)
+        })
+    }
+
+    /// # Safety
+    /// `strm` has to be null or point to a valid `z_stream`: `next_in` and `next_out` have to
+    /// be null or valid for `avail_in` and `avail_out` bytes, and `state` has to be null or set up
+    /// by one of the init functions with the allocation functions of the stream.
+    pub unsafe extern "C" fn inflateBackEnd(strm: *mut z_stream) -> c_int {
+        with_mz_stream(strm, StateTypeEnum::Inflate, |stream| {
+            mz_inflateBackEnd(stream)
+        })
+    }
+
+    /// # Safety
+    /// `dest_len` has to be null or valid, `dest` has to be valid for writes of `*dest_len` bytes
+    /// and `source` for reads of `source_len` bytes.
+    pub unsafe extern "C" fn compress(
+        dest: *mut u8,
+        dest_len: *mut c_ulong,
+        source: *const u8,
+        source_len: c_ulong,
+    ) -> c_int {
+        zlib_return_code(mz_compress(dest, dest_len, source, source_len))
+    }
+
+    /// # Safety
+    /// `dest_len` has to be null or valid, `dest` has to be valid for writes of `*dest_len` bytes
+    /// and `source` for reads of `source_len` bytes.
+    pub unsafe extern "C" fn compress2(
+        dest: *mut u8,
+        dest_len: *mut c_ulong,
+        source: *const u8,
+        source_len: c_ulong,
+        level: c_int,
+    ) -> c_int {
+        zlib_return_code(mz_compress2(dest, dest_len, source, source_len, level))
+    }
+
+    pub extern "C" fn compressBound(source_len: c_ulong) -> c_ulong {
+        mz_compressBound(source_len)
+    }
+
+    /// # Safety
+    /// `dest_len` has to be null or valid, `dest` has to be valid for writes of `*dest_len` bytes
+    /// and `source` for reads of `source_len` bytes.
+    pub unsafe extern "C" fn uncompress(
+        dest: *mut u8,
+        dest_len: *mut c_ulong,
+        source: *const u8,
+        source_len: c_ulong,
+    ) -> c_int {
+        zlib_return_code(mz_uncompress(dest, dest_len, source, source_len))
+    }
+
+    /// # Safety
+    /// `buf` has to be null or valid for reads of `len` bytes.
+    pub unsafe extern "C" fn adler32(adler: c_ulong, buf: *const u8, len: c_uint) -> c_ulong {
+        mz_adler32(adler, buf, len as usize)
+    }
+
+    /// # Safety
+    /// `buf` has to be null or valid for reads of `len` bytes.
+    pub unsafe extern "C" fn crc32(crc: c_ulong, buf: *const u8, len: c_uint) -> c_ulong {
+        mz_crc32(crc, buf, len as usize)
+    }
+);
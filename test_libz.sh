@@ -0,0 +1,49 @@
+#!/usr/bin/env bash
+
+# This script builds the C API as a shared library with the zlib_names feature
+# and runs python's zlib module on top of it using LD_PRELOAD.
+
+set -e
+
+cd $(dirname $0)
+
+OLD="\#CRATE_TYPE"
+NEW="crate-type = \['cdylib', 'rlib'\]"
+
+sed -i "s/$OLD/$NEW/g" Cargo.toml
+trap 'sed -i "s/$NEW/$OLD/g" Cargo.toml' EXIT
+
+cargo build --release --features=zlib_names
+
+LD_PRELOAD=$PWD/target/release/libminiz_oxide_c_api.so python3 - <<'PY'
+import zlib
+
+data = open("miniz_oxide/tests/test_data/numbers.txt", "rb").read()
+for level in range(10):
+    compressed = zlib.compress(data, level)
+    assert zlib.decompress(compressed) == data
+    c = zlib.compressobj(level, zlib.DEFLATED, -15)
+    raw = c.compress(data) + c.flush()
+    d = zlib.decompressobj(-15)
+    assert d.decompress(raw) + d.flush() == data
+
+c = zlib.compressobj()
+compressed = b"".join(c.compress(data[i:i + 1000]) + c.flush(zlib.Z_SYNC_FLUSH)
+                      for i in range(0, len(data), 1000)) + c.flush()
+d = zlib.decompressobj()
+out = b"".join(d.decompress(compressed[i:i + 100], 500) + d.decompress(d.unconsumed_tail)
+               for i in range(0, len(compressed), 100))
+assert out + d.flush() == data and d.eof
+# deflateCopy/inflateCopy
+c = zlib.compressobj()
+head = c.compress(data[:20000])
+copy = c.copy()
+tails = [c.compress(data[20000:]) + c.flush(), copy.compress(data[20000:]) + copy.flush()]
+assert tails[0] == tails[1]
+d = zlib.decompressobj()
+out = d.decompress(head + tails[0][:100])
+copy = d.copy()
+assert out + d.decompress(tails[0][100:]) == data
+assert out + copy.decompress(tails[0][100:]) == data
+print("libz drop-in ok, zlib runtime version", zlib.ZLIB_RUNTIME_VERSION)
+PY
@@ -4,7 +4,7 @@ extern crate miniz_oxide_c_api;
 use std::io::Read;
 
 use miniz_oxide::deflate::compress_to_vec;
-use miniz_oxide::inflate::decompress_to_vec;
+use miniz_oxide::inflate::{decompress_to_vec, decompress_to_vec_zlib};
 
 fn get_test_file_data(name: &str) -> Vec<u8> {
     use std::fs::File;
@@ -21,11 +21,27 @@ fn get_test_data() -> Vec<u8> {
     get_test_file_data(&path)
 }
 
+/// Linear congruential generator, the same as the one of the miniz_oxide unit tests.
+struct Lcg(u32);
+
+impl Lcg {
+    fn next_u32(&mut self) -> u32 {
+        self.0 = self.0.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        self.0
+    }
+}
+
+/// Pseudo-random data that doesn't compress.
+fn random_data(len: usize) -> Vec<u8> {
+    let mut lcg = Lcg(98_765);
+    (0..len).map(|_| (lcg.next_u32() >> 16) as u8).collect()
+}
+
 #[test]
 fn roundtrip() {
     let level = 9;
     let data = get_test_data();
-    let enc = compress_to_vec(&data.as_slice()[..], level);
+    let enc = compress_to_vec(data.as_slice(), level);
     println!(
         "Input len: {}, compressed len: {}, level: {}",
         data.len(),
@@ -40,7 +56,7 @@ fn roundtrip() {
 fn roundtrip_level_1() {
     let level = 1;
     let data = get_test_data();
-    let enc = compress_to_vec(&data.as_slice()[..], level);
+    let enc = compress_to_vec(data.as_slice(), level);
     println!(
         "Input len: {}, compressed len: {}, level: {}",
         data.len(),
@@ -123,3 +139,731 @@ fn c_api() {
 
     assert_eq!(data[..], decompressed[0..decompressed_size as usize]);
 }
+
+/// Allocation counters passed to the custom allocation functions through `opaque`.
+#[derive(Default)]
+struct AllocCounter {
+    allocs: usize,
+    frees: usize,
+    /// Total number of bytes allocated.
+    bytes: usize,
+    /// Fail allocations once this many have been made.
+    fail_after: Option<usize>,
+}
+
+unsafe extern "C" fn counting_alloc(
+    opaque: *mut libc::c_void,
+    items: libc::size_t,
+    size: libc::size_t,
+) -> *mut libc::c_void {
+    let counter = &mut *(opaque as *mut AllocCounter);
+    if counter.fail_after == Some(counter.allocs) {
+        return std::ptr::null_mut();
+    }
+    counter.allocs += 1;
+    counter.bytes += items * size;
+    libc::malloc(items * size)
+}
+
+unsafe extern "C" fn counting_free(opaque: *mut libc::c_void, address: *mut libc::c_void) {
+    let counter = &mut *(opaque as *mut AllocCounter);
+    counter.frees += 1;
+    libc::free(address)
+}
+
+#[test]
+fn c_api_custom_allocator() {
+    use miniz_oxide::MZStatus;
+    use miniz_oxide_c_api::{
+        mz_deflate, mz_deflateEnd, mz_deflateInit, mz_inflate, mz_inflateEnd, mz_inflateInit,
+        mz_stream,
+    };
+    let mut data = get_test_data();
+    let mut compressed = vec![0; data.len() + 10];
+    let mut decompressed = vec![0; data.len()];
+    let mut counter = AllocCounter::default();
+    let opaque = &mut counter as *mut AllocCounter as *mut libc::c_void;
+    unsafe {
+        let mut stream = mz_stream {
+            next_in: data.as_mut_ptr(),
+            avail_in: data.len() as u32,
+            next_out: compressed.as_mut_ptr(),
+            avail_out: compressed.len() as u32,
+            zalloc: Some(counting_alloc),
+            zfree: Some(counting_free),
+            opaque,
+            ..Default::default()
+        };
+
+        assert_eq!(mz_deflateInit(&mut stream, 6), MZStatus::Ok as i32);
+        assert!((*(opaque as *mut AllocCounter)).allocs > 0);
+        assert_eq!(mz_deflate(&mut stream, 4), MZStatus::StreamEnd as i32);
+        // The allocator is kept in the stream between calls.
+        assert!(stream.zalloc.is_some() && stream.zfree.is_some());
+        assert_eq!(stream.opaque, opaque);
+        assert_eq!(mz_deflateEnd(&mut stream), MZStatus::Ok as i32);
+        let compressed_size = stream.total_out as usize;
+
+        let mut stream = mz_stream {
+            next_in: compressed.as_mut_ptr(),
+            avail_in: compressed_size as u32,
+            next_out: decompressed.as_mut_ptr(),
+            avail_out: decompressed.len() as u32,
+            zalloc: Some(counting_alloc),
+            zfree: Some(counting_free),
+            opaque,
+            ..Default::default()
+        };
+        assert_eq!(mz_inflateInit(&mut stream), MZStatus::Ok as i32);
+        assert_eq!(mz_inflate(&mut stream, 4), MZStatus::StreamEnd as i32);
+        assert_eq!(mz_inflateEnd(&mut stream), MZStatus::Ok as i32);
+        assert_eq!(stream.total_out as usize, data.len());
+    }
+    assert_eq!(data, decompressed);
+    assert!(counter.allocs >= 4);
+    assert_eq!(counter.allocs, counter.frees);
+}
+
+#[test]
+fn c_api_copy() {
+    use miniz_oxide::{MZError, MZStatus};
+    use miniz_oxide_c_api::{
+        mz_deflate, mz_deflateCopy, mz_deflateEnd, mz_deflateInit, mz_inflate, mz_inflateCopy,
+        mz_inflateEnd, mz_inflateInit, mz_stream,
+    };
+    let data = get_test_data();
+    let half = data.len() / 2;
+    let mut counter = AllocCounter::default();
+    let opaque = &mut counter as *mut AllocCounter as *mut libc::c_void;
+    let mut compressed = [vec![0; data.len() + 100], vec![0; data.len() + 100]];
+    let mut decompressed = [vec![0; data.len()], vec![0; data.len()]];
+    unsafe {
+        let mut stream = mz_stream {
+            next_in: data.as_ptr(),
+            avail_in: half as u32,
+            next_out: compressed[0].as_mut_ptr(),
+            avail_out: compressed[0].len() as u32,
+            zalloc: Some(counting_alloc),
+            zfree: Some(counting_free),
+            opaque,
+            ..Default::default()
+        };
+        assert_eq!(mz_deflateInit(&mut stream, 6), MZStatus::Ok as i32);
+        assert_eq!(mz_deflate(&mut stream, 0), MZStatus::Ok as i32);
+        let allocs = (*(opaque as *mut AllocCounter)).allocs;
+        let mut copy = mz_stream::default();
+        assert_eq!(mz_deflateCopy(&mut copy, &mut stream), MZStatus::Ok as i32);
+        assert_eq!((*(opaque as *mut AllocCounter)).allocs, allocs * 2);
+        assert!(copy.state != stream.state);
+        assert_eq!(copy.total_in, stream.total_in);
+        assert_eq!(
+            mz_deflateCopy(std::ptr::null_mut(), &mut stream),
+            MZError::Stream as i32
+        );
+
+        let written = stream.total_out as usize;
+        let prefix = compressed[0][..written].to_vec();
+        compressed[1][..written].copy_from_slice(&prefix);
+        copy.next_out = compressed[1][written..].as_mut_ptr();
+        for (stream, compressed) in [&mut stream, &mut copy].iter_mut().zip(&mut compressed) {
+            stream.next_in = data[half..].as_ptr();
+            stream.avail_in = (data.len() - half) as u32;
+            assert_eq!(mz_deflate(*stream, 4), MZStatus::StreamEnd as i32);
+            compressed.truncate(stream.total_out as usize);
+            assert_eq!(mz_deflateEnd(*stream), MZStatus::Ok as i32);
+        }
+    }
+    assert!(compressed[0] == compressed[1]);
+
+    unsafe {
+        let mut stream = mz_stream {
+            next_in: compressed[0].as_ptr(),
+            avail_in: compressed[0].len() as u32,
+            next_out: decompressed[0].as_mut_ptr(),
+            avail_out: 1000,
+            zalloc: Some(counting_alloc),
+            zfree: Some(counting_free),
+            opaque,
+            ..Default::default()
+        };
+        assert_eq!(mz_inflateInit(&mut stream), MZStatus::Ok as i32);
+        assert_eq!(mz_inflate(&mut stream, 0), MZStatus::Ok as i32);
+        let mut copy = mz_stream::default();
+        assert_eq!(
+            mz_deflateCopy(&mut copy, &mut stream),
+            MZError::Param as i32
+        );
+        assert_eq!(mz_inflateCopy(&mut copy, &mut stream), MZStatus::Ok as i32);
+
+        let prefix = decompressed[0][..1000].to_vec();
+        decompressed[1][..1000].copy_from_slice(&prefix);
+        copy.next_out = decompressed[1][1000..].as_mut_ptr();
+        for stream in &mut [&mut stream, &mut copy] {
+            stream.avail_out = (data.len() - 1000) as u32;
+            while mz_inflate(*stream, 0) == MZStatus::Ok as i32 {}
+            assert_eq!(stream.total_out as usize, data.len());
+            assert_eq!(mz_inflateEnd(*stream), MZStatus::Ok as i32);
+        }
+    }
+    assert!(decompressed[0] == data);
+    assert!(decompressed[1] == data);
+    assert_eq!(counter.allocs, counter.frees);
+}
+
+#[test]
+fn c_api_allocation_failure() {
+    use miniz_oxide::MZError;
+    use miniz_oxide_c_api::{mz_deflateInit, mz_inflateInit, mz_stream};
+    // Fail each of the allocations made when initializing in turn, and check that
+    // everything allocated before the failure is freed again.
+    for fail_after in 0..6 {
+        let mut counter = AllocCounter {
+            fail_after: Some(fail_after),
+            ..Default::default()
+        };
+        let opaque = &mut counter as *mut AllocCounter as *mut libc::c_void;
+        unsafe {
+            let mut stream = mz_stream {
+                zalloc: Some(counting_alloc),
+                zfree: Some(counting_free),
+                opaque,
+                ..Default::default()
+            };
+            assert_eq!(mz_deflateInit(&mut stream, 6), MZError::Mem as i32);
+            assert!(stream.state.is_null());
+            if fail_after < 2 {
+                let mut stream = mz_stream {
+                    zalloc: Some(counting_alloc),
+                    zfree: Some(counting_free),
+                    opaque,
+                    ..Default::default()
+                };
+                assert_eq!(mz_inflateInit(&mut stream), MZError::Mem as i32);
+                assert!(stream.state.is_null());
+            }
+        }
+        assert_eq!(counter.allocs, counter.frees);
+    }
+}
+
+#[test]
+fn c_api_mem_level() {
+    use miniz_oxide::MZStatus;
+    use miniz_oxide_c_api::{
+        mz_deflate, mz_deflateEnd, mz_deflateInit2, mz_stream, MZ_DEFAULT_WINDOW_BITS, MZ_DEFLATED,
+    };
+    let mut data = get_test_data();
+    let mut bytes = Vec::new();
+    let mut compressed_sizes = Vec::new();
+    for mem_level in 1..=9 {
+        let mut compressed = vec![0; data.len() + 100];
+        let mut counter = AllocCounter::default();
+        let opaque = &mut counter as *mut AllocCounter as *mut libc::c_void;
+        unsafe {
+            let mut stream = mz_stream {
+                next_in: data.as_mut_ptr(),
+                avail_in: data.len() as u32,
+                next_out: compressed.as_mut_ptr(),
+                avail_out: compressed.len() as u32,
+                zalloc: Some(counting_alloc),
+                zfree: Some(counting_free),
+                opaque,
+                ..Default::default()
+            };
+            assert_eq!(
+                mz_deflateInit2(
+                    &mut stream,
+                    6,
+                    MZ_DEFLATED,
+                    MZ_DEFAULT_WINDOW_BITS,
+                    mem_level,
+                    0
+                ),
+                MZStatus::Ok as i32
+            );
+            assert_eq!(mz_deflate(&mut stream, 4), MZStatus::StreamEnd as i32);
+            assert_eq!(mz_deflateEnd(&mut stream), MZStatus::Ok as i32);
+            compressed.truncate(stream.total_out as usize);
+        }
+        assert_eq!(decompress_to_vec_zlib(&compressed).unwrap(), data);
+        assert_eq!(counter.allocs, counter.frees);
+        bytes.push(counter.bytes);
+        compressed_sizes.push(compressed.len());
+    }
+
+    // Each memory level roughly halves the level dependent buffers, and the lowest ones the
+    // window as well.
+    for pair in bytes.windows(2) {
+        assert!(pair[0] < pair[1]);
+    }
+    assert!(bytes[0] < 24 * 1024, "{:?}", bytes);
+    assert!(bytes[8] > 300 * 1024, "{:?}", bytes);
+    assert!(compressed_sizes[0] > compressed_sizes[8]);
+}
+
+#[test]
+fn c_api_deflate_bound() {
+    use miniz_oxide::deflate::compress_bound;
+    use miniz_oxide::{DataFormat, MZStatus};
+    use miniz_oxide_c_api::{
+        mz_compress2, mz_compressBound, mz_deflate, mz_deflateBound, mz_deflateEnd,
+        mz_deflateInit2, mz_stream, MZ_DEFLATED,
+    };
+    use std::ptr;
+
+    // Data that doesn't compress.
+    let data = random_data(100_000);
+    let len = data.len() as libc::c_ulong;
+
+    // (level, window_bits, mem_level, strategy), including static blocks that take more
+    // space than the data, and the small blocks of the lowest memory level.
+    for &(level, window_bits, mem_level, strategy) in
+        &[(6, 15, 9, 0), (1, -15, 9, 4), (0, 15, 1, 0), (9, 15, 1, 4)]
+    {
+        unsafe {
+            let mut stream = mz_stream {
+                next_in: data.as_ptr(),
+                avail_in: data.len() as u32,
+                ..Default::default()
+            };
+            assert_eq!(
+                mz_deflateInit2(
+                    &mut stream,
+                    level,
+                    MZ_DEFLATED,
+                    window_bits,
+                    mem_level,
+                    strategy
+                ),
+                MZStatus::Ok as i32
+            );
+            let bound = mz_deflateBound(&mut stream, len);
+            assert!(bound <= mz_deflateBound(ptr::null_mut(), len));
+            let mut compressed = vec![0; bound as usize];
+            stream.next_out = compressed.as_mut_ptr();
+            stream.avail_out = compressed.len() as u32;
+            assert_eq!(mz_deflate(&mut stream, 4), MZStatus::StreamEnd as i32);
+            assert_eq!(mz_deflateEnd(&mut stream), MZStatus::Ok as i32);
+        }
+    }
+
+    let bound = mz_compressBound(len);
+    assert_eq!(
+        bound as usize,
+        compress_bound(data.len(), DataFormat::Zlib, 6)
+    );
+    assert!(bound < len + len / 200);
+    for level in 0..=10 {
+        let mut compressed = vec![0; bound as usize];
+        let mut compressed_len = bound;
+        let status = unsafe {
+            mz_compress2(
+                compressed.as_mut_ptr(),
+                &mut compressed_len,
+                data.as_ptr(),
+                len,
+                level,
+            )
+        };
+        assert_eq!(status, MZStatus::Ok as i32);
+        compressed.truncate(compressed_len as usize);
+        assert_eq!(decompress_to_vec_zlib(&compressed).unwrap(), data);
+    }
+}
+
+#[test]
+fn c_api_error_msg() {
+    use miniz_oxide::deflate::compress_to_vec_zlib;
+    use miniz_oxide::{MZError, MZStatus};
+    use miniz_oxide_c_api::{mz_inflate, mz_inflateEnd, mz_inflateInit2, mz_stream};
+    use std::ffi::CStr;
+
+    fn inflate_msg(input: &[u8], window_bits: i32) -> Option<String> {
+        let mut output = vec![0; 1024];
+        unsafe {
+            let mut stream = mz_stream {
+                next_in: input.as_ptr(),
+                avail_in: input.len() as u32,
+                next_out: output.as_mut_ptr(),
+                avail_out: output.len() as u32,
+                ..Default::default()
+            };
+            assert_eq!(
+                mz_inflateInit2(&mut stream, window_bits),
+                MZStatus::Ok as i32
+            );
+            assert!(stream.msg.is_null());
+            assert_eq!(mz_inflate(&mut stream, 4), MZError::Data as i32);
+            let msg = stream
+                .msg
+                .as_ref()
+                .map(|msg| CStr::from_ptr(msg).to_str().unwrap().to_string());
+            assert_eq!(mz_inflateEnd(&mut stream), MZStatus::Ok as i32);
+            msg
+        }
+    }
+
+    assert_eq!(
+        inflate_msg(
+            &[0x0c, 0xc0, 0x81, 0, 0, 0, 0, 0, 0x90, 0xff, 0x6b, 0x4, 0],
+            -15
+        )
+        .unwrap(),
+        "invalid distance too far back"
+    );
+    assert_eq!(inflate_msg(&[6], -15).unwrap(), "invalid block type");
+    assert_eq!(
+        inflate_msg(&[0x78, 0x00], 15).unwrap(),
+        "incorrect header check"
+    );
+
+    let mut compressed = compress_to_vec_zlib(b"Hello, hello!", 6);
+    *compressed.last_mut().unwrap() ^= 1;
+    assert_eq!(
+        inflate_msg(&compressed, 15).unwrap(),
+        "incorrect data check"
+    );
+}
+
+#[test]
+fn c_api_error_and_version_strings() {
+    use miniz_oxide::{MZError, MZStatus};
+    use miniz_oxide_c_api::{mz_error, mz_version, zError};
+    use std::ffi::CStr;
+
+    let error = |err: i32| unsafe { CStr::from_ptr(mz_error(err)).to_str().unwrap() };
+    assert_eq!(error(MZStatus::Ok as i32), "");
+    assert_eq!(error(MZStatus::StreamEnd as i32), "stream end");
+    assert_eq!(error(MZError::Data as i32), "data error");
+    assert_eq!(error(MZError::Param as i32), "parameter error");
+    assert!(mz_error(12345).is_null());
+    assert_eq!(zError(MZError::Buf as i32), mz_error(MZError::Buf as i32));
+
+    let version = unsafe { CStr::from_ptr(mz_version()).to_str().unwrap() };
+    assert_eq!(version, "10.0.1");
+    // With `zlib_names`, `zlibVersion` reports the emulated zlib version instead.
+    #[cfg(not(feature = "zlib_names"))]
+    assert_eq!(miniz_oxide_c_api::zlibVersion(), mz_version());
+}
+
+#[test]
+fn c_api_inflate_reset() {
+    use miniz_oxide::deflate::compress_to_vec_zlib;
+    use miniz_oxide::MZStatus;
+    use miniz_oxide_c_api::{
+        mz_inflate, mz_inflateEnd, mz_inflateInit, mz_inflateReset, mz_inflateReset2, mz_stream,
+    };
+
+    let data = get_test_data();
+    let zlib = compress_to_vec_zlib(&data, 6);
+    let raw = compress_to_vec(&data, 6);
+    let mut decompressed = vec![0; data.len()];
+    unsafe {
+        let mut stream = mz_stream::default();
+        assert_eq!(mz_inflateInit(&mut stream), MZStatus::Ok as i32);
+        for &(compressed, reset) in &[(&zlib, 0), (&zlib, 15), (&raw, -15)] {
+            match reset {
+                0 => (),
+                15 => assert_eq!(mz_inflateReset(&mut stream), MZStatus::Ok as i32),
+                window_bits => assert_eq!(
+                    mz_inflateReset2(&mut stream, window_bits),
+                    MZStatus::Ok as i32
+                ),
+            }
+            decompressed.iter_mut().for_each(|b| *b = 0);
+            stream.next_in = compressed.as_ptr();
+            stream.avail_in = compressed.len() as u32;
+            stream.next_out = decompressed.as_mut_ptr();
+            stream.avail_out = decompressed.len() as u32;
+            assert_eq!(mz_inflate(&mut stream, 0), MZStatus::StreamEnd as i32);
+            assert_eq!(stream.total_out as usize, data.len());
+            assert!(decompressed == data);
+        }
+        assert_eq!(mz_inflateEnd(&mut stream), MZStatus::Ok as i32);
+        assert!(mz_inflateReset(&mut stream) != MZStatus::Ok as i32);
+    }
+}
+
+#[test]
+fn c_api_prime_pending_mark() {
+    use miniz_oxide::{MZError, MZStatus};
+    use miniz_oxide_c_api::{
+        mz_deflate, mz_deflateEnd, mz_deflateInit2, mz_deflatePending, mz_deflatePrime,
+        mz_deflateTune, mz_inflate, mz_inflateEnd, mz_inflateInit2, mz_inflateMark,
+        mz_inflatePrime, mz_stream, MZ_DEFLATED,
+    };
+
+    let data = get_test_data();
+    let mut compressed = vec![0; data.len() + 100];
+    unsafe {
+        let mut stream = mz_stream {
+            next_in: data.as_ptr(),
+            avail_in: data.len() as u32,
+            next_out: compressed.as_mut_ptr(),
+            avail_out: 100,
+            ..Default::default()
+        };
+        assert_eq!(
+            mz_deflateInit2(&mut stream, 6, MZ_DEFLATED, -15, 9, 0),
+            MZStatus::Ok as i32
+        );
+        assert_eq!(mz_deflatePrime(&mut stream, 3, 0b101), MZStatus::Ok as i32);
+        assert_eq!(mz_deflatePrime(&mut stream, 16, 0), MZError::Buf as i32);
+        assert_eq!(
+            mz_deflateTune(&mut stream, 8, 16, 128, 64),
+            MZStatus::Ok as i32
+        );
+        assert_eq!(mz_deflate(&mut stream, 4), MZStatus::Ok as i32);
+        let (mut pending, mut bits) = (0, 0);
+        assert_eq!(
+            mz_deflatePending(&mut stream, &mut pending, &mut bits),
+            MZStatus::Ok as i32
+        );
+        assert!(pending > 0);
+        stream.avail_out = (compressed.len() - 100) as u32;
+        assert_eq!(mz_deflate(&mut stream, 4), MZStatus::StreamEnd as i32);
+        assert_eq!(
+            mz_deflatePending(&mut stream, &mut pending, std::ptr::null_mut()),
+            MZStatus::Ok as i32
+        );
+        assert_eq!(pending, 0);
+        compressed.truncate(stream.total_out as usize);
+        assert_eq!(mz_deflateEnd(&mut stream), MZStatus::Ok as i32);
+    }
+    assert_eq!(compressed[0] & 0b111, 0b101);
+
+    let mut decompressed = vec![0; data.len()];
+    unsafe {
+        let mut stream = mz_stream {
+            next_in: compressed[1..].as_ptr(),
+            avail_in: (compressed.len() - 1) as u32,
+            next_out: decompressed.as_mut_ptr(),
+            avail_out: decompressed.len() as u32,
+            ..Default::default()
+        };
+        assert_eq!(mz_inflateInit2(&mut stream, -15), MZStatus::Ok as i32);
+        assert_eq!(mz_inflateMark(&mut stream), -65536);
+        assert_eq!(
+            mz_inflatePrime(&mut stream, 5, i32::from(compressed[0] >> 3)),
+            MZStatus::Ok as i32
+        );
+        // Inside the compressed data, also while output is waiting for space.
+        stream.avail_in = 200;
+        stream.avail_out = 1;
+        assert_eq!(mz_inflate(&mut stream, 0), MZStatus::Ok as i32);
+        let mark = mz_inflateMark(&mut stream);
+        assert!(mark >= 0 && mark >> 16 < 64, "{}", mark);
+        stream.avail_out = (decompressed.len() - 1) as u32;
+        assert_eq!(mz_inflate(&mut stream, 0), MZStatus::Ok as i32);
+        let mark = mz_inflateMark(&mut stream);
+        assert!(mark >= 0 && mark >> 16 < 64, "{}", mark);
+        stream.avail_in = (compressed.len() - 201) as u32;
+        assert_eq!(mz_inflate(&mut stream, 4), MZStatus::StreamEnd as i32);
+        assert_eq!(mz_inflateMark(&mut stream), -65536);
+        assert_eq!(mz_inflateEnd(&mut stream), MZStatus::Ok as i32);
+        assert_eq!(mz_inflateMark(&mut stream), -65536);
+    }
+    assert!(decompressed == data);
+}
+
+/// Input and output of `mz_inflateBack` in the tests.
+struct InflateBackIo<'a> {
+    chunks: std::slice::Chunks<'a, u8>,
+    output: Vec<u8>,
+}
+
+unsafe extern "C" fn inflate_back_in(desc: *mut libc::c_void, buf: *mut *const u8) -> libc::c_uint {
+    let io = &mut *(desc as *mut InflateBackIo);
+    match io.chunks.next() {
+        Some(chunk) => {
+            *buf = chunk.as_ptr();
+            chunk.len() as libc::c_uint
+        }
+        None => 0,
+    }
+}
+
+unsafe extern "C" fn inflate_back_out(
+    desc: *mut libc::c_void,
+    buf: *mut u8,
+    len: libc::c_uint,
+) -> libc::c_int {
+    let io = &mut *(desc as *mut InflateBackIo);
+    io.output
+        .extend_from_slice(std::slice::from_raw_parts(buf, len as usize));
+    0
+}
+
+#[test]
+fn c_api_inflate_back() {
+    use miniz_oxide::{MZError, MZStatus};
+    use miniz_oxide_c_api::{mz_inflateBack, mz_inflateBackEnd, mz_inflateBackInit, mz_stream};
+
+    let data = get_test_data();
+    let compressed = compress_to_vec(&data, 6);
+    let mut window = vec![0; 1 << 15];
+    let mut inflate_back = |input: &[u8], next_in: &[u8]| unsafe {
+        let mut io = InflateBackIo {
+            chunks: input.chunks(1000),
+            output: Vec::new(),
+        };
+        let mut stream = mz_stream {
+            next_in: next_in.as_ptr(),
+            avail_in: next_in.len() as u32,
+            ..Default::default()
+        };
+        assert_eq!(
+            mz_inflateBackInit(&mut stream, 15, window.as_mut_ptr()),
+            MZStatus::Ok as i32
+        );
+        let io_ptr = &mut io as *mut InflateBackIo as *mut libc::c_void;
+        let status = mz_inflateBack(
+            &mut stream,
+            Some(inflate_back_in),
+            io_ptr,
+            Some(inflate_back_out),
+            io_ptr,
+        );
+        assert_eq!(mz_inflateBackEnd(&mut stream), MZStatus::Ok as i32);
+        (status, io.output)
+    };
+
+    let (status, output) = inflate_back(&compressed[100..], &compressed[..100]);
+    assert_eq!(status, MZStatus::StreamEnd as i32);
+    assert!(output == data);
+
+    let (status, _) = inflate_back(&compressed[..compressed.len() / 2], &[]);
+    assert_eq!(status, MZError::Buf as i32);
+
+    let (status, _) = inflate_back(&[6], &[]);
+    assert_eq!(status, MZError::Data as i32);
+
+    unsafe {
+        let mut stream = mz_stream::default();
+        assert_eq!(
+            mz_inflateBackInit(&mut stream, 16, window.as_mut_ptr()),
+            MZError::Stream as i32
+        );
+    }
+}
+
+#[cfg(feature = "zlib_names")]
+#[test]
+fn zlib_names() {
+    use miniz_oxide::{MZError, MZStatus};
+    use miniz_oxide_c_api::zlib_names::{
+        deflate, deflateEnd, deflateInit_, inflate, inflateEnd, inflateInit2_, inflateInit_,
+        z_stream, zlibVersion,
+    };
+    use std::ffi::CStr;
+    use std::mem;
+
+    let version = unsafe { CStr::from_ptr(zlibVersion()) };
+    assert_eq!(version.to_str().unwrap(), "1.2.13");
+    let size = mem::size_of::<z_stream>() as i32;
+
+    let data = get_test_data();
+    let mut compressed = vec![0; data.len() + 100];
+    let mut decompressed = vec![0; data.len()];
+    unsafe {
+        // zlib doesn't require the state and message to be initialized.
+        let mut stream: z_stream = mem::zeroed();
+        stream.state = std::ptr::dangling_mut();
+        stream.msg = std::ptr::dangling();
+        stream.next_in = data.as_ptr();
+        stream.avail_in = data.len() as u32;
+        stream.next_out = compressed.as_mut_ptr();
+        stream.avail_out = compressed.len() as u32;
+        assert_eq!(
+            deflateInit_(&mut stream, 6, version.as_ptr(), size),
+            MZStatus::Ok as i32
+        );
+        assert_eq!(deflate(&mut stream, 4), MZStatus::StreamEnd as i32);
+        assert_eq!(stream.data_type, 2);
+        // Using a compressor to decompress is a stream error, not a parameter error.
+        assert_eq!(inflate(&mut stream, 4), MZError::Stream as i32);
+        assert_eq!(deflateEnd(&mut stream), MZStatus::Ok as i32);
+        compressed.truncate(stream.total_out as usize);
+
+        let mut stream: z_stream = mem::zeroed();
+        assert_eq!(
+            inflateInit_(&mut stream, b"2.0.0\0".as_ptr() as *const _, size),
+            MZError::Version as i32
+        );
+        assert_eq!(
+            inflateInit_(&mut stream, version.as_ptr(), size - 1),
+            MZError::Version as i32
+        );
+        stream.next_in = compressed.as_ptr();
+        stream.avail_in = compressed.len() as u32;
+        // Automatic header detection.
+        assert_eq!(
+            inflateInit2_(&mut stream, 15 + 32, version.as_ptr(), size),
+            MZStatus::Ok as i32
+        );
+        // Unlike miniz, zlib allows finishing with a small output buffer, filling it
+        // completely on each call.
+        for chunk in decompressed.chunks_mut(1000) {
+            stream.next_out = chunk.as_mut_ptr();
+            stream.avail_out = chunk.len() as u32;
+            let status = inflate(&mut stream, 4);
+            assert_eq!(stream.avail_out, 0);
+            if stream.total_out as usize == data.len() {
+                assert_eq!(status, MZStatus::StreamEnd as i32);
+            } else {
+                assert_eq!(status, MZError::Buf as i32);
+            }
+        }
+        assert_eq!(inflate(&mut stream, 4), MZStatus::StreamEnd as i32);
+        assert_eq!(inflateEnd(&mut stream), MZStatus::Ok as i32);
+    }
+    assert!(decompressed == data);
+}
+
+#[test]
+fn c_api_png() {
+    use miniz_oxide_c_api::{
+        miniz_def_free_func, tdefl_write_image_to_png_file_in_memory,
+        tdefl_write_image_to_png_file_in_memory_ex,
+    };
+    use std::{ptr, slice};
+
+    // 3x2 RGB image.
+    let image: Vec<u8> = (0..18).collect();
+    let mut len = 0;
+    unsafe {
+        let png = tdefl_write_image_to_png_file_in_memory_ex(
+            image.as_ptr() as *const _,
+            3,
+            2,
+            3,
+            &mut len,
+            9,
+            1,
+        );
+        assert!(!png.is_null());
+        let data = slice::from_raw_parts(png as *const u8, len);
+        assert_eq!(&data[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&data[12..16], b"IHDR");
+        assert_eq!(&data[16..29], &[0, 0, 0, 3, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        assert_eq!(&data[37..41], b"IDAT");
+        let idat_len = u32::from_be_bytes([data[33], data[34], data[35], data[36]]) as usize;
+        assert_eq!(len, 57 + idat_len);
+        assert_eq!(&data[len - 12..len - 4], b"\0\0\0\0IEND");
+
+        // The image is flipped and stored without filtering.
+        let rows = decompress_to_vec_zlib(&data[41..41 + idat_len]).unwrap();
+        let mut expected = vec![0];
+        expected.extend_from_slice(&image[9..]);
+        expected.push(0);
+        expected.extend_from_slice(&image[..9]);
+        assert_eq!(rows, expected);
+        miniz_def_free_func(ptr::null_mut(), png);
+
+        // Too many channels.
+        let png =
+            tdefl_write_image_to_png_file_in_memory(image.as_ptr() as *const _, 3, 1, 5, &mut len);
+        assert!(png.is_null());
+        assert_eq!(len, 0);
+    }
+}